@@ -26,6 +26,9 @@ pub enum Error {
 
     /// The port is missing.
     MissingPort,
+
+    /// The host is an IP address where a DNS name is required.
+    IpLiteral,
 }
 
 // === impl Addr ===
@@ -151,9 +154,17 @@ impl NameAddr {
             return Err(Error::InvalidHost);
         }
 
-        Name::try_from(host.as_bytes())
-            .map(|name| NameAddr { name, port })
-            .map_err(|_| Error::InvalidHost)
+        // IP addresses are never valid names, but they are distinguished from
+        // otherwise invalid hosts so that callers can report them clearly.
+        let unbracketed = host.trim_start_matches('[').trim_end_matches(']');
+        if IpAddr::from_str(unbracketed).is_ok() {
+            return Err(Error::IpLiteral);
+        }
+
+        let name = Name::try_from(host.as_bytes()).map_err(|_| Error::InvalidHost)?;
+        name.validate_within(Name::MAX_BYTES, Name::MAX_LABELS)
+            .map_err(|_| Error::InvalidHost)?;
+        Ok(NameAddr { name, port })
     }
 
     pub fn from_authority_with_default_port(
@@ -222,4 +233,32 @@ mod tests {
             assert_eq!(a.is_loopback(), *expected_result, "{:?}", host)
         }
     }
+
+    #[test]
+    fn test_name_addr_rejects_ip_literals() {
+        for host in &["1.2.3.4", "::1", "[::1]"] {
+            assert_eq!(
+                NameAddr::from_str_and_port(host, 80),
+                Err(Error::IpLiteral),
+                "{:?}",
+                host
+            );
+        }
+        assert_eq!(NameAddr::from_str("1.2.3.4:80"), Err(Error::IpLiteral));
+
+        let name = NameAddr::from_str_and_port("web.example.com", 80).unwrap();
+        assert_eq!(name.name().as_ref(), "web.example.com");
+
+        // Invalid names are still reported as such.
+        assert_eq!(
+            NameAddr::from_str_and_port("1.2.3.4.5", 80),
+            Err(Error::InvalidHost)
+        );
+
+        // `Addr` parses IP literals as socket addresses.
+        assert_eq!(
+            Addr::from_str_and_port("::1", 80),
+            Ok(Addr::Socket(([0, 0, 0, 0, 0, 0, 0, 1], 80).into()))
+        );
+    }
 }
@@ -6,6 +6,8 @@ use std::time::Duration;
 pub struct Config {
     pub min_ttl: Option<Duration>,
     pub max_ttl: Option<Duration>,
+    /// Bounds each lookup, from which the per-query timeout is derived.
+    pub lookup_budget: Option<LookupBudget>,
     pub resolv_conf_path: PathBuf,
 }
 
@@ -17,20 +19,27 @@ pub struct Dns {
 // === impl Config ===
 
 impl Config {
-    pub fn build(self) -> Dns {
+    pub fn build(self, metrics: Metrics) -> Dns {
         let (resolver, task) =
             Resolver::from_system_config_with(&self).expect("system DNS config must be valid");
-        Dns { resolver, task }
+        Dns {
+            resolver: resolver.with_metrics(metrics),
+            task,
+        }
     }
 }
 
 impl ConfigureResolver for Config {
     /// Modify a `trust-dns-resolver::config::ResolverOpts` to reflect
-    /// the configured minimum and maximum DNS TTL values.
+    /// the configured minimum and maximum DNS TTL values and per-query
+    /// timeout.
     fn configure_resolver(&self, opts: &mut ResolverOpts) {
         opts.positive_min_ttl = self.min_ttl;
         opts.positive_max_ttl = self.max_ttl;
         opts.negative_min_ttl = self.min_ttl;
         opts.negative_max_ttl = self.max_ttl;
+        if let Some(budget) = self.lookup_budget {
+            budget.configure(opts);
+        }
     }
 }
@@ -4,6 +4,7 @@ pub use linkerd2_proxy_api_resolve as api_resolve;
 pub use linkerd2_proxy_core as core;
 pub use linkerd2_proxy_detect as detect;
 pub use linkerd2_proxy_discover as discover;
+pub use linkerd2_proxy_dns_resolve as dns_resolve;
 pub use linkerd2_proxy_http::{
     self as http,
     // TODO(eliza): port
@@ -5,7 +5,7 @@ use linkerd2_app_core::{
     metric_labels::{prefix_labels, EndpointLabels},
     profiles,
     proxy::{
-        api_resolve::{Metadata, ProtocolHint},
+        api_resolve::Metadata,
         http::override_authority::CanOverrideAuthority,
         http::{self, identity_from_header, Settings},
         identity,
@@ -166,7 +166,7 @@ impl<T: connect::ConnectAddr> connect::ConnectAddr for Target<T> {
 
 impl HttpEndpoint {
     pub fn can_use_orig_proto(&self) -> bool {
-        if let ProtocolHint::Unknown = self.metadata.protocol_hint() {
+        if !self.metadata.prefers_http2() {
             return false;
         }
 
@@ -4,7 +4,9 @@ use http_body::Body as HttpBody;
 use indexmap::IndexSet;
 use linkerd2_app_core::{
     config::{ControlAddr, ControlConfig},
-    dns, profiles, Error,
+    dns, profiles,
+    proxy::{api_resolve, resolve::metrics::ChurnMetrics},
+    Error,
 };
 use std::time::Duration;
 use tonic::{
@@ -18,6 +20,9 @@ pub struct Config {
     pub context: String,
     pub get_suffixes: IndexSet<dns::Suffix>,
     pub get_networks: IndexSet<ipnet::IpNet>,
+    pub get_max_lifetime: Option<Duration>,
+    pub get_invalid_argument_fatal: bool,
+    pub get_dns_fallback: bool,
     pub profile_suffixes: IndexSet<dns::Suffix>,
     pub initial_profile_timeout: Duration,
 }
@@ -33,7 +38,13 @@ pub struct Dst<S> {
 
 impl Config {
     // XXX This is unfortunate -- the service should be built here, but it's annoying to name.
-    pub fn build<S>(self, svc: S) -> Result<Dst<S>, Error>
+    pub fn build<S>(
+        self,
+        svc: S,
+        metrics: api_resolve::Metrics,
+        churn: ChurnMetrics,
+        dns: dns::Resolver,
+    ) -> Result<Dst<S>, Error>
     where
         S: GrpcService<BoxBody> + Clone + Send + 'static,
         S::Error: Into<Error> + Send,
@@ -48,6 +59,15 @@ impl Config {
             self.get_networks,
             &self.context,
             self.control.connect.backoff,
+            self.get_max_lifetime,
+            self.get_invalid_argument_fatal,
+            metrics,
+            churn,
+            if self.get_dns_fallback {
+                Some(dns)
+            } else {
+                None
+            },
         );
 
         let profiles = profiles::Client::new(
@@ -1,27 +1,41 @@
+use futures::{future, TryFutureExt};
 use http_body::Body as HttpBody;
 use ipnet::{Contains, IpNet};
 use linkerd2_app_core::{
-    dns::Suffix,
+    dns::{self, Suffix, SuffixSet},
     exp_backoff::{ExponentialBackoff, ExponentialBackoffStream},
     proxy::{
         api_resolve as api,
-        resolve::{self, recover},
+        core::resolve::{self as core_resolve, ErrorKind, ResolveError, Update},
+        dns_resolve,
+        resolve::{self, fallback, metrics::ChurnMetrics, recover},
     },
-    request_filter, Addr, DiscoveryRejected, Error, Recover,
+    request_filter, Addr, DiscoveryRejected, Error, NameAddr, Recover,
 };
 use linkerd2_app_outbound::Target;
 use linkerd2_error::Never;
+use std::fmt;
 use std::net::IpAddr;
+use std::pin::Pin;
 use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
 use tonic::{
     body::{Body, BoxBody},
     client::GrpcService,
     Code, Status,
 };
 
-pub type Resolve<S> = request_filter::Service<
-    PermitConfiguredDsts,
-    recover::Resolve<BackoffUnlessInvalidArgument, resolve::make_unpin::Resolve<api::Resolve<S>>>,
+pub type Resolve<S> = fallback::Resolve<
+    request_filter::Service<
+        PermitConfiguredDsts,
+        recover::Resolve<
+            BackoffUnlessInvalidArgument,
+            resolve::make_unpin::Resolve<api::Resolve<S>>,
+            ChurnMetrics,
+        >,
+    >,
+    recover::Resolve<BackoffUnlessInvalidArgument, DnsFallback>,
 >;
 
 pub fn new<S>(
@@ -30,6 +44,11 @@ pub fn new<S>(
     nets: impl IntoIterator<Item = IpNet>,
     token: &str,
     backoff: ExponentialBackoff,
+    max_lifetime: Option<Duration>,
+    invalid_argument_fatal: bool,
+    metrics: api::Metrics,
+    churn: ChurnMetrics,
+    dns_fallback: Option<dns::Resolver>,
 ) -> Resolve<S>
 where
     S: GrpcService<BoxBody> + Clone + Send + 'static,
@@ -39,23 +58,61 @@ where
     <S::ResponseBody as HttpBody>::Error: Into<Error> + Send,
     S::Future: Send,
 {
-    request_filter::Service::new(
-        PermitConfiguredDsts::new(suffixes, nets),
-        recover::Resolve::new(
-            backoff.into(),
-            resolve::make_unpin(api::Resolve::new(service).with_context_token(token)),
+    let recover = recover::Resolve::new(
+        BackoffUnlessInvalidArgument::from(backoff)
+            .with_invalid_argument_fatal(invalid_argument_fatal),
+        resolve::make_unpin(
+            api::Resolve::new(service)
+                .with_context_token(token)
+                .with_metrics(metrics),
         ),
     )
+    .with_churn_metrics(churn);
+    let recover = match max_lifetime {
+        Some(max_lifetime) => recover.with_max_lifetime(max_lifetime),
+        None => recover,
+    };
+    let dns_fallback = recover::Resolve::new(
+        BackoffUnlessInvalidArgument::from(backoff),
+        DnsFallback(dns_fallback.map(dns_resolve::Resolve::new)),
+    );
+    fallback::Resolve::new(
+        fallback::RecoveryPolicy::Replace,
+        request_filter::Service::new(PermitConfiguredDsts::new(suffixes, nets), recover),
+        dns_fallback,
+    )
 }
 
+/// Only permits the resolution of names under the configured search suffixes
+/// and of addresses in the configured networks.
+///
+/// Other destinations are rejected before the control plane is queried.
 #[derive(Clone, Debug)]
 pub struct PermitConfiguredDsts {
-    name_suffixes: Arc<Vec<Suffix>>,
+    name_suffixes: Arc<SuffixSet>,
     networks: Arc<Vec<IpNet>>,
 }
 
+/// Resolves the names of permitted destinations via DNS, so that their
+/// endpoints are known when the control plane has none.
+///
+/// Destinations that are not names, or all destinations if no resolver is
+/// configured, have no fallback endpoints.
+#[derive(Clone, Debug)]
+pub struct DnsFallback(Option<dns_resolve::Resolve>);
+
+pub struct DnsFallbackResolution(Option<dns_resolve::Resolution>);
+
 #[derive(Clone, Debug, Default)]
-pub struct BackoffUnlessInvalidArgument(ExponentialBackoff);
+pub struct BackoffUnlessInvalidArgument {
+    backoff: ExponentialBackoff,
+    invalid_argument_fatal: bool,
+}
+
+/// Indicates that the control plane rejected a destination with an
+/// `InvalidArgument` status, e.g. because it should not be resolved here.
+#[derive(Clone, Debug)]
+pub struct InvalidDestination(String);
 
 // === impl PermitConfiguredDsts ===
 
@@ -76,10 +133,7 @@ impl<T> request_filter::RequestFilter<Target<T>> for PermitConfiguredDsts {
 
     fn filter(&self, t: Target<T>) -> Result<Target<T>, Self::Error> {
         let permitted = match t.addr {
-            Addr::Name(ref name) => self
-                .name_suffixes
-                .iter()
-                .any(|suffix| suffix.contains(name.name())),
+            Addr::Name(ref name) => self.name_suffixes.contains(name.name()),
             Addr::Socket(sa) => self.networks.iter().any(|net| match (net, sa.ip()) {
                 (IpNet::V4(net), IpAddr::V4(addr)) => net.contains(&addr),
                 (IpNet::V6(net), IpAddr::V6(addr)) => net.contains(&addr),
@@ -95,11 +149,83 @@ impl<T> request_filter::RequestFilter<Target<T>> for PermitConfiguredDsts {
     }
 }
 
+// === impl DnsFallback ===
+
+impl<T> tower::Service<Target<T>> for DnsFallback {
+    type Response = DnsFallbackResolution;
+    type Error = ResolveError;
+    type Future = future::Either<
+        future::MapOk<
+            <dns_resolve::Resolve as tower::Service<NameAddr>>::Future,
+            fn(dns_resolve::Resolution) -> DnsFallbackResolution,
+        >,
+        future::Ready<Result<DnsFallbackResolution, ResolveError>>,
+    >;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        match self.0 {
+            Some(ref mut dns) => tower::Service::<NameAddr>::poll_ready(dns, cx),
+            None => Poll::Ready(Ok(())),
+        }
+    }
+
+    fn call(&mut self, target: Target<T>) -> Self::Future {
+        match (self.0.as_mut(), target.addr) {
+            (Some(dns), Addr::Name(name)) => {
+                let resolution: fn(_) -> _ = |r| DnsFallbackResolution(Some(r));
+                future::Either::Left(tower::Service::call(dns, name).map_ok(resolution))
+            }
+            _ => future::Either::Right(future::ok(DnsFallbackResolution(None))),
+        }
+    }
+}
+
+// === impl DnsFallbackResolution ===
+
+impl core_resolve::Resolution for DnsFallbackResolution {
+    type Endpoint = api::Metadata;
+    type Error = ResolveError;
+
+    fn poll(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Update<Self::Endpoint>, Self::Error>> {
+        match self.get_mut().0 {
+            Some(ref mut resolution) => core_resolve::Resolution::poll_unpin(resolution, cx),
+            None => Poll::Pending,
+        }
+    }
+}
+
 // === impl BackoffUnlessInvalidArgument ===
 
 impl From<ExponentialBackoff> for BackoffUnlessInvalidArgument {
-    fn from(eb: ExponentialBackoff) -> Self {
-        BackoffUnlessInvalidArgument(eb)
+    fn from(backoff: ExponentialBackoff) -> Self {
+        Self {
+            backoff,
+            invalid_argument_fatal: false,
+        }
+    }
+}
+
+impl BackoffUnlessInvalidArgument {
+    /// Fails with an `InvalidDestination` error when the control plane
+    /// rejects a destination with an `InvalidArgument` status.
+    ///
+    /// By default, such destinations are rejected as undiscoverable, so that
+    /// they may be handled without discovery.
+    pub fn with_invalid_argument_fatal(self, invalid_argument_fatal: bool) -> Self {
+        Self {
+            invalid_argument_fatal,
+            ..self
+        }
+    }
+
+    fn reject(&self, status: &Status) -> Error {
+        if self.invalid_argument_fatal {
+            return InvalidDestination(status.message().to_string()).into();
+        }
+        DiscoveryRejected::new().into()
     }
 }
 
@@ -108,15 +234,117 @@ impl Recover<Error> for BackoffUnlessInvalidArgument {
     type Error = Never;
 
     fn recover(&self, err: Error) -> Result<Self::Backoff, Error> {
-        match err.downcast::<Status>() {
-            Ok(ref status) if status.code() == Code::InvalidArgument => {
+        // Resolutions fail with categorized errors, while profile lookups fail
+        // with the Destination service's status.
+        let status = match err.downcast_ref::<ResolveError>() {
+            // Only an `InvalidArgument` status is categorized as a
+            // configuration error.
+            Some(error) if error.kind() == ErrorKind::Config => {
+                error.get_ref().downcast_ref::<Status>()
+            }
+            Some(_) => None,
+            None => err.downcast_ref::<Status>(),
+        };
+
+        match status {
+            Some(status) if status.code() == Code::InvalidArgument => {
                 tracing::debug!(message = "cannot recover", %status);
-                return Err(DiscoveryRejected::new().into());
+                Err(self.reject(status))
+            }
+            _ => {
+                tracing::trace!(message = "recovering", error = %err);
+                Ok(self.backoff.stream())
             }
-            Ok(status) => tracing::trace!(message = "recovering", %status),
-            Err(error) => tracing::trace!(message = "recovering", %error),
         }
+    }
+}
+
+// === impl InvalidDestination ===
+
+impl fmt::Display for InvalidDestination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the control plane rejected the destination as invalid: {}",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for InvalidDestination {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use linkerd2_app_core::proxy::core::resolve::DiscoverySource;
+    use linkerd2_app_core::request_filter::RequestFilter;
+    use std::convert::TryFrom;
+
+    fn permit() -> PermitConfiguredDsts {
+        let suffixes = vec![Suffix::try_from("svc.cluster.local.").unwrap()];
+        let nets = vec!["10.0.0.0/8".parse::<IpNet>().unwrap()];
+        PermitConfiguredDsts::new(suffixes, nets)
+    }
+
+    fn target(addr: &str) -> Target<()> {
+        Target {
+            addr: Addr::from_str(addr).unwrap(),
+            inner: (),
+        }
+    }
+
+    #[test]
+    fn permits_names_in_suffixes() {
+        assert!(permit()
+            .filter(target("web.ns.svc.cluster.local:8080"))
+            .is_ok());
+        assert!(permit().filter(target("10.1.2.3:8080")).is_ok());
+    }
+
+    fn invalid_argument() -> Error {
+        let status = Status::new(Code::InvalidArgument, "not a cluster service");
+        ResolveError::new(DiscoverySource::Destination, ErrorKind::Config, status).into()
+    }
+
+    #[test]
+    fn invalid_argument_rejects_discovery() {
+        let err = BackoffUnlessInvalidArgument::default()
+            .recover(invalid_argument())
+            .err()
+            .expect("must not recover");
+        assert!(err.is::<DiscoveryRejected>());
+    }
+
+    #[test]
+    fn invalid_argument_fatal() {
+        let err = BackoffUnlessInvalidArgument::default()
+            .with_invalid_argument_fatal(true)
+            .recover(invalid_argument())
+            .err()
+            .expect("must not recover");
+        assert!(err.is::<InvalidDestination>());
+        assert_eq!(
+            err.to_string(),
+            "the control plane rejected the destination as invalid: not a cluster service"
+        );
+
+        // Other errors are recovered.
+        let status = Status::new(Code::FailedPrecondition, "not ready");
+        let error = ResolveError::new(DiscoverySource::Destination, ErrorKind::Transient, status);
+        assert!(BackoffUnlessInvalidArgument::default()
+            .with_invalid_argument_fatal(true)
+            .recover(error.into())
+            .is_ok());
+        let error = ResolveError::new(DiscoverySource::Dns, ErrorKind::Config, "invalid name");
+        assert!(BackoffUnlessInvalidArgument::default()
+            .with_invalid_argument_fatal(true)
+            .recover(error.into())
+            .is_ok());
+    }
 
-        Ok(self.0.stream())
+    #[test]
+    fn rejects_names_outside_suffixes() {
+        assert!(permit().filter(target("example.com:443")).is_err());
+        assert!(permit().filter(target("192.0.2.1:8080")).is_err());
     }
 }
@@ -156,8 +156,39 @@ pub const ENV_DESTINATION_CONTEXT: &str = "LINKERD2_PROXY_DESTINATION_CONTEXT";
 pub const ENV_DESTINATION_PROFILE_INITIAL_TIMEOUT: &str =
     "LINKERD2_PROXY_DESTINATION_PROFILE_INITIAL_TIMEOUT";
 
+/// Configures the maximum amount of time a destination resolution stream may
+/// be used before it is reconnected.
+///
+/// Resolutions are not reconnected proactively unless this is set.
+pub const ENV_DESTINATION_GET_MAX_LIFETIME: &str = "LINKERD2_PROXY_DESTINATION_GET_MAX_LIFETIME";
+
+/// Fails resolutions whose destination the control plane rejects with an
+/// `InvalidArgument` status, rather than treating the destination as
+/// undiscoverable.
+///
+/// Rejected destinations are treated as undiscoverable unless this is set.
+pub const ENV_DESTINATION_GET_INVALID_ARGUMENT_FATAL: &str =
+    "LINKERD2_PROXY_DESTINATION_GET_INVALID_ARGUMENT_FATAL";
+
+/// Resolves destinations via DNS while the control plane has no endpoints for
+/// them.
+///
+/// Only names that may be resolved by the control plane are resolved via DNS.
+/// Destinations are not resolved via DNS unless this is set.
+pub const ENV_DESTINATION_GET_DNS_FALLBACK: &str = "LINKERD2_PROXY_DESTINATION_GET_DNS_FALLBACK";
+
 pub const ENV_TAP_DISABLED: &str = "LINKERD2_PROXY_TAP_DISABLED";
 pub const ENV_TAP_SVC_NAME: &str = "LINKERD2_PROXY_TAP_SVC_NAME";
+/// Constrains which request authorities may be tapped.
+///
+/// When set, requests are only offered to taps if their authority is within
+/// one of these comma-separated suffixes.
+pub const ENV_TAP_AUTHORITY_SUFFIXES: &str = "LINKERD2_PROXY_TAP_AUTHORITY_SUFFIXES";
+/// Accepts tap connections over HTTP/1.1 in addition to HTTP/2.
+///
+/// The gRPC tap protocol relies on HTTP/2, so HTTP/1.1 clients may not stream
+/// tap events.
+pub const ENV_TAP_ALLOW_HTTP1: &str = "LINKERD2_PROXY_TAP_ALLOW_HTTP1";
 const ENV_RESOLV_CONF: &str = "LINKERD2_PROXY_RESOLV_CONF";
 
 /// Configures a minimum value for the TTL of DNS lookups.
@@ -168,6 +199,11 @@ const ENV_DNS_MIN_TTL: &str = "LINKERD2_PROXY_DNS_MIN_TTL";
 ///
 /// Lookups with TTLs above this value will use this value instead.
 const ENV_DNS_MAX_TTL: &str = "LINKERD2_PROXY_DNS_MAX_TTL";
+/// Configures the overall amount of time allotted to each DNS lookup.
+///
+/// The timeout of each individual DNS query is derived from this value, so
+/// that all of a lookup's attempts complete within it.
+const ENV_DNS_LOOKUP_TIMEOUT: &str = "LINKERD2_PROXY_DNS_LOOKUP_TIMEOUT";
 
 /// The amount of time to wait for a DNS query to succeed before falling back to
 /// an uncanonicalized address.
@@ -290,6 +326,7 @@ pub fn parse_config<S: Strings>(strings: &S) -> Result<super::Config, EnvError>
 
     let dns_min_ttl = parse(strings, ENV_DNS_MIN_TTL, parse_duration);
     let dns_max_ttl = parse(strings, ENV_DNS_MAX_TTL, parse_duration);
+    let dns_lookup_timeout = parse(strings, ENV_DNS_LOOKUP_TIMEOUT, parse_duration);
 
     let dns_canonicalize_timeout = parse(strings, ENV_DNS_CANONICALIZE_TIMEOUT, parse_duration);
 
@@ -321,6 +358,13 @@ pub fn parse_config<S: Strings>(strings: &S) -> Result<super::Config, EnvError>
     let gateway_suffixes = parse(strings, ENV_INBOUND_GATEWAY_SUFFIXES, parse_dns_suffixes);
     let dst_get_suffixes = parse(strings, ENV_DESTINATION_GET_SUFFIXES, parse_dns_suffixes);
     let dst_get_networks = parse(strings, ENV_DESTINATION_GET_NETWORKS, parse_networks);
+    let dst_get_max_lifetime = parse(strings, ENV_DESTINATION_GET_MAX_LIFETIME, parse_duration);
+    let dst_get_invalid_argument_fatal = strings
+        .get(ENV_DESTINATION_GET_INVALID_ARGUMENT_FATAL)
+        .map(|v| v.map(|v| !v.is_empty()).unwrap_or(false));
+    let dst_get_dns_fallback = strings
+        .get(ENV_DESTINATION_GET_DNS_FALLBACK)
+        .map(|v| v.map(|v| !v.is_empty()).unwrap_or(false));
     let dst_profile_initial_timeout = parse(
         strings,
         ENV_DESTINATION_PROFILE_INITIAL_TIMEOUT,
@@ -337,6 +381,10 @@ pub fn parse_config<S: Strings>(strings: &S) -> Result<super::Config, EnvError>
         parse(strings, ENV_INITIAL_CONNECTION_WINDOW_SIZE, parse_number);
 
     let tap = parse_tap_config(strings, id_disabled);
+    let tap_authority_suffixes = parse(strings, ENV_TAP_AUTHORITY_SUFFIXES, parse_dns_suffixes);
+    let tap_allow_http1 = strings
+        .get(ENV_TAP_ALLOW_HTTP1)
+        .map(|v| v.map(|v| !v.is_empty()).unwrap_or(false));
 
     let h2_settings = h2::Settings {
         initial_stream_window_size: Some(
@@ -484,6 +532,9 @@ pub fn parse_config<S: Strings>(strings: &S) -> Result<super::Config, EnvError>
             get_suffixes: dst_get_suffixes?
                 .unwrap_or(parse_dns_suffixes(DEFAULT_DESTINATION_GET_SUFFIXES).unwrap()),
             get_networks: dst_get_networks?.unwrap_or_default(),
+            get_max_lifetime: dst_get_max_lifetime?,
+            get_invalid_argument_fatal: dst_get_invalid_argument_fatal?,
+            get_dns_fallback: dst_get_dns_fallback?,
             profile_suffixes: dst_profile_suffixes?
                 .unwrap_or(parse_dns_suffixes(DEFAULT_DESTINATION_PROFILE_SUFFIXES).unwrap()),
             initial_profile_timeout: dst_profile_initial_timeout?
@@ -511,6 +562,7 @@ pub fn parse_config<S: Strings>(strings: &S) -> Result<super::Config, EnvError>
     let dns = dns::Config {
         min_ttl: dns_min_ttl?,
         max_ttl: dns_max_ttl?,
+        lookup_budget: dns_lookup_timeout?.map(dns::LookupBudget::new),
         resolv_conf_path: resolv_conf_path?
             .unwrap_or(DEFAULT_RESOLV_CONF.into())
             .into(),
@@ -544,9 +596,13 @@ pub fn parse_config<S: Strings>(strings: &S) -> Result<super::Config, EnvError>
         }
     };
 
+    let tap_authority_suffixes = tap_authority_suffixes?;
+    let tap_allow_http1 = tap_allow_http1?;
     let tap = tap?
         .map(|(addr, ids)| super::tap::Config::Enabled {
             permitted_peer_identities: ids,
+            authority_suffixes: tap_authority_suffixes.map(|sfxs| sfxs.into_iter().collect()),
+            allow_http1: tap_allow_http1,
             config: ServerConfig {
                 bind: listen::Bind::new(addr, inbound.proxy.server.bind.keepalive()),
                 h2_settings,
@@ -805,13 +861,9 @@ fn parse_dns_suffixes(list: &str) -> Result<IndexSet<dns::Suffix>, ParseError> {
 }
 
 fn parse_dns_suffix(s: &str) -> Result<dns::Suffix, ParseError> {
-    if s == "." {
-        return Ok(dns::Suffix::Root);
-    }
-
-    dns::Name::try_from(s.as_bytes())
-        .map(dns::Suffix::Name)
-        .map_err(|_| ParseError::NotADomainSuffix)
+    // Accepts the root suffix and wildcards, e.g. `*.svc.cluster.local`, as
+    // well as names.
+    dns::Suffix::try_from(s).map_err(|_| ParseError::NotADomainSuffix)
 }
 
 fn parse_networks(list: &str) -> Result<IndexSet<ipnet::IpNet>, ParseError> {
@@ -1195,5 +1247,10 @@ mod tests {
             Ok(vec!["multi.case.name".to_owned()]),
             "names are coerced to lowercase"
         );
+        assert_eq!(
+            p("*.svc.cluster.local."),
+            Ok(vec!["*.svc.cluster.local.".to_owned()]),
+            "wildcards are valid"
+        );
     }
 }
@@ -91,7 +91,7 @@ impl Config {
         debug!("building app");
         let (metrics, report) = Metrics::new(admin.metrics_retain_idle);
 
-        let dns = dns.build();
+        let dns = dns.build(metrics.dns.clone());
 
         let identity = info_span!("identity")
             .in_scope(|| identity.build(dns.resolver.clone(), metrics.control.clone()))?;
@@ -102,8 +102,11 @@ impl Config {
         let dst = {
             use linkerd2_app_core::{classify, control, reconnect, transport::tls};
 
+            let dst_metrics = metrics.dst.clone();
+            let churn_metrics = metrics.resolve.clone();
             let metrics = metrics.control.clone();
             let dns = dns.resolver.clone();
+            let dns_fallback = dns.clone();
             info_span!("dst").in_scope(|| {
                 // XXX This is unfortunate. But we don't daemonize the service into a
                 // task in the build, so we'd have to name it. And that's not
@@ -124,7 +127,7 @@ impl Config {
                     .into_new_service()
                     .push_on_response(svc::layers().push_spawn_buffer(dst.control.buffer_capacity))
                     .new_service(dst.control.addr.clone());
-                dst.build(svc)
+                dst.build(svc, dst_metrics, churn_metrics, dns_fallback)
             })
         }?;
 
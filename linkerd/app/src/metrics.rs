@@ -1,6 +1,6 @@
 pub use linkerd2_app_core::{
     classify::Class,
-    errors, handle_time, http_metrics as metrics,
+    dns, errors, handle_time, http_metrics as metrics,
     metric_labels::{ControlLabels, EndpointLabels, RouteLabels},
     metrics::FmtMetrics,
     opencensus, proxy, stack_metrics, telemetry, transport, ControlHttpMetrics, ProxyMetrics,
@@ -12,6 +12,9 @@ pub struct Metrics {
     pub outbound: ProxyMetrics,
     pub control: ControlHttpMetrics,
     pub opencensus: opencensus::metrics::Registry,
+    pub dns: dns::Metrics,
+    pub dst: proxy::api_resolve::Metrics,
+    pub resolve: proxy::resolve::metrics::ChurnMetrics,
 }
 
 impl Metrics {
@@ -63,6 +66,12 @@ impl Metrics {
 
         let (opencensus, opencensus_report) = opencensus::metrics::new();
 
+        let dns = dns::Metrics::default();
+
+        let dst = proxy::api_resolve::Metrics::default();
+
+        let resolve = proxy::resolve::metrics::ChurnMetrics::default();
+
         let metrics = Metrics {
             inbound: ProxyMetrics {
                 http_handle_time: inbound_handle_time,
@@ -86,6 +95,9 @@ impl Metrics {
             },
             control,
             opencensus,
+            dns: dns.clone(),
+            dst: dst.clone(),
+            resolve: resolve.clone(),
         };
 
         let report = (http_errors.report())
@@ -97,6 +109,9 @@ impl Metrics {
             .and_then(handle_time_report)
             .and_then(transport_report)
             .and_then(opencensus_report)
+            .and_then(dns)
+            .and_then(dst)
+            .and_then(resolve)
             .and_then(stack)
             .and_then(process);
 
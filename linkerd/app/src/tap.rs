@@ -1,7 +1,7 @@
 use indexmap::IndexSet;
 use linkerd2_app_core::{
     config::ServerConfig,
-    drain,
+    dns, drain,
     proxy::{identity, tap},
     serve,
     transport::tls,
@@ -16,6 +16,13 @@ pub enum Config {
     Enabled {
         config: ServerConfig,
         permitted_peer_identities: IndexSet<identity::Name>,
+        /// If set, only requests to authorities within these suffixes may
+        /// be tapped. This is a coarse pre-filter; each tap's match rules
+        /// still apply.
+        authority_suffixes: Option<dns::SuffixSet>,
+        /// Whether tap connections may use HTTP/1.1. Only a subset of the
+        /// tap protocol is available over HTTP/1.1.
+        allow_http1: bool,
     },
 }
 
@@ -46,12 +53,20 @@ impl Config {
             Config::Enabled {
                 config,
                 permitted_peer_identities,
+                authority_suffixes,
+                allow_http1,
             } => {
+                let layer = match authority_suffixes {
+                    Some(suffixes) => layer.with_authority_filter(suffixes),
+                    None => layer,
+                };
+
                 let (listen_addr, listen) = config.bind.bind()?;
 
                 let accept = tls::AcceptTls::new(
                     identity,
-                    tap::AcceptPermittedClients::new(permitted_peer_identities.into(), server),
+                    tap::AcceptPermittedClients::new(permitted_peer_identities.into(), server)
+                        .with_http1(allow_http1),
                 );
 
                 let serve = Box::pin(serve::serve(listen, accept, drain.signal()));
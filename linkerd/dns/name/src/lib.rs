@@ -3,5 +3,5 @@
 mod name;
 mod suffix;
 
-pub use self::name::{InvalidName, Name};
-pub use self::suffix::Suffix;
+pub use self::name::{IdnPolicy, InvalidName, Name};
+pub use self::suffix::{InvalidSuffix, Suffix, SuffixSet};
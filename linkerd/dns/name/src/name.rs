@@ -10,7 +10,40 @@ pub struct Name(webpki::DNSName);
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub struct InvalidName;
 
+/// Determines whether names may contain internationalized (punycode) labels.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum IdnPolicy {
+    /// Names with punycode labels are valid.
+    Allow,
+    /// Names with punycode labels are invalid, e.g. where only ASCII service
+    /// names are expected.
+    Reject,
+}
+
 impl Name {
+    /// The maximum length of a name, in bytes, excluding a trailing dot.
+    pub const MAX_BYTES: usize = 253;
+
+    /// The maximum number of labels in a name, excluding the root label.
+    pub const MAX_LABELS: usize = 127;
+
+    /// Parses a name, rejecting it if it contains punycode labels that are
+    /// not permitted by `policy`.
+    pub fn try_from_with_policy(s: &[u8], policy: IdnPolicy) -> Result<Self, InvalidName> {
+        let name = Self::try_from(s)?;
+        if policy == IdnPolicy::Reject && name.contains_idn() {
+            return Err(InvalidName);
+        }
+        Ok(name)
+    }
+
+    /// Returns true if any of the name's labels is an internationalized
+    /// label encoded as punycode, i.e. prefixed by `xn--`.
+    pub fn contains_idn(&self) -> bool {
+        self.labels()
+            .any(|label| label.len() >= 4 && label[..4].eq_ignore_ascii_case("xn--"))
+    }
+
     pub fn is_localhost(&self) -> bool {
         *self == Name::try_from("localhost.".as_bytes()).unwrap()
     }
@@ -19,11 +52,66 @@ impl Name {
         self.as_ref().trim_end_matches('.')
     }
 
+    /// Returns the name's labels, from left to right, excluding the empty
+    /// root label of an absolute name.
+    pub fn labels(&self) -> impl DoubleEndedIterator<Item = &str> + '_ {
+        self.without_trailing_dot().split('.')
+    }
+
+    /// Returns the length of the name in bytes, excluding a trailing dot.
+    pub fn byte_len(&self) -> usize {
+        self.without_trailing_dot().len()
+    }
+
+    /// Returns the number of labels in the name, excluding the empty root
+    /// label of an absolute name.
+    pub fn label_count(&self) -> usize {
+        self.labels().count()
+    }
+
+    /// Returns true if this name is a subdomain of `parent`, i.e. if
+    /// `parent`'s labels are a proper suffix of this name's labels.
+    ///
+    /// A name is not a subdomain of itself. Labels are compared
+    /// case-insensitively, and trailing dots are ignored.
+    pub fn is_subdomain_of(&self, parent: &Name) -> bool {
+        self.label_count() > parent.label_count() && self.is_subdomain_of_or_equal(parent)
+    }
+
+    /// Returns true if this name is equal to or a subdomain of `parent`.
+    ///
+    /// Labels are compared case-insensitively, and trailing dots are ignored.
+    pub fn is_subdomain_of_or_equal(&self, parent: &Name) -> bool {
+        let mut labels = self.labels().rev();
+        parent.labels().rev().all(|p| match labels.next() {
+            Some(label) => label.eq_ignore_ascii_case(p),
+            None => false,
+        })
+    }
+
+    /// Rejects the name if it is longer than `max_bytes` or has more than
+    /// `max_labels` labels.
+    ///
+    /// Names that parse may still be impractical to resolve, so this should
+    /// be checked where names are accepted from outside of the proxy.
+    pub fn validate_within(&self, max_bytes: usize, max_labels: usize) -> Result<(), InvalidName> {
+        if self.byte_len() > max_bytes || self.label_count() > max_labels {
+            return Err(InvalidName);
+        }
+        Ok(())
+    }
+
     pub fn as_dns_name_ref(&self) -> webpki::DNSNameRef<'_> {
         self.0.as_ref()
     }
 }
 
+impl Default for IdnPolicy {
+    fn default() -> Self {
+        IdnPolicy::Allow
+    }
+}
+
 impl fmt::Debug for Name {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
         let s: &str = AsRef::<str>::as_ref(&self.0);
@@ -105,4 +193,112 @@ mod tests {
         }
         assert!(Name::try_from(".".as_bytes()).is_err());
     }
+
+    #[test]
+    fn test_labels() {
+        for host in &["web.svc.local", "web.svc.local."] {
+            let name = Name::try_from(host.as_bytes()).unwrap();
+            assert_eq!(
+                name.labels().collect::<Vec<_>>(),
+                vec!["web", "svc", "local"]
+            );
+        }
+    }
+
+    #[test]
+    fn test_is_subdomain_of() {
+        let name = |n: &str| Name::try_from(n.as_bytes()).unwrap();
+        let cases = &[
+            // (name, parent, strict, inclusive)
+            ("a.b.c", "b.c", true, true),
+            ("a.b.c.", "b.c", true, true),
+            ("A.B.C", "b.c.", true, true),
+            ("a.b.c", "c", true, true),
+            ("b.c", "b.c", false, true),
+            ("b.c.", "B.C", false, true),
+            ("b.c", "a.b.c", false, false),
+            ("ab.c", "b.c", false, false),
+            ("a.b.c", "a.b", false, false),
+            ("a.bb.c", "b.c", false, false),
+        ];
+        for (n, parent, strict, inclusive) in cases {
+            let (n, parent) = (name(n), name(parent));
+            assert_eq!(n.is_subdomain_of(&parent), *strict, "{:?} {:?}", n, parent);
+            assert_eq!(
+                n.is_subdomain_of_or_equal(&parent),
+                *inclusive,
+                "{:?} {:?}",
+                n,
+                parent
+            );
+        }
+    }
+
+    #[test]
+    fn test_validate_within() {
+        // 63 + 1 + 63 + 1 + 63 + 1 + 61 = 253 bytes.
+        let longest = format!(
+            "{}.{}.{}.{}",
+            "a".repeat(63),
+            "b".repeat(63),
+            "c".repeat(63),
+            "d".repeat(61)
+        );
+        let name = Name::try_from(longest.as_bytes()).unwrap();
+        assert_eq!(name.byte_len(), Name::MAX_BYTES);
+        assert_eq!(name.label_count(), 4);
+        assert_eq!(
+            name.validate_within(Name::MAX_BYTES, Name::MAX_LABELS),
+            Ok(())
+        );
+        assert_eq!(
+            name.validate_within(Name::MAX_BYTES - 1, 4),
+            Err(InvalidName)
+        );
+        assert_eq!(name.validate_within(Name::MAX_BYTES, 3), Err(InvalidName));
+
+        // A trailing dot is not counted.
+        let absolute = Name::try_from("web.svc.local.".as_bytes()).unwrap();
+        assert_eq!(absolute.byte_len(), 13);
+        assert_eq!(absolute.label_count(), 3);
+        assert_eq!(absolute.validate_within(13, 3), Ok(()));
+
+        // 127 single-character labels are 253 bytes.
+        let most_labels = vec!["a"; Name::MAX_LABELS].join(".");
+        let name = Name::try_from(most_labels.as_bytes()).unwrap();
+        assert_eq!(name.byte_len(), Name::MAX_BYTES);
+        assert_eq!(name.label_count(), Name::MAX_LABELS);
+        assert_eq!(
+            name.validate_within(Name::MAX_BYTES, Name::MAX_LABELS),
+            Ok(())
+        );
+        assert_eq!(
+            name.validate_within(Name::MAX_BYTES, Name::MAX_LABELS - 1),
+            Err(InvalidName)
+        );
+    }
+
+    #[test]
+    fn test_idn() {
+        let idn = "xn--bcher-kva.example.com";
+        let name = Name::try_from(idn.as_bytes()).unwrap();
+        assert!(name.contains_idn());
+        assert_eq!(
+            Name::try_from_with_policy(idn.as_bytes(), IdnPolicy::default()),
+            Ok(name)
+        );
+        assert_eq!(
+            Name::try_from_with_policy(idn.as_bytes(), IdnPolicy::Reject),
+            Err(InvalidName)
+        );
+
+        for ascii in &["books.example.com", "xn.example.com", "axn--b.example.com"] {
+            let name = Name::try_from(ascii.as_bytes()).unwrap();
+            assert!(!name.contains_idn(), "{} must not contain an IDN", ascii);
+            assert_eq!(
+                Name::try_from_with_policy(ascii.as_bytes(), IdnPolicy::Reject),
+                Ok(name)
+            );
+        }
+    }
 }
@@ -1,4 +1,4 @@
-use crate::Name;
+use crate::{InvalidName, Name};
 use std::convert::TryFrom;
 use std::fmt;
 
@@ -6,6 +6,9 @@ use std::fmt;
 pub enum Suffix {
     Root, // The `.` suffix.
     Name(Name),
+    /// A `*.`-prefixed suffix, e.g. `*.example.com`, which contains names
+    /// with exactly one label before the named suffix.
+    Wildcard(Name),
 }
 
 impl fmt::Display for Suffix {
@@ -13,6 +16,7 @@ impl fmt::Display for Suffix {
         match self {
             Suffix::Root => write!(f, "."),
             Suffix::Name(n) => n.fmt(f),
+            Suffix::Wildcard(n) => write!(f, "*.{}", n),
         }
     }
 }
@@ -29,6 +33,8 @@ impl<'s> TryFrom<&'s str> for Suffix {
     fn try_from(s: &str) -> Result<Self, Self::Error> {
         if s == "." {
             Ok(Suffix::Root)
+        } else if s.starts_with("*.") {
+            Name::try_from(s[2..].as_bytes()).map(Suffix::Wildcard)
         } else {
             Name::try_from(s.as_bytes()).map(|n| n.into())
         }
@@ -36,22 +42,212 @@ impl<'s> TryFrom<&'s str> for Suffix {
 }
 
 impl Suffix {
+    /// Returns true if `name` is equal to or a subdomain of this suffix.
+    ///
+    /// Labels are compared case-insensitively, regardless of how either
+    /// name was constructed.
     pub fn contains(&self, name: &Name) -> bool {
         match self {
             Suffix::Root => true,
-            Suffix::Name(ref sfx) => {
-                let name = name.without_trailing_dot();
-                let sfx = sfx.without_trailing_dot();
-                name.ends_with(sfx) && {
-                    name.len() == sfx.len() || {
-                        // foo.bar.bah (11)
-                        // bar.bah (7)
-                        let idx = name.len() - sfx.len();
-                        let (hd, _) = name.split_at(idx);
-                        hd.ends_with('.')
-                    }
-                }
+            Suffix::Name(ref sfx) => name.is_subdomain_of_or_equal(sfx),
+            Suffix::Wildcard(ref sfx) => {
+                name.label_count() == sfx.label_count() + 1
+                    && Suffix::Name(sfx.clone()).contains(name)
             }
         }
     }
+
+    /// Expands a wildcard suffix into a concrete name by substituting `label`
+    /// for its wildcard, e.g. so that `*.example.com` with `web` is resolved
+    /// as `web.example.com`.
+    ///
+    /// The label must be a single, valid label, and the resulting name must
+    /// be within the limits of `Name::validate_within`. Other suffixes cannot
+    /// be expanded.
+    pub fn expand(&self, label: &str) -> Result<Name, InvalidName> {
+        let sfx = match self {
+            Suffix::Wildcard(ref sfx) => sfx,
+            Suffix::Root | Suffix::Name(_) => return Err(InvalidName),
+        };
+        if label.is_empty() || label.contains('.') {
+            return Err(InvalidName);
+        }
+
+        let name = Name::try_from(format!("{}.{}", label, sfx).as_bytes())?;
+        name.validate_within(Name::MAX_BYTES, Name::MAX_LABELS)?;
+        Ok(name)
+    }
+}
+
+/// A set of DNS suffixes.
+///
+/// A name is contained by the set if it is contained by any of its suffixes.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SuffixSet(Vec<Suffix>);
+
+/// Indicates that an entry in a list of suffixes is not a valid suffix.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct InvalidSuffix(String);
+
+// === impl SuffixSet ===
+
+impl SuffixSet {
+    /// Parses a comma-separated list of suffixes.
+    ///
+    /// Whitespace around each entry is ignored. Empty entries are invalid.
+    pub fn parse_list(list: &str) -> Result<Self, InvalidSuffix> {
+        list.split(',')
+            .map(|entry| {
+                let entry = entry.trim();
+                Suffix::try_from(entry).map_err(|_| InvalidSuffix(entry.to_string()))
+            })
+            .collect()
+    }
+
+    pub fn contains(&self, name: &Name) -> bool {
+        self.0.iter().any(|sfx| sfx.contains(name))
+    }
+
+    /// Returns true if the set contains the root suffix, i.e. if it contains
+    /// all names.
+    pub fn contains_root(&self) -> bool {
+        self.0.iter().any(|sfx| *sfx == Suffix::Root)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Suffix> {
+        self.0.iter()
+    }
+}
+
+impl From<Vec<Suffix>> for SuffixSet {
+    fn from(suffixes: Vec<Suffix>) -> Self {
+        suffixes.into_iter().collect()
+    }
+}
+
+impl std::iter::FromIterator<Suffix> for SuffixSet {
+    fn from_iter<I: IntoIterator<Item = Suffix>>(iter: I) -> Self {
+        let mut suffixes = Vec::new();
+        for sfx in iter {
+            if !suffixes.contains(&sfx) {
+                suffixes.push(sfx);
+            }
+        }
+        SuffixSet(suffixes)
+    }
+}
+
+impl fmt::Display for SuffixSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut suffixes = self.0.iter();
+        if let Some(sfx) = suffixes.next() {
+            sfx.fmt(f)?;
+            for sfx in suffixes {
+                write!(f, ",{}", sfx)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// === impl InvalidSuffix ===
+
+impl InvalidSuffix {
+    /// Returns the entry that could not be parsed.
+    pub fn entry(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for InvalidSuffix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid DNS suffix: {:?}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidSuffix {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contains_ignores_case() {
+        let sfx = Suffix::try_from("example.com").unwrap();
+        for name in &["Example.COM", "web.EXAMPLE.com.", "example.com"] {
+            let name = Name::try_from(name.as_bytes()).unwrap();
+            assert!(sfx.contains(&name), "{} must contain {}", sfx, name);
+        }
+        let sfx = Suffix::try_from("EXAMPLE.com.").unwrap();
+        assert!(sfx.contains(&Name::try_from("web.example.com".as_bytes()).unwrap()));
+
+        for name in &["example.net", "com", "badexample.com"] {
+            let name = Name::try_from(name.as_bytes()).unwrap();
+            assert!(!sfx.contains(&name), "{} must not contain {}", sfx, name);
+        }
+    }
+
+    #[test]
+    fn wildcard() {
+        let sfx = Suffix::try_from("*.example.com").unwrap();
+        assert_eq!(
+            sfx,
+            Suffix::Wildcard(Name::try_from("example.com".as_bytes()).unwrap())
+        );
+        assert_eq!(sfx.to_string(), "*.example.com");
+
+        let web = sfx.expand("web").unwrap();
+        assert_eq!(web, Name::try_from("web.example.com".as_bytes()).unwrap());
+        assert!(sfx.contains(&web));
+        for name in &["example.com", "a.web.example.com", "web.example.net"] {
+            let name = Name::try_from(name.as_bytes()).unwrap();
+            assert!(!sfx.contains(&name), "{} must not contain {}", sfx, name);
+        }
+
+        for label in &["a.web", "web.", "", "-web", "w*b"] {
+            assert_eq!(sfx.expand(label), Err(InvalidName), "{:?}", label);
+        }
+        assert_eq!(
+            sfx.expand(&"a".repeat(64)),
+            Err(InvalidName),
+            "labels must not exceed 63 bytes"
+        );
+
+        let name = Suffix::try_from("example.com").unwrap();
+        assert_eq!(name.expand("web"), Err(InvalidName));
+        assert_eq!(Suffix::Root.expand("web"), Err(InvalidName));
+    }
+
+    #[test]
+    fn parse_list() {
+        let set = SuffixSet::parse_list("svc.cluster.local., example.com").unwrap();
+        let expected = vec![
+            Suffix::try_from("svc.cluster.local.").unwrap(),
+            Suffix::try_from("example.com").unwrap(),
+        ];
+        assert_eq!(set.iter().cloned().collect::<Vec<_>>(), expected);
+        assert!(!set.contains_root());
+    }
+
+    #[test]
+    fn parse_list_with_root() {
+        let set = SuffixSet::parse_list("example.com,.").unwrap();
+        assert!(set.contains_root());
+        assert!(set.contains(&Name::try_from("foo.bar".as_bytes()).unwrap()));
+    }
+
+    #[test]
+    fn parse_list_invalid() {
+        let err = SuffixSet::parse_list("example.com, foo..bar, svc.cluster.local").unwrap_err();
+        assert_eq!(err.entry(), "foo..bar");
+
+        for list in &["", "example.com,", "example.com,,svc.cluster.local", " , "] {
+            let err = SuffixSet::parse_list(list).unwrap_err();
+            assert_eq!(err.entry(), "", "{:?} must be invalid", list);
+        }
+    }
 }
@@ -0,0 +1,134 @@
+use std::time::Duration;
+use trust_dns_resolver::config::{LookupIpStrategy, ResolverOpts};
+
+/// The overall amount of time allotted to a lookup.
+///
+/// A lookup may issue several queries, each of which is bounded by
+/// `ResolverOpts::timeout`. Callers bound the lookup as a whole (e.g. by
+/// canonicalization's timeout), so the per-query timeout should be derived
+/// from that overall budget rather than configured independently.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct LookupBudget {
+    budget: Duration,
+}
+
+// === impl LookupBudget ===
+
+impl LookupBudget {
+    /// Allots `budget` to each lookup.
+    pub fn new(budget: Duration) -> Self {
+        Self { budget }
+    }
+
+    pub fn budget(&self) -> Duration {
+        self.budget
+    }
+
+    /// Returns the timeout for each query of a lookup that is configured by
+    /// `opts`.
+    ///
+    /// Each record type is queried up to `attempts + 1` times, and some
+    /// lookup strategies query one record type after the other. The budget
+    /// is split evenly between all of these queries, with one share held in
+    /// reserve, so that all queries time out before the overall budget is
+    /// exhausted.
+    pub fn query_timeout(&self, opts: &ResolverOpts) -> Duration {
+        let sequential = match opts.ip_strategy {
+            LookupIpStrategy::Ipv4thenIpv6 | LookupIpStrategy::Ipv6thenIpv4 => 2,
+            _ => 1,
+        };
+        let queries = (opts.attempts as u32 + 1) * sequential;
+        self.budget / (queries + 1)
+    }
+
+    /// Configures the resolver's per-query timeout from its configured
+    /// attempts and lookup strategy, e.g. from resolv.conf.
+    ///
+    /// The number of attempts is not changed.
+    pub fn configure(&self, opts: &mut ResolverOpts) {
+        opts.timeout = self.query_timeout(opts);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Error, Name, Resolver};
+    use std::convert::TryFrom;
+    use tokio::time;
+    use trust_dns_resolver::config::{
+        NameServerConfig, NameServerConfigGroup, Protocol, ResolverConfig,
+    };
+
+    #[test]
+    fn query_timeouts_fit_within_budget() {
+        let strategies = [
+            (LookupIpStrategy::Ipv4Only, 1),
+            (LookupIpStrategy::Ipv4AndIpv6, 1),
+            (LookupIpStrategy::Ipv4thenIpv6, 2),
+            (LookupIpStrategy::Ipv6thenIpv4, 2),
+        ];
+        for &budget in &[1, 100, 1_000, 5_000] {
+            let b = LookupBudget::new(Duration::from_millis(budget));
+            for &(ip_strategy, sequential) in &strategies {
+                for attempts in 0..=5 {
+                    let mut opts = ResolverOpts::default();
+                    opts.attempts = attempts;
+                    opts.ip_strategy = ip_strategy;
+                    let queries = (attempts as u32 + 1) * sequential;
+                    assert!(
+                        b.query_timeout(&opts) * queries < b.budget(),
+                        "{:?} must fit {} queries within the budget",
+                        b,
+                        queries
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn configures_resolver_opts() {
+        let b = LookupBudget::new(Duration::from_millis(300));
+        let mut opts = ResolverOpts::default();
+        opts.attempts = 1;
+        opts.ip_strategy = LookupIpStrategy::Ipv4thenIpv6;
+        b.configure(&mut opts);
+        assert_eq!(opts.timeout, Duration::from_millis(60));
+        assert_eq!(opts.attempts, 1, "attempts must not be changed");
+    }
+
+    #[tokio::test]
+    async fn lookup_against_unresponsive_server_fails_within_budget() {
+        // The socket is bound so that queries are not refused, but they are
+        // never answered.
+        let server = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let config = ResolverConfig::from_parts(
+            None,
+            vec![],
+            NameServerConfigGroup::from(vec![NameServerConfig {
+                socket_addr: server.local_addr().unwrap(),
+                protocol: Protocol::Udp,
+                tls_dns_name: None,
+                trust_nx_responses: true,
+                #[cfg(feature = "dns-over-tls")]
+                tls_config: None,
+            }]),
+        );
+        let b = LookupBudget::new(Duration::from_millis(300));
+        let mut opts = ResolverOpts::default();
+        opts.attempts = 2;
+        opts.cache_size = 0;
+        b.configure(&mut opts);
+
+        let (resolver, task) = Resolver::new(config, opts).unwrap();
+        tokio::spawn(task);
+        let name = Name::try_from("web.example.com.".as_bytes()).unwrap();
+        let res = time::timeout(b.budget(), resolver.resolve_one_ip(&name)).await;
+        assert!(
+            matches!(res, Ok(Err(Error::ResolutionFailed(_)))),
+            "the lookup must fail before the budget is exhausted: {:?}",
+            res
+        );
+    }
+}
@@ -0,0 +1,223 @@
+use crate::{IpList, IpListFuture, Name, Resolver};
+use futures::future;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tracing::trace;
+
+/// Caches a `Resolver`'s results until they expire or are explicitly
+/// invalidated.
+///
+/// Unlike trust-dns's cache, entries may be invalidated before their TTL
+/// expires, e.g. when a backend is known to have moved.
+#[derive(Clone, Debug)]
+pub struct CachingResolver {
+    resolver: Resolver,
+    cache: Arc<Mutex<Cache>>,
+}
+
+/// The kind of lookup whose results are cached.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum QueryType {
+    /// A lookup of all of a name's IPv4 and IPv6 addresses.
+    Ip,
+}
+
+#[derive(Debug, Default)]
+struct Cache {
+    entries: HashMap<(Name, QueryType), IpList>,
+    /// Incremented on each invalidation so that lookups that were in flight
+    /// when their name was invalidated are not cached.
+    generation: u64,
+}
+
+// === impl CachingResolver ===
+
+impl CachingResolver {
+    pub fn new(resolver: Resolver) -> Self {
+        Self {
+            resolver,
+            cache: Arc::new(Mutex::new(Cache::default())),
+        }
+    }
+
+    /// Resolves all of the addresses for a name, using a cached result if one
+    /// has not expired.
+    pub fn resolve_all_ips(&self, name: &Name) -> IpListFuture {
+        let key = (name.clone(), QueryType::Ip);
+        let generation = {
+            let mut cache = self.cache.lock().unwrap();
+            let cached = cache.get(&key);
+            if let Some(metrics) = self.resolver.metrics.as_ref() {
+                match cached {
+                    Some(_) => metrics.cache_hit(QueryType::Ip),
+                    None => metrics.cache_miss(QueryType::Ip),
+                }
+            }
+            if let Some(ips) = cached {
+                trace!(%name, "Cached");
+                return Box::pin(future::ok(ips));
+            }
+            cache.generation
+        };
+
+        let lookup = self.resolver.resolve_all_ips(name);
+        let cache = self.cache.clone();
+        Box::pin(async move {
+            let ips = lookup.await?;
+            let mut cache = cache.lock().unwrap();
+            if cache.generation == generation {
+                cache.entries.insert(key, ips.clone());
+            }
+            Ok(ips)
+        })
+    }
+
+    /// Drops all cached results for `name`, so that it is resolved again on
+    /// its next lookup.
+    pub fn invalidate(&self, name: &Name) {
+        let mut cache = self.cache.lock().unwrap();
+        cache.entries.retain(|(n, _), _| n != name);
+        cache.generation += 1;
+    }
+}
+
+// === impl Cache ===
+
+impl Cache {
+    fn get(&mut self, key: &(Name, QueryType)) -> Option<IpList> {
+        let expired = self.entries.get(key)?.valid_until() <= Instant::now();
+        if expired {
+            self.entries.remove(key);
+            return None;
+        }
+        self.entries.get(key).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{lookup_ip, mock};
+    use crate::{Metrics, ResolveRequest};
+    use std::convert::TryFrom;
+    use std::net::IpAddr;
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+    use tokio_test::{assert_pending, assert_ready_ok, task};
+
+    fn name() -> Name {
+        Name::try_from("web.example.com".as_bytes()).unwrap()
+    }
+
+    fn other_name() -> Name {
+        Name::try_from("other.example.com".as_bytes()).unwrap()
+    }
+
+    /// Resolves `name` via the cache, answering the lookup with `ip`.
+    fn resolve(
+        cache: &CachingResolver,
+        rx: &mut mpsc::UnboundedReceiver<ResolveRequest>,
+        ip: IpAddr,
+        ttl: Duration,
+    ) -> IpList {
+        resolve_name(cache, rx, &name(), ip, ttl)
+    }
+
+    fn resolve_name(
+        cache: &CachingResolver,
+        rx: &mut mpsc::UnboundedReceiver<ResolveRequest>,
+        name: &Name,
+        ip: IpAddr,
+        ttl: Duration,
+    ) -> IpList {
+        let mut lookup = task::spawn(cache.resolve_all_ips(name));
+        assert_pending!(lookup.poll());
+        let req = rx.try_recv().expect("lookup must be requested");
+        let res = lookup_ip(&req.name, &[ip], ttl);
+        req.result_tx.send(Ok(res)).ok().unwrap();
+        assert_ready_ok!(lookup.poll())
+    }
+
+    #[test]
+    fn hit() {
+        let (resolver, mut rx) = mock();
+        let cache = CachingResolver::new(resolver);
+
+        let ips = resolve(
+            &cache,
+            &mut rx,
+            [10, 0, 0, 1].into(),
+            Duration::from_secs(60),
+        );
+        let mut lookup = task::spawn(cache.resolve_all_ips(&name()));
+        assert_eq!(assert_ready_ok!(lookup.poll()), ips);
+        assert!(rx.try_recv().is_err(), "cache hit must not be resolved");
+    }
+
+    #[test]
+    fn expiry() {
+        let (resolver, mut rx) = mock();
+        let cache = CachingResolver::new(resolver);
+        let ip0 = IpAddr::from([10, 0, 0, 1]);
+        let ip1 = IpAddr::from([10, 0, 0, 2]);
+
+        // The first result expires immediately, so the name is resolved again.
+        resolve(&cache, &mut rx, ip0, Duration::from_secs(0));
+        let ips = resolve(&cache, &mut rx, ip1, Duration::from_secs(60));
+        assert_eq!(ips.iter().collect::<Vec<_>>(), vec![ip1]);
+    }
+
+    #[test]
+    fn invalidation() {
+        let (resolver, mut rx) = mock();
+        let cache = CachingResolver::new(resolver);
+        let ip0 = IpAddr::from([10, 0, 0, 1]);
+        let ip1 = IpAddr::from([10, 0, 0, 2]);
+
+        resolve(&cache, &mut rx, ip0, Duration::from_secs(60));
+        cache.invalidate(&name());
+        let ips = resolve(&cache, &mut rx, ip1, Duration::from_secs(60));
+        assert_eq!(ips.iter().collect::<Vec<_>>(), vec![ip1]);
+    }
+
+    #[test]
+    fn in_flight_lookup_not_cached_after_invalidation() {
+        let (resolver, mut rx) = mock();
+        let cache = CachingResolver::new(resolver);
+
+        let mut lookup = task::spawn(cache.resolve_all_ips(&name()));
+        assert_pending!(lookup.poll());
+        cache.invalidate(&name());
+
+        let req = rx.try_recv().expect("lookup must be requested");
+        let res = lookup_ip(&req.name, &[[10, 0, 0, 1].into()], Duration::from_secs(60));
+        req.result_tx.send(Ok(res)).ok().unwrap();
+        assert_ready_ok!(lookup.poll());
+
+        let mut lookup = task::spawn(cache.resolve_all_ips(&name()));
+        assert_pending!(lookup.poll());
+        assert!(rx.try_recv().is_ok(), "stale lookup must not be cached");
+    }
+
+    #[test]
+    fn hits_and_misses_are_counted() {
+        let metrics = Metrics::default();
+        let (resolver, mut rx) = mock();
+        let cache = CachingResolver::new(resolver.with_metrics(metrics.clone()));
+        let ip = IpAddr::from([10, 0, 0, 1]);
+        let ttl = Duration::from_secs(60);
+
+        resolve_name(&cache, &mut rx, &name(), ip, ttl);
+        resolve_name(&cache, &mut rx, &other_name(), ip, ttl);
+        assert_eq!(metrics.cache_misses(QueryType::Ip), 2);
+        assert_eq!(metrics.cache_hits(QueryType::Ip), 0);
+
+        for _ in 0..3 {
+            let mut lookup = task::spawn(cache.resolve_all_ips(&name()));
+            assert_ready_ok!(lookup.poll());
+        }
+        assert_eq!(metrics.cache_misses(QueryType::Ip), 2);
+        assert_eq!(metrics.cache_hits(QueryType::Ip), 3);
+    }
+}
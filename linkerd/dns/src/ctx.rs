@@ -0,0 +1,73 @@
+use pin_project::pin_project;
+use std::cell::RefCell;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tracing::Span;
+
+/// A future whose DNS lookups are correlated with the request that triggered
+/// them, e.g. by its trace id.
+#[pin_project]
+#[derive(Debug)]
+pub struct Ctx<F> {
+    trace_id: Arc<str>,
+    #[pin]
+    future: F,
+}
+
+thread_local! {
+    /// The trace id of the `Ctx` that is being polled on this thread.
+    static TRACE_ID: RefCell<Option<Arc<str>>> = RefCell::new(None);
+}
+
+/// Correlates the lookups that are started while `future` is polled with
+/// `trace_id`.
+///
+/// Each such lookup's span records the id as its `trace` field. The id only
+/// applies to `future`'s lookups, so a `Resolver` may be shared by futures
+/// with different ids.
+pub fn context_future<F: Future>(trace_id: impl Into<String>, future: F) -> Ctx<F> {
+    Ctx {
+        trace_id: trace_id.into().into(),
+        future,
+    }
+}
+
+/// Records the current context's trace id, if any, as `span`'s `trace` field.
+pub(crate) fn record_trace_id(span: &Span) {
+    TRACE_ID.with(|id| {
+        if let Some(id) = id.borrow().as_ref() {
+            span.record("trace", &tracing::field::display(id));
+        }
+    });
+}
+
+// === impl Ctx ===
+
+impl<F: Future> Future for Ctx<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        let this = self.project();
+        let _guard = Guard::enter(this.trace_id.clone());
+        this.future.poll(cx)
+    }
+}
+
+/// Restores the enclosing context's trace id when dropped, e.g. if the inner
+/// future panics.
+struct Guard(Option<Arc<str>>);
+
+impl Guard {
+    fn enter(trace_id: Arc<str>) -> Self {
+        Guard(TRACE_ID.with(|id| id.borrow_mut().replace(trace_id)))
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        let prior = self.0.take();
+        TRACE_ID.with(|id| *id.borrow_mut() = prior);
+    }
+}
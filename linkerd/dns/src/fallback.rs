@@ -0,0 +1,105 @@
+use std::net::IpAddr;
+
+/// Determines the order and number of addresses returned by
+/// `Resolver::resolve_one_ip_with_fallbacks`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FallbackPolicy {
+    /// The maximum number of addresses to attempt.
+    pub max: usize,
+    /// Whether addresses should alternate between families, as described by
+    /// [Happy Eyeballs](https://tools.ietf.org/html/rfc8305#section-4).
+    pub interleave_families: bool,
+}
+
+// === impl FallbackPolicy ===
+
+impl Default for FallbackPolicy {
+    fn default() -> Self {
+        Self {
+            max: 4,
+            interleave_families: true,
+        }
+    }
+}
+
+impl FallbackPolicy {
+    /// Orders `ips` for connection attempts, truncating the list to `max`
+    /// addresses.
+    pub fn apply(&self, ips: impl IntoIterator<Item = IpAddr>) -> Vec<IpAddr> {
+        let ips = ips.into_iter();
+        if self.interleave_families {
+            interleave(ips).take(self.max).collect()
+        } else {
+            ips.take(self.max).collect()
+        }
+    }
+}
+
+/// Alternates between address families, starting with the family of the
+/// first address.
+///
+/// Once one family is exhausted, the remaining addresses of the other family
+/// are returned. The order of addresses within each family is preserved.
+fn interleave(ips: impl Iterator<Item = IpAddr>) -> impl Iterator<Item = IpAddr> {
+    let mut ips = ips.peekable();
+    let first_v6 = ips.peek().map(IpAddr::is_ipv6).unwrap_or(false);
+    let (first, second): (Vec<_>, Vec<_>) = ips.partition(|ip| ip.is_ipv6() == first_v6);
+
+    let mut first = first.into_iter();
+    let mut second = second.into_iter();
+    let mut next_first = true;
+    std::iter::from_fn(move || {
+        let (a, b) = if next_first {
+            (&mut first, &mut second)
+        } else {
+            (&mut second, &mut first)
+        };
+        next_first = !next_first;
+        a.next().or_else(|| b.next())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FallbackPolicy;
+    use std::net::IpAddr;
+
+    fn ips() -> Vec<IpAddr> {
+        vec![
+            "fd00::1".parse().unwrap(),
+            "fd00::2".parse().unwrap(),
+            "fd00::3".parse().unwrap(),
+            "10.0.0.1".parse().unwrap(),
+            "10.0.0.2".parse().unwrap(),
+        ]
+    }
+
+    #[test]
+    fn interleaves_families() {
+        let ips = ips();
+        let policy = FallbackPolicy {
+            max: 10,
+            interleave_families: true,
+        };
+        assert_eq!(
+            policy.apply(ips.clone()),
+            vec![ips[0], ips[3], ips[1], ips[4], ips[2]]
+        );
+    }
+
+    #[test]
+    fn truncates_to_max() {
+        let ips = ips();
+        let policy = FallbackPolicy {
+            max: 3,
+            interleave_families: true,
+        };
+        assert_eq!(policy.apply(ips.clone()), vec![ips[0], ips[3], ips[1]]);
+
+        let policy = FallbackPolicy {
+            max: 3,
+            interleave_families: false,
+        };
+        assert_eq!(policy.apply(ips.clone()), vec![ips[0], ips[1], ips[2]]);
+    }
+}
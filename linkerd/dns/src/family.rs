@@ -0,0 +1,134 @@
+use std::net::IpAddr;
+
+/// Constrains which IP address families are used, and in which order.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FamilyPreference {
+    /// Addresses of both families are used in the order they were resolved.
+    Any,
+    /// IPv4 addresses are used before IPv6 addresses.
+    V4First,
+    /// IPv6 addresses are used before IPv4 addresses.
+    V6First,
+    /// Only IPv4 addresses are used.
+    V4Only,
+    /// Only IPv6 addresses are used.
+    V6Only,
+}
+
+// === impl FamilyPreference ===
+
+impl Default for FamilyPreference {
+    fn default() -> Self {
+        FamilyPreference::Any
+    }
+}
+
+impl FamilyPreference {
+    /// Returns true if addresses of `ip`'s family may be used.
+    pub fn permits(&self, ip: &IpAddr) -> bool {
+        match self {
+            FamilyPreference::V4Only => ip.is_ipv4(),
+            FamilyPreference::V6Only => ip.is_ipv6(),
+            _ => true,
+        }
+    }
+
+    /// Drops the addresses that may not be used and orders the remainder by
+    /// family.
+    ///
+    /// The resolved order of addresses within each family is preserved.
+    pub fn apply(&self, ips: impl IntoIterator<Item = IpAddr>) -> Vec<IpAddr> {
+        let mut ips = ips
+            .into_iter()
+            .filter(|ip| self.permits(ip))
+            .collect::<Vec<_>>();
+        match self {
+            // `sort_by_key` is stable, so each family's order is retained.
+            FamilyPreference::V4First => ips.sort_by_key(|ip| ip.is_ipv6()),
+            FamilyPreference::V6First => ips.sort_by_key(|ip| ip.is_ipv4()),
+            _ => {}
+        }
+        ips
+    }
+
+    /// Returns the usable addresses of the most preferred family that has
+    /// any, so that a preference degrades gracefully for names that only
+    /// have addresses of the other family.
+    ///
+    /// The result is empty only if there are no usable addresses.
+    pub fn preferred(&self, ips: impl IntoIterator<Item = IpAddr>) -> Vec<IpAddr> {
+        let mut ips = self.apply(ips);
+        if let FamilyPreference::V4First | FamilyPreference::V6First = self {
+            if let Some(first) = ips.first().map(IpAddr::is_ipv4) {
+                ips.retain(|ip| ip.is_ipv4() == first);
+            }
+        }
+        ips
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FamilyPreference;
+    use std::net::IpAddr;
+
+    fn ips() -> Vec<IpAddr> {
+        vec![
+            "10.0.0.1".parse().unwrap(),
+            "fd00::1".parse().unwrap(),
+            "10.0.0.2".parse().unwrap(),
+            "fd00::2".parse().unwrap(),
+        ]
+    }
+
+    #[test]
+    fn any_preserves_order() {
+        assert_eq!(FamilyPreference::Any.apply(ips()), ips());
+    }
+
+    #[test]
+    fn first_orders_by_family() {
+        let ips = ips();
+        assert_eq!(
+            FamilyPreference::V4First.apply(ips.clone()),
+            vec![ips[0], ips[2], ips[1], ips[3]]
+        );
+        assert_eq!(
+            FamilyPreference::V6First.apply(ips.clone()),
+            vec![ips[1], ips[3], ips[0], ips[2]]
+        );
+    }
+
+    #[test]
+    fn preferred_falls_back_to_other_family() {
+        let ips = ips();
+        assert_eq!(
+            FamilyPreference::V6First.preferred(ips.clone()),
+            vec![ips[1], ips[3]]
+        );
+        assert_eq!(
+            FamilyPreference::V6First.preferred(vec![ips[0], ips[2]]),
+            vec![ips[0], ips[2]]
+        );
+        assert_eq!(
+            FamilyPreference::V4First.preferred(vec![ips[1]]),
+            vec![ips[1]]
+        );
+        assert_eq!(FamilyPreference::Any.preferred(ips.clone()), ips);
+        assert!(FamilyPreference::V4Only.preferred(vec![ips[1]]).is_empty());
+        assert!(FamilyPreference::V6First.preferred(vec![]).is_empty());
+    }
+
+    #[test]
+    fn only_filters_by_family() {
+        let ips = ips();
+        assert_eq!(
+            FamilyPreference::V4Only.apply(ips.clone()),
+            vec![ips[0], ips[2]]
+        );
+        assert_eq!(
+            FamilyPreference::V6Only.apply(ips.clone()),
+            vec![ips[1], ips[3]]
+        );
+    }
+}
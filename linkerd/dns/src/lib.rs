@@ -1,46 +1,169 @@
 #![deny(warnings, rust_2018_idioms)]
 
+mod budget;
+mod cache;
+mod ctx;
+mod fallback;
+mod family;
+mod metrics;
+mod probe;
 mod refine;
+mod srv;
+#[cfg(feature = "dns-over-tls")]
+mod upstream;
 
-pub use self::refine::{MakeRefine, Refine};
-pub use linkerd2_dns_name::{InvalidName, Name, Suffix};
+pub use self::budget::LookupBudget;
+pub use self::cache::{CachingResolver, QueryType};
+pub use self::ctx::{context_future, Ctx};
+pub use self::fallback::FallbackPolicy;
+pub use self::family::FamilyPreference;
+pub use self::metrics::Metrics;
+pub use self::probe::{ProbePolicy, ProbedIpList};
+pub use self::refine::{CancelRefine, MakeRefine, Refine};
+pub use self::srv::{SrvList, SrvTarget};
+#[cfg(feature = "dns-over-tls")]
+pub use self::upstream::TlsUpstream;
+use arc_swap::ArcSwap;
+use futures::future::{FutureExt, Shared, WeakShared};
+use linkerd2_addr::NameAddr;
+pub use linkerd2_dns_name::{IdnPolicy, InvalidName, InvalidSuffix, Name, Suffix, SuffixSet};
+use rand::seq::IteratorRandom;
+use std::collections::HashMap;
 use std::future::Future;
 use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use std::{fmt, net};
-use tokio::sync::{mpsc, oneshot};
-use tracing::{info_span, trace, Span};
+use tokio::runtime;
+use tokio::sync::{mpsc, oneshot, Semaphore};
+use tokio::time;
+use tracing::{debug, info_span, trace, Span};
 use tracing_futures::Instrument;
 pub use trust_dns_resolver::config::ResolverOpts;
 pub use trust_dns_resolver::error::{ResolveError, ResolveErrorKind};
 use trust_dns_resolver::lookup_ip::LookupIp;
-use trust_dns_resolver::{config::ResolverConfig, system_conf, AsyncResolver};
+pub use trust_dns_resolver::proto::op::ResponseCode;
+use trust_dns_resolver::proto::rr::RData;
+pub use trust_dns_resolver::proto::rr::RecordType;
+use trust_dns_resolver::proto::xfer::DnsRequestOptions;
+use trust_dns_resolver::{config::ResolverConfig, system_conf, TokioAsyncResolver};
 
 #[derive(Clone)]
 pub struct Resolver {
     tx: mpsc::UnboundedSender<ResolveRequest>,
+    /// The resolver used by the background task, which is replaced when the
+    /// configuration is reloaded.
+    resolver: Arc<ArcSwap<TokioAsyncResolver>>,
+    /// The nameservers of the current configuration, since the resolver does
+    /// not expose its configuration once it is built.
+    nameservers: Arc<ArcSwap<Vec<net::SocketAddr>>>,
+    configure: Option<Arc<dyn ConfigureResolver + Send + Sync>>,
+    selection: IpSelection,
+    /// The address family preferred by `resolve_one_ip`.
+    family: FamilyPreference,
+    metrics: Option<Metrics>,
+    /// The maximum number of addresses returned for a name, if it is limited.
+    max_addresses: Option<usize>,
+    /// Refinements that are in flight, so that concurrent refinements of a
+    /// name share a single lookup.
+    refines: Arc<Mutex<HashMap<(Name, Option<RecordType>), WeakShared<RefinementFuture>>>>,
+    concurrency: Option<ConcurrencyLimit>,
+}
+
+/// Bounds the number of lookups in flight across all clones of a `Resolver`.
+#[derive(Clone, Debug)]
+struct ConcurrencyLimit {
+    permits: Arc<Semaphore>,
+    /// How long a lookup may wait for a permit before it fails.
+    timeout: Duration,
+}
+
+/// Determines which address `Resolver::resolve_one_ip` returns when a name
+/// resolves to several addresses.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum IpSelection {
+    /// The first address is returned, so that results are reproducible.
+    First,
+    /// An address is chosen uniformly at random, so that connections are
+    /// spread across all of a name's addresses.
+    Random,
 }
 
 pub trait ConfigureResolver {
     fn configure_resolver(&self, _: &mut ResolverOpts);
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum Error {
     NoAddressesFound,
     ResolutionFailed(ResolveError),
     TaskLost,
+    /// A lookup could not begin within the given time because the resolver's
+    /// concurrency limit was reached.
+    Saturated(Duration),
+    /// The operation was canceled by its caller, e.g. via a `CancelRefine`.
+    Canceled,
 }
 
 pub type Task = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
 
 pub type IpAddrFuture = Pin<Box<dyn Future<Output = Result<net::IpAddr, Error>> + Send + 'static>>;
 
+pub type IpAddrsFuture =
+    Pin<Box<dyn Future<Output = Result<Vec<net::IpAddr>, Error>> + Send + 'static>>;
+
+pub type IpListFuture = Pin<Box<dyn Future<Output = Result<IpList, Error>> + Send + 'static>>;
+
+pub type NamedIpListFuture =
+    Pin<Box<dyn Future<Output = Result<NamedIpList, Error>> + Send + 'static>>;
+
+pub type ProbedIpListFuture =
+    Pin<Box<dyn Future<Output = Result<ProbedIpList, Error>> + Send + 'static>>;
+
+pub type SrvListFuture = Pin<Box<dyn Future<Output = Result<SrvList, Error>> + Send + 'static>>;
+
+pub type NameFuture = Pin<Box<dyn Future<Output = Result<Name, Error>> + Send + 'static>>;
+
+type RefinementFuture =
+    Pin<Box<dyn Future<Output = Result<refine::Refinement, Error>> + Send + 'static>>;
+
+pub type ValidNameFuture =
+    Pin<Box<dyn Future<Output = Result<(Name, Instant), Error>> + Send + 'static>>;
+
+pub type NameAddrFuture = Pin<Box<dyn Future<Output = Result<NameAddr, Error>> + Send + 'static>>;
+
+/// All of the addresses resolved for a name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IpList {
+    ips: Vec<net::IpAddr>,
+    valid_until: Instant,
+    /// The time until which each address may be used, if the addresses'
+    /// records have differing TTLs.
+    expiries: Option<Vec<Instant>>,
+}
+
+/// All of the addresses resolved for a name, with the name's canonical form.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NamedIpList {
+    name: Name,
+    ips: IpList,
+}
+
 struct ResolveRequest {
     name: Name,
+    /// The type of records to look up, or `None` to look up IP addresses.
+    record_type: Option<RecordType>,
     result_tx: oneshot::Sender<Result<LookupIp, ResolveError>>,
     span: tracing::Span,
 }
 
+/// Performs lookups on behalf of the background task.
+trait Lookup: Send + Sync + 'static {
+    type Future: Future<Output = Result<LookupIp, ResolveError>> + Send + 'static;
+
+    fn lookup(&self, name: Name, record_type: Option<RecordType>) -> Self::Future;
+}
+
 impl Resolver {
     /// Construct a new `Resolver` from environment variables and system
     /// configuration.
@@ -51,73 +174,429 @@ impl Resolver {
     /// could not be parsed.
     ///
     /// TODO: This should be infallible like it is in the `domain` crate.
-    pub fn from_system_config_with<C: ConfigureResolver>(
-        c: &C,
-    ) -> Result<(Self, Task), ResolveError> {
+    pub fn from_system_config_with<C>(c: &C) -> Result<(Self, Task), ResolveError>
+    where
+        C: ConfigureResolver + Clone + Send + Sync + 'static,
+    {
         let (config, mut opts) = system_conf::read_system_conf()?;
         c.configure_resolver(&mut opts);
         trace!("DNS config: {:?}", &config);
         trace!("DNS opts: {:?}", &opts);
-        Self::new(config, opts)
+        let (resolver, task) = Self::new(config, opts)?;
+        let resolver = Self {
+            configure: Some(Arc::new(c.clone())),
+            ..resolver
+        };
+        Ok((resolver, task))
     }
 
-    pub fn new(
-        config: ResolverConfig,
-        mut opts: ResolverOpts,
+    /// Constructs a new `Resolver` that sends all queries to `upstream` via
+    /// DNS-over-TLS, rather than to the system's nameservers.
+    ///
+    /// Such a resolver should not be reloaded from the system configuration,
+    /// which would replace the upstream with the system's nameservers.
+    #[cfg(feature = "dns-over-tls")]
+    pub fn from_tls_upstream(
+        upstream: &TlsUpstream,
+        opts: ResolverOpts,
     ) -> Result<(Self, Task), ResolveError> {
-        // Disable Trust-DNS's caching.
-        opts.cache_size = 0;
+        let config = upstream.resolver_config();
+        trace!("DNS config: {:?}", &config);
+        Self::new(config, opts)
+    }
+
+    pub fn new(config: ResolverConfig, opts: ResolverOpts) -> Result<(Self, Task), ResolveError> {
+        let nameservers = Arc::new(ArcSwap::from_pointee(nameservers(&config)));
+        let resolver = Arc::new(ArcSwap::from_pointee(build_resolver(config, opts)?));
 
         // XXX(eliza): figure out an appropriate bound for the channel...
-        let (tx, mut rx) = mpsc::unbounded_channel();
-        let task = Box::pin(async move {
-            let resolver = match AsyncResolver::tokio(config, opts) {
-                Ok(resolver) => resolver,
-                Err(e) => unreachable!("constructing resolver should not fail: {}", e),
+        let (tx, rx) = mpsc::unbounded_channel();
+        let task = Box::pin(run(resolver.clone(), rx));
+        let resolver = Resolver {
+            tx,
+            resolver,
+            nameservers,
+            configure: None,
+            selection: IpSelection::default(),
+            family: FamilyPreference::default(),
+            metrics: None,
+            max_addresses: None,
+            refines: Arc::new(Mutex::new(HashMap::new())),
+            concurrency: None,
+        };
+        Ok((resolver, task))
+    }
+
+    /// Constructs a new `Resolver` whose background task, and thus all of its
+    /// lookups, runs on `handle`'s runtime rather than on the runtime that
+    /// spawns the task returned by `Resolver::new`.
+    pub fn spawn_on(
+        config: ResolverConfig,
+        opts: ResolverOpts,
+        handle: &runtime::Handle,
+    ) -> Result<Self, ResolveError> {
+        let (resolver, task) = handle.enter(|| Self::new(config, opts))?;
+        handle.spawn(task);
+        Ok(resolver)
+    }
+
+    /// Rebuilds the resolver from the current system configuration.
+    ///
+    /// Lookups that are already in flight complete against the previous
+    /// configuration; all subsequent lookups use the new configuration. If
+    /// the configuration cannot be read, the current configuration remains
+    /// in use.
+    pub fn reload_from_system_config(&self) -> Result<(), ResolveError> {
+        let (config, mut opts) = system_conf::read_system_conf()?;
+        if let Some(c) = self.configure.as_ref() {
+            c.configure_resolver(&mut opts);
+        }
+        trace!("Reloaded DNS config: {:?}", &config);
+        trace!("Reloaded DNS opts: {:?}", &opts);
+        let nameservers = nameservers(&config);
+        let resolver = build_resolver(config, opts)?;
+        self.resolver.store(Arc::new(resolver));
+        self.nameservers.store(Arc::new(nameservers));
+        Ok(())
+    }
+
+    /// Returns the addresses of the nameservers in the current configuration.
+    ///
+    /// Each address is listed once, even if it is configured for more than
+    /// one protocol.
+    pub fn nameservers(&self) -> Vec<net::SocketAddr> {
+        self.nameservers.load().as_ref().clone()
+    }
+
+    /// Configures how `resolve_one_ip` chooses among a name's addresses.
+    pub fn with_ip_selection(self, selection: IpSelection) -> Self {
+        Self { selection, ..self }
+    }
+
+    /// Configures which address family `resolve_one_ip` prefers.
+    ///
+    /// An address of the preferred family is returned if the name has one;
+    /// otherwise, an address of the other family is returned, unless the
+    /// preference only permits a single family. By default, any address may
+    /// be returned.
+    pub fn with_family_preference(self, family: FamilyPreference) -> Self {
+        Self { family, ..self }
+    }
+
+    /// Records metrics about this resolver's lookups in `metrics`.
+    pub fn with_metrics(self, metrics: Metrics) -> Self {
+        Self {
+            metrics: Some(metrics),
+            ..self
+        }
+    }
+
+    /// Limits `resolve_all_ips` and `resolve_all_ips_with_name` to the first
+    /// `max` of a name's addresses.
+    ///
+    /// This bounds the number of endpoints derived from a single name. By
+    /// default, all addresses are returned.
+    pub fn with_max_addresses(self, max: usize) -> Self {
+        Self {
+            max_addresses: Some(max),
+            ..self
+        }
+    }
+
+    /// Limits the number of lookups in flight at once to `max`, across all of
+    /// this resolver's clones.
+    ///
+    /// Additional lookups wait for an in-flight lookup to complete. A lookup
+    /// that cannot begin within `timeout` fails with `Error::Saturated`. By
+    /// default, lookups are not limited.
+    pub fn with_concurrency_limit(self, max: usize, timeout: Duration) -> Self {
+        Self {
+            concurrency: Some(ConcurrencyLimit {
+                permits: Arc::new(Semaphore::new(max.max(1))),
+                timeout,
+            }),
+            ..self
+        }
+    }
+
+    fn lookup_ip(
+        &self,
+        name: Name,
+        span: Span,
+    ) -> impl Future<Output = Result<LookupIp, Error>> + Send + 'static {
+        self.lookup(name, None, span)
+    }
+
+    fn lookup(
+        &self,
+        name: Name,
+        record_type: Option<RecordType>,
+        span: Span,
+    ) -> impl Future<Output = Result<LookupIp, Error>> + Send + 'static {
+        // The lookup is considered in-flight as soon as its future is created,
+        // and until the future is either completed or dropped.
+        let in_flight = self.metrics.as_ref().map(Metrics::in_flight);
+        let tx = self.tx.clone();
+        let concurrency = self.concurrency.clone();
+        async move {
+            let _in_flight = in_flight;
+            // The permit is held until the lookup completes or is dropped.
+            let _permit = match concurrency.as_ref() {
+                Some(ConcurrencyLimit { permits, timeout }) => {
+                    let permit = time::timeout(*timeout, permits.acquire())
+                        .await
+                        .map_err(|_| Error::Saturated(*timeout))?;
+                    Some(permit)
+                }
+                None => None,
             };
-            while let Some(ResolveRequest {
+            let (result_tx, rx) = oneshot::channel();
+            tx.send(ResolveRequest {
                 name,
+                record_type,
                 result_tx,
                 span,
-            }) = rx.recv().await
-            {
-                let resolver = resolver.clone();
-                tokio::spawn(
-                    async move {
-                        let res = resolver.lookup_ip(name.as_ref()).await;
-                        if result_tx.send(res).is_err() {
-                            tracing::debug!("resolution canceled");
-                        }
-                    }
-                    .instrument(span),
-                );
+            })?;
+            let ips = rx.await??;
+            Ok(ips)
+        }
+    }
+
+    pub fn resolve_one_ip(
+        &self,
+        name: &Name,
+    ) -> Pin<Box<dyn Future<Output = Result<net::IpAddr, Error>> + Send + 'static>> {
+        let span = info_span!("resolve_one_ip", %name, trace = tracing::field::Empty);
+        ctx::record_trace_id(&span);
+        let lookup = self.lookup_ip(name.clone(), span);
+        let selection = self.selection;
+        let family = self.family;
+        Box::pin(async move {
+            let ips = family.preferred(lookup.await?.iter());
+            selection
+                .select(ips.into_iter())
+                .ok_or_else(|| Error::NoAddressesFound)
+        })
+    }
+
+    /// Resolves a name to an ordered list of addresses to attempt to connect
+    /// to, as determined by `policy`.
+    ///
+    /// Fails with `Error::NoAddressesFound` if the lookup succeeds without
+    /// any addresses.
+    pub fn resolve_one_ip_with_fallbacks(
+        &self,
+        name: &Name,
+        policy: FallbackPolicy,
+    ) -> IpAddrsFuture {
+        let span =
+            info_span!("resolve_one_ip_with_fallbacks", %name, trace = tracing::field::Empty);
+        ctx::record_trace_id(&span);
+        let lookup = self.lookup_ip(name.clone(), span);
+        Box::pin(async move {
+            let ips = policy.apply(lookup.await?.iter());
+            if ips.is_empty() {
+                return Err(Error::NoAddressesFound);
             }
-            tracing::debug!("all resolver handles dropped; terminating.");
-        });
-        Ok((Resolver { tx }, task))
+            Ok(ips)
+        })
     }
 
-    async fn lookup_ip(&self, name: Name, span: Span) -> Result<LookupIp, Error> {
-        let (result_tx, rx) = oneshot::channel();
-        self.tx.send(ResolveRequest {
-            name,
-            result_tx,
-            span,
-        })?;
-        let ips = rx.await??;
-        Ok(ips)
+    /// Resolves all of the addresses for a name.
+    ///
+    /// Fails with `Error::NoAddressesFound` if the lookup succeeds without
+    /// any addresses.
+    pub fn resolve_all_ips(&self, name: &Name) -> IpListFuture {
+        let span = info_span!("resolve_all_ips", %name, trace = tracing::field::Empty);
+        ctx::record_trace_id(&span);
+        let lookup = self.lookup_ip(name.clone(), span);
+        let max = self.max_addresses;
+        Box::pin(async move {
+            let lookup = lookup.await?;
+            let ips = IpList::from_lookup(&lookup).truncate(max);
+            if ips.is_empty() {
+                return Err(Error::NoAddressesFound);
+            }
+            Ok(ips)
+        })
     }
 
-    pub fn resolve_one_ip(
+    /// Resolves all of the addresses for a name and probes whether each
+    /// accepts TCP connections on `port`, as determined by `policy`.
+    ///
+    /// This is intended for warming up, so that reachable addresses may be
+    /// preferred. Fails with `Error::NoAddressesFound` if the lookup succeeds
+    /// without any addresses.
+    pub fn resolve_reachable_ips(
         &self,
         name: &Name,
-    ) -> Pin<Box<dyn Future<Output = Result<net::IpAddr, Error>> + Send + 'static>> {
-        let name = name.clone();
+        port: u16,
+        policy: ProbePolicy,
+    ) -> ProbedIpListFuture {
+        let ips = self.resolve_all_ips(name);
+        Box::pin(async move {
+            let ips = ips.await?;
+            Ok(policy.probe(ips, port).await)
+        })
+    }
+
+    /// Resolves all of the addresses for a name, along with the name's
+    /// canonical form, in a single lookup.
+    ///
+    /// The canonical name is the target of the name's chain of CNAME
+    /// records, if it is an alias, or the queried name otherwise.
+    ///
+    /// Fails with `Error::NoAddressesFound` if the lookup succeeds without
+    /// any addresses.
+    pub fn resolve_all_ips_with_name(&self, name: &Name) -> NamedIpListFuture {
+        let span = info_span!("resolve_all_ips_with_name", %name, trace = tracing::field::Empty);
+        ctx::record_trace_id(&span);
+        let lookup = self.lookup_ip(name.clone(), span);
+        let max = self.max_addresses;
+        Box::pin(async move {
+            let lookup = lookup.await?;
+            let ips = IpList::from_lookup(&lookup).truncate(max);
+            if ips.is_empty() {
+                return Err(Error::NoAddressesFound);
+            }
+            Ok(NamedIpList {
+                name: refine::canonical_name(&lookup),
+                ips,
+            })
+        })
+    }
+
+    /// Resolves the targets of a name's SRV records.
+    ///
+    /// The targets' addresses are not resolved. Fails with
+    /// `Error::NoAddressesFound` if the lookup succeeds without any usable
+    /// targets.
+    pub fn resolve_srv(&self, name: &Name) -> SrvListFuture {
+        let span = info_span!("resolve_srv", %name, trace = tracing::field::Empty);
+        ctx::record_trace_id(&span);
+        let lookup = self.lookup(name.clone(), Some(RecordType::SRV), span);
+        Box::pin(async move {
+            let srv = SrvList::from_lookup(&lookup.await?);
+            if srv.is_empty() {
+                return Err(Error::NoAddressesFound);
+            }
+            Ok(srv)
+        })
+    }
+
+    /// Resolves a name to its canonical, fully-qualified form.
+    ///
+    /// The name is refined via an IP lookup. If the name has no addresses and
+    /// a `fallback` record type is provided, the name is instead refined via a
+    /// lookup of that record type, so that names that only resolve indirectly
+    /// (e.g. via CNAME records) may still be refined.
+    ///
+    /// Concurrent refinements of the same name share a single lookup, and
+    /// all observe its result. The lookup is canceled if all of them are
+    /// dropped.
+    pub fn refine_name(&self, name: &Name, fallback: Option<RecordType>) -> NameFuture {
+        Box::pin(
+            self.refine(name, fallback)
+                .map(|res| res.map(|refinement| refinement.name)),
+        )
+    }
+
+    /// Refines a name as by `refine_name`, with a handle that cancels the
+    /// refinement.
+    ///
+    /// Unlike dropping the returned future, canceling releases the lookup
+    /// immediately, even while the future is held (e.g. in a batch), and
+    /// the future then fails with `Error::Canceled`. A lookup that is shared
+    /// with other refinements continues on their behalf.
+    pub fn refine_name_cancelable(
+        &self,
+        name: &Name,
+        fallback: Option<RecordType>,
+    ) -> (NameFuture, CancelRefine) {
+        refine::cancelable(self.refine_name(name, fallback))
+    }
+
+    /// Refines the name of a `NameAddr`, retaining its port.
+    ///
+    /// The name is refined as by `refine_name`, without a fallback record
+    /// type.
+    pub fn refine_name_addr(&self, na: &NameAddr) -> NameAddrFuture {
+        let port = na.port();
+        Box::pin(
+            self.refine_name(na.name(), None)
+                .map(move |res| res.map(|name| NameAddr::new(name, port))),
+        )
+    }
+
+    /// Refines a name as by `refine_name`, without a fallback record type,
+    /// along with the time until which the refinement is valid, as
+    /// determined by the lookup's TTL.
+    ///
+    /// The lookup is shared with concurrent refinements of the name, including
+    /// those of `Refine` services.
+    pub fn refine_name_with_ttl(&self, name: &Name) -> ValidNameFuture {
+        Box::pin(
+            self.refine(name, None)
+                .map(|res| res.map(|refinement| (refinement.name, refinement.valid_until))),
+        )
+    }
+
+    /// Refines a name, sharing the lookup with concurrent refinements of the
+    /// same name and fallback record type.
+    ///
+    /// The lookup is canceled if all of its refinements are dropped.
+    fn refine(&self, name: &Name, fallback: Option<RecordType>) -> Shared<RefinementFuture> {
+        let key = (name.clone(), fallback);
+        let mut refines = self.refines.lock().unwrap();
+        if let Some(refine) = refines.get(&key).and_then(WeakShared::upgrade) {
+            trace!(%name, "Refinement in flight");
+            return refine;
+        }
+
+        let refine = self.refine_name_uncoalesced(name, fallback);
+        let in_flight = self.refines.clone();
+        let k = key.clone();
+        let refine: Shared<RefinementFuture> = async move {
+            let res = refine.await;
+            in_flight.lock().unwrap().remove(&k);
+            res
+        }
+        .boxed()
+        .shared();
+        // Drop the refinements that were canceled.
+        refines.retain(|_, weak| weak.upgrade().is_some());
+        if let Some(weak) = refine.downgrade() {
+            refines.insert(key, weak);
+        }
+        refine
+    }
+
+    fn refine_name_uncoalesced(
+        &self,
+        name: &Name,
+        fallback: Option<RecordType>,
+    ) -> RefinementFuture {
+        let span = info_span!("refine_name", %name, trace = tracing::field::Empty);
+        ctx::record_trace_id(&span);
+        let lookup = self.lookup_ip(name.clone(), span.clone());
         let resolver = self.clone();
+        let name = name.clone();
         Box::pin(async move {
-            let span = info_span!("resolve_one_ip", %name);
-            let ips = resolver.lookup_ip(name, span).await?;
-            ips.iter().next().ok_or_else(|| Error::NoAddressesFound)
+            let error = match lookup.await {
+                Ok(lookup) => match refine::Refinement::from_lookup_ip(&lookup) {
+                    Ok(refinement) => return Ok(refinement),
+                    Err(error) => error,
+                },
+                Err(error) => error,
+            };
+            match fallback {
+                Some(record_type) if error.is_no_records() => {
+                    debug!(?record_type, "No addresses found; falling back");
+                    let lookup = resolver.lookup(name, Some(record_type), span).await?;
+                    Ok(refine::Refinement::from_lookup(&lookup))
+                }
+                _ => Err(error),
+            }
         })
     }
 
@@ -127,6 +606,199 @@ impl Resolver {
     }
 }
 
+// === impl IpList ===
+
+impl IpList {
+    pub fn new(ips: Vec<net::IpAddr>, valid_until: Instant) -> Self {
+        Self {
+            ips,
+            valid_until,
+            expiries: None,
+        }
+    }
+
+    /// Builds a list from a lookup, noting each address's own expiry if the
+    /// records' TTLs differ.
+    ///
+    /// The lookup is valid until its shortest-lived record expires, so each
+    /// address's expiry is offset from that by the difference in TTLs.
+    fn from_lookup(lookup: &LookupIp) -> Self {
+        let valid_until = lookup.valid_until();
+        let ips = lookup.iter().collect::<Vec<_>>();
+
+        let records = lookup.as_lookup().record_iter().collect::<Vec<_>>();
+        let min_ttl = match records.iter().map(|r| r.ttl()).min() {
+            Some(ttl) => ttl,
+            None => return Self::new(ips, valid_until),
+        };
+        let ttl = |ip: &net::IpAddr| {
+            records.iter().find_map(|r| match (r.rdata(), ip) {
+                (RData::A(a), net::IpAddr::V4(ip)) if a == ip => Some(r.ttl()),
+                (RData::AAAA(a), net::IpAddr::V6(ip)) if a == ip => Some(r.ttl()),
+                _ => None,
+            })
+        };
+        let expiries = ips
+            .iter()
+            .map(|ip| {
+                let extra = ttl(ip)?.saturating_sub(min_ttl);
+                Some(valid_until + Duration::from_secs(extra.into()))
+            })
+            .collect::<Option<Vec<_>>>()
+            .filter(|expiries| expiries.iter().any(|e| *e != valid_until));
+
+        Self {
+            ips,
+            valid_until,
+            expiries,
+        }
+    }
+
+    /// Retains only the first `max` addresses, if a maximum is set.
+    pub fn truncate(mut self, max: Option<usize>) -> Self {
+        if let Some(max) = max {
+            if self.ips.len() > max {
+                debug!(addrs = self.ips.len(), %max, "Truncating addresses");
+                self.ips.truncate(max);
+                if let Some(expiries) = self.expiries.as_mut() {
+                    expiries.truncate(max);
+                }
+            }
+        }
+        self
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = net::IpAddr> + '_ {
+        self.ips.iter().cloned()
+    }
+
+    pub fn len(&self) -> usize {
+        self.ips.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ips.is_empty()
+    }
+
+    /// Returns the time until which the addresses may be used, as determined
+    /// by the records' TTLs.
+    pub fn valid_until(&self) -> Instant {
+        self.valid_until
+    }
+
+    /// Returns each address with the time until which it may be used, as
+    /// determined by its own record's TTL.
+    ///
+    /// When per-address TTLs are not known, every address is valid until
+    /// `valid_until`.
+    pub fn iter_with_ttl(&self) -> impl Iterator<Item = (net::IpAddr, Instant)> + '_ {
+        let valid_until = self.valid_until;
+        self.ips.iter().enumerate().map(move |(i, ip)| {
+            let expiry = self.expiries.as_ref().map(|e| e[i]).unwrap_or(valid_until);
+            (*ip, expiry)
+        })
+    }
+}
+
+// === impl NamedIpList ===
+
+impl NamedIpList {
+    /// Returns the canonical name.
+    pub fn name(&self) -> &Name {
+        &self.name
+    }
+
+    pub fn ips(&self) -> &IpList {
+        &self.ips
+    }
+
+    pub fn into_parts(self) -> (Name, IpList) {
+        (self.name, self.ips)
+    }
+}
+
+// === impl IpSelection ===
+
+impl Default for IpSelection {
+    fn default() -> Self {
+        IpSelection::First
+    }
+}
+
+impl IpSelection {
+    fn select(&self, mut ips: impl Iterator<Item = net::IpAddr>) -> Option<net::IpAddr> {
+        match self {
+            IpSelection::First => ips.next(),
+            IpSelection::Random => ips.choose(&mut rand::thread_rng()),
+        }
+    }
+}
+
+fn nameservers(config: &ResolverConfig) -> Vec<net::SocketAddr> {
+    let mut addrs = Vec::new();
+    for ns in config.name_servers() {
+        if !addrs.contains(&ns.socket_addr) {
+            addrs.push(ns.socket_addr);
+        }
+    }
+    addrs
+}
+
+fn build_resolver(
+    config: ResolverConfig,
+    mut opts: ResolverOpts,
+) -> Result<TokioAsyncResolver, ResolveError> {
+    // Disable Trust-DNS's caching.
+    opts.cache_size = 0;
+    TokioAsyncResolver::tokio(config, opts)
+}
+
+/// Serves lookup requests until all resolver handles are dropped.
+///
+/// Each lookup is issued against the resolver that is current when the
+/// request is received, so a reload does not disrupt lookups in flight.
+async fn run<L: Lookup>(
+    resolver: Arc<ArcSwap<L>>,
+    mut rx: mpsc::UnboundedReceiver<ResolveRequest>,
+) {
+    while let Some(ResolveRequest {
+        name,
+        record_type,
+        result_tx,
+        span,
+    }) = rx.recv().await
+    {
+        let lookup = resolver.load().lookup(name, record_type);
+        tokio::spawn(
+            async move {
+                let res = lookup.await;
+                if result_tx.send(res).is_err() {
+                    tracing::debug!("resolution canceled");
+                }
+            }
+            .instrument(span),
+        );
+    }
+    tracing::debug!("all resolver handles dropped; terminating.");
+}
+
+impl Lookup for TokioAsyncResolver {
+    type Future = Pin<Box<dyn Future<Output = Result<LookupIp, ResolveError>> + Send + 'static>>;
+
+    fn lookup(&self, name: Name, record_type: Option<RecordType>) -> Self::Future {
+        let resolver = self.clone();
+        match record_type {
+            None => Box::pin(async move { resolver.lookup_ip(name.as_ref()).await }),
+            Some(record_type) => Box::pin(async move {
+                let lookup = resolver
+                    .lookup(name.as_ref(), record_type, DnsRequestOptions::default())
+                    .await?;
+                Ok(lookup.into())
+            }),
+        }
+    }
+}
+
 /// Note: `AsyncResolver` does not implement `Debug`, so we must manually
 ///       implement this.
 impl fmt::Debug for Resolver {
@@ -137,6 +809,22 @@ impl fmt::Debug for Resolver {
     }
 }
 
+// === impl Error ===
+
+impl Error {
+    /// Returns true if the lookup succeeded but found no matching records.
+    pub fn is_no_records(&self) -> bool {
+        match self {
+            Self::NoAddressesFound => true,
+            Self::ResolutionFailed(e) => match e.kind() {
+                ResolveErrorKind::NoRecordsFound { .. } => true,
+                _ => false,
+            },
+            Self::TaskLost | Self::Saturated(_) | Self::Canceled => false,
+        }
+    }
+}
+
 impl<T> From<mpsc::error::SendError<T>> for Error {
     fn from(_: mpsc::error::SendError<T>) -> Self {
         Self::TaskLost
@@ -161,6 +849,12 @@ impl fmt::Display for Error {
             Self::NoAddressesFound => f.pad("no addresses found"),
             Self::ResolutionFailed(e) => fmt::Display::fmt(e, f),
             Self::TaskLost => f.pad("background task terminated unexpectedly"),
+            Self::Saturated(timeout) => write!(
+                f,
+                "DNS lookup concurrency limit reached; no lookup could begin within {:?}",
+                timeout
+            ),
+            Self::Canceled => f.pad("canceled"),
         }
     }
 }
@@ -176,8 +870,780 @@ impl std::error::Error for Error {
 
 #[cfg(test)]
 mod tests {
-    use super::{Name, Suffix};
+    use super::*;
+    use linkerd2_tracing_test::Recorder;
     use std::convert::TryFrom;
+    use std::net::IpAddr;
+    use std::time::{Duration, Instant};
+    use tokio_test::{assert_pending, assert_ready, assert_ready_ok, task};
+    use trust_dns_resolver::{
+        lookup,
+        proto::{
+            op::Query,
+            rr::{rdata, RData, Record},
+        },
+    };
+
+    /// Returns a resolver whose lookups are answered by the test.
+    pub(crate) fn mock() -> (Resolver, mpsc::UnboundedReceiver<ResolveRequest>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let resolver = build_resolver(Default::default(), Default::default()).unwrap();
+        let resolver = Resolver {
+            tx,
+            resolver: Arc::new(ArcSwap::from_pointee(resolver)),
+            nameservers: Arc::new(ArcSwap::from_pointee(Vec::new())),
+            configure: None,
+            selection: IpSelection::default(),
+            family: FamilyPreference::default(),
+            metrics: None,
+            max_addresses: None,
+            refines: Arc::new(Mutex::new(HashMap::new())),
+            concurrency: None,
+        };
+        (resolver, rx)
+    }
+
+    /// A `Lookup` whose lookups are answered by the test.
+    struct MockLookup {
+        id: usize,
+        lookups: mpsc::UnboundedSender<(usize, Name, oneshot::Sender<LookupIp>)>,
+    }
+
+    impl Lookup for MockLookup {
+        type Future =
+            Pin<Box<dyn Future<Output = Result<LookupIp, ResolveError>> + Send + 'static>>;
+
+        fn lookup(&self, name: Name, _: Option<RecordType>) -> Self::Future {
+            let (tx, rx) = oneshot::channel();
+            self.lookups.send((self.id, name, tx)).ok().unwrap();
+            Box::pin(async move { Ok(rx.await.expect("lookup must be answered")) })
+        }
+    }
+
+    /// Builds a successful lookup result for `name`.
+    pub(crate) fn lookup_ip(name: &Name, ips: &[IpAddr], ttl: Duration) -> LookupIp {
+        let name = trust_dns_resolver::Name::from_ascii(name.as_ref()).unwrap();
+        let records = ips
+            .iter()
+            .map(|ip| {
+                let rdata = match ip {
+                    IpAddr::V4(ip) => RData::A(*ip),
+                    IpAddr::V6(ip) => RData::AAAA(*ip),
+                };
+                Record::from_rdata(name.clone(), ttl.as_secs() as u32, rdata)
+            })
+            .collect::<Vec<_>>();
+        let query = Query::query(name, RecordType::A);
+        lookup::Lookup::new_with_deadline(query, records.into(), Instant::now() + ttl).into()
+    }
+
+    /// Builds a successful CNAME lookup result for `name`.
+    fn lookup_cname(name: &Name, ttl: Duration) -> LookupIp {
+        let name = trust_dns_resolver::Name::from_ascii(name.as_ref()).unwrap();
+        let target = trust_dns_resolver::Name::from_ascii("web.example.net").unwrap();
+        let record = Record::from_rdata(name.clone(), ttl.as_secs() as u32, RData::CNAME(target));
+        let query = Query::query(name, RecordType::CNAME);
+        lookup::Lookup::new_with_deadline(query, vec![record].into(), Instant::now() + ttl).into()
+    }
+
+    /// Builds a successful SRV lookup result for `name`.
+    fn lookup_srv(name: &Name, targets: &[(&str, u16, u16, u16)], ttl: Duration) -> LookupIp {
+        let name = trust_dns_resolver::Name::from_ascii(name.as_ref()).unwrap();
+        let records = targets
+            .iter()
+            .map(|(target, port, priority, weight)| {
+                let target = trust_dns_resolver::Name::from_ascii(target).unwrap();
+                let srv = rdata::SRV::new(*priority, *weight, *port, target);
+                Record::from_rdata(name.clone(), ttl.as_secs() as u32, RData::SRV(srv))
+            })
+            .collect::<Vec<_>>();
+        let query = Query::query(name, RecordType::SRV);
+        lookup::Lookup::new_with_deadline(query, records.into(), Instant::now() + ttl).into()
+    }
+
+    #[test]
+    fn nameservers() {
+        use trust_dns_resolver::config::NameServerConfigGroup;
+
+        let ips = [
+            IpAddr::from([10, 0, 0, 10]),
+            IpAddr::from([0xfd00, 0, 0, 0, 0, 0, 0, 10]),
+        ];
+        // Each nameserver is configured for both UDP and TCP.
+        let config = ResolverConfig::from_parts(
+            None,
+            vec![],
+            NameServerConfigGroup::from_ips_clear(&ips, 53, true),
+        );
+        let (resolver, _task) = Resolver::new(config, ResolverOpts::default()).unwrap();
+        assert_eq!(
+            resolver.nameservers(),
+            vec![
+                net::SocketAddr::new(ips[0], 53),
+                net::SocketAddr::new(ips[1], 53)
+            ]
+        );
+    }
+
+    #[test]
+    fn in_flight_lookups_gauge() {
+        let metrics = Metrics::default();
+        let (resolver, mut rx) = mock();
+        let resolver = resolver.with_metrics(metrics.clone());
+        let name = Name::try_from("web.example.com".as_bytes()).unwrap();
+
+        let mut lookup0 = task::spawn(resolver.resolve_one_ip(&name));
+        let mut lookup1 = task::spawn(resolver.resolve_one_ip(&name));
+        assert_eq!(metrics.lookups_in_flight(), 2);
+        assert_pending!(lookup0.poll());
+        assert_pending!(lookup1.poll());
+        assert_eq!(metrics.lookups_in_flight(), 2);
+
+        // Complete the first lookup.
+        let ip = IpAddr::from([10, 0, 0, 1]);
+        let req = rx.try_recv().expect("lookup must be requested");
+        let res = lookup_ip(&req.name, &[ip], Duration::from_secs(10));
+        req.result_tx.send(Ok(res)).ok().unwrap();
+        assert_eq!(assert_ready_ok!(lookup0.poll()), ip);
+        drop(lookup0);
+        assert_eq!(metrics.lookups_in_flight(), 1);
+
+        // Cancel the second lookup.
+        drop(lookup1);
+        assert_eq!(metrics.lookups_in_flight(), 0);
+    }
+
+    #[test]
+    fn resolve_one_ip_falls_back_to_other_family() {
+        let (resolver, mut rx) = mock();
+        let resolver = resolver
+            .with_family_preference(FamilyPreference::V6First)
+            .with_ip_selection(IpSelection::Random);
+        let name = Name::try_from("web.example.com".as_bytes()).unwrap();
+        let ip4 = IpAddr::from([10, 0, 0, 1]);
+        let ip6 = "fd00::1".parse::<IpAddr>().unwrap();
+
+        let mut resolve = |ips: &[IpAddr]| {
+            let mut lookup = task::spawn(resolver.resolve_one_ip(&name));
+            assert_pending!(lookup.poll());
+            let req = rx.try_recv().expect("lookup must be requested");
+            let res = lookup_ip(&req.name, ips, Duration::from_secs(10));
+            req.result_tx.send(Ok(res)).ok().unwrap();
+            assert_ready!(lookup.poll())
+        };
+
+        // The preferred family is always chosen when it has an address.
+        for _ in 0..10 {
+            assert_eq!(resolve(&[ip4, ip6]).unwrap(), ip6);
+        }
+        // Otherwise, the other family is used.
+        assert_eq!(resolve(&[ip4]).unwrap(), ip4);
+        assert!(matches!(resolve(&[]), Err(Error::NoAddressesFound)));
+    }
+
+    #[test]
+    fn first_ip_selection() {
+        let ips = vec![IpAddr::from([10, 0, 0, 1]), IpAddr::from([10, 0, 0, 2])];
+        for _ in 0..10 {
+            assert_eq!(IpSelection::First.select(ips.iter().cloned()), Some(ips[0]));
+        }
+        assert_eq!(IpSelection::First.select(std::iter::empty()), None);
+    }
+
+    #[test]
+    fn random_ip_selection_is_roughly_uniform() {
+        const CALLS: usize = 3_000;
+        let ips = vec![
+            IpAddr::from([10, 0, 0, 1]),
+            IpAddr::from([10, 0, 0, 2]),
+            IpAddr::from([10, 0, 0, 3]),
+        ];
+
+        let mut counts = std::collections::HashMap::<IpAddr, usize>::new();
+        for _ in 0..CALLS {
+            let ip = IpSelection::Random.select(ips.iter().cloned()).unwrap();
+            *counts.entry(ip).or_default() += 1;
+        }
+
+        // Each address is expected to be chosen 1000 times; allow for
+        // generous variance so that the test isn't flakey.
+        for ip in ips.iter() {
+            let count = counts.get(ip).cloned().unwrap_or_default();
+            assert!(
+                count > 800 && count < 1200,
+                "{} chosen {} of {} times",
+                ip,
+                count,
+                CALLS
+            );
+        }
+        assert_eq!(IpSelection::Random.select(std::iter::empty()), None);
+    }
+
+    #[tokio::test]
+    async fn reload_does_not_disrupt_in_flight_lookups() {
+        let (lookups_tx, mut lookups) = mpsc::unbounded_channel();
+        let current = Arc::new(ArcSwap::from_pointee(MockLookup {
+            id: 0,
+            lookups: lookups_tx.clone(),
+        }));
+        let (resolver, rx) = mock();
+        tokio::spawn(run(current.clone(), rx));
+        let name = Name::try_from("web.example.com".as_bytes()).unwrap();
+        let ip0 = IpAddr::from([10, 0, 0, 1]);
+        let ip1 = IpAddr::from([10, 0, 0, 2]);
+
+        let lookup0 = tokio::spawn(resolver.resolve_one_ip(&name));
+        let (id, name0, tx0) = lookups.recv().await.unwrap();
+        assert_eq!(id, 0, "lookup must use the initial config");
+
+        // Swap in a new resolver while the first lookup is in flight.
+        current.store(Arc::new(MockLookup {
+            id: 1,
+            lookups: lookups_tx,
+        }));
+
+        let lookup1 = tokio::spawn(resolver.resolve_one_ip(&name));
+        let (id, name1, tx1) = lookups.recv().await.unwrap();
+        assert_eq!(id, 1, "lookup must use the reloaded config");
+
+        let ttl = Duration::from_secs(10);
+        tx0.send(lookup_ip(&name0, &[ip0], ttl)).ok().unwrap();
+        assert_eq!(lookup0.await.unwrap().unwrap(), ip0);
+        tx1.send(lookup_ip(&name1, &[ip1], ttl)).ok().unwrap();
+        assert_eq!(lookup1.await.unwrap().unwrap(), ip1);
+    }
+
+    #[test]
+    fn resolve_all_ips_returns_all_addresses() {
+        let (resolver, mut rx) = mock();
+        let name = Name::try_from("web.example.com".as_bytes()).unwrap();
+        let ips = vec![IpAddr::from([10, 0, 0, 1]), IpAddr::from([10, 0, 0, 2])];
+
+        let mut lookup = task::spawn(resolver.resolve_all_ips(&name));
+        assert_pending!(lookup.poll());
+
+        let req = rx.try_recv().expect("lookup must be requested");
+        let res = lookup_ip(&req.name, &ips, Duration::from_secs(10));
+        let valid_until = res.valid_until();
+        req.result_tx.send(Ok(res)).ok().unwrap();
+
+        let list = assert_ready_ok!(lookup.poll());
+        assert_eq!(list, IpList::new(ips, valid_until));
+    }
+
+    #[test]
+    fn resolve_all_ips_with_max_addresses() {
+        let (resolver, mut rx) = mock();
+        let resolver = resolver.with_max_addresses(2);
+        let name = Name::try_from("web.example.com".as_bytes()).unwrap();
+        let ips = (1..=5)
+            .map(|i| IpAddr::from([10, 0, 0, i]))
+            .collect::<Vec<_>>();
+
+        let mut lookup = task::spawn(resolver.resolve_all_ips(&name));
+        assert_pending!(lookup.poll());
+
+        let req = rx.try_recv().expect("lookup must be requested");
+        let res = lookup_ip(&req.name, &ips, Duration::from_secs(10));
+        let valid_until = res.valid_until();
+        req.result_tx.send(Ok(res)).ok().unwrap();
+
+        let list = assert_ready_ok!(lookup.poll());
+        assert_eq!(list.len(), 2);
+        assert_eq!(list.iter().collect::<Vec<_>>(), ips[..2].to_vec());
+        assert_eq!(list, IpList::new(ips[..2].to_vec(), valid_until));
+
+        // Lists within the limit are unchanged.
+        let list = IpList::new(ips.clone(), valid_until);
+        assert_eq!(list.clone().truncate(Some(5)), list);
+        assert_eq!(list.clone().truncate(None), list);
+    }
+
+    #[test]
+    fn resolve_srv_returns_targets() {
+        let (resolver, mut rx) = mock();
+        let name = Name::try_from("_http._tcp.web.example.com".as_bytes()).unwrap();
+
+        let mut lookup = task::spawn(resolver.resolve_srv(&name));
+        assert_pending!(lookup.poll());
+
+        let req = rx.try_recv().expect("lookup must be requested");
+        assert_eq!(req.record_type, Some(RecordType::SRV));
+        let res = lookup_srv(
+            &req.name,
+            &[
+                ("web-0.example.com.", 8080, 10, 1),
+                (".", 8080, 10, 1),
+                ("web-1.example.com.", 8081, 20, 3),
+            ],
+            Duration::from_secs(10),
+        );
+        let valid_until = res.valid_until();
+        req.result_tx.send(Ok(res)).ok().unwrap();
+
+        // The root target, which indicates that the service is unavailable,
+        // is ignored.
+        let srv = assert_ready_ok!(lookup.poll());
+        let web = |n: &str| Name::try_from(n.as_bytes()).unwrap();
+        assert_eq!(
+            srv,
+            SrvList::new(
+                vec![
+                    SrvTarget::new(web("web-0.example.com."), 8080, 10, 1),
+                    SrvTarget::new(web("web-1.example.com."), 8081, 20, 3),
+                ],
+                valid_until
+            )
+        );
+
+        let mut lookup = task::spawn(resolver.resolve_srv(&name));
+        assert_pending!(lookup.poll());
+        let req = rx.try_recv().expect("lookup must be requested");
+        let res = lookup_srv(&req.name, &[(".", 0, 0, 0)], Duration::from_secs(10));
+        req.result_tx.send(Ok(res)).ok().unwrap();
+        assert!(matches!(
+            assert_ready!(lookup.poll()),
+            Err(Error::NoAddressesFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn resolve_reachable_ips_probes_each_address() {
+        let (resolver, mut rx) = mock();
+        let name = Name::try_from("web.example.com".as_bytes()).unwrap();
+        let mut listener =
+            tokio::net::TcpListener::bind(net::SocketAddr::from(([127, 0, 0, 1], 0)))
+                .await
+                .unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move { while listener.accept().await.is_ok() {} });
+
+        // Nothing listens on the second loopback address.
+        let reachable = IpAddr::from([127, 0, 0, 1]);
+        let unreachable = IpAddr::from([127, 0, 0, 2]);
+        let policy = ProbePolicy {
+            timeout: Duration::from_secs(5),
+            concurrency: 2,
+        };
+        let probe = tokio::spawn(resolver.resolve_reachable_ips(&name, port, policy));
+
+        let req = rx.recv().await.expect("lookup must be requested");
+        let res = lookup_ip(
+            &req.name,
+            &[unreachable, reachable],
+            Duration::from_secs(10),
+        );
+        req.result_tx.send(Ok(res)).ok().unwrap();
+
+        let probed = probe.await.unwrap().expect("probe must succeed");
+        assert_eq!(
+            probed.iter().collect::<Vec<_>>(),
+            vec![(unreachable, false), (reachable, true)]
+        );
+        assert_eq!(probed.reachable().collect::<Vec<_>>(), vec![reachable]);
+        assert_eq!(probed.reachable_first(), vec![reachable, unreachable]);
+    }
+
+    #[test]
+    fn lookup_spans_record_trace_id() {
+        let (resolver, _rx) = mock();
+        let name = Name::try_from("web.example.com".as_bytes()).unwrap();
+
+        let spans = Recorder::default();
+        tracing::subscriber::with_default(spans.clone(), || {
+            let _ = resolver.resolve_all_ips(&name);
+            let mut traced = task::spawn(context_future(
+                "4bf92f3577b34da6",
+                futures::future::lazy(|_| resolver.resolve_all_ips(&name)),
+            ));
+            let _ = assert_ready!(traced.poll());
+            // The id only applies to the lookups of the context's future.
+            let _ = resolver.resolve_all_ips(&name);
+        });
+        assert_eq!(
+            spans.spans(),
+            vec![
+                "resolve_all_ips name=web.example.com",
+                "resolve_all_ips name=web.example.com trace=4bf92f3577b34da6",
+                "resolve_all_ips name=web.example.com",
+            ]
+        );
+    }
+
+    #[test]
+    fn resolve_all_ips_with_mixed_ttls() {
+        let (resolver, mut rx) = mock();
+        let name = Name::try_from("web.example.com".as_bytes()).unwrap();
+        let ip0 = IpAddr::from([10, 0, 0, 1]);
+        let ip1 = IpAddr::from([10, 0, 0, 2]);
+
+        let mut lookup = task::spawn(resolver.resolve_all_ips(&name));
+        assert_pending!(lookup.poll());
+        let req = rx.try_recv().expect("lookup must be requested");
+        let dns_name = trust_dns_resolver::Name::from_ascii(req.name.as_ref()).unwrap();
+        let records = vec![
+            Record::from_rdata(dns_name.clone(), 300, RData::A([10, 0, 0, 1].into())),
+            Record::from_rdata(dns_name.clone(), 5, RData::A([10, 0, 0, 2].into())),
+        ];
+        let valid_until = Instant::now() + Duration::from_secs(5);
+        let query = Query::query(dns_name, RecordType::A);
+        let res = lookup::Lookup::new_with_deadline(query, records.into(), valid_until);
+        req.result_tx.send(Ok(res.into())).ok().unwrap();
+
+        let list = assert_ready_ok!(lookup.poll());
+        assert_eq!(
+            list.valid_until(),
+            valid_until,
+            "must expire with the shortest TTL"
+        );
+        assert_eq!(
+            list.iter_with_ttl().collect::<Vec<_>>(),
+            vec![
+                (ip0, valid_until + Duration::from_secs(295)),
+                (ip1, valid_until),
+            ]
+        );
+
+        // Without per-address TTLs, all addresses share the list's expiry.
+        let list = IpList::new(vec![ip0, ip1], valid_until);
+        assert_eq!(
+            list.iter_with_ttl().collect::<Vec<_>>(),
+            vec![(ip0, valid_until), (ip1, valid_until)]
+        );
+    }
+
+    #[test]
+    fn resolve_all_ips_with_name_follows_cnames() {
+        let (resolver, mut rx) = mock();
+        let name = Name::try_from("web.example.com".as_bytes()).unwrap();
+        let ip = IpAddr::from([10, 0, 0, 1]);
+        let ttl = Duration::from_secs(10);
+
+        // web.example.com -> lb.example.net -> lb-1.example.net
+        let mut lookup = task::spawn(resolver.resolve_all_ips_with_name(&name));
+        assert_pending!(lookup.poll());
+        let req = rx.try_recv().expect("lookup must be requested");
+        let chain = ["web.example.com", "lb.example.net", "lb-1.example.net"]
+            .iter()
+            .map(|n| trust_dns_resolver::Name::from_ascii(n).unwrap())
+            .collect::<Vec<_>>();
+        let records = vec![
+            Record::from_rdata(chain[1].clone(), 10, RData::CNAME(chain[2].clone())),
+            Record::from_rdata(chain[0].clone(), 10, RData::CNAME(chain[1].clone())),
+            Record::from_rdata(chain[2].clone(), 10, RData::A([10, 0, 0, 1].into())),
+        ];
+        let query = Query::query(chain[0].clone(), RecordType::A);
+        let valid_until = Instant::now() + ttl;
+        let res = lookup::Lookup::new_with_deadline(query, records.into(), valid_until);
+        req.result_tx.send(Ok(res.into())).ok().unwrap();
+
+        let (canonical, ips) = assert_ready_ok!(lookup.poll()).into_parts();
+        assert_eq!(canonical.as_ref(), "lb-1.example.net");
+        assert_eq!(ips, IpList::new(vec![ip], valid_until));
+
+        // Without aliases, the queried name is returned.
+        let mut lookup = task::spawn(resolver.resolve_all_ips_with_name(&name));
+        assert_pending!(lookup.poll());
+        let req = rx.try_recv().expect("lookup must be requested");
+        let res = lookup_ip(&req.name, &[ip], ttl);
+        req.result_tx.send(Ok(res)).ok().unwrap();
+
+        let named = assert_ready_ok!(lookup.poll());
+        assert_eq!(named.name(), &name);
+        assert_eq!(named.ips().iter().collect::<Vec<_>>(), vec![ip]);
+    }
+
+    #[test]
+    fn resolve_one_ip_with_fallbacks_interleaves_families() {
+        let (resolver, mut rx) = mock();
+        let name = Name::try_from("web.example.com".as_bytes()).unwrap();
+        let ips = vec![
+            IpAddr::from([10, 0, 0, 1]),
+            IpAddr::from([10, 0, 0, 2]),
+            "fd00::1".parse::<IpAddr>().unwrap(),
+            "fd00::2".parse::<IpAddr>().unwrap(),
+        ];
+        let policy = FallbackPolicy {
+            max: 3,
+            interleave_families: true,
+        };
+
+        let mut lookup = task::spawn(resolver.resolve_one_ip_with_fallbacks(&name, policy));
+        assert_pending!(lookup.poll());
+
+        let req = rx.try_recv().expect("lookup must be requested");
+        let res = lookup_ip(&req.name, &ips, Duration::from_secs(10));
+        req.result_tx.send(Ok(res)).ok().unwrap();
+
+        let attempts = assert_ready_ok!(lookup.poll());
+        assert_eq!(attempts, vec![ips[0], ips[2], ips[1]]);
+    }
+
+    #[test]
+    fn refine_name_falls_back_without_addresses() {
+        let (resolver, mut rx) = mock();
+        let name = Name::try_from("web".as_bytes()).unwrap();
+        let canonical = Name::try_from("web.example.com".as_bytes()).unwrap();
+        let ttl = Duration::from_secs(10);
+        let mut refine = task::spawn(resolver.refine_name(&name, Some(RecordType::CNAME)));
+
+        // The name has no A or AAAA records.
+        assert_pending!(refine.poll());
+        let req = rx.try_recv().expect("IP lookup must be requested");
+        assert_eq!(req.record_type, None);
+        let res = lookup_ip(&canonical, &[], ttl);
+        req.result_tx.send(Ok(res)).ok().unwrap();
+
+        assert_pending!(refine.poll());
+        let req = rx.try_recv().expect("fallback lookup must be requested");
+        assert_eq!(req.record_type, Some(RecordType::CNAME));
+        let res = lookup_cname(&canonical, ttl);
+        req.result_tx.send(Ok(res)).ok().unwrap();
+
+        assert_eq!(assert_ready_ok!(refine.poll()), canonical);
+    }
+
+    #[test]
+    fn concurrent_refines_share_lookup() {
+        let (resolver, mut rx) = mock();
+        let name = Name::try_from("web.example.com".as_bytes()).unwrap();
+        let mut refine0 = task::spawn(resolver.refine_name(&name, None));
+        let mut refine1 = task::spawn(resolver.refine_name(&name, None));
+        assert_pending!(refine0.poll());
+        assert_pending!(refine1.poll());
+
+        let req = rx.try_recv().expect("lookup must be requested");
+        assert!(rx.try_recv().is_err(), "only one lookup must be requested");
+        let res = lookup_ip(&req.name, &[[10, 0, 0, 1].into()], Duration::from_secs(10));
+        req.result_tx.send(Ok(res)).ok().unwrap();
+        assert!(refine1.is_woken());
+        assert_eq!(assert_ready_ok!(refine0.poll()), name);
+        assert_eq!(assert_ready_ok!(refine1.poll()), name);
+
+        // Once complete, a subsequent refinement issues a new lookup.
+        let mut refine = task::spawn(resolver.refine_name(&name, None));
+        assert_pending!(refine.poll());
+        assert!(rx.try_recv().is_ok(), "lookup must be requested");
+    }
+
+    #[test]
+    fn refine_service_shares_lookup() {
+        use linkerd2_stack::NewService;
+        use tower::Service;
+
+        let (resolver, mut rx) = mock();
+        let name = Name::try_from("web.example.com".as_bytes()).unwrap();
+        let ip = IpAddr::from([10, 0, 0, 1]);
+        let mut svc = resolver
+            .clone()
+            .into_make_refine()
+            .new_service(name.clone());
+        let mut ready = task::spawn(futures::future::poll_fn(|cx| svc.poll_ready(cx)));
+        let mut refine = task::spawn(resolver.refine_name_with_ttl(&name));
+        assert_pending!(ready.poll());
+        assert_pending!(refine.poll());
+
+        let req = rx.try_recv().expect("lookup must be requested");
+        assert!(rx.try_recv().is_err(), "only one lookup must be requested");
+        let res = lookup_ip(&req.name, &[ip], Duration::from_secs(10));
+        let valid_until = res.valid_until();
+        req.result_tx.send(Ok(res)).ok().unwrap();
+        assert_ready_ok!(ready.poll());
+        assert_eq!(assert_ready_ok!(refine.poll()), (name.clone(), valid_until));
+        drop(ready);
+        assert_eq!(
+            assert_ready_ok!(task::spawn(svc.call(())).poll()),
+            (name, ip)
+        );
+    }
+
+    #[tokio::test]
+    async fn concurrency_limit_serializes_lookups() {
+        let (resolver, mut rx) = mock();
+        let resolver = resolver.with_concurrency_limit(1, Duration::from_secs(10));
+        let name = Name::try_from("web.example.com".as_bytes()).unwrap();
+        let ip = IpAddr::from([10, 0, 0, 1]);
+        let mut lookup0 = task::spawn(resolver.resolve_all_ips(&name));
+        let mut lookup1 = task::spawn(resolver.clone().resolve_all_ips(&name));
+
+        assert_pending!(lookup0.poll());
+        assert_pending!(lookup1.poll());
+        let req = rx.try_recv().expect("lookup must be requested");
+        assert!(rx.try_recv().is_err(), "only one lookup may be in flight");
+
+        // Once the first lookup completes, the second begins.
+        let res = lookup_ip(&req.name, &[ip], Duration::from_secs(10));
+        req.result_tx.send(Ok(res)).ok().unwrap();
+        assert_ready_ok!(lookup0.poll());
+        assert!(lookup1.is_woken());
+        assert_pending!(lookup1.poll());
+        let req = rx.try_recv().expect("lookup must be requested");
+        let res = lookup_ip(&req.name, &[ip], Duration::from_secs(10));
+        req.result_tx.send(Ok(res)).ok().unwrap();
+        assert_ready_ok!(lookup1.poll());
+    }
+
+    #[tokio::test]
+    async fn concurrency_limit_times_out() {
+        let (resolver, mut rx) = mock();
+        let timeout = Duration::from_millis(10);
+        let resolver = resolver.with_concurrency_limit(1, timeout);
+        let name = Name::try_from("web.example.com".as_bytes()).unwrap();
+        let mut lookup0 = task::spawn(resolver.resolve_all_ips(&name));
+        assert_pending!(lookup0.poll());
+        let _req = rx.try_recv().expect("lookup must be requested");
+
+        match resolver.resolve_all_ips(&name).await {
+            Err(Error::Saturated(t)) => assert_eq!(t, timeout),
+            res => panic!("lookup must fail; got {:?}", res),
+        }
+        assert!(rx.try_recv().is_err(), "no lookup must be requested");
+    }
+
+    #[test]
+    fn canceling_a_refine_releases_its_lookup() {
+        let (resolver, mut rx) = mock();
+        let names = [
+            "web-0.example.com",
+            "web-1.example.com",
+            "web-2.example.com",
+        ]
+        .iter()
+        .map(|n| Name::try_from(n.as_bytes()).unwrap())
+        .collect::<Vec<_>>();
+        let mut batch = names
+            .iter()
+            .map(|name| {
+                let (refine, cancel) = resolver.refine_name_cancelable(name, None);
+                (task::spawn(refine), cancel)
+            })
+            .collect::<Vec<_>>();
+        let mut reqs = Vec::new();
+        for (refine, _) in batch.iter_mut() {
+            assert_pending!(refine.poll());
+            reqs.push(rx.try_recv().expect("lookup must be requested"));
+        }
+
+        // The canceled refinement fails and its lookup is released, even
+        // though its future is still held.
+        batch[1].1.cancel();
+        assert!(batch[1].0.is_woken());
+        assert!(reqs[1].result_tx.is_closed(), "lookup must be released");
+        match assert_ready!(batch[1].0.poll()) {
+            Err(Error::Canceled) => {}
+            res => panic!("refinement must be canceled; got {:?}", res),
+        }
+
+        // The others are undisturbed.
+        for (i, req) in reqs.into_iter().enumerate().filter(|(i, _)| *i != 1) {
+            assert!(
+                !req.result_tx.is_closed(),
+                "lookup {} must not be released",
+                i
+            );
+            let res = lookup_ip(&req.name, &[[10, 0, 0, 1].into()], Duration::from_secs(10));
+            req.result_tx.send(Ok(res)).ok().unwrap();
+        }
+        assert_eq!(assert_ready_ok!(batch[0].0.poll()), names[0]);
+        assert_eq!(assert_ready_ok!(batch[2].0.poll()), names[2]);
+
+        // Canceling a completed refinement has no effect.
+        batch[0].1.cancel();
+    }
+
+    #[test]
+    fn refine_name_addr_retains_port() {
+        let (resolver, mut rx) = mock();
+        let na = NameAddr::from_str("web:8080").unwrap();
+        let mut refine = task::spawn(resolver.refine_name_addr(&na));
+        assert_pending!(refine.poll());
+
+        let req = rx.try_recv().expect("lookup must be requested");
+        assert_eq!(req.name, *na.name());
+        let canonical = Name::try_from("web.example.com".as_bytes()).unwrap();
+        let res = lookup_ip(&canonical, &[[10, 0, 0, 1].into()], Duration::from_secs(10));
+        req.result_tx.send(Ok(res)).ok().unwrap();
+        assert_eq!(
+            assert_ready_ok!(refine.poll()),
+            NameAddr::from_str("web.example.com:8080").unwrap()
+        );
+    }
+
+    #[test]
+    fn refine_name_with_ttl_returns_expiry() {
+        let (resolver, mut rx) = mock();
+        let name = Name::try_from("web".as_bytes()).unwrap();
+        let refined = Name::try_from("web.ns.svc.cluster.local.".as_bytes()).unwrap();
+
+        let mut refine = task::spawn(resolver.refine_name_with_ttl(&name));
+        assert_pending!(refine.poll());
+        let req = rx.try_recv().expect("lookup must be requested");
+        let res = lookup_ip(
+            &refined,
+            &[IpAddr::from([10, 0, 0, 1])],
+            Duration::from_secs(10),
+        );
+        let valid_until = res.valid_until();
+        req.result_tx.send(Ok(res)).ok().unwrap();
+        assert_eq!(assert_ready_ok!(refine.poll()), (refined, valid_until));
+    }
+
+    #[test]
+    fn concurrent_refines_share_errors() {
+        let (resolver, mut rx) = mock();
+        let name = Name::try_from("web.example.com".as_bytes()).unwrap();
+        let mut refine0 = task::spawn(resolver.refine_name(&name, None));
+        let refine1 = task::spawn(resolver.refine_name(&name, None));
+        assert_pending!(refine0.poll());
+
+        let req = rx.try_recv().expect("lookup must be requested");
+        let res = lookup_ip(&req.name, &[], Duration::from_secs(10));
+        req.result_tx.send(Ok(res)).ok().unwrap();
+        for refine in &mut [refine0, refine1] {
+            match assert_ready!(refine.poll()) {
+                Err(Error::NoAddressesFound) => {}
+                res => panic!("unexpected result: {:?}", res),
+            }
+        }
+    }
+
+    #[test]
+    fn canceled_refines_drop_lookup() {
+        let (resolver, mut rx) = mock();
+        let name = Name::try_from("web.example.com".as_bytes()).unwrap();
+        let mut refine0 = task::spawn(resolver.refine_name(&name, None));
+        let mut refine1 = task::spawn(resolver.refine_name(&name, None));
+        assert_pending!(refine0.poll());
+        assert_pending!(refine1.poll());
+        let req = rx.try_recv().expect("lookup must be requested");
+
+        drop((refine0, refine1));
+        assert!(req.result_tx.is_closed(), "lookup must be canceled");
+
+        let mut refine = task::spawn(resolver.refine_name(&name, None));
+        assert_pending!(refine.poll());
+        assert!(rx.try_recv().is_ok(), "lookup must be requested");
+    }
+
+    #[test]
+    fn spawn_on_runtime() {
+        let rt = runtime::Builder::new()
+            .threaded_scheduler()
+            .core_threads(1)
+            .enable_all()
+            .build()
+            .expect("runtime must build");
+        let resolver = Resolver::spawn_on(Default::default(), Default::default(), rt.handle())
+            .expect("resolver must build");
+
+        // The lookup is not driven by a runtime here, so it can only complete
+        // if the resolver runs on `rt`.
+        let name = Name::try_from("localhost.".as_bytes()).unwrap();
+        let ip = futures::executor::block_on(resolver.resolve_one_ip(&name))
+            .expect("localhost must resolve");
+        assert!(ip.is_loopback(), "{} must be a loopback address", ip);
+    }
 
     #[test]
     fn test_dns_name_parsing() {
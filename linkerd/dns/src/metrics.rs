@@ -0,0 +1,108 @@
+use crate::QueryType;
+use linkerd2_metrics::{metrics, Counter, FmtLabels, FmtMetrics, Gauge};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+metrics! {
+    dns_lookups_in_flight: Gauge { "Number of DNS lookups that have been started but not yet completed" },
+    dns_cache_hits_total: Counter { "Total number of DNS lookups served by the proxy's cache" },
+    dns_cache_misses_total: Counter { "Total number of DNS lookups not served by the proxy's cache" }
+}
+
+/// Tracks the resolver's DNS lookups.
+#[derive(Clone, Debug, Default)]
+pub struct Metrics {
+    in_flight: Arc<Gauge>,
+    cache: Arc<Mutex<HashMap<QueryType, CacheCounts>>>,
+}
+
+/// Counts lookups served by a `CachingResolver`.
+///
+/// Trust-DNS's own cache is disabled, so it is not counted.
+#[derive(Debug, Default)]
+struct CacheCounts {
+    hits: Counter,
+    misses: Counter,
+}
+
+/// Counts a lookup as in-flight for as long as it is held.
+///
+/// The gauge is decremented when the guard is dropped, so lookups that are
+/// canceled before they complete are not counted forever.
+#[derive(Debug)]
+pub(crate) struct InFlight(Arc<Gauge>);
+
+// === impl Metrics ===
+
+impl Metrics {
+    pub(crate) fn in_flight(&self) -> InFlight {
+        self.in_flight.incr();
+        InFlight(self.in_flight.clone())
+    }
+
+    /// Returns the number of lookups currently in flight.
+    pub fn lookups_in_flight(&self) -> u64 {
+        self.in_flight.value()
+    }
+
+    pub(crate) fn cache_hit(&self, query: QueryType) {
+        let mut cache = self.cache.lock().unwrap();
+        cache.entry(query).or_default().hits.incr();
+    }
+
+    pub(crate) fn cache_miss(&self, query: QueryType) {
+        let mut cache = self.cache.lock().unwrap();
+        cache.entry(query).or_default().misses.incr();
+    }
+
+    /// Returns the number of `query` lookups served by the cache.
+    pub fn cache_hits(&self, query: QueryType) -> u64 {
+        let cache = self.cache.lock().unwrap();
+        cache.get(&query).map(|c| c.hits.value()).unwrap_or(0)
+    }
+
+    /// Returns the number of `query` lookups not served by the cache.
+    pub fn cache_misses(&self, query: QueryType) -> u64 {
+        let cache = self.cache.lock().unwrap();
+        cache.get(&query).map(|c| c.misses.value()).unwrap_or(0)
+    }
+}
+
+impl FmtMetrics for Metrics {
+    fn fmt_metrics(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        dns_lookups_in_flight.fmt_help(f)?;
+        dns_lookups_in_flight.fmt_metric(f, &self.in_flight)?;
+
+        let cache = self.cache.lock().unwrap();
+        if cache.is_empty() {
+            return Ok(());
+        }
+
+        dns_cache_hits_total.fmt_help(f)?;
+        dns_cache_hits_total.fmt_scopes(f, cache.iter(), |c| &c.hits)?;
+
+        dns_cache_misses_total.fmt_help(f)?;
+        dns_cache_misses_total.fmt_scopes(f, cache.iter(), |c| &c.misses)?;
+
+        Ok(())
+    }
+}
+
+// === impl QueryType ===
+
+impl FmtLabels for QueryType {
+    fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueryType::Ip => write!(f, "query_type=\"ip\""),
+        }
+    }
+}
+
+// === impl InFlight ===
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.decr();
+    }
+}
@@ -0,0 +1,96 @@
+use crate::IpList;
+use futures::prelude::*;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use tokio::{net::TcpStream, time};
+use tracing::trace;
+
+/// Determines how `Resolver::resolve_reachable_ips` probes each of a name's
+/// addresses.
+///
+/// Probes are plain TCP connects, which are closed as soon as they are
+/// established. They are intended for warming up, not for every request.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ProbePolicy {
+    /// How long to wait for each connection to be established.
+    pub timeout: Duration,
+    /// The maximum number of probes in flight at once.
+    pub concurrency: usize,
+}
+
+/// All of the addresses resolved for a name, with whether each could be
+/// connected to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProbedIpList {
+    ips: IpList,
+    /// Whether each of `ips`, in order, is reachable.
+    reachable: Vec<bool>,
+}
+
+// === impl ProbePolicy ===
+
+impl Default for ProbePolicy {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(1),
+            concurrency: 8,
+        }
+    }
+}
+
+impl ProbePolicy {
+    /// Probes each of `ips` on `port`.
+    pub(crate) async fn probe(self, ips: IpList, port: u16) -> ProbedIpList {
+        let timeout = self.timeout;
+        let reachable = stream::iter(ips.iter())
+            .map(|ip| Self::connect(SocketAddr::new(ip, port), timeout))
+            .buffered(self.concurrency.max(1))
+            .collect::<Vec<_>>()
+            .await;
+        ProbedIpList { ips, reachable }
+    }
+
+    async fn connect(addr: SocketAddr, timeout: Duration) -> bool {
+        match time::timeout(timeout, TcpStream::connect(addr)).await {
+            Ok(Ok(_)) => true,
+            Ok(Err(error)) => {
+                trace!(%addr, %error, "Unreachable");
+                false
+            }
+            Err(_) => {
+                trace!(%addr, ?timeout, "Timed out");
+                false
+            }
+        }
+    }
+}
+
+// === impl ProbedIpList ===
+
+impl ProbedIpList {
+    pub fn ips(&self) -> &IpList {
+        &self.ips
+    }
+
+    /// Returns each address with whether it is reachable.
+    pub fn iter(&self) -> impl Iterator<Item = (IpAddr, bool)> + '_ {
+        self.ips.iter().zip(self.reachable.iter().cloned())
+    }
+
+    /// Returns the reachable addresses, in the order they were resolved.
+    pub fn reachable(&self) -> impl Iterator<Item = IpAddr> + '_ {
+        self.iter()
+            .filter_map(|(ip, ok)| if ok { Some(ip) } else { None })
+    }
+
+    /// Returns all of the addresses, with reachable addresses ordered before
+    /// unreachable addresses.
+    pub fn reachable_first(&self) -> Vec<IpAddr> {
+        let (reachable, unreachable): (Vec<_>, Vec<_>) = self.iter().partition(|(_, ok)| *ok);
+        reachable
+            .into_iter()
+            .chain(unreachable)
+            .map(|(ip, _)| ip)
+            .collect()
+    }
+}
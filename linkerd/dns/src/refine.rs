@@ -1,15 +1,15 @@
-use futures::{future, ready};
+use futures::{future, future::Shared, ready, FutureExt};
 
-use super::{Error, Resolver};
+use super::{Error, NameFuture, RefinementFuture, Resolver};
 use linkerd2_dns_name::Name;
 use linkerd2_stack::NewService;
 use std::convert::TryFrom;
-use std::future::Future;
 use std::net::IpAddr;
-use std::pin::Pin;
-use std::task::{Context, Poll};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
 use std::time::Instant;
 use trust_dns_resolver::lookup_ip::LookupIp;
+use trust_dns_resolver::proto::rr::RData;
 
 /// A `MakeService` that produces a `Refine` for a given name.
 #[derive(Clone)]
@@ -22,9 +22,31 @@ pub struct Refine {
     state: State,
 }
 
+/// The result of refining a name, which may be shared by concurrent
+/// refinements.
+#[derive(Clone, Debug)]
+pub(super) struct Refinement {
+    pub name: Name,
+    /// The name's addresses, which are empty if the name was refined via a
+    /// fallback lookup.
+    pub ips: Vec<IpAddr>,
+    pub valid_until: Instant,
+}
+
+/// Cancels a refinement created by `Resolver::refine_name_cancelable`.
+#[derive(Clone)]
+pub struct CancelRefine(Arc<Mutex<Cancelable>>);
+
+/// A refinement that may be canceled by its `CancelRefine`.
+struct Cancelable {
+    /// The refinement, until it is canceled.
+    future: Option<NameFuture>,
+    waker: Option<Waker>,
+}
+
 enum State {
     Init,
-    Pending(Pin<Box<dyn Future<Output = Result<LookupIp, Error>> + Send + 'static>>),
+    Pending(Shared<RefinementFuture>),
     Refined {
         name: Name,
         ips: Vec<IpAddr>,
@@ -53,22 +75,14 @@ impl tower::Service<()> for Refine {
     fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
         loop {
             self.state = match self.state {
-                State::Init => {
-                    let resolver = self.resolver.clone();
-                    let name = self.name.clone();
-                    let span = tracing::Span::current();
-                    State::Pending(Box::pin(
-                        async move { resolver.lookup_ip(name, span).await },
-                    ))
-                }
+                State::Init => State::Pending(self.resolver.refine(&self.name, None)),
                 State::Pending(ref mut fut) => {
-                    let lookup = ready!(fut.as_mut().poll(cx))?;
-                    let valid_until = lookup.valid_until();
-                    let n = lookup.query().name();
-                    let name = Name::try_from(n.to_ascii().as_bytes())
-                        .expect("Name returned from resolver must be valid");
-                    let ips = lookup.iter().collect::<Vec<_>>();
-                    assert!(ips.len() > 0);
+                    let Refinement {
+                        name,
+                        ips,
+                        valid_until,
+                    } = ready!(fut.poll_unpin(cx))?;
+                    assert!(!ips.is_empty());
                     State::Refined {
                         name,
                         ips,
@@ -102,3 +116,102 @@ impl tower::Service<()> for Refine {
         unreachable!("called before ready");
     }
 }
+
+// === impl CancelRefine ===
+
+/// Pairs a refinement with a handle that cancels it.
+pub(super) fn cancelable(future: NameFuture) -> (NameFuture, CancelRefine) {
+    let cancelable = Arc::new(Mutex::new(Cancelable {
+        future: Some(future),
+        waker: None,
+    }));
+    let handle = CancelRefine(cancelable.clone());
+    let future = future::poll_fn(move |cx| {
+        let mut cancelable = cancelable.lock().unwrap();
+        let poll = match cancelable.future.as_mut() {
+            Some(future) => future.as_mut().poll(cx),
+            None => return Poll::Ready(Err(Error::Canceled)),
+        };
+        match poll {
+            Poll::Pending => {
+                cancelable.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+            Poll::Ready(res) => {
+                cancelable.future = None;
+                Poll::Ready(res)
+            }
+        }
+    });
+    (Box::pin(future), handle)
+}
+
+impl CancelRefine {
+    /// Cancels the refinement, dropping its interest in the lookup.
+    ///
+    /// Has no effect once the refinement has completed.
+    pub fn cancel(&self) {
+        let mut cancelable = self.0.lock().unwrap();
+        if cancelable.future.take().is_some() {
+            tracing::trace!("Refinement canceled");
+            if let Some(waker) = cancelable.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+// === impl Refinement ===
+
+impl Refinement {
+    /// Fails with `Error::NoAddressesFound` if the lookup has no addresses.
+    pub(super) fn from_lookup_ip(lookup: &LookupIp) -> Result<Self, Error> {
+        let ips = lookup.iter().collect::<Vec<_>>();
+        if ips.is_empty() {
+            return Err(Error::NoAddressesFound);
+        }
+        Ok(Self {
+            name: refined_name(lookup),
+            ips,
+            valid_until: lookup.valid_until(),
+        })
+    }
+
+    /// Refines a name via a lookup that need not have any addresses, e.g.
+    /// of CNAME records.
+    pub(super) fn from_lookup(lookup: &LookupIp) -> Self {
+        Self {
+            name: refined_name(lookup),
+            ips: Vec::new(),
+            valid_until: lookup.valid_until(),
+        }
+    }
+}
+
+/// Returns the fully-qualified name that was queried by a successful lookup.
+pub(super) fn refined_name(lookup: &LookupIp) -> Name {
+    let n = lookup.query().name();
+    Name::try_from(n.to_ascii().as_bytes()).expect("Name returned from resolver must be valid")
+}
+
+/// Returns the canonical name of a successful lookup, i.e. the last target of
+/// the answer's chain of CNAME records.
+///
+/// If the queried name was not an alias, the queried name is returned.
+pub(super) fn canonical_name(lookup: &LookupIp) -> Name {
+    let records = lookup.as_lookup().record_iter().collect::<Vec<_>>();
+    let mut name = lookup.query().name();
+    // Each record may be followed at most once, so that a cyclic chain
+    // terminates.
+    for _ in 0..records.len() {
+        let target = records.iter().find_map(|record| match record.rdata() {
+            RData::CNAME(target) if record.name() == name => Some(target),
+            _ => None,
+        });
+        match target {
+            Some(target) => name = target,
+            None => break,
+        }
+    }
+    Name::try_from(name.to_ascii().as_bytes()).expect("Name returned from resolver must be valid")
+}
@@ -0,0 +1,115 @@
+use linkerd2_dns_name::Name;
+use std::convert::TryFrom;
+use std::time::Instant;
+use tracing::debug;
+use trust_dns_resolver::lookup_ip::LookupIp;
+use trust_dns_resolver::proto::rr::RData;
+
+/// All of the targets of a name's SRV records.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SrvList {
+    targets: Vec<SrvTarget>,
+    valid_until: Instant,
+}
+
+/// A host and port that serves a name, as described by an SRV record.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SrvTarget {
+    name: Name,
+    port: u16,
+    priority: u16,
+    weight: u16,
+}
+
+// === impl SrvList ===
+
+impl SrvList {
+    pub fn new(targets: Vec<SrvTarget>, valid_until: Instant) -> Self {
+        Self {
+            targets,
+            valid_until,
+        }
+    }
+
+    /// Builds a list from a lookup's SRV records.
+    ///
+    /// Records whose target is not a valid name, e.g. the root name, which
+    /// indicates that the service is not available, are ignored.
+    pub(super) fn from_lookup(lookup: &LookupIp) -> Self {
+        let targets = lookup
+            .as_lookup()
+            .record_iter()
+            .filter_map(|record| match record.rdata() {
+                RData::SRV(srv) => {
+                    let target = srv.target().to_ascii();
+                    match Name::try_from(target.as_bytes()) {
+                        Ok(name) => Some(SrvTarget::new(
+                            name,
+                            srv.port(),
+                            srv.priority(),
+                            srv.weight(),
+                        )),
+                        Err(_) => {
+                            debug!(%target, "Ignoring SRV record with an invalid target");
+                            None
+                        }
+                    }
+                }
+                _ => None,
+            })
+            .collect();
+        Self::new(targets, lookup.valid_until())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &SrvTarget> + '_ {
+        self.targets.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.targets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.targets.is_empty()
+    }
+
+    /// Returns the time until which the targets may be used, as determined
+    /// by the records' TTLs.
+    pub fn valid_until(&self) -> Instant {
+        self.valid_until
+    }
+}
+
+// === impl SrvTarget ===
+
+impl SrvTarget {
+    pub fn new(name: Name, port: u16, priority: u16, weight: u16) -> Self {
+        Self {
+            name,
+            port,
+            priority,
+            weight,
+        }
+    }
+
+    /// Returns the name of the host that serves the target.
+    pub fn name(&self) -> &Name {
+        &self.name
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Returns the target's priority. Targets with lower priorities should be
+    /// preferred.
+    pub fn priority(&self) -> u16 {
+        self.priority
+    }
+
+    /// Returns the target's weight relative to other targets with the same
+    /// priority.
+    pub fn weight(&self) -> u16 {
+        self.weight
+    }
+}
@@ -0,0 +1,79 @@
+use std::net::SocketAddr;
+use trust_dns_resolver::config::{
+    NameServerConfig, NameServerConfigGroup, Protocol, ResolverConfig,
+};
+
+/// Nameservers that are queried via DNS-over-TLS rather than the system's
+/// configured nameservers, e.g. where plaintext DNS egress is not permitted.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TlsUpstream {
+    addrs: Vec<SocketAddr>,
+    tls_name: String,
+}
+
+// === impl TlsUpstream ===
+
+impl TlsUpstream {
+    /// Queries the nameservers at `addrs`, each of which must present a
+    /// certificate for `tls_name`.
+    pub fn new(addrs: Vec<SocketAddr>, tls_name: impl Into<String>) -> Self {
+        Self {
+            addrs,
+            tls_name: tls_name.into(),
+        }
+    }
+
+    pub fn addrs(&self) -> &[SocketAddr] {
+        &self.addrs
+    }
+
+    pub fn tls_name(&self) -> &str {
+        &self.tls_name
+    }
+
+    /// Builds a configuration that sends all queries to the upstream
+    /// nameservers over TLS.
+    ///
+    /// No search domains are configured, so only fully-qualified names
+    /// should be resolved.
+    pub(super) fn resolver_config(&self) -> ResolverConfig {
+        let nameservers = self
+            .addrs
+            .iter()
+            .map(|addr| NameServerConfig {
+                socket_addr: *addr,
+                protocol: Protocol::Tls,
+                tls_dns_name: Some(self.tls_name.clone()),
+                trust_nx_responses: true,
+                tls_config: None,
+            })
+            .collect::<Vec<_>>();
+        ResolverConfig::from_parts(None, vec![], NameServerConfigGroup::from(nameservers))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn configures_tls_nameservers() {
+        let addrs = vec![
+            SocketAddr::from(([192, 0, 2, 53], 853)),
+            SocketAddr::from(([192, 0, 2, 54], 853)),
+        ];
+        let upstream = TlsUpstream::new(addrs.clone(), "dns.example.com");
+        let config = upstream.resolver_config();
+        assert_eq!(config.name_servers().len(), 2);
+        for (ns, addr) in config.name_servers().iter().zip(addrs.iter()) {
+            assert_eq!(ns.socket_addr, *addr);
+            assert_eq!(ns.protocol, Protocol::Tls);
+            assert_eq!(ns.tls_dns_name.as_deref(), Some("dns.example.com"));
+        }
+
+        // The resolver builds without contacting the nameservers.
+        let (resolver, _task) = crate::Resolver::from_tls_upstream(&upstream, Default::default())
+            .expect("resolver must build");
+        assert_eq!(resolver.nameservers(), addrs);
+    }
+}
@@ -66,6 +66,13 @@ impl ExponentialBackoff {
         Ok(ExponentialBackoff { min, max, jitter })
     }
 
+    /// Returns the jittered duration to wait after `iterations` consecutive
+    /// backoffs.
+    pub fn duration<R: rand::Rng>(&self, iterations: u32, rng: &mut R) -> Duration {
+        let base = self.base(iterations);
+        base + self.jitter(base, rng)
+    }
+
     fn base(&self, iterations: u32) -> Duration {
         debug_assert!(
             self.min <= self.max,
@@ -117,10 +124,7 @@ impl Stream for ExponentialBackoffStream {
                 return Poll::Ready(None);
             }
 
-            let backoff = {
-                let base = this.backoff.base(*this.iterations);
-                base + this.backoff.jitter(base, &mut this.rng)
-            };
+            let backoff = this.backoff.duration(*this.iterations, &mut this.rng);
             this.delay.as_mut().set(Some(time::delay_for(backoff)));
         }
     }
@@ -0,0 +1,363 @@
+use crate::metadata::Metadata;
+use indexmap::IndexMap;
+use linkerd2_metrics::Counter;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tracing::warn;
+
+/// A predicate over endpoints' labels that determines which endpoints are
+/// used.
+///
+/// This enables client-side subset routing, e.g. to only route to endpoints
+/// with a `version=stable` label. By default, all endpoints are accepted.
+#[derive(Clone, Default)]
+pub struct LabelFilter(Option<Arc<Predicate>>);
+
+type Predicate = dyn Fn(&IndexMap<String, String>) -> bool + Send + Sync;
+
+/// Limits the number of endpoints a resolution may hold, protecting the
+/// proxy's memory from a control plane that returns an excessive number of
+/// endpoints.
+///
+/// Higher-weight endpoints are preferred: they are admitted first, and they
+/// replace lower-weight endpoints once the limit has been reached.
+#[derive(Clone, Debug)]
+pub struct EndpointLimit {
+    max: usize,
+    dropped: Arc<Counter>,
+}
+
+/// Applies a `LabelFilter` and an `EndpointLimit` to a resolution's updates.
+///
+/// The accepted addresses are tracked, with their normalized weights, so that
+/// removals of addresses that were never added are dropped.
+#[derive(Debug, Default)]
+pub(crate) struct Filtered {
+    filter: LabelFilter,
+    limit: Option<EndpointLimit>,
+    added: HashMap<SocketAddr, f64>,
+}
+
+// === impl LabelFilter ===
+
+impl LabelFilter {
+    pub fn new<F>(f: F) -> Self
+    where
+        F: Fn(&IndexMap<String, String>) -> bool + Send + Sync + 'static,
+    {
+        LabelFilter(Some(Arc::new(f)))
+    }
+
+    /// Only accepts endpoints that have all of the given labels.
+    pub fn matching_all(labels: impl IntoIterator<Item = (String, String)>) -> Self {
+        let labels = labels.into_iter().collect::<Vec<_>>();
+        Self::new(move |endpoint| {
+            labels
+                .iter()
+                .all(|(k, v)| endpoint.get(k).map(|ev| ev == v).unwrap_or(false))
+        })
+    }
+
+    pub fn accepts(&self, labels: &IndexMap<String, String>) -> bool {
+        match self.0 {
+            Some(ref f) => f(labels),
+            None => true,
+        }
+    }
+}
+
+impl fmt::Debug for LabelFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(_) => f.write_str("LabelFilter(..)"),
+            None => f.write_str("LabelFilter(AcceptAll)"),
+        }
+    }
+}
+
+// === impl EndpointLimit ===
+
+impl EndpointLimit {
+    /// Limits resolutions to `max` endpoints.
+    pub fn new(max: usize) -> Self {
+        Self {
+            max,
+            dropped: Arc::new(Counter::default()),
+        }
+    }
+
+    /// Returns the total number of endpoints that have been dropped because
+    /// they exceeded the limit.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.value()
+    }
+}
+
+// === impl Filtered ===
+
+impl Filtered {
+    pub(crate) fn new(filter: LabelFilter, limit: Option<EndpointLimit>) -> Self {
+        Self {
+            filter,
+            limit,
+            added: HashMap::new(),
+        }
+    }
+
+    /// Endpoints need only be tracked if they may be dropped.
+    fn is_passthru(&self) -> bool {
+        self.filter.0.is_none() && self.limit.is_none()
+    }
+
+    /// Drops the added endpoints that are not accepted by the filter or that
+    /// exceed the limit.
+    ///
+    /// Returns the accepted endpoints and the previously-accepted addresses
+    /// that are no longer accepted, because their labels have changed or
+    /// because they were replaced by higher-weight endpoints. The latter must
+    /// be removed.
+    pub(crate) fn add(
+        &mut self,
+        endpoints: Vec<(SocketAddr, Metadata)>,
+    ) -> (Vec<(SocketAddr, Metadata)>, Vec<SocketAddr>) {
+        if self.is_passthru() {
+            return (endpoints, Vec::new());
+        }
+
+        let mut removed = Vec::new();
+        let mut candidates = Vec::with_capacity(endpoints.len());
+        for (addr, meta) in endpoints.into_iter() {
+            if self.filter.accepts(meta.labels()) {
+                candidates.push((addr, meta));
+            } else if self.added.remove(&addr).is_some() {
+                removed.push(addr);
+            }
+        }
+
+        // Admit the highest-weight endpoints first, so that they are not
+        // dropped in favor of lower-weight endpoints. The sort is stable, so
+        // endpoints of equal weight are admitted in order.
+        let mut order = (0..candidates.len()).collect::<Vec<_>>();
+        if self.limit.is_some() {
+            order.sort_by(|&a, &b| {
+                let (wa, wb) = (&candidates[a].1, &candidates[b].1);
+                wb.normalized_weight()
+                    .partial_cmp(&wa.normalized_weight())
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            });
+        }
+
+        let mut admitted = vec![false; candidates.len()];
+        let mut dropped = 0;
+        for i in order.into_iter() {
+            let (addr, ref meta) = candidates[i];
+            let weight = meta.normalized_weight();
+            if !self.added.contains_key(&addr) {
+                if let Some(ref limit) = self.limit {
+                    if self.added.len() >= limit.max {
+                        match self.lightest_below(weight) {
+                            Some(evicted) => {
+                                self.added.remove(&evicted);
+                                removed.push(evicted);
+                            }
+                            None => {
+                                dropped += 1;
+                                continue;
+                            }
+                        }
+                        dropped += 1;
+                    }
+                }
+            }
+            self.added.insert(addr, weight);
+            admitted[i] = true;
+        }
+
+        // An endpoint may have been admitted and then replaced by a
+        // higher-weight endpoint in the same update.
+        let added = &self.added;
+        let accepted = candidates
+            .into_iter()
+            .zip(admitted)
+            .filter(|((addr, _), admitted)| *admitted && added.contains_key(addr))
+            .map(|(ep, _)| ep)
+            .collect::<Vec<_>>();
+        removed.retain(|addr| !added.contains_key(addr));
+
+        if let Some(ref limit) = self.limit {
+            if dropped > 0 {
+                warn!(%dropped, max = %limit.max, "Dropping endpoints in excess of the limit");
+                limit.dropped.add(dropped);
+            }
+        }
+
+        (accepted, removed)
+    }
+
+    /// Returns the added address with the lowest weight, if its weight is
+    /// less than `weight`.
+    fn lightest_below(&self, weight: f64) -> Option<SocketAddr> {
+        self.added
+            .iter()
+            .filter(|(_, w)| **w < weight)
+            .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
+            .map(|(addr, _)| *addr)
+    }
+
+    /// Drops the removed addresses that were never added.
+    pub(crate) fn remove(&mut self, addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+        if self.is_passthru() {
+            return addrs;
+        }
+
+        let added = &mut self.added;
+        addrs
+            .into_iter()
+            .filter(|addr| added.remove(addr).is_some())
+            .collect()
+    }
+
+    /// Forgets all added addresses, i.e. when the resolution has no endpoints.
+    pub(crate) fn clear(&mut self) {
+        self.added.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::ProtocolHint;
+    use linkerd2_tracing_test::Recorder;
+
+    fn endpoint(port: u16, version: &str) -> (SocketAddr, Metadata) {
+        weighted(port, version, 10_000)
+    }
+
+    fn weighted(port: u16, version: &str, weight: u32) -> (SocketAddr, Metadata) {
+        let mut labels = IndexMap::new();
+        labels.insert("version".to_string(), version.to_string());
+        let meta = Metadata::new(labels, ProtocolHint::Unknown, None, weight, None);
+        (([10, 0, 0, 1], port).into(), meta)
+    }
+
+    #[test]
+    fn accepts_all_by_default() {
+        let mut filtered = Filtered::default();
+        let endpoints = vec![endpoint(8080, "stable"), endpoint(8081, "canary")];
+        assert_eq!(filtered.add(endpoints.clone()), (endpoints.clone(), vec![]));
+        assert_eq!(filtered.remove(vec![endpoints[1].0]), vec![endpoints[1].0]);
+    }
+
+    #[test]
+    fn drops_unmatched_endpoints_and_their_removals() {
+        let filter = LabelFilter::matching_all(vec![("version".into(), "stable".into())]);
+        let mut filtered = Filtered::new(filter, None);
+
+        let stable = endpoint(8080, "stable");
+        let canary = endpoint(8081, "canary");
+        assert_eq!(
+            filtered.add(vec![stable.clone(), canary.clone()]),
+            (vec![stable.clone()], vec![])
+        );
+        assert_eq!(
+            filtered.remove(vec![stable.0, canary.0]),
+            vec![stable.0],
+            "removal of a dropped endpoint must be dropped"
+        );
+    }
+
+    #[test]
+    fn relabeled_endpoints() {
+        let filter = LabelFilter::matching_all(vec![("version".into(), "stable".into())]);
+        let mut filtered = Filtered::new(filter, None);
+
+        let canary = endpoint(8080, "canary");
+        assert_eq!(filtered.add(vec![canary.clone()]), (vec![], vec![]));
+
+        let stable = endpoint(8080, "stable");
+        assert_eq!(
+            filtered.add(vec![stable.clone()]),
+            (vec![stable.clone()], vec![])
+        );
+
+        // An endpoint that is no longer accepted must be removed.
+        assert_eq!(filtered.add(vec![canary.clone()]), (vec![], vec![canary.0]));
+        assert!(filtered.remove(vec![canary.0]).is_empty());
+    }
+
+    #[test]
+    fn limits_endpoints() {
+        let limit = EndpointLimit::new(2);
+        let mut filtered = Filtered::new(LabelFilter::default(), Some(limit.clone()));
+        let warnings = Recorder::new(tracing::Level::WARN);
+
+        let eps = (8080..8084)
+            .map(|port| endpoint(port, "stable"))
+            .collect::<Vec<_>>();
+        tracing::subscriber::with_default(warnings.clone(), || {
+            assert_eq!(
+                filtered.add(eps[..3].to_vec()),
+                (eps[..2].to_vec(), vec![]),
+                "an add must be truncated to the limit"
+            );
+        });
+        assert_eq!(limit.dropped(), 1);
+        assert_eq!(warnings.events().len(), 1);
+
+        // Endpoints that are already known may be updated.
+        tracing::subscriber::with_default(warnings.clone(), || {
+            assert_eq!(
+                filtered.add(vec![eps[1].clone(), eps[3].clone()]),
+                (vec![eps[1].clone()], vec![]),
+                "the limit must apply to all live endpoints"
+            );
+        });
+        assert_eq!(limit.dropped(), 2);
+        assert_eq!(warnings.events().len(), 2);
+
+        // Once an endpoint is removed, another may be added.
+        assert_eq!(filtered.remove(vec![eps[0].0]), vec![eps[0].0]);
+        assert_eq!(
+            filtered.add(vec![eps[3].clone()]),
+            (vec![eps[3].clone()], vec![])
+        );
+        assert_eq!(limit.dropped(), 2);
+    }
+
+    #[test]
+    fn limit_prefers_higher_weights() {
+        let limit = EndpointLimit::new(2);
+        let mut filtered = Filtered::new(LabelFilter::default(), Some(limit.clone()));
+
+        let zero = weighted(8080, "stable", 0);
+        let default = weighted(8081, "stable", 10_000);
+        let heavy = weighted(8082, "stable", 20_000);
+        let light = weighted(8083, "stable", 5_000);
+        assert_eq!(
+            filtered.add(vec![
+                zero.clone(),
+                default.clone(),
+                heavy.clone(),
+                light.clone()
+            ]),
+            (vec![default.clone(), heavy.clone()], vec![]),
+            "the highest-weight endpoints must be admitted, in order"
+        );
+        assert_eq!(limit.dropped(), 2);
+
+        // A heavier endpoint replaces the lightest admitted endpoint.
+        let heaviest = weighted(8084, "stable", 30_000);
+        assert_eq!(
+            filtered.add(vec![heaviest.clone()]),
+            (vec![heaviest.clone()], vec![default.0])
+        );
+        assert_eq!(limit.dropped(), 3);
+
+        // Lighter endpoints do not replace admitted endpoints.
+        assert_eq!(filtered.add(vec![light.clone()]), (vec![], vec![]));
+        assert_eq!(limit.dropped(), 4);
+        assert_eq!(filtered.remove(vec![default.0, heavy.0]), vec![heavy.0]);
+    }
+}
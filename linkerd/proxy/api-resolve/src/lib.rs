@@ -4,9 +4,18 @@ use linkerd2_identity as identity;
 use linkerd2_proxy_api as api;
 use linkerd2_proxy_core as core;
 
+mod filter;
+mod locality;
 mod metadata;
+mod metrics;
+mod overrides;
 mod pb;
 mod resolve;
 
-pub use self::metadata::{Metadata, ProtocolHint};
-pub use self::resolve::Resolve;
+pub use self::filter::{EndpointLimit, LabelFilter};
+pub use self::locality::Locality;
+pub use self::metadata::{Metadata, ProtocolHint, StableId};
+pub use self::metrics::Metrics;
+pub use self::overrides::Overrides;
+pub use self::pb::{InvalidAddr, InvalidAddrs};
+pub use self::resolve::{DestinationStatus, Resolve};
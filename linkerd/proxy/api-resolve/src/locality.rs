@@ -0,0 +1,101 @@
+use crate::metadata::Metadata;
+use indexmap::IndexMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+/// The proxy's locality, as described by labels (e.g. `zone`).
+///
+/// When configured, each resolved endpoint is tagged with whether its labels
+/// match the proxy's, and same-locality endpoints are ordered before others,
+/// so that topology-aware consumers may prefer them. By default, the proxy's
+/// locality is unknown and endpoints are not tagged.
+#[derive(Clone, Debug, Default)]
+pub struct Locality(Option<Arc<Vec<(String, String)>>>);
+
+// === impl Locality ===
+
+impl Locality {
+    /// Describes the proxy's locality by `labels`.
+    ///
+    /// An endpoint is in the same locality if it has all of the labels.
+    pub fn new(labels: impl IntoIterator<Item = (String, String)>) -> Self {
+        let labels = labels.into_iter().collect::<Vec<_>>();
+        if labels.is_empty() {
+            return Self::default();
+        }
+        Locality(Some(Arc::new(labels)))
+    }
+
+    fn contains(labels: &[(String, String)], endpoint: &IndexMap<String, String>) -> bool {
+        labels
+            .iter()
+            .all(|(k, v)| endpoint.get(k).map(|ev| ev == v).unwrap_or(false))
+    }
+
+    /// Tags each endpoint with whether it is in the proxy's locality,
+    /// ordering same-locality endpoints first.
+    pub(crate) fn tag(
+        &self,
+        endpoints: Vec<(SocketAddr, Metadata)>,
+    ) -> Vec<(SocketAddr, Metadata)> {
+        let labels = match self.0 {
+            Some(ref labels) => labels,
+            None => return endpoints,
+        };
+
+        let mut endpoints = endpoints
+            .into_iter()
+            .map(|(addr, meta)| {
+                let same = Self::contains(labels, meta.labels());
+                (addr, meta.with_same_locality(same))
+            })
+            .collect::<Vec<_>>();
+        // The sort is stable, so the endpoints are otherwise kept in order.
+        endpoints.sort_by_key(|(_, meta)| meta.same_locality() != Some(true));
+        endpoints
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::ProtocolHint;
+
+    fn endpoint(port: u16, zone: &str) -> (SocketAddr, Metadata) {
+        let mut labels = IndexMap::new();
+        labels.insert("zone".to_string(), zone.to_string());
+        let meta = Metadata::new(labels, ProtocolHint::Unknown, None, 10_000, None);
+        (SocketAddr::from(([10, 0, 0, 1], port)), meta)
+    }
+
+    #[test]
+    fn prefers_same_zone() {
+        let endpoints = vec![
+            endpoint(1, "us-west-1b"),
+            endpoint(2, "us-west-1a"),
+            endpoint(3, "us-west-1c"),
+            endpoint(4, "us-west-1a"),
+        ];
+
+        let locality = Locality::new(vec![("zone".to_string(), "us-west-1a".to_string())]);
+        let tagged = locality
+            .tag(endpoints.clone())
+            .into_iter()
+            .map(|(addr, meta)| (addr.port(), meta.same_locality()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tagged,
+            vec![
+                (2, Some(true)),
+                (4, Some(true)),
+                (1, Some(false)),
+                (3, Some(false)),
+            ]
+        );
+
+        // Without a locality, endpoints are neither tagged nor reordered.
+        let untagged = Locality::default().tag(endpoints.clone());
+        assert_eq!(untagged, endpoints);
+        assert!(untagged.iter().all(|(_, m)| m.same_locality().is_none()));
+    }
+}
@@ -1,6 +1,12 @@
 use crate::identity;
 use http::uri::Authority;
 use indexmap::IndexMap;
+#[cfg(any(test, feature = "record"))]
+use serde_json::{json, Value};
+#[cfg(any(test, feature = "record"))]
+use std::convert::TryFrom;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
 
 /// Metadata describing an endpoint.
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -28,6 +34,43 @@ pub struct Metadata {
 
     /// Used to override the the authority if needed
     authority_override: Option<Authority>,
+
+    /// The time until which the endpoint is known to be valid.
+    ///
+    /// This is set for endpoints discovered via DNS, where it reflects the
+    /// records' TTL. Endpoints discovered via the control plane remain valid
+    /// until they are removed, so this is `None` for them.
+    valid_until: Option<Instant>,
+
+    /// An opaque key that identifies the endpoint across metadata updates,
+    /// e.g. for consistent hashing.
+    affinity_key: Option<String>,
+
+    /// Whether the endpoint is in the same locality (e.g. zone) as the proxy,
+    /// if the proxy's locality is known.
+    same_locality: Option<bool>,
+
+    /// A hint from the controller limiting the number of connections the
+    /// proxy should hold to the endpoint.
+    max_connections: Option<usize>,
+
+    /// A hint from the controller for how long the proxy should retain idle
+    /// connections to the endpoint.
+    idle_timeout: Option<Duration>,
+
+    /// The endpoint's priority, e.g. from a DNS SRV record. Endpoints with
+    /// lower priorities should be preferred.
+    priority: Option<u16>,
+}
+
+/// Identifies an endpoint across updates that change only its labels or
+/// weight, e.g. so that a balancer may retain its affinity for the endpoint.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub enum StableId {
+    /// The endpoint's affinity key.
+    Key(String),
+    /// The endpoint's address, for endpoints without an affinity key.
+    Addr(SocketAddr),
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -42,13 +85,22 @@ pub enum ProtocolHint {
 // === impl Metadata ===
 
 impl Metadata {
+    /// The weight corresponding to a relative weight of 1.0.
+    pub const DEFAULT_WEIGHT: u32 = 10_000;
+
     pub fn empty() -> Self {
         Self {
             labels: IndexMap::default(),
             protocol_hint: ProtocolHint::Unknown,
             identity: None,
-            weight: 10_000,
+            weight: Self::DEFAULT_WEIGHT,
             authority_override: None,
+            valid_until: None,
+            affinity_key: None,
+            same_locality: None,
+            max_connections: None,
+            idle_timeout: None,
+            priority: None,
         }
     }
 
@@ -65,9 +117,76 @@ impl Metadata {
             identity,
             weight,
             authority_override,
+            valid_until: None,
+            affinity_key: None,
+            same_locality: None,
+            max_connections: None,
+            idle_timeout: None,
+            priority: None,
+        }
+    }
+
+    /// Returns a copy of this metadata that expires at `valid_until`.
+    pub fn with_valid_until(self, valid_until: Instant) -> Self {
+        Self {
+            valid_until: Some(valid_until),
+            ..self
+        }
+    }
+
+    /// Returns a copy of this metadata with the given affinity key.
+    pub fn with_affinity_key(self, affinity_key: String) -> Self {
+        Self {
+            affinity_key: Some(affinity_key),
+            ..self
+        }
+    }
+
+    /// Returns a copy of this metadata that indicates whether the endpoint is
+    /// in the proxy's locality.
+    pub fn with_same_locality(self, same_locality: bool) -> Self {
+        Self {
+            same_locality: Some(same_locality),
+            ..self
+        }
+    }
+
+    /// Returns a copy of this metadata that limits the number of connections
+    /// to the endpoint.
+    pub fn with_max_connections(self, max_connections: usize) -> Self {
+        Self {
+            max_connections: Some(max_connections),
+            ..self
         }
     }
 
+    /// Returns a copy of this metadata that retains idle connections to the
+    /// endpoint for `idle_timeout`.
+    pub fn with_idle_timeout(self, idle_timeout: Duration) -> Self {
+        Self {
+            idle_timeout: Some(idle_timeout),
+            ..self
+        }
+    }
+
+    /// Returns a copy of this metadata with the given priority.
+    pub fn with_priority(self, priority: u16) -> Self {
+        Self {
+            priority: Some(priority),
+            ..self
+        }
+    }
+
+    pub fn weight(&self) -> u32 {
+        self.weight
+    }
+
+    /// Returns the endpoint's weight relative to the default weight, i.e. so
+    /// that the default weight is 1.0.
+    pub fn normalized_weight(&self) -> f64 {
+        f64::from(self.weight) / f64::from(Self::DEFAULT_WEIGHT)
+    }
+
     /// Returns the endpoint's labels from the destination service, if it has them.
     pub fn labels(&self) -> &IndexMap<String, String> {
         &self.labels
@@ -77,6 +196,11 @@ impl Metadata {
         self.protocol_hint
     }
 
+    /// Returns true if the destination is known to accept HTTP/2 messages.
+    pub fn prefers_http2(&self) -> bool {
+        self.protocol_hint.is_http2()
+    }
+
     pub fn identity(&self) -> Option<&identity::Name> {
         self.identity.as_ref()
     }
@@ -84,4 +208,263 @@ impl Metadata {
     pub fn authority_override(&self) -> Option<&Authority> {
         self.authority_override.as_ref()
     }
+
+    /// Returns the time until which the endpoint is known to be valid, if it
+    /// expires.
+    pub fn valid_until(&self) -> Option<Instant> {
+        self.valid_until
+    }
+
+    /// Returns a stable key for the endpoint, if one was provided.
+    ///
+    /// Endpoints without an affinity key should be identified by their
+    /// address.
+    pub fn affinity_key(&self) -> Option<&str> {
+        self.affinity_key.as_deref()
+    }
+
+    /// Returns an identity for the endpoint at `addr` that is preserved when
+    /// only its labels or weight change.
+    pub fn stable_id(&self, addr: SocketAddr) -> StableId {
+        match self.affinity_key {
+            Some(ref key) => StableId::Key(key.clone()),
+            None => StableId::Addr(addr),
+        }
+    }
+
+    /// Returns true if the endpoint is in the same locality as the proxy.
+    ///
+    /// Returns `None` if the proxy's locality is not known, in which case
+    /// endpoints should be treated equally.
+    pub fn same_locality(&self) -> Option<bool> {
+        self.same_locality
+    }
+
+    /// Returns the maximum number of connections the controller advises
+    /// holding to the endpoint, if it provided one.
+    pub fn max_connections(&self) -> Option<usize> {
+        self.max_connections
+    }
+
+    /// Returns how long the controller advises retaining idle connections to
+    /// the endpoint, if it provided a timeout.
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout
+    }
+
+    /// Returns the endpoint's priority, if it has one. Endpoints with lower
+    /// priorities should be preferred.
+    pub fn priority(&self) -> Option<u16> {
+        self.priority
+    }
+}
+
+impl linkerd2_proxy_resolve::expire::Expires for Metadata {
+    fn valid_until(&self) -> Option<Instant> {
+        self.valid_until
+    }
+}
+
+/// Endpoints are identified by their `host` label, so that a dual-stack
+/// host's endpoints may be collapsed.
+impl linkerd2_proxy_resolve::dual_stack::HostIdentity for Metadata {
+    fn host_id(&self) -> Option<&str> {
+        self.labels.get("host").map(String::as_str)
+    }
+}
+
+impl linkerd2_proxy_resolve::conflict::TlsIdentity for Metadata {
+    fn tls_identity(&self) -> Option<&str> {
+        self.identity.as_ref().map(AsRef::as_ref)
+    }
+}
+
+/// Metadata is recorded so that resolutions may be replayed. Its expiry is not
+/// recorded, since an `Instant` is only meaningful to the process that
+/// recorded it.
+#[cfg(any(test, feature = "record"))]
+impl linkerd2_proxy_resolve::record::Endpoint for Metadata {
+    fn to_json(&self) -> Value {
+        let protocol_hint = match self.protocol_hint {
+            ProtocolHint::Unknown => "unknown",
+            ProtocolHint::Http2 => "http2",
+        };
+        let labels = self
+            .labels
+            .iter()
+            .map(|(k, v)| (k.clone(), Value::from(v.as_str())))
+            .collect::<serde_json::Map<_, _>>();
+        json!({
+            "weight": self.weight,
+            "labels": labels,
+            "protocol_hint": protocol_hint,
+            "identity": self.identity.as_ref().map(AsRef::<str>::as_ref),
+            "authority_override": self.authority_override.as_ref().map(Authority::as_str),
+            "affinity_key": self.affinity_key,
+            "same_locality": self.same_locality,
+            "max_connections": self.max_connections,
+            "idle_timeout_ms": self.idle_timeout.map(|t| t.as_millis() as u64),
+            "priority": self.priority,
+        })
+    }
+
+    fn from_json(value: &Value) -> Option<Self> {
+        /// Parses an optional field, returning `None` if it is present but
+        /// invalid.
+        fn optional<T>(
+            value: &Value,
+            key: &str,
+            parse: impl FnOnce(&Value) -> Option<T>,
+        ) -> Option<Option<T>> {
+            match value.get(key) {
+                None | Some(Value::Null) => Some(None),
+                Some(v) => parse(v).map(Some),
+            }
+        }
+
+        let labels = value
+            .get("labels")?
+            .as_object()?
+            .iter()
+            .map(|(k, v)| Some((k.clone(), v.as_str()?.to_string())))
+            .collect::<Option<_>>()?;
+        let protocol_hint = match value.get("protocol_hint")?.as_str()? {
+            "unknown" => ProtocolHint::Unknown,
+            "http2" => ProtocolHint::Http2,
+            _ => return None,
+        };
+        Some(Self {
+            weight: u32::try_from(value.get("weight")?.as_u64()?).ok()?,
+            labels,
+            protocol_hint,
+            identity: optional(value, "identity", |v| {
+                identity::Name::from_hostname(v.as_str()?.as_bytes()).ok()
+            })?,
+            authority_override: optional(value, "authority_override", |v| {
+                v.as_str()?.parse().ok()
+            })?,
+            valid_until: None,
+            affinity_key: optional(value, "affinity_key", |v| Some(v.as_str()?.to_string()))?,
+            same_locality: optional(value, "same_locality", Value::as_bool)?,
+            max_connections: optional(value, "max_connections", |v| {
+                usize::try_from(v.as_u64()?).ok()
+            })?,
+            idle_timeout: optional(value, "idle_timeout_ms", |v| {
+                v.as_u64().map(Duration::from_millis)
+            })?,
+            priority: optional(value, "priority", |v| u16::try_from(v.as_u64()?).ok())?,
+        })
+    }
+}
+
+// === impl ProtocolHint ===
+
+impl ProtocolHint {
+    /// Returns true if the hint does not describe the destination's protocol.
+    pub fn is_unknown(&self) -> bool {
+        *self == ProtocolHint::Unknown
+    }
+
+    /// Returns true if the destination can receive HTTP2 messages.
+    pub fn is_http2(&self) -> bool {
+        *self == ProtocolHint::Http2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata(hint: ProtocolHint) -> Metadata {
+        Metadata::new(IndexMap::default(), hint, None, 10_000, None)
+    }
+
+    #[test]
+    fn unknown_hint() {
+        let meta = metadata(ProtocolHint::Unknown);
+        assert_eq!(meta.protocol_hint(), ProtocolHint::Unknown);
+        assert!(meta.protocol_hint().is_unknown());
+        assert!(!meta.protocol_hint().is_http2());
+        assert!(!meta.prefers_http2());
+        assert!(!Metadata::empty().prefers_http2());
+    }
+
+    #[test]
+    fn normalized_weight() {
+        let weight = |w| Metadata::new(IndexMap::default(), ProtocolHint::Unknown, None, w, None);
+        assert_eq!(Metadata::empty().normalized_weight(), 1.0);
+        assert_eq!(weight(0).normalized_weight(), 0.0);
+        assert_eq!(weight(1).normalized_weight(), 0.0001);
+        assert_eq!(weight(25_000).normalized_weight(), 2.5);
+    }
+
+    #[test]
+    fn http2_hint() {
+        let meta = metadata(ProtocolHint::Http2);
+        assert_eq!(meta.protocol_hint(), ProtocolHint::Http2);
+        assert!(!meta.protocol_hint().is_unknown());
+        assert!(meta.protocol_hint().is_http2());
+        assert!(meta.prefers_http2());
+    }
+
+    #[test]
+    fn stable_id_ignores_labels_and_weight() {
+        let addr = ([10, 1, 1, 1], 8080).into();
+        let before = metadata(ProtocolHint::Unknown).with_affinity_key("pod-0".into());
+
+        let mut labels = IndexMap::default();
+        labels.insert("zone".to_string(), "west".to_string());
+        let after = Metadata::new(labels, ProtocolHint::Unknown, None, 25_000, None)
+            .with_affinity_key("pod-0".into());
+        assert_ne!(before, after);
+        assert_eq!(before.stable_id(addr), after.stable_id(addr));
+        assert_eq!(before.stable_id(addr), StableId::Key("pod-0".into()));
+
+        // Endpoints without an affinity key are identified by their address.
+        assert_eq!(
+            Metadata::empty().stable_id(addr),
+            metadata(ProtocolHint::Http2).stable_id(addr)
+        );
+        assert_eq!(Metadata::empty().stable_id(addr), StableId::Addr(addr));
+    }
+
+    #[test]
+    fn record_round_trip() {
+        use linkerd2_proxy_resolve::record::Endpoint;
+
+        let mut labels = IndexMap::default();
+        labels.insert("zone".to_string(), "west".to_string());
+        let identity =
+            identity::Name::from_hostname(b"web.ns.serviceaccount.identity.linkerd.cluster.local")
+                .unwrap();
+        let meta = Metadata::new(
+            labels,
+            ProtocolHint::Http2,
+            Some(identity),
+            25_000,
+            Some(Authority::from_static("web.ns.svc.cluster.local:8080")),
+        )
+        .with_affinity_key("pod-0".into())
+        .with_same_locality(true)
+        .with_max_connections(100)
+        .with_idle_timeout(Duration::from_secs(10))
+        .with_priority(1);
+        assert_eq!(Metadata::from_json(&meta.to_json()), Some(meta));
+        assert_eq!(
+            Metadata::from_json(&Metadata::empty().to_json()),
+            Some(Metadata::empty())
+        );
+
+        // The expiry is not recorded.
+        let expiring = Metadata::empty().with_valid_until(Instant::now());
+        assert_eq!(
+            Metadata::from_json(&expiring.to_json()),
+            Some(Metadata::empty())
+        );
+
+        assert_eq!(Metadata::from_json(&Value::Null), None);
+        let mut invalid = Metadata::empty().to_json();
+        invalid["priority"] = json!(-1);
+        assert_eq!(Metadata::from_json(&invalid), None);
+    }
 }
@@ -0,0 +1,166 @@
+use linkerd2_metrics::{metrics, Counter, FmtLabels, FmtMetrics, Gauge};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+metrics! {
+    destination_streams_stale: Gauge {
+        "Number of Destination response streams that have not produced an update within the configured interval"
+    },
+    destination_stream_ends_total: Counter {
+        "Total number of Destination response streams that ended without an error, prompting a reconnect"
+    }
+}
+
+/// Tracks the Destination service's response streams.
+///
+/// An authority's stream ends are dropped once they have been reported after
+/// all of its resolutions have completed, so that the number of authorities
+/// that are tracked is bounded by the number of active resolutions.
+#[derive(Clone, Debug, Default)]
+pub struct Metrics {
+    stale: Arc<Gauge>,
+    stream_ends: Arc<Mutex<HashMap<Authority, StreamEnds>>>,
+}
+
+/// Counts the graceful ends of a single authority's response streams.
+///
+/// A default `StreamEnds` is not registered, so its count is not reported.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct StreamEnds(Arc<Counter>);
+
+/// Counts a response stream as stale for as long as it is held.
+#[derive(Debug)]
+pub(crate) struct Stale(Arc<Gauge>);
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct Authority(String);
+
+// === impl Metrics ===
+
+impl Metrics {
+    pub(crate) fn stale(&self) -> Stale {
+        self.stale.incr();
+        Stale(self.stale.clone())
+    }
+
+    /// Returns the number of response streams that are currently stale.
+    pub fn stale_streams(&self) -> u64 {
+        self.stale.value()
+    }
+
+    /// Returns the counter of `authority`'s graceful stream ends,
+    /// registering it if necessary.
+    pub(crate) fn authority(&self, authority: &str) -> StreamEnds {
+        let mut stream_ends = self.stream_ends.lock().unwrap();
+        stream_ends
+            .entry(Authority(authority.to_string()))
+            .or_default()
+            .clone()
+    }
+
+    /// Returns the number of `authority`'s response streams that have ended
+    /// gracefully.
+    pub fn stream_ends(&self, authority: &str) -> u64 {
+        let stream_ends = self.stream_ends.lock().unwrap();
+        stream_ends
+            .get(&Authority(authority.to_string()))
+            .map(|ends| ends.0.value())
+            .unwrap_or(0)
+    }
+}
+
+impl FmtMetrics for Metrics {
+    fn fmt_metrics(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        destination_streams_stale.fmt_help(f)?;
+        destination_streams_stale.fmt_metric(f, &self.stale)?;
+
+        let mut stream_ends = self.stream_ends.lock().unwrap();
+        if stream_ends.is_empty() {
+            return Ok(());
+        }
+
+        destination_stream_ends_total.fmt_help(f)?;
+        destination_stream_ends_total.fmt_scopes(f, stream_ends.iter(), |ends| &ends.0)?;
+
+        // Authorities that are no longer resolved have now reported their
+        // final counts.
+        stream_ends.retain(|_, ends| ends.is_active());
+
+        Ok(())
+    }
+}
+
+// === impl StreamEnds ===
+
+impl StreamEnds {
+    /// Records that a response stream ended gracefully.
+    ///
+    /// Streams that fail are not counted.
+    pub(crate) fn incr(&self) {
+        self.0.incr();
+    }
+
+    /// Returns true if the counter is shared with a resolution, i.e. other
+    /// than by the `Metrics` that registered it.
+    fn is_active(&self) -> bool {
+        Arc::strong_count(&self.0) > 1
+    }
+}
+
+// === impl Stale ===
+
+impl Drop for Stale {
+    fn drop(&mut self) {
+        self.0.decr();
+    }
+}
+
+// === impl Authority ===
+
+impl FmtLabels for Authority {
+    fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "authority=\"{}\"", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evicts_authorities_without_resolutions() {
+        let metrics = Metrics::default();
+        let web = metrics.authority("web.example.com:8080");
+        metrics.authority("db.example.com:5432").incr();
+
+        // An authority's final count is reported once its resolutions have
+        // completed.
+        let text = metrics.as_display().to_string();
+        assert!(
+            text.contains("destination_stream_ends_total{authority=\"db.example.com:5432\"} 1"),
+            "{:?}",
+            text
+        );
+        let text = metrics.as_display().to_string();
+        assert!(!text.contains("db.example.com:5432"), "{:?}", text);
+
+        // Authorities with resolutions are retained.
+        web.incr();
+        let text = metrics.as_display().to_string();
+        assert!(
+            text.contains("destination_stream_ends_total{authority=\"web.example.com:8080\"} 1"),
+            "{:?}",
+            text
+        );
+        drop(web);
+        let text = metrics.as_display().to_string();
+        assert!(
+            text.contains("destination_stream_ends_total{authority=\"web.example.com:8080\"} 1"),
+            "{:?}",
+            text
+        );
+        let text = metrics.as_display().to_string();
+        assert!(!text.contains("web.example.com:8080"), "{:?}", text);
+    }
+}
@@ -0,0 +1,49 @@
+use crate::metadata::Metadata;
+use linkerd2_addr::NameAddr;
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+};
+
+/// Statically-configured endpoints that are resolved in place of querying
+/// the Destination service, by authority.
+///
+/// Overrides may be changed at runtime and are shared by all clones. Changes
+/// only apply to resolutions that are started after the change.
+#[derive(Clone, Debug, Default)]
+pub struct Overrides(Arc<RwLock<HashMap<NameAddr, Endpoints>>>);
+
+type Endpoints = Vec<(SocketAddr, Metadata)>;
+
+// === impl Overrides ===
+
+impl Overrides {
+    /// Resolves `addr` to `endpoints`.
+    pub fn set(&self, addr: NameAddr, endpoints: Vec<(SocketAddr, Metadata)>) {
+        self.0
+            .write()
+            .expect("overrides lock")
+            .insert(addr, endpoints);
+    }
+
+    /// Resolves `addr` via the Destination service.
+    pub fn remove(&self, addr: &NameAddr) {
+        self.0.write().expect("overrides lock").remove(addr);
+    }
+
+    /// Replaces all overrides.
+    pub fn replace(&self, overrides: HashMap<NameAddr, Vec<(SocketAddr, Metadata)>>) {
+        *self.0.write().expect("overrides lock") = overrides;
+    }
+
+    /// Returns the endpoints that `path` is resolved to, if it is overridden.
+    pub(crate) fn get(&self, path: &str) -> Option<Vec<(SocketAddr, Metadata)>> {
+        let overrides = self.0.read().expect("overrides lock");
+        if overrides.is_empty() {
+            return None;
+        }
+        let addr = NameAddr::from_str(path).ok()?;
+        overrides.get(&addr).cloned()
+    }
+}
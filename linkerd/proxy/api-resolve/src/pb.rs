@@ -6,15 +6,57 @@ use crate::identity;
 use crate::metadata::{Metadata, ProtocolHint};
 use http::uri::Authority;
 use indexmap::IndexMap;
-use std::{collections::HashMap, net::SocketAddr};
+use linkerd2_metrics::Counter;
+use std::{
+    collections::HashMap, fmt, net::SocketAddr, num::NonZeroUsize, sync::Arc, time::Duration,
+};
+
+/// Describes why an address in a Destination response is invalid.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum InvalidAddr {
+    MissingIp,
+    MissingIpFamily,
+    ZeroPort,
+    PortOutOfRange,
+}
+
+/// Counts the invalid addresses that have been dropped from Destination
+/// responses, by reason.
+#[derive(Clone, Debug, Default)]
+pub struct InvalidAddrs(Arc<InvalidAddrCounts>);
+
+#[derive(Debug, Default)]
+struct InvalidAddrCounts {
+    missing_ip: Counter,
+    missing_ip_family: Counter,
+    zero_port: Counter,
+    port_out_of_range: Counter,
+}
+
+/// The endpoint label that holds an endpoint's affinity key.
+const AFFINITY_LABEL: &str = "affinity";
+
+/// The endpoint label that advises a limit on connections to the endpoint.
+const MAX_CONNS_LABEL: &str = "max-conns";
+
+/// The endpoint label that advises an idle timeout, in milliseconds, for
+/// connections to the endpoint.
+const IDLE_TIMEOUT_LABEL: &str = "idle-timeout-ms";
 
 /// Construct a new labeled `SocketAddr `from a protobuf `WeightedAddr`.
 pub(in crate) fn to_addr_meta(
     pb: WeightedAddr,
     set_labels: &HashMap<String, String>,
+    invalid: &InvalidAddrs,
 ) -> Option<(SocketAddr, Metadata)> {
     let authority_override = pb.authority_override.and_then(to_authority);
-    let addr = pb.addr.and_then(to_sock_addr)?;
+    let addr = match pb.addr {
+        Some(addr) => to_sock_addr(addr, invalid)?,
+        None => {
+            invalid.record(InvalidAddr::MissingIp);
+            return None;
+        }
+    };
 
     let meta = {
         let mut t = set_labels
@@ -42,11 +84,41 @@ pub(in crate) fn to_addr_meta(
         }
     }
 
+    let affinity_key = meta.get(AFFINITY_LABEL).cloned();
+    let max_conns = meta
+        .get(MAX_CONNS_LABEL)
+        .and_then(|v| parse_hint(MAX_CONNS_LABEL, v))
+        .map(NonZeroUsize::get);
+    let idle_timeout = meta
+        .get(IDLE_TIMEOUT_LABEL)
+        .and_then(|v| parse_hint(IDLE_TIMEOUT_LABEL, v))
+        .map(Duration::from_millis);
     let tls_id = pb.tls_identity.and_then(to_id);
-    let meta = Metadata::new(meta, proto_hint, tls_id, pb.weight, authority_override);
+    let mut meta = Metadata::new(meta, proto_hint, tls_id, pb.weight, authority_override);
+    if let Some(key) = affinity_key {
+        meta = meta.with_affinity_key(key);
+    }
+    if let Some(max) = max_conns {
+        meta = meta.with_max_connections(max);
+    }
+    if let Some(timeout) = idle_timeout {
+        meta = meta.with_idle_timeout(timeout);
+    }
     Some((addr, meta))
 }
 
+/// Parses a connection pool hint from an endpoint label, ignoring it if it is
+/// malformed.
+fn parse_hint<T: std::str::FromStr>(label: &str, value: &str) -> Option<T> {
+    match value.parse() {
+        Ok(v) => Some(v),
+        Err(_) => {
+            tracing::warn!(%label, %value, "Ignoring malformed connection pool hint");
+            None
+        }
+    }
+}
+
 fn to_id(pb: TlsIdentity) -> Option<identity::Name> {
     use crate::api::destination::tls_identity::Strategy;
 
@@ -73,8 +145,21 @@ pub(in crate) fn to_authority(o: AuthorityOverride) -> Option<Authority> {
     }
 }
 
-pub(in crate) fn to_sock_addr(pb: TcpAddress) -> Option<SocketAddr> {
+/// Converts a protobuf address, recording why it was dropped if it is
+/// invalid.
+pub(in crate) fn to_sock_addr(pb: TcpAddress, invalid: &InvalidAddrs) -> Option<SocketAddr> {
+    match try_to_sock_addr(pb) {
+        Ok(addr) => Some(addr),
+        Err(reason) => {
+            invalid.record(reason);
+            None
+        }
+    }
+}
+
+fn try_to_sock_addr(pb: TcpAddress) -> Result<SocketAddr, InvalidAddr> {
     use crate::api::net::ip_address::Ip;
+    use std::convert::TryFrom;
     use std::net::{Ipv4Addr, Ipv6Addr};
     /*
     current structure is:
@@ -91,11 +176,16 @@ pub(in crate) fn to_sock_addr(pb: TcpAddress) -> Option<SocketAddr> {
         port: u32,
     }
     */
+    let port = match u16::try_from(pb.port) {
+        Ok(0) => return Err(InvalidAddr::ZeroPort),
+        Ok(port) => port,
+        Err(_) => return Err(InvalidAddr::PortOutOfRange),
+    };
     match pb.ip {
         Some(ip) => match ip.ip {
             Some(Ip::Ipv4(octets)) => {
                 let ipv4 = Ipv4Addr::from(octets);
-                Some(SocketAddr::from((ipv4, pb.port as u16)))
+                Ok(SocketAddr::from((ipv4, port)))
             }
             Some(Ip::Ipv6(v6)) => {
                 let octets = [
@@ -117,10 +207,167 @@ pub(in crate) fn to_sock_addr(pb: TcpAddress) -> Option<SocketAddr> {
                     v6.last as u8,
                 ];
                 let ipv6 = Ipv6Addr::from(octets);
-                Some(SocketAddr::from((ipv6, pb.port as u16)))
+                Ok(SocketAddr::from((ipv6, port)))
             }
-            None => None,
+            None => Err(InvalidAddr::MissingIpFamily),
         },
-        None => None,
+        None => Err(InvalidAddr::MissingIp),
+    }
+}
+
+// === impl InvalidAddr ===
+
+impl fmt::Display for InvalidAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvalidAddr::MissingIp => f.pad("missing IP address"),
+            InvalidAddr::MissingIpFamily => f.pad("missing IP address family"),
+            InvalidAddr::ZeroPort => f.pad("zero port"),
+            InvalidAddr::PortOutOfRange => f.pad("port out of range"),
+        }
+    }
+}
+
+// === impl InvalidAddrs ===
+
+impl InvalidAddrs {
+    fn record(&self, reason: InvalidAddr) {
+        tracing::warn!(%reason, "Dropping invalid address");
+        self.counter(reason).incr();
+    }
+
+    /// Returns the number of addresses that have been dropped for `reason`.
+    pub fn dropped(&self, reason: InvalidAddr) -> u64 {
+        self.counter(reason).value()
+    }
+
+    fn counter(&self, reason: InvalidAddr) -> &Counter {
+        match reason {
+            InvalidAddr::MissingIp => &self.0.missing_ip,
+            InvalidAddr::MissingIpFamily => &self.0.missing_ip_family,
+            InvalidAddr::ZeroPort => &self.0.zero_port,
+            InvalidAddr::PortOutOfRange => &self.0.port_out_of_range,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::net::{ip_address::Ip, IpAddress};
+
+    fn tcp(ip: Option<Ip>, port: u32) -> TcpAddress {
+        TcpAddress {
+            ip: Some(IpAddress { ip }),
+            port,
+        }
+    }
+
+    #[test]
+    fn valid_addr() {
+        let invalid = InvalidAddrs::default();
+        let addr = to_sock_addr(tcp(Some(Ip::Ipv4(0x0a00_0001)), 8080), &invalid);
+        assert_eq!(addr, Some(SocketAddr::from(([10, 0, 0, 1], 8080))));
+        for reason in &[
+            InvalidAddr::MissingIp,
+            InvalidAddr::MissingIpFamily,
+            InvalidAddr::ZeroPort,
+            InvalidAddr::PortOutOfRange,
+        ] {
+            assert_eq!(invalid.dropped(*reason), 0);
+        }
+    }
+
+    #[test]
+    fn invalid_addrs() {
+        let cases = vec![
+            (
+                TcpAddress {
+                    ip: None,
+                    port: 8080,
+                },
+                InvalidAddr::MissingIp,
+            ),
+            (tcp(None, 8080), InvalidAddr::MissingIpFamily),
+            (tcp(Some(Ip::Ipv4(0x0a00_0001)), 0), InvalidAddr::ZeroPort),
+            (
+                tcp(Some(Ip::Ipv4(0x0a00_0001)), 65_536),
+                InvalidAddr::PortOutOfRange,
+            ),
+            (
+                tcp(Some(Ip::Ipv4(0x0a00_0001)), 0x1_1f90),
+                InvalidAddr::PortOutOfRange,
+            ),
+        ];
+        for (pb, reason) in cases.into_iter() {
+            let invalid = InvalidAddrs::default();
+            assert_eq!(to_sock_addr(pb, &invalid), None);
+            assert_eq!(invalid.dropped(reason), 1, "{} must be recorded", reason);
+        }
+    }
+
+    #[test]
+    fn weighted_addr_without_addr() {
+        let invalid = InvalidAddrs::default();
+        let pb = WeightedAddr::default();
+        assert!(to_addr_meta(pb, &HashMap::new(), &invalid).is_none());
+        assert_eq!(invalid.dropped(InvalidAddr::MissingIp), 1);
+    }
+
+    #[test]
+    fn affinity_label() {
+        let invalid = InvalidAddrs::default();
+        let pb = |labels: HashMap<String, String>| WeightedAddr {
+            addr: Some(tcp(Some(Ip::Ipv4(0x0a00_0001)), 8080)),
+            metric_labels: labels,
+            ..WeightedAddr::default()
+        };
+
+        let mut labels = HashMap::new();
+        labels.insert("affinity".to_string(), "pod-0".to_string());
+        let (_, meta) = to_addr_meta(pb(labels), &HashMap::new(), &invalid).unwrap();
+        assert_eq!(meta.affinity_key(), Some("pod-0"));
+
+        let (_, meta) = to_addr_meta(pb(HashMap::new()), &HashMap::new(), &invalid).unwrap();
+        assert_eq!(meta.affinity_key(), None);
+    }
+
+    #[test]
+    fn pool_hint_labels() {
+        let invalid = InvalidAddrs::default();
+        let meta = |max_conns: &str, idle_timeout: &str| {
+            let mut labels = HashMap::new();
+            labels.insert(MAX_CONNS_LABEL.to_string(), max_conns.to_string());
+            labels.insert(IDLE_TIMEOUT_LABEL.to_string(), idle_timeout.to_string());
+            let pb = WeightedAddr {
+                addr: Some(tcp(Some(Ip::Ipv4(0x0a00_0001)), 8080)),
+                metric_labels: labels,
+                ..WeightedAddr::default()
+            };
+            to_addr_meta(pb, &HashMap::new(), &invalid).unwrap().1
+        };
+
+        let valid = meta("100", "30000");
+        assert_eq!(valid.max_connections(), Some(100));
+        assert_eq!(valid.idle_timeout(), Some(Duration::from_secs(30)));
+
+        for (max_conns, idle_timeout) in &[("-1", "1.5"), ("0", "30s"), ("many", "")] {
+            let malformed = meta(max_conns, idle_timeout);
+            assert_eq!(malformed.max_connections(), None, "{}", max_conns);
+            assert_eq!(malformed.idle_timeout(), None, "{}", idle_timeout);
+            // The labels are retained regardless.
+            assert_eq!(
+                malformed.labels().get(MAX_CONNS_LABEL).map(String::as_str),
+                Some(*max_conns)
+            );
+        }
+
+        let pb = WeightedAddr {
+            addr: Some(tcp(Some(Ip::Ipv4(0x0a00_0001)), 8080)),
+            ..WeightedAddr::default()
+        };
+        let (_, unhinted) = to_addr_meta(pb, &HashMap::new(), &invalid).unwrap();
+        assert_eq!(unhinted.max_connections(), None);
+        assert_eq!(unhinted.idle_timeout(), None);
     }
 }
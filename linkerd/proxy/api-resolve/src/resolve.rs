@@ -1,34 +1,87 @@
 use crate::api::destination as api;
-use crate::core::resolve::{self, Update};
+use crate::core::resolve::{self, DiscoverySource, ErrorKind, ResolveError, StatusDetail, Update};
+use crate::filter::{EndpointLimit, Filtered, LabelFilter};
+use crate::locality::Locality;
 use crate::metadata::Metadata;
-use crate::pb;
+use crate::metrics::{Metrics, Stale, StreamEnds};
+use crate::overrides::Overrides;
+use crate::pb::{self, InvalidAddrs};
 use api::destination_client::DestinationClient;
-use futures::{ready, Stream};
+use futures::{future, ready, FutureExt, Stream};
 use http_body::Body as HttpBody;
 use pin_project::pin_project;
 use std::error::Error;
 use std::future::Future;
 use std::pin::Pin;
 use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::time;
 use tonic::{
     self as grpc,
     body::{Body, BoxBody},
     client::GrpcService,
 };
 use tower::Service;
-use tracing::{debug, info, trace};
+use tracing::{debug, info, trace, warn};
 
 #[derive(Clone)]
 pub struct Resolve<S> {
     service: DestinationClient<S>,
     scheme: String,
     context_token: String,
+    label_filter: LabelFilter,
+    endpoint_limit: Option<EndpointLimit>,
+    invalid_addrs: InvalidAddrs,
+    overrides: Overrides,
+    metrics: Metrics,
+    locality: Locality,
+    stale_after: Option<Duration>,
 }
 
 #[pin_project]
-pub struct Resolution {
+pub struct Resolution<S = grpc::Streaming<api::Update>> {
+    /// The Destination service's response stream, unless the resolution's
+    /// endpoints are overridden.
     #[pin]
-    inner: grpc::Streaming<api::Update>,
+    inner: Option<S>,
+    /// The resolved authority, for metrics.
+    path: String,
+    filtered: Filtered,
+    invalid_addrs: InvalidAddrs,
+    metrics: Metrics,
+    stream_ends: StreamEnds,
+    locality: Locality,
+    liveness: Option<Liveness>,
+    /// An update that has been produced but not yet returned.
+    pending: Option<Update<Metadata>>,
+    status: DestinationStatus,
+}
+
+/// Describes what the Destination service has reported about a resolution's
+/// destination.
+///
+/// This distinguishes a destination that exists but has no endpoints from one
+/// that the control plane does not manage, so that callers may, e.g., fail
+/// fast in the former case and fall back to another resolver in the latter.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DestinationStatus {
+    /// No updates have been received.
+    Unknown,
+    /// The destination exists and endpoints have been added.
+    Exists,
+    /// The destination exists, but has no endpoints.
+    NoEndpoints,
+    /// The destination is not known to the Destination service, or its name
+    /// was rejected with an `InvalidArgument` status.
+    NotFound,
+}
+
+/// Detects a response stream that has stopped producing updates.
+struct Liveness {
+    interval: Duration,
+    timeout: time::Delay,
+    /// Set while the stream is stale.
+    stale: Option<Stale>,
 }
 
 // === impl Resolver ===
@@ -47,9 +100,20 @@ where
             service: DestinationClient::new(svc),
             scheme: "".into(),
             context_token: "".into(),
+            label_filter: LabelFilter::default(),
+            endpoint_limit: None,
+            invalid_addrs: InvalidAddrs::default(),
+            overrides: Overrides::default(),
+            metrics: Metrics::default(),
+            locality: Locality::default(),
+            stale_after: None,
         }
     }
 
+    /// Sets the scheme that is sent to the Destination service with each
+    /// request, for control planes that expect one.
+    ///
+    /// By default, no scheme is sent.
     pub fn with_scheme<T: ToString>(self, scheme: T) -> Self {
         Self {
             scheme: scheme.to_string(),
@@ -63,6 +127,74 @@ where
             ..self
         }
     }
+
+    /// Only resolves endpoints whose labels are accepted by `label_filter`.
+    pub fn with_label_filter(self, label_filter: LabelFilter) -> Self {
+        Self {
+            label_filter,
+            ..self
+        }
+    }
+
+    /// Limits the number of endpoints each resolution may hold.
+    ///
+    /// Endpoints in excess of the limit are dropped.
+    pub fn with_endpoint_limit(self, limit: EndpointLimit) -> Self {
+        Self {
+            endpoint_limit: Some(limit),
+            ..self
+        }
+    }
+
+    /// Records the invalid addresses that are dropped from responses in
+    /// `invalid_addrs`.
+    pub fn with_invalid_addrs(self, invalid_addrs: InvalidAddrs) -> Self {
+        Self {
+            invalid_addrs,
+            ..self
+        }
+    }
+
+    /// Resolves authorities that are overridden in `overrides` to their
+    /// static endpoints, without querying the Destination service.
+    ///
+    /// Overridden endpoints are filtered, limited, and tagged with their
+    /// locality like the Destination service's endpoints.
+    pub fn with_overrides(self, overrides: Overrides) -> Self {
+        Self { overrides, ..self }
+    }
+
+    /// Records metrics about the Destination service's response streams in
+    /// `metrics`.
+    pub fn with_metrics(self, metrics: Metrics) -> Self {
+        Self { metrics, ..self }
+    }
+
+    /// Tags resolved endpoints with whether they are in the proxy's
+    /// `locality`, ordering same-locality endpoints first.
+    pub fn with_locality(self, locality: Locality) -> Self {
+        Self { locality, ..self }
+    }
+
+    /// Marks each resolution's response stream as stale in `metrics` while
+    /// it has not produced an update for `interval`.
+    ///
+    /// This only signals that the stream may be stuck; its endpoints are not
+    /// changed. By default, streams are not checked for staleness.
+    pub fn with_stale_after(self, interval: Duration) -> Self {
+        Self {
+            stale_after: Some(interval),
+            ..self
+        }
+    }
+
+    fn get_destination(&self, path: String) -> api::GetDestination {
+        api::GetDestination {
+            path,
+            scheme: self.scheme.clone(),
+            context_token: self.context_token.clone(),
+        }
+    }
 }
 
 impl<T, S> Service<T> for Resolve<S>
@@ -76,7 +208,7 @@ where
     S::Future: Send,
 {
     type Response = Resolution;
-    type Error = grpc::Status;
+    type Error = ResolveError;
     type Future =
         Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
 
@@ -87,34 +219,99 @@ where
 
     fn call(&mut self, target: T) -> Self::Future {
         let path = target.to_string();
+        let mut filtered = Filtered::new(self.label_filter.clone(), self.endpoint_limit.clone());
+        if let Some(endpoints) = self.overrides.get(&path) {
+            debug!(dst = %path, endpoints = %endpoints.len(), "Overridden");
+            // No endpoints have been added, so none are replaced.
+            let (endpoints, _) = filtered.add(self.locality.tag(endpoints));
+            let update = if endpoints.is_empty() {
+                Update::Empty
+            } else {
+                Update::Add(endpoints)
+            };
+            return Box::pin(future::ok(Resolution {
+                inner: None,
+                path,
+                filtered,
+                invalid_addrs: self.invalid_addrs.clone(),
+                metrics: self.metrics.clone(),
+                // Overridden resolutions have no response stream.
+                stream_ends: StreamEnds::default(),
+                locality: self.locality.clone(),
+                liveness: None,
+                pending: Some(update),
+                status: DestinationStatus::Unknown,
+            }));
+        }
+
         debug!(dst = %path, context = %self.context_token, "Resolving");
         let mut svc = self.service.clone();
-        let req = api::GetDestination {
-            path,
-            scheme: self.scheme.clone(),
-            context_token: self.context_token.clone(),
-        };
+        let invalid_addrs = self.invalid_addrs.clone();
+        let metrics = self.metrics.clone();
+        let stream_ends = self.metrics.authority(&path);
+        let locality = self.locality.clone();
+        let stale_after = self.stale_after;
+        let req = self.get_destination(path.clone());
         Box::pin(async move {
-            let rsp = svc.get(grpc::Request::new(req)).await?;
+            let rsp = svc
+                .get(grpc::Request::new(req))
+                .await
+                .map_err(to_resolve_error)?;
             trace!(metadata = ?rsp.metadata());
             Ok(Resolution {
-                inner: rsp.into_inner(),
+                inner: Some(rsp.into_inner()),
+                path,
+                filtered,
+                invalid_addrs,
+                metrics,
+                stream_ends,
+                locality,
+                liveness: stale_after.map(Liveness::new),
+                pending: None,
+                status: DestinationStatus::Unknown,
             })
         })
     }
 }
 
-impl resolve::Resolution for Resolution {
-    type Endpoint = Metadata;
-    type Error = grpc::Status;
+impl<S> Resolution<S>
+where
+    S: Stream<Item = Result<api::Update, grpc::Status>>,
+{
+    /// Returns what the Destination service has reported about this
+    /// resolution's destination.
+    pub fn status(&self) -> DestinationStatus {
+        self.status
+    }
 
-    fn poll(
+    fn poll_update(
         self: Pin<&mut Self>,
         cx: &mut Context<'_>,
-    ) -> Poll<Result<Update<Self::Endpoint>, Self::Error>> {
+    ) -> Poll<Result<Update<Metadata>, grpc::Status>> {
         let mut this = self.project();
+        if let Some(update) = this.pending.take() {
+            return Poll::Ready(Ok(update));
+        }
+
         loop {
-            match ready!(this.inner.as_mut().poll_next(cx)) {
+            // Overridden resolutions produce no further updates.
+            let inner = match this.inner.as_mut().as_pin_mut() {
+                Some(inner) => inner,
+                None => return Poll::Pending,
+            };
+            let next = match inner.poll_next(cx) {
+                Poll::Ready(next) => next,
+                Poll::Pending => {
+                    if let Some(liveness) = this.liveness.as_mut() {
+                        liveness.poll_stale(cx, this.path, this.metrics);
+                    }
+                    return Poll::Pending;
+                }
+            };
+            if let Some(liveness) = this.liveness.as_mut() {
+                liveness.observe(this.path);
+            }
+            match next {
                 Some(update) => match update?.update {
                     Some(api::update::Update::Add(api::WeightedAddrSet {
                         addrs,
@@ -122,8 +319,20 @@ impl resolve::Resolution for Resolution {
                     })) => {
                         let addr_metas = addrs
                             .into_iter()
-                            .filter_map(|addr| pb::to_addr_meta(addr, &metric_labels))
+                            .filter_map(|addr| {
+                                pb::to_addr_meta(addr, &metric_labels, this.invalid_addrs)
+                            })
                             .collect::<Vec<_>>();
+                        let addr_metas = this.locality.tag(addr_metas);
+                        let (addr_metas, removed) = this.filtered.add(addr_metas);
+                        if !removed.is_empty() {
+                            debug!(endpoints = %removed.len(), "Remove");
+                            if !addr_metas.is_empty() {
+                                debug!(endpoints = %addr_metas.len(), "Add");
+                                *this.pending = Some(Update::Add(addr_metas));
+                            }
+                            return Poll::Ready(Ok(Update::Remove(removed)));
+                        }
                         if !addr_metas.is_empty() {
                             debug!(endpoints = %addr_metas.len(), "Add");
                             return Poll::Ready(Ok(Update::Add(addr_metas)));
@@ -133,8 +342,9 @@ impl resolve::Resolution for Resolution {
                     Some(api::update::Update::Remove(api::AddrSet { addrs })) => {
                         let sock_addrs = addrs
                             .into_iter()
-                            .filter_map(pb::to_sock_addr)
+                            .filter_map(|addr| pb::to_sock_addr(addr, this.invalid_addrs))
                             .collect::<Vec<_>>();
+                        let sock_addrs = this.filtered.remove(sock_addrs);
                         if !sock_addrs.is_empty() {
                             debug!(endpoints = %sock_addrs.len(), "Remove");
                             return Poll::Ready(Ok(Update::Remove(sock_addrs)));
@@ -143,6 +353,7 @@ impl resolve::Resolution for Resolution {
 
                     Some(api::update::Update::NoEndpoints(api::NoEndpoints { exists })) => {
                         info!("No endpoints");
+                        this.filtered.clear();
                         let update = if exists {
                             Update::Empty
                         } else {
@@ -154,9 +365,405 @@ impl resolve::Resolution for Resolution {
                     None => {} // continue
                 },
                 None => {
-                    return Poll::Ready(Err(grpc::Status::new(grpc::Code::Ok, "end of stream")))
+                    debug!(dst = %this.path, "Stream ended");
+                    this.stream_ends.incr();
+                    return Poll::Ready(Err(grpc::Status::new(grpc::Code::Ok, "end of stream")));
                 }
             };
         }
     }
 }
+
+impl<S> resolve::Resolution for Resolution<S>
+where
+    S: Stream<Item = Result<api::Update, grpc::Status>>,
+{
+    type Endpoint = Metadata;
+    type Error = ResolveError;
+
+    fn poll(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Update<Self::Endpoint>, Self::Error>> {
+        let res = ready!(self.as_mut().poll_update(cx));
+        self.as_mut().project().status.observe(&res);
+        Poll::Ready(res.map_err(to_resolve_error))
+    }
+}
+
+/// The status metadata key with which the Destination service may describe
+/// why it failed a resolution.
+const REASON_KEY: &str = "reason";
+
+/// Categorizes a Destination service error by its status code, preserving the
+/// status's code, message, and reason.
+fn to_resolve_error(status: grpc::Status) -> ResolveError {
+    let kind = match status.code() {
+        grpc::Code::NotFound => ErrorKind::NotFound,
+        grpc::Code::PermissionDenied | grpc::Code::Unauthenticated => ErrorKind::Permission,
+        // The control plane rejects destinations that it will not resolve
+        // as invalid arguments.
+        grpc::Code::InvalidArgument => ErrorKind::Config,
+        // Includes the end of the stream, which is reported as `Ok`.
+        _ => ErrorKind::Transient,
+    };
+    let reason = status
+        .metadata()
+        .get(REASON_KEY)
+        .and_then(|v| v.to_str().ok())
+        .map(String::from);
+    let detail = StatusDetail::new(status.code() as i32, status.message(), reason);
+    ResolveError::new(DiscoverySource::Destination, kind, status).with_status(detail)
+}
+
+// === impl Liveness ===
+
+impl Liveness {
+    fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            timeout: time::delay_for(interval),
+            stale: None,
+        }
+    }
+
+    /// Notes that the stream has produced an item.
+    fn observe(&mut self, path: &str) {
+        if self.stale.take().is_some() {
+            debug!(dst = %path, "Stream recovered");
+        }
+        self.timeout.reset(time::Instant::now() + self.interval);
+    }
+
+    /// Marks the stream as stale if it has not produced an item within the
+    /// interval.
+    fn poll_stale(&mut self, cx: &mut Context<'_>, path: &str, metrics: &Metrics) {
+        if self.stale.is_none() && self.timeout.poll_unpin(cx).is_ready() {
+            warn!(dst = %path, interval = ?self.interval, "No updates received; stream may be stale");
+            self.stale = Some(metrics.stale());
+        }
+    }
+}
+
+// === impl DestinationStatus ===
+
+impl DestinationStatus {
+    fn observe(&mut self, res: &Result<Update<Metadata>, grpc::Status>) {
+        match res {
+            Ok(Update::Add(_)) => *self = DestinationStatus::Exists,
+            Ok(Update::Remove(_)) => {}
+            Ok(Update::Empty) => *self = DestinationStatus::NoEndpoints,
+            Ok(Update::DoesNotExist) => *self = DestinationStatus::NotFound,
+            Err(status) if status.code() == grpc::Code::InvalidArgument => {
+                *self = DestinationStatus::NotFound
+            }
+            Err(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use linkerd2_addr::NameAddr;
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// A Destination service that is always unavailable, counting each
+    /// request.
+    #[derive(Clone, Debug, Default)]
+    struct Unavailable(Arc<AtomicUsize>);
+
+    impl Service<http::Request<BoxBody>> for Unavailable {
+        type Response = http::Response<BoxBody>;
+        type Error = Box<dyn Error + Send + Sync>;
+        type Future = future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _: http::Request<BoxBody>) -> Self::Future {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            future::err("unavailable".into())
+        }
+    }
+
+    #[test]
+    fn status_distinguishes_empty_from_not_found() {
+        let addr = SocketAddr::from(([198, 51, 100, 1], 8080));
+        let steps = vec![
+            (
+                Ok(Update::Add(vec![(addr, Metadata::empty())])),
+                DestinationStatus::Exists,
+            ),
+            (Ok(Update::Remove(vec![addr])), DestinationStatus::Exists),
+            (Ok(Update::Empty), DestinationStatus::NoEndpoints),
+            (
+                Err(grpc::Status::new(grpc::Code::Unavailable, "unavailable")),
+                DestinationStatus::NoEndpoints,
+            ),
+            (Ok(Update::DoesNotExist), DestinationStatus::NotFound),
+            (
+                Ok(Update::Add(vec![(addr, Metadata::empty())])),
+                DestinationStatus::Exists,
+            ),
+            (
+                Err(grpc::Status::new(
+                    grpc::Code::InvalidArgument,
+                    "unresolvable",
+                )),
+                DestinationStatus::NotFound,
+            ),
+        ];
+
+        let mut status = DestinationStatus::Unknown;
+        for (res, expected) in steps.into_iter() {
+            status.observe(&res);
+            assert_eq!(status, expected, "after {:?}", res);
+        }
+    }
+
+    #[test]
+    fn get_destination_includes_scheme() {
+        let resolve = Resolve::new(Unavailable::default()).with_context_token("ns:web");
+        let req = resolve.get_destination("web.example.com:8080".to_string());
+        assert_eq!(req.path, "web.example.com:8080");
+        assert_eq!(req.scheme, "");
+        assert_eq!(req.context_token, "ns:web");
+
+        let req = resolve
+            .with_scheme("consul")
+            .get_destination("web.example.com:8080".to_string());
+        assert_eq!(req.scheme, "consul");
+        assert_eq!(req.context_token, "ns:web");
+    }
+
+    fn code(error: &ResolveError) -> grpc::Code {
+        error
+            .get_ref()
+            .downcast_ref::<grpc::Status>()
+            .expect("error must be a status")
+            .code()
+    }
+
+    #[test]
+    fn categorizes_status_codes() {
+        let cases = vec![
+            (grpc::Code::Unavailable, ErrorKind::Transient),
+            (grpc::Code::DeadlineExceeded, ErrorKind::Transient),
+            (grpc::Code::Ok, ErrorKind::Transient),
+            (grpc::Code::NotFound, ErrorKind::NotFound),
+            (grpc::Code::PermissionDenied, ErrorKind::Permission),
+            (grpc::Code::Unauthenticated, ErrorKind::Permission),
+            (grpc::Code::FailedPrecondition, ErrorKind::Transient),
+            (grpc::Code::OutOfRange, ErrorKind::Transient),
+            (grpc::Code::Unimplemented, ErrorKind::Transient),
+            (grpc::Code::InvalidArgument, ErrorKind::Config),
+        ];
+        for (c, kind) in cases.into_iter() {
+            let error = to_resolve_error(grpc::Status::new(c, "failed"));
+            assert_eq!(error.discovery_source(), DiscoverySource::Destination);
+            assert_eq!(error.kind(), kind, "{:?}", c);
+            assert_eq!(code(&error), c);
+        }
+    }
+
+    #[test]
+    fn preserves_status_detail() {
+        let mut metadata = grpc::metadata::MetadataMap::new();
+        metadata.insert(REASON_KEY, "identity-mismatch".parse().unwrap());
+        let status =
+            grpc::Status::with_metadata(grpc::Code::PermissionDenied, "not authorized", metadata);
+        let error = to_resolve_error(status);
+        let detail = error.status().expect("status must be preserved");
+        assert_eq!(detail.code(), grpc::Code::PermissionDenied as i32);
+        assert_eq!(detail.message(), "not authorized");
+        assert_eq!(detail.reason(), Some("identity-mismatch"));
+
+        let error = to_resolve_error(grpc::Status::new(grpc::Code::Unavailable, "unavailable"));
+        let detail = error.status().expect("status must be preserved");
+        assert_eq!(detail.code(), grpc::Code::Unavailable as i32);
+        assert_eq!(detail.reason(), None);
+    }
+
+    #[tokio::test]
+    async fn counts_graceful_stream_ends() {
+        use resolve::Resolution as _;
+
+        let metrics = Metrics::default();
+        let resolution = |updates: Vec<Result<api::Update, grpc::Status>>| Resolution {
+            inner: Some(futures::stream::iter(updates)),
+            path: "web.example.com:8080".to_string(),
+            filtered: Filtered::new(LabelFilter::default(), None),
+            invalid_addrs: InvalidAddrs::default(),
+            metrics: metrics.clone(),
+            stream_ends: metrics.authority("web.example.com:8080"),
+            locality: Locality::default(),
+            liveness: None,
+            pending: None,
+            status: DestinationStatus::Unknown,
+        };
+
+        let mut ended = resolution(vec![]);
+        let res = future::poll_fn(|cx| Pin::new(&mut ended).poll(cx)).await;
+        let error = res.unwrap_err();
+        assert!(error.is_transient());
+        assert_eq!(code(&error), grpc::Code::Ok);
+        assert_eq!(metrics.stream_ends("web.example.com:8080"), 1);
+
+        // Streams that fail are not counted.
+        let unavailable = grpc::Status::new(grpc::Code::Unavailable, "unavailable");
+        let mut failed = resolution(vec![Err(unavailable)]);
+        let res = future::poll_fn(|cx| Pin::new(&mut failed).poll(cx)).await;
+        assert_eq!(code(&res.unwrap_err()), grpc::Code::Unavailable);
+        assert_eq!(metrics.stream_ends("web.example.com:8080"), 1);
+        assert_eq!(metrics.stream_ends("other.example.com:8080"), 0);
+    }
+
+    #[tokio::test]
+    async fn marks_silent_streams_stale() {
+        use resolve::Resolution as _;
+
+        let metrics = Metrics::default();
+        let (tx, rx) = futures::channel::mpsc::unbounded();
+        let mut resolution = Resolution {
+            inner: Some(rx),
+            path: "web.example.com:8080".to_string(),
+            filtered: Filtered::new(LabelFilter::default(), None),
+            invalid_addrs: InvalidAddrs::default(),
+            metrics: metrics.clone(),
+            stream_ends: StreamEnds::default(),
+            locality: Locality::default(),
+            liveness: Some(Liveness::new(Duration::from_millis(10))),
+            pending: None,
+            status: DestinationStatus::Unknown,
+        };
+
+        let res = time::timeout(
+            Duration::from_millis(50),
+            future::poll_fn(|cx| Pin::new(&mut resolution).poll(cx)),
+        )
+        .await;
+        assert!(res.is_err(), "no updates expected");
+        assert_eq!(metrics.stale_streams(), 1);
+
+        // Once the stream produces an update, it is no longer stale.
+        let no_endpoints = api::Update {
+            update: Some(api::update::Update::NoEndpoints(api::NoEndpoints {
+                exists: true,
+            })),
+        };
+        tx.unbounded_send(Ok(no_endpoints)).unwrap();
+        let update = future::poll_fn(|cx| Pin::new(&mut resolution).poll(cx))
+            .await
+            .expect("update must succeed");
+        assert_eq!(update, Update::Empty);
+        assert_eq!(metrics.stale_streams(), 0);
+
+        // A stream that is dropped while stale is no longer counted.
+        let res = time::timeout(
+            Duration::from_millis(50),
+            future::poll_fn(|cx| Pin::new(&mut resolution).poll(cx)),
+        )
+        .await;
+        assert!(res.is_err(), "no updates expected");
+        assert_eq!(metrics.stale_streams(), 1);
+        drop(resolution);
+        assert_eq!(metrics.stale_streams(), 0);
+    }
+
+    #[tokio::test]
+    async fn overridden_authorities_resolve_statically() {
+        use resolve::Resolution as _;
+
+        let addr = SocketAddr::from(([198, 51, 100, 1], 8080));
+        let overrides = Overrides::default();
+        overrides.set(
+            NameAddr::from_str("pinned.example.com:8080").unwrap(),
+            vec![(addr, Metadata::empty())],
+        );
+        let svc = Unavailable::default();
+        let requests = svc.0.clone();
+        let mut resolve = Resolve::new(svc).with_overrides(overrides.clone());
+
+        let mut resolution = resolve
+            .call("pinned.example.com:8080")
+            .await
+            .expect("overridden resolution must succeed");
+        let update = future::poll_fn(|cx| Pin::new(&mut resolution).poll(cx))
+            .await
+            .expect("update must succeed");
+        assert_eq!(update, Update::Add(vec![(addr, Metadata::empty())]));
+        assert_eq!(resolution.status(), DestinationStatus::Exists);
+        assert_eq!(requests.load(Ordering::SeqCst), 0);
+
+        // Authorities without overrides are resolved by the Destination
+        // service.
+        assert!(resolve.call("other.example.com:8080").await.is_err());
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        // Once the override is removed, the authority is resolved by the
+        // Destination service.
+        overrides.remove(&NameAddr::from_str("pinned.example.com:8080").unwrap());
+        assert!(resolve.call("pinned.example.com:8080").await.is_err());
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn overridden_endpoints_are_filtered_limited_and_tagged() {
+        use crate::metadata::ProtocolHint;
+        use indexmap::IndexMap;
+        use resolve::Resolution as _;
+
+        fn endpoint(n: u8, zone: &str, keep: bool) -> (SocketAddr, Metadata) {
+            let mut labels = IndexMap::new();
+            labels.insert("zone".to_string(), zone.to_string());
+            labels.insert("keep".to_string(), keep.to_string());
+            let meta = Metadata::new(labels, ProtocolHint::Unknown, None, 10_000, None);
+            (SocketAddr::from(([198, 51, 100, n], 8080)), meta)
+        }
+
+        let endpoints = vec![
+            endpoint(1, "us-west-1a", true),
+            endpoint(2, "us-west-1b", true),
+            endpoint(3, "us-west-1b", false),
+            endpoint(4, "us-west-1b", true),
+        ];
+        let overrides = Overrides::default();
+        overrides.set(
+            NameAddr::from_str("pinned.example.com:8080").unwrap(),
+            endpoints.clone(),
+        );
+        let mut resolve = Resolve::new(Unavailable::default())
+            .with_overrides(overrides)
+            .with_label_filter(LabelFilter::matching_all(vec![(
+                "keep".to_string(),
+                "true".to_string(),
+            )]))
+            .with_endpoint_limit(EndpointLimit::new(2))
+            .with_locality(Locality::new(vec![(
+                "zone".to_string(),
+                "us-west-1b".to_string(),
+            )]));
+
+        let mut resolution = resolve
+            .call("pinned.example.com:8080")
+            .await
+            .expect("overridden resolution must succeed");
+        let update = future::poll_fn(|cx| Pin::new(&mut resolution).poll(cx))
+            .await
+            .expect("update must succeed");
+        // Same-locality endpoints are ordered first, so the endpoint in
+        // another locality is in excess of the limit.
+        let local = |(addr, meta): (SocketAddr, Metadata)| (addr, meta.with_same_locality(true));
+        assert_eq!(
+            update,
+            Update::Add(vec![
+                local(endpoints[1].clone()),
+                local(endpoints[3].clone()),
+            ])
+        );
+    }
+}
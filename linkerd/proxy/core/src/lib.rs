@@ -2,6 +2,8 @@
 
 mod accept;
 pub mod resolve;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
 
 pub use self::{
     accept::Accept,
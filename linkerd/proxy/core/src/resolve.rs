@@ -1,7 +1,15 @@
+use futures::{task::AtomicWaker, Stream};
+use indexmap::IndexMap;
 use linkerd2_error::Error;
+use pin_project::pin_project;
+use std::fmt;
 use std::future::Future;
 use std::net::SocketAddr;
 use std::pin::Pin;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
 use std::task::{Context, Poll};
 
 /// Resolves `T`-typed names/addresses as a `Resolution`.
@@ -42,6 +50,64 @@ pub trait Resolution {
     {
         Pin::new(self).poll(cx)
     }
+
+    /// Adapts this resolution into a `Stream` of updates.
+    ///
+    /// The stream ends only after the resolution fails.
+    fn into_stream(self) -> ResolutionStream<Self>
+    where
+        Self: Sized,
+    {
+        ResolutionStream {
+            resolution: self,
+            failed: false,
+        }
+    }
+
+    /// Adapts this resolution into a `Stream` of its complete set of
+    /// endpoints, rather than of incremental updates.
+    ///
+    /// Endpoints are ordered by when they were added. A snapshot is produced
+    /// after each change. Updates that are ready
+    /// together are coalesced, so that only the latest snapshot is produced.
+    /// The stream ends only after the resolution fails.
+    fn snapshots(self) -> Snapshots<Self>
+    where
+        Self: Sized,
+        Self::Endpoint: Clone,
+    {
+        Snapshots {
+            resolution: self,
+            endpoints: IndexMap::new(),
+            error: None,
+            failed: false,
+        }
+    }
+}
+
+/// Reports whether a `Resolution` currently has any endpoints.
+///
+/// This is distinct from whether a resolution has been initialized: an
+/// initialized resolution may have no endpoints, e.g. if every endpoint has
+/// been removed.
+pub trait ResolutionStatus {
+    /// Returns true if the resolution currently holds at least one endpoint.
+    fn is_fully_resolved(&self) -> bool;
+}
+
+/// A handle that forces a resolution to re-resolve its target immediately,
+/// e.g. so that an admin endpoint may "kick" a resolution after its backends
+/// are known to have changed.
+///
+/// The handle may be cloned and used from any task. Requests that are made
+/// before the resolution is next polled are coalesced.
+#[derive(Clone, Debug, Default)]
+pub struct Refresh(Arc<RefreshShared>);
+
+#[derive(Debug, Default)]
+struct RefreshShared {
+    requested: AtomicBool,
+    task: AtomicWaker,
 }
 
 #[derive(Clone, Debug)]
@@ -55,6 +121,78 @@ pub enum Update<T> {
     DoesNotExist,
 }
 
+/// A `Stream` of a `Resolution`'s updates.
+#[pin_project]
+#[derive(Debug)]
+pub struct ResolutionStream<R> {
+    #[pin]
+    resolution: R,
+    failed: bool,
+}
+
+/// A `Stream` of the complete set of a `Resolution`'s endpoints.
+#[pin_project]
+#[derive(Debug)]
+pub struct Snapshots<R: Resolution> {
+    #[pin]
+    resolution: R,
+    endpoints: IndexMap<SocketAddr, R::Endpoint>,
+    /// The error that ended the resolution, which is returned after the
+    /// final snapshot.
+    error: Option<R::Error>,
+    failed: bool,
+}
+
+/// A resolution failure, categorized independently of the backend that
+/// produced it, so that failures may be handled consistently, e.g. when
+/// deciding whether to retry.
+#[derive(Debug)]
+pub struct ResolveError {
+    source: DiscoverySource,
+    kind: ErrorKind,
+    error: Error,
+    status: Option<StatusDetail>,
+}
+
+/// The detail of a gRPC status with which a backend failed, preserved so that
+/// callers may inspect it without parsing the error's message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StatusDetail {
+    code: i32,
+    message: String,
+    reason: Option<String>,
+}
+
+/// Describes how an endpoint was discovered, or which source failed to
+/// resolve a target.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum DiscoverySource {
+    /// The endpoint was resolved by the control plane's Destination service.
+    Destination,
+    /// The endpoint was resolved via DNS.
+    Dns,
+    /// The endpoint was statically configured.
+    Static,
+}
+
+/// Describes why a resolution failed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    /// The resolution may succeed if it is retried.
+    Transient,
+    /// The target does not exist.
+    NotFound,
+    /// The proxy is not permitted to resolve the target.
+    Permission,
+    /// The resolution cannot succeed as configured, e.g. because the target
+    /// is invalid.
+    Config,
+}
+
+/// Formats an `Update` by its addresses, omitting endpoint metadata.
+#[derive(Debug)]
+pub struct DisplayUpdate<'a, T>(pub &'a Update<T>);
+
 // === impl Resolve ===
 
 impl<S, T, R> Resolve<T> for S
@@ -100,3 +238,326 @@ where
         self.0.resolve(target)
     }
 }
+
+// === impl ResolutionStream ===
+
+impl<R: Resolution> Stream for ResolutionStream<R> {
+    type Item = Result<Update<R::Endpoint>, R::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        if *this.failed {
+            return Poll::Ready(None);
+        }
+
+        let res = futures::ready!(this.resolution.poll(cx));
+        *this.failed = res.is_err();
+        Poll::Ready(Some(res))
+    }
+}
+
+// === impl Snapshots ===
+
+impl<R> Stream for Snapshots<R>
+where
+    R: Resolution,
+    R::Endpoint: Clone,
+{
+    type Item = Result<Vec<(SocketAddr, R::Endpoint)>, R::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        if *this.failed {
+            return Poll::Ready(None);
+        }
+        if let Some(error) = this.error.take() {
+            *this.failed = true;
+            return Poll::Ready(Some(Err(error)));
+        }
+
+        let mut changed = false;
+        loop {
+            match this.resolution.as_mut().poll(cx) {
+                Poll::Ready(Ok(update)) => {
+                    changed = true;
+                    match update {
+                        Update::Add(endpoints) => this.endpoints.extend(endpoints),
+                        Update::Remove(addrs) => {
+                            // Retain the order in which endpoints were added.
+                            this.endpoints.retain(|addr, _| !addrs.contains(addr))
+                        }
+                        Update::Empty | Update::DoesNotExist => this.endpoints.clear(),
+                    }
+                }
+                Poll::Ready(Err(error)) => {
+                    if !changed {
+                        *this.failed = true;
+                        return Poll::Ready(Some(Err(error)));
+                    }
+                    *this.error = Some(error);
+                    break;
+                }
+                Poll::Pending if changed => break,
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+
+        let snapshot = this
+            .endpoints
+            .iter()
+            .map(|(addr, ep)| (*addr, ep.clone()))
+            .collect();
+        Poll::Ready(Some(Ok(snapshot)))
+    }
+}
+
+// === impl Refresh ===
+
+impl Refresh {
+    /// Requests that the resolution re-resolve its target.
+    pub fn refresh(&self) {
+        self.0.requested.store(true, Ordering::Release);
+        self.0.task.wake();
+    }
+
+    /// Returns true if a refresh has been requested since this was last
+    /// called.
+    ///
+    /// The current task is notified when a refresh is next requested.
+    pub fn poll_requested(&self, cx: &mut Context<'_>) -> bool {
+        self.0.task.register(cx.waker());
+        self.0.requested.swap(false, Ordering::AcqRel)
+    }
+}
+
+// === impl ResolveError ===
+
+impl ResolveError {
+    pub fn new(source: DiscoverySource, kind: ErrorKind, error: impl Into<Error>) -> Self {
+        Self {
+            source,
+            kind,
+            error: error.into(),
+            status: None,
+        }
+    }
+
+    /// Records the gRPC status with which the backend failed.
+    pub fn with_status(self, status: StatusDetail) -> Self {
+        Self {
+            status: Some(status),
+            ..self
+        }
+    }
+
+    /// Returns the source that failed to resolve the target.
+    pub fn discovery_source(&self) -> DiscoverySource {
+        self.source
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// Returns true if the resolution may succeed if it is retried.
+    pub fn is_transient(&self) -> bool {
+        self.kind == ErrorKind::Transient
+    }
+
+    /// Returns the backend's error.
+    pub fn get_ref(&self) -> &(dyn std::error::Error + Send + Sync + 'static) {
+        self.error.as_ref()
+    }
+
+    pub fn into_inner(self) -> Error {
+        self.error
+    }
+
+    /// Returns the gRPC status with which the backend failed, if any.
+    pub fn status(&self) -> Option<&StatusDetail> {
+        self.status.as_ref()
+    }
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} resolution failed: {}", self.source, self.error)
+    }
+}
+
+impl std::error::Error for ResolveError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.error.as_ref())
+    }
+}
+
+// === impl StatusDetail ===
+
+impl StatusDetail {
+    pub fn new(code: i32, message: impl Into<String>, reason: Option<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            reason,
+        }
+    }
+
+    /// Returns the status's numeric gRPC code.
+    pub fn code(&self) -> i32 {
+        self.code
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Returns the reason that the backend attached to the status, if any.
+    pub fn reason(&self) -> Option<&str> {
+        self.reason.as_deref()
+    }
+}
+
+// === impl DiscoverySource ===
+
+impl fmt::Display for DiscoverySource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DiscoverySource::Destination => f.write_str("destination"),
+            DiscoverySource::Dns => f.write_str("DNS"),
+            DiscoverySource::Static => f.write_str("static"),
+        }
+    }
+}
+
+// === impl DisplayUpdate ===
+
+impl<'a, T> fmt::Display for DisplayUpdate<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn fmt_addrs<'a>(
+            f: &mut fmt::Formatter<'_>,
+            addrs: impl Iterator<Item = &'a SocketAddr>,
+        ) -> fmt::Result {
+            f.write_str("[")?;
+            for (i, addr) in addrs.enumerate() {
+                if i > 0 {
+                    f.write_str(", ")?;
+                }
+                write!(f, "{}", addr)?;
+            }
+            f.write_str("]")
+        }
+
+        match self.0 {
+            Update::Add(eps) => {
+                f.write_str("Add ")?;
+                fmt_addrs(f, eps.iter().map(|(addr, _)| addr))
+            }
+            Update::Remove(addrs) => {
+                f.write_str("Remove ")?;
+                fmt_addrs(f, addrs.iter())
+            }
+            Update::Empty => f.write_str("Empty"),
+            Update::DoesNotExist => f.write_str("DoesNotExist"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::Updates;
+    use futures::{executor, StreamExt};
+
+    fn addr(port: u16) -> SocketAddr {
+        ([192, 0, 2, 1], port).into()
+    }
+
+    #[test]
+    fn stream_yields_updates() {
+        let updates = vec![
+            Update::Add(vec![(addr(8080), ()), (addr(8081), ())]),
+            Update::Remove(vec![addr(8080)]),
+            Update::Empty,
+        ];
+        let stream = updates.iter().cloned().collect::<Updates<()>>().into_stream();
+
+        let collected = executor::block_on(stream.take(2).collect::<Vec<_>>());
+        let collected = collected
+            .into_iter()
+            .map(|res| res.expect("update must succeed"))
+            .collect::<Vec<_>>();
+        assert_eq!(collected, updates[..2].to_vec());
+    }
+
+    #[test]
+    fn stream_ends_after_error() {
+        let updates = vec![Update::Empty].into_iter().collect::<Updates<()>>();
+        updates.fail("resolution failed");
+        let stream = updates.into_stream();
+
+        let collected = executor::block_on(stream.collect::<Vec<_>>());
+        assert_eq!(collected.len(), 2);
+        assert_eq!(collected[0].as_ref().ok(), Some(&Update::Empty));
+        assert!(collected[1].is_err(), "the stream must yield the error");
+    }
+
+    #[test]
+    fn snapshots_yield_complete_endpoints() {
+        let updates = Updates::default();
+        let push = |update| updates.push(update);
+        let mut snapshots = updates.clone().snapshots();
+        let mut next = || {
+            let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+            match Pin::new(&mut snapshots).poll_next(&mut cx) {
+                Poll::Ready(Some(Ok(snapshot))) => Some(snapshot),
+                Poll::Ready(res) => panic!("unexpected {:?}", res.map(|r| r.is_ok())),
+                Poll::Pending => None,
+            }
+        };
+        assert_eq!(next(), None);
+
+        push(Update::Add(vec![(addr(8080), 0), (addr(8081), 0)]));
+        assert_eq!(next(), Some(vec![(addr(8080), 0), (addr(8081), 0)]));
+        assert_eq!(next(), None);
+
+        // Each snapshot reflects the current endpoints, including updated
+        // metadata.
+        push(Update::Add(vec![(addr(8081), 1), (addr(8082), 0)]));
+        assert_eq!(
+            next(),
+            Some(vec![(addr(8080), 0), (addr(8081), 1), (addr(8082), 0)])
+        );
+        push(Update::Remove(vec![addr(8080)]));
+        assert_eq!(next(), Some(vec![(addr(8081), 1), (addr(8082), 0)]));
+
+        // Updates that are ready together are coalesced.
+        push(Update::Empty);
+        push(Update::Add(vec![(addr(8083), 0)]));
+        push(Update::Remove(vec![addr(8084)]));
+        assert_eq!(next(), Some(vec![(addr(8083), 0)]));
+        push(Update::DoesNotExist);
+        assert_eq!(next(), Some(vec![]));
+
+        // The final snapshot is yielded before the error.
+        push(Update::Add(vec![(addr(8080), 0)]));
+        updates.fail("resolution failed");
+        let collected = executor::block_on(snapshots.collect::<Vec<_>>());
+        assert_eq!(collected.len(), 2);
+        assert_eq!(collected[0].as_ref().ok(), Some(&vec![(addr(8080), 0)]));
+        assert!(collected[1].is_err(), "the stream must yield the error");
+    }
+
+    #[test]
+    fn resolve_error() {
+        let error = ResolveError::new(DiscoverySource::Dns, ErrorKind::NotFound, "no records");
+        assert_eq!(error.discovery_source(), DiscoverySource::Dns);
+        assert_eq!(error.kind(), ErrorKind::NotFound);
+        assert!(!error.is_transient());
+        assert_eq!(error.to_string(), "DNS resolution failed: no records");
+
+        // The error is preserved when it is boxed.
+        let boxed: Error = error.into();
+        let error = boxed.downcast::<ResolveError>().unwrap();
+        assert_eq!(error.into_inner().to_string(), "no records");
+    }
+}
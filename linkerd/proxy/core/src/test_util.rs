@@ -0,0 +1,92 @@
+//! Resolutions and helpers shared by the tests of resolution middlewares.
+
+use crate::resolve::{Resolution, Update};
+use linkerd2_error::Error;
+use std::collections::VecDeque;
+use std::fmt;
+use std::iter::FromIterator;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+/// A resolution that produces the updates pushed to it.
+///
+/// Clones share the same updates, so a test may retain a clone to push
+/// updates after the resolution has been handed to a middleware. The
+/// resolution is pending while no updates remain.
+#[derive(Debug)]
+pub struct Updates<E>(Arc<Mutex<VecDeque<Result<Update<E>, Error>>>>);
+
+/// Returns the resolution's next update.
+///
+/// # Panics
+///
+/// If the resolution fails.
+pub async fn next<R>(resolution: &mut R) -> Update<R::Endpoint>
+where
+    R: Resolution + Unpin,
+    R::Error: fmt::Debug,
+{
+    futures::future::poll_fn(|cx| Pin::new(&mut *resolution).poll(cx))
+        .await
+        .expect("update must succeed")
+}
+
+/// Returns the resolution's next update, or `None` if it is not produced
+/// within `timeout`.
+pub async fn next_within<R>(resolution: &mut R, timeout: Duration) -> Option<Update<R::Endpoint>>
+where
+    R: Resolution + Unpin,
+    R::Error: fmt::Debug,
+{
+    tokio::time::timeout(timeout, next(resolution)).await.ok()
+}
+
+// === impl Updates ===
+
+impl<E> Updates<E> {
+    pub fn push(&self, update: Update<E>) {
+        self.0.lock().unwrap().push_back(Ok(update));
+    }
+
+    /// Fails the resolution once the updates that precede the error have
+    /// been produced.
+    pub fn fail(&self, error: impl Into<Error>) {
+        self.0.lock().unwrap().push_back(Err(error.into()));
+    }
+}
+
+impl<E> Default for Updates<E> {
+    fn default() -> Self {
+        Updates(Arc::new(Mutex::new(VecDeque::new())))
+    }
+}
+
+impl<E> Clone for Updates<E> {
+    fn clone(&self) -> Self {
+        Updates(self.0.clone())
+    }
+}
+
+impl<E> FromIterator<Update<E>> for Updates<E> {
+    fn from_iter<I: IntoIterator<Item = Update<E>>>(iter: I) -> Self {
+        let updates = Self::default();
+        for update in iter {
+            updates.push(update);
+        }
+        updates
+    }
+}
+
+impl<E> Resolution for Updates<E> {
+    type Endpoint = E;
+    type Error = Error;
+
+    fn poll(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<Update<E>, Error>> {
+        match self.0.lock().unwrap().pop_front() {
+            Some(res) => Poll::Ready(res),
+            None => Poll::Pending,
+        }
+    }
+}
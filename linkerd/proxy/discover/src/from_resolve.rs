@@ -1,17 +1,62 @@
+use crate::status::EndpointStatus;
 use futures::{ready, Stream, TryFuture};
 use indexmap::IndexSet;
+use linkerd2_metrics::Counter;
 use linkerd2_proxy_core::resolve::{Resolution, Resolve, Update};
 use pin_project::pin_project;
+use rand::seq::SliceRandom;
 use std::collections::VecDeque;
 use std::future::Future;
 use std::net::SocketAddr;
 use std::pin::Pin;
+use std::sync::Arc;
 use std::task::{Context, Poll};
 use tower::discover::Change;
+use tracing::{info, warn};
 
 #[derive(Clone, Debug)]
 pub struct FromResolve<R> {
     resolve: R,
+    order: InsertOrder,
+    log_no_endpoints: bool,
+    limit: ActiveLimit,
+}
+
+/// Limits the number of distinct endpoints a discover may hold.
+///
+/// This is a safety valve against a control plane that adds endpoints
+/// without ever removing them: once the limit is reached, new endpoints are
+/// refused, while updates to endpoints that are already active are applied.
+#[derive(Clone, Debug)]
+pub struct ActiveLimit {
+    max: usize,
+    refused: Arc<Counter>,
+}
+
+/// Determines the order in which a resolution's added endpoints are
+/// inserted.
+///
+/// Services that are built for the endpoints, e.g. via `MakeEndpoint`, may
+/// complete in any order. The discover `Layer` only holds services so that
+/// they are inserted in this order when it is `Sorted`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum InsertOrder {
+    /// Endpoints are inserted in the order in which they were resolved.
+    AsReceived,
+    /// Each batch of added endpoints is shuffled, so that consumers that
+    /// favor earlier insertions are not biased by the resolution's order.
+    ///
+    /// Round-robin attempts should also use this order: balancing is the
+    /// balancer's concern, but a shuffled insert order avoids always
+    /// starting with the same endpoint.
+    Random,
+    /// Each batch of added endpoints is sorted by address, so that endpoints
+    /// are inserted in the same order regardless of the order in which they
+    /// were resolved, e.g. so that behavior is reproducible in tests.
+    ///
+    /// The balancer does not depend on insertion order to weigh endpoints,
+    /// so this only determines how equal-weight endpoints are ordered.
+    Sorted,
 }
 
 #[pin_project]
@@ -19,6 +64,9 @@ pub struct FromResolve<R> {
 pub struct DiscoverFuture<F> {
     #[pin]
     future: F,
+    order: InsertOrder,
+    log_no_endpoints: bool,
+    limit: ActiveLimit,
 }
 
 /// Observes an `R`-typed resolution stream, using an `M`-typed endpoint stack to
@@ -27,8 +75,17 @@ pub struct DiscoverFuture<F> {
 pub struct Discover<R: Resolution> {
     #[pin]
     resolution: R,
+    order: InsertOrder,
+    /// Whether the endpoints are logged when the resolution becomes empty.
+    log_no_endpoints: bool,
+    limit: ActiveLimit,
+    /// Reports the endpoints that have been inserted, if set.
+    status: Option<EndpointStatus>,
     active: IndexSet<SocketAddr>,
     pending: VecDeque<Change<SocketAddr, R::Endpoint>>,
+    /// Set once the discover is drained, after which the resolution is no
+    /// longer polled.
+    draining: bool,
 }
 
 // === impl FromResolve ===
@@ -38,7 +95,32 @@ impl<R> FromResolve<R> {
     where
         R: Resolve<T>,
     {
-        Self { resolve }
+        Self {
+            resolve,
+            order: InsertOrder::default(),
+            log_no_endpoints: false,
+            limit: ActiveLimit::default(),
+        }
+    }
+
+    /// Sets the order in which each resolution's added endpoints are
+    /// inserted.
+    pub fn with_insert_order(self, order: InsertOrder) -> Self {
+        Self { order, ..self }
+    }
+
+    /// Logs each resolution's endpoints at `info` when it has no endpoints,
+    /// so that it is clear which endpoints disappeared together.
+    pub fn with_no_endpoints_logging(self, log_no_endpoints: bool) -> Self {
+        Self {
+            log_no_endpoints,
+            ..self
+        }
+    }
+
+    /// Limits the number of distinct endpoints each discover may hold.
+    pub fn with_active_limit(self, limit: ActiveLimit) -> Self {
+        Self { limit, ..self }
     }
 }
 
@@ -59,10 +141,48 @@ where
     fn call(&mut self, target: T) -> Self::Future {
         Self::Future {
             future: self.resolve.resolve(target),
+            order: self.order,
+            log_no_endpoints: self.log_no_endpoints,
+            limit: self.limit.clone(),
         }
     }
 }
 
+// === impl ActiveLimit ===
+
+impl ActiveLimit {
+    /// By default, the limit is high enough that it should only be reached
+    /// when the control plane misbehaves.
+    pub const DEFAULT_MAX: usize = 100_000;
+
+    pub fn new(max: usize) -> Self {
+        Self {
+            max,
+            refused: Arc::new(Counter::default()),
+        }
+    }
+
+    /// Returns the total number of endpoints that have been refused because
+    /// the limit was reached.
+    pub fn refused(&self) -> u64 {
+        self.refused.value()
+    }
+}
+
+impl Default for ActiveLimit {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_MAX)
+    }
+}
+
+// === impl InsertOrder ===
+
+impl Default for InsertOrder {
+    fn default() -> Self {
+        InsertOrder::AsReceived
+    }
+}
+
 // === impl DiscoverFuture ===
 
 impl<F> Future for DiscoverFuture<F>
@@ -73,8 +193,12 @@ where
     type Output = Result<Discover<F::Ok>, F::Error>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let resolution = ready!(self.project().future.try_poll(cx))?;
-        Poll::Ready(Ok(Discover::new(resolution)))
+        let this = self.project();
+        let resolution = ready!(this.future.try_poll(cx))?;
+        Poll::Ready(Ok(Discover::new(resolution)
+            .with_insert_order(*this.order)
+            .with_no_endpoints_logging(*this.log_no_endpoints)
+            .with_active_limit(this.limit.clone())))
     }
 }
 
@@ -84,10 +208,52 @@ impl<R: Resolution> Discover<R> {
     pub fn new(resolution: R) -> Self {
         Self {
             resolution,
+            order: InsertOrder::default(),
+            log_no_endpoints: false,
+            limit: ActiveLimit::default(),
+            status: None,
             active: IndexSet::default(),
             pending: VecDeque::new(),
+            draining: false,
+        }
+    }
+
+    /// Sets the order in which each batch of added endpoints is inserted.
+    pub fn with_insert_order(self, order: InsertOrder) -> Self {
+        Self { order, ..self }
+    }
+
+    /// Logs the endpoints that are removed at `info` when the resolution has
+    /// no endpoints.
+    pub fn with_no_endpoints_logging(self, log_no_endpoints: bool) -> Self {
+        Self {
+            log_no_endpoints,
+            ..self
+        }
+    }
+
+    /// Limits the number of distinct endpoints that may be active.
+    pub fn with_active_limit(self, limit: ActiveLimit) -> Self {
+        Self { limit, ..self }
+    }
+
+    /// Reports the endpoints that are inserted and removed via `status`.
+    pub fn with_endpoint_status(self, status: EndpointStatus) -> Self {
+        Self {
+            status: Some(status),
+            ..self
         }
     }
+
+    /// Tears down all discovered endpoints, e.g. when a service is being
+    /// decommissioned.
+    ///
+    /// Changes that have already been produced are still returned, followed
+    /// by a remove for each remaining endpoint. Thereafter, the resolution is
+    /// not polled and no further changes are produced.
+    pub fn drain(self: Pin<&mut Self>) {
+        *self.project().draining = true;
+    }
 }
 
 impl<R: Resolution> Stream for Discover<R> {
@@ -97,15 +263,48 @@ impl<R: Resolution> Stream for Discover<R> {
         loop {
             let this = self.as_mut().project();
             if let Some(change) = this.pending.pop_front() {
+                if let Some(status) = this.status.as_ref() {
+                    match change {
+                        Change::Insert(addr, _) => status.insert(addr),
+                        Change::Remove(ref addr) => status.remove(addr),
+                    }
+                }
                 return Poll::Ready(Some(Ok(change)));
             }
 
+            if *this.draining {
+                if this.active.is_empty() {
+                    return Poll::Pending;
+                }
+                this.pending
+                    .extend(this.active.drain(..).map(Change::Remove));
+                continue;
+            }
+
             match ready!(this.resolution.poll(cx))? {
-                Update::Add(endpoints) => {
+                Update::Add(mut endpoints) => {
+                    match *this.order {
+                        InsertOrder::AsReceived => {}
+                        InsertOrder::Random => endpoints.shuffle(&mut rand::thread_rng()),
+                        InsertOrder::Sorted => endpoints.sort_by_key(|(addr, _)| *addr),
+                    }
+                    let mut refused = 0;
                     for (addr, endpoint) in endpoints.into_iter() {
+                        if !this.active.contains(&addr) && this.active.len() >= this.limit.max {
+                            refused += 1;
+                            continue;
+                        }
                         this.active.insert(addr);
                         this.pending.push_back(Change::Insert(addr, endpoint));
                     }
+                    if refused > 0 {
+                        warn!(
+                            %refused,
+                            max = %this.limit.max,
+                            "Refusing endpoints in excess of the limit"
+                        );
+                        this.limit.refused.add(refused);
+                    }
                 }
                 Update::Remove(addrs) => {
                     for addr in addrs.into_iter() {
@@ -115,6 +314,10 @@ impl<R: Resolution> Stream for Discover<R> {
                     }
                 }
                 Update::DoesNotExist | Update::Empty => {
+                    if *this.log_no_endpoints && !this.active.is_empty() {
+                        let removed = this.active.iter().collect::<Vec<_>>();
+                        info!(?removed, "No endpoints");
+                    }
                     this.pending
                         .extend(this.active.drain(..).map(Change::Remove));
                 }
@@ -122,3 +325,237 @@ impl<R: Resolution> Stream for Discover<R> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::task::noop_waker_ref;
+    use linkerd2_proxy_core::test_util::Updates;
+    use linkerd2_tracing_test::Recorder;
+
+    /// Adds a fixed set of endpoints and then never updates.
+    struct AddOnce(Option<Vec<(SocketAddr, ())>>);
+
+    impl Resolution for AddOnce {
+        type Endpoint = ();
+        type Error = linkerd2_error::Error;
+
+        fn poll(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+        ) -> Poll<Result<Update<()>, Self::Error>> {
+            match self.0.take() {
+                Some(endpoints) => Poll::Ready(Ok(Update::Add(endpoints))),
+                None => Poll::Pending,
+            }
+        }
+    }
+
+    fn addr(n: u8) -> SocketAddr {
+        ([198, 51, 100, n], 8080).into()
+    }
+
+    fn next(discover: &mut Discover<AddOnce>) -> Option<Change<SocketAddr, ()>> {
+        let mut cx = Context::from_waker(noop_waker_ref());
+        match Pin::new(discover).poll_next(&mut cx) {
+            Poll::Ready(Some(change)) => Some(change.expect("change must succeed")),
+            Poll::Ready(None) => panic!("discover must not end"),
+            Poll::Pending => None,
+        }
+    }
+
+    #[test]
+    fn drain_removes_after_pending_inserts() {
+        let mut discover = Discover::new(AddOnce(Some(vec![(addr(1), ()), (addr(2), ())])));
+        match next(&mut discover) {
+            Some(Change::Insert(a, ())) => assert_eq!(a, addr(1)),
+            c => panic!("expected insert; got {:?}", c),
+        }
+
+        Pin::new(&mut discover).drain();
+
+        // The insert that was already produced is not lost.
+        match next(&mut discover) {
+            Some(Change::Insert(a, ())) => assert_eq!(a, addr(2)),
+            c => panic!("expected insert; got {:?}", c),
+        }
+        for n in 1..=2 {
+            match next(&mut discover) {
+                Some(Change::Remove(a)) => assert_eq!(a, addr(n)),
+                c => panic!("expected remove; got {:?}", c),
+            }
+        }
+        assert!(next(&mut discover).is_none(), "no further changes expected");
+    }
+
+    fn inserts(order: InsertOrder, addrs: &[SocketAddr]) -> Vec<SocketAddr> {
+        let endpoints = addrs.iter().map(|a| (*a, ())).collect();
+        let mut discover = Discover::new(AddOnce(Some(endpoints))).with_insert_order(order);
+        let mut inserted = Vec::new();
+        while let Some(change) = next(&mut discover) {
+            match change {
+                Change::Insert(a, ()) => inserted.push(a),
+                c => panic!("expected insert; got {:?}", c),
+            }
+        }
+        inserted
+    }
+
+    #[test]
+    fn inserts_as_received() {
+        let addrs = (1..=8).map(addr).collect::<Vec<_>>();
+        for _ in 0..10 {
+            assert_eq!(inserts(InsertOrder::AsReceived, &addrs), addrs);
+        }
+    }
+
+    #[test]
+    fn inserts_in_sorted_order() {
+        let sorted = vec![
+            SocketAddr::from(([10, 0, 0, 1], 8080)),
+            SocketAddr::from(([10, 0, 0, 1], 8081)),
+            addr(1),
+            addr(2),
+            addr(10),
+        ];
+        let received = vec![sorted[3], sorted[4], sorted[1], sorted[2], sorted[0]];
+        assert_eq!(inserts(InsertOrder::Sorted, &received), sorted);
+    }
+
+    #[test]
+    fn inserts_in_random_order() {
+        let addrs = (1..=8).map(addr).collect::<Vec<_>>();
+        let mut reordered = false;
+        for _ in 0..10 {
+            let mut inserted = inserts(InsertOrder::Random, &addrs);
+            reordered = reordered || inserted != addrs;
+            inserted.sort();
+            assert_eq!(inserted, addrs, "every endpoint must be inserted once");
+        }
+        // The odds of 10 shuffles of 8 endpoints all preserving the order
+        // are negligible.
+        assert!(reordered, "endpoints must be shuffled");
+    }
+
+    #[test]
+    fn refuses_endpoints_beyond_limit() {
+        let limit = ActiveLimit::new(3);
+        let updates = vec![
+            Update::Add(vec![(addr(1), ()), (addr(2), ())]),
+            Update::Add(vec![
+                (addr(2), ()),
+                (addr(3), ()),
+                (addr(4), ()),
+                (addr(5), ()),
+            ]),
+            Update::Remove(vec![addr(1)]),
+            Update::Add(vec![(addr(6), ()), (addr(7), ())]),
+        ];
+        let mut discover = Discover::new(updates.into_iter().collect::<Updates<()>>())
+            .with_active_limit(limit.clone());
+
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let mut changes = Vec::new();
+        while let Poll::Ready(Some(change)) = Pin::new(&mut discover).poll_next(&mut cx) {
+            changes.push(match change.expect("change must succeed") {
+                Change::Insert(a, ()) => format!("+{}", a.ip()),
+                Change::Remove(a) => format!("-{}", a.ip()),
+            });
+        }
+
+        // Updates to active endpoints are applied at the limit, and a removal
+        // admits one new endpoint.
+        assert_eq!(
+            changes,
+            vec![
+                "+198.51.100.1",
+                "+198.51.100.2",
+                "+198.51.100.2",
+                "+198.51.100.3",
+                "-198.51.100.1",
+                "+198.51.100.6",
+            ]
+        );
+        assert_eq!(limit.refused(), 3);
+    }
+
+    #[test]
+    fn endpoint_status_aggregates_readiness() {
+        let updates = vec![
+            Update::Add(vec![(addr(1), ()), (addr(2), ())]),
+            Update::Remove(vec![addr(1)]),
+            Update::Empty,
+        ];
+        let status = EndpointStatus::tracking_readiness();
+        let mut discover = Discover::new(updates.into_iter().collect::<Updates<()>>())
+            .with_endpoint_status(status.clone());
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let mut poll = |n: usize| {
+            for _ in 0..n {
+                let poll = Pin::new(&mut discover).poll_next(&mut cx);
+                assert!(matches!(poll, Poll::Ready(Some(Ok(_)))), "change expected");
+            }
+        };
+        assert!(status.is_empty());
+        assert!(!status.any_ready());
+
+        // Endpoints are present, but not ready until they are marked ready.
+        poll(2);
+        assert!(!status.is_empty());
+        assert!(!status.any_ready());
+        status.set_ready(addr(1), true);
+        assert!(status.any_ready());
+
+        // The ready endpoint is removed.
+        poll(1);
+        assert!(!status.is_empty());
+        assert!(!status.any_ready());
+        status.set_ready(addr(2), true);
+        assert!(status.any_ready());
+        status.set_ready(addr(2), false);
+        assert!(!status.any_ready());
+        status.set_ready(addr(2), true);
+
+        poll(1);
+        assert!(status.is_empty());
+        assert!(!status.any_ready());
+
+        // By default, present endpoints are ready.
+        let status = EndpointStatus::default();
+        let mut discover =
+            Discover::new(AddOnce(Some(vec![(addr(1), ())]))).with_endpoint_status(status.clone());
+        assert!(next(&mut discover).is_some());
+        assert!(status.any_ready());
+    }
+
+    fn empty_after_add(log_no_endpoints: bool) -> Vec<String> {
+        let updates = vec![
+            Update::Add(vec![(addr(1), ()), (addr(2), ())]),
+            Update::Empty,
+        ];
+        let mut discover = Discover::new(updates.into_iter().collect::<Updates<()>>())
+            .with_no_endpoints_logging(log_no_endpoints);
+
+        let events = Recorder::default();
+        tracing::subscriber::with_default(events.clone(), || {
+            let mut cx = Context::from_waker(noop_waker_ref());
+            let mut removed = Vec::new();
+            while let Poll::Ready(Some(change)) = Pin::new(&mut discover).poll_next(&mut cx) {
+                if let Change::Remove(addr) = change.expect("change must succeed") {
+                    removed.push(addr);
+                }
+            }
+            assert_eq!(removed, vec![addr(1), addr(2)]);
+        });
+        events.events()
+    }
+
+    #[test]
+    fn logs_removed_endpoints_when_empty() {
+        assert_eq!(
+            empty_after_add(true),
+            vec!["message=No endpoints removed=[198.51.100.1:8080, 198.51.100.2:8080]"]
+        );
+        assert!(empty_after_add(false).is_empty());
+    }
+}
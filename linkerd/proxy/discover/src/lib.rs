@@ -3,21 +3,27 @@
 use linkerd2_error::Error;
 use linkerd2_proxy_core::Resolve;
 use std::fmt;
+use std::future::Future;
 use std::time::Duration;
 
 pub mod buffer;
 pub mod from_resolve;
 pub mod make_endpoint;
+pub mod ready_gate;
+pub mod status;
 
 use self::buffer::Buffer;
-use self::from_resolve::FromResolve;
+use self::from_resolve::{FromResolve, InsertOrder};
 use self::make_endpoint::MakeEndpoint;
+use self::ready_gate::{MakeReady, ReadyGate};
 
 #[derive(Clone, Debug)]
-pub struct Layer<T, R> {
+pub struct Layer<T, R, G = ()> {
     capacity: usize,
     watchdog: Duration,
     resolve: R,
+    insert_order: InsertOrder,
+    ready_gate: G,
     _marker: std::marker::PhantomData<fn(T)>,
 }
 
@@ -33,9 +39,33 @@ impl<T, R> Layer<T, R> {
             capacity,
             watchdog,
             resolve,
+            insert_order: InsertOrder::default(),
+            ready_gate: (),
             _marker: std::marker::PhantomData,
         }
     }
+
+    /// Sets the order in which each resolution's added endpoints are
+    /// inserted.
+    pub fn with_insert_order(self, insert_order: InsertOrder) -> Self {
+        Self {
+            insert_order,
+            ..self
+        }
+    }
+
+    /// Only inserts each endpoint once it is ready, as indicated by the
+    /// future that `gate` returns for its service.
+    pub fn with_ready_gate<G>(self, gate: G) -> Layer<T, R, ReadyGate<G>> {
+        Layer {
+            capacity: self.capacity,
+            watchdog: self.watchdog,
+            resolve: self.resolve,
+            insert_order: self.insert_order,
+            ready_gate: ReadyGate::new(gate),
+            _marker: self._marker,
+        }
+    }
 }
 
 impl<T, R, M> tower::layer::Layer<M> for Layer<T, R>
@@ -54,8 +84,37 @@ where
     type Service = Buffer<MakeEndpoint<FromResolve<R>, M>>;
 
     fn layer(&self, make_endpoint: M) -> Self::Service {
-        let make_discover =
-            MakeEndpoint::new(make_endpoint, FromResolve::new(self.resolve.clone()));
+        let from_resolve =
+            FromResolve::new(self.resolve.clone()).with_insert_order(self.insert_order);
+        let make_discover = MakeEndpoint::new(make_endpoint, from_resolve)
+            .with_ordered_inserts(self.insert_order == InsertOrder::Sorted);
+        Buffer::new(self.capacity, self.watchdog, make_discover)
+    }
+}
+
+impl<T, R, M, G, F> tower::layer::Layer<M> for Layer<T, R, ReadyGate<G>>
+where
+    T: fmt::Display,
+    R: Resolve<T> + Send + Clone + 'static,
+    R::Error: Into<Error>,
+    R::Endpoint: fmt::Debug + Clone + PartialEq + Send,
+    R::Resolution: Send + 'static,
+    R::Future: Send + 'static,
+    M: tower::Service<R::Endpoint> + Clone + Send + 'static,
+    M::Error: Into<Error>,
+    M::Response: Send + 'static,
+    M::Future: Send + 'static,
+    G: Fn(&M::Response) -> F + Clone + Send + 'static,
+    F: Future<Output = ()> + Send + 'static,
+{
+    type Service = Buffer<MakeEndpoint<FromResolve<R>, MakeReady<M, G>>>;
+
+    fn layer(&self, make_endpoint: M) -> Self::Service {
+        let make_endpoint = self.ready_gate.clone().into_make(make_endpoint);
+        let from_resolve =
+            FromResolve::new(self.resolve.clone()).with_insert_order(self.insert_order);
+        let make_discover = MakeEndpoint::new(make_endpoint, from_resolve)
+            .with_ordered_inserts(self.insert_order == InsertOrder::Sorted);
         Buffer::new(self.capacity, self.watchdog, make_discover)
     }
 }
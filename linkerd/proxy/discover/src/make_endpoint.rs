@@ -1,18 +1,24 @@
 use futures::{ready, stream::FuturesUnordered, Stream, TryFuture};
-use indexmap::IndexMap;
+use indexmap::{IndexMap, IndexSet};
 use linkerd2_error::Error;
-use pin_project::pin_project;
+use pin_project::{pin_project, pinned_drop};
+use std::collections::{HashMap, VecDeque};
 use std::future::Future;
 use std::hash::Hash;
 use std::pin::Pin;
 use std::task::{Context, Poll};
+use std::time::Duration;
 use tokio::sync::oneshot;
+use tokio::time::{delay_queue, DelayQueue};
 use tower::discover::{self, Change};
 
 #[derive(Clone, Debug)]
 pub struct MakeEndpoint<D, E> {
     make_discover: D,
     make_endpoint: E,
+    remove_before_reinsert: bool,
+    remove_grace: Option<Duration>,
+    ordered_inserts: bool,
 }
 
 #[pin_project]
@@ -21,6 +27,9 @@ pub struct DiscoverFuture<F, M> {
     #[pin]
     future: F,
     make_endpoint: Option<M>,
+    remove_before_reinsert: bool,
+    remove_grace: Option<Duration>,
+    ordered_inserts: bool,
 }
 
 /// Observes an `R`-typed resolution stream, using an `M`-typed endpoint stack to
@@ -33,27 +42,57 @@ pub struct Discover<D: discover::Discover, E: tower::Service<D::Service>> {
     #[pin]
     make_futures: MakeFutures<D::Key, E::Future>,
     pending_removals: Vec<D::Key>,
+    /// The keys of the services that have been inserted and not removed.
+    inserted: IndexSet<D::Key>,
+    remove_before_reinsert: bool,
+    remove_grace: Option<Duration>,
+    /// Removals that are delayed by the grace period.
+    delayed_removals: DelayedRemovals<D::Key>,
 }
 
-#[pin_project]
-struct MakeFutures<K, F> {
+/// Removals that are released once their grace period elapses, unless they
+/// are canceled by a re-insert.
+struct DelayedRemovals<K> {
+    queue: DelayQueue<K>,
+    keys: IndexMap<K, delay_queue::Key>,
+}
+
+#[pin_project(PinnedDrop)]
+struct MakeFutures<K, F: TryFuture> {
     #[pin]
     futures: FuturesUnordered<MakeFuture<K, F>>,
     cancelations: IndexMap<K, oneshot::Sender<()>>,
+    /// When set, makes are released in the order in which they were pushed.
+    ordered: bool,
+    next_seq: u64,
+    /// The sequence numbers of the makes that have not been released, in the
+    /// order in which they were pushed, if `ordered` is set.
+    seqs: VecDeque<u64>,
+    /// Makes that have completed, but are held until all of the makes that
+    /// were pushed before them are released.
+    completed: HashMap<u64, Made<K, F::Ok>>,
 }
 
 #[pin_project]
 struct MakeFuture<K, F> {
+    seq: u64,
     key: Option<K>,
     #[pin]
     inner: F,
-    #[pin]
+    canceled: Option<oneshot::Receiver<()>>,
+}
+
+/// A completed make, which may still be canceled while it is held.
+struct Made<K, S> {
+    seq: u64,
+    key: K,
+    svc: S,
     canceled: oneshot::Receiver<()>,
 }
 
 enum MakeError<E> {
     Inner(E),
-    Canceled,
+    Canceled(u64),
 }
 
 // === impl MakeEndpoint ===
@@ -71,6 +110,49 @@ impl<D, E> MakeEndpoint<D, E> {
         Self {
             make_discover,
             make_endpoint,
+            remove_before_reinsert: false,
+            remove_grace: None,
+            ordered_inserts: false,
+        }
+    }
+
+    /// Delays the removal of each inserted service by `grace`, so that it
+    /// may continue to serve in-flight requests while its endpoint drains.
+    ///
+    /// A delayed removal is canceled if the endpoint is inserted again
+    /// before the grace period elapses. By default, services are removed
+    /// immediately.
+    pub fn with_remove_grace(self, grace: Duration) -> Self {
+        Self {
+            remove_grace: Some(grace),
+            ..self
+        }
+    }
+
+    /// When an endpoint that has already been inserted is inserted again,
+    /// e.g. because its metadata changed, removes the original service
+    /// before the new service is inserted.
+    ///
+    /// This ensures that a balancer never holds two services for the same
+    /// endpoint.
+    pub fn with_remove_before_reinsert(self, remove_before_reinsert: bool) -> Self {
+        Self {
+            remove_before_reinsert,
+            ..self
+        }
+    }
+
+    /// Inserts services in the order in which their endpoints were
+    /// discovered, rather than in the order in which they are built, e.g. so
+    /// that a `from_resolve::InsertOrder` is preserved.
+    ///
+    /// A service that is built early is held until the services of all of
+    /// the endpoints that were discovered before it have been inserted or
+    /// canceled, so a slow make delays the inserts that follow it.
+    pub fn with_ordered_inserts(self, ordered_inserts: bool) -> Self {
+        Self {
+            ordered_inserts,
+            ..self
         }
     }
 }
@@ -99,6 +181,9 @@ where
         DiscoverFuture {
             future,
             make_endpoint: Some(self.make_endpoint.clone()),
+            remove_before_reinsert: self.remove_before_reinsert,
+            remove_grace: self.remove_grace,
+            ordered_inserts: self.ordered_inserts,
         }
     }
 }
@@ -120,7 +205,14 @@ where
         let this = self.project();
         let resolution = ready!(this.future.try_poll(cx))?;
         let make_endpoint = this.make_endpoint.take().expect("polled after ready");
-        Poll::Ready(Ok(Discover::new(resolution, make_endpoint)))
+        let discover = Discover::new(resolution, make_endpoint)
+            .with_remove_before_reinsert(*this.remove_before_reinsert)
+            .with_ordered_inserts(*this.ordered_inserts);
+        let discover = match *this.remove_grace {
+            Some(grace) => discover.with_remove_grace(grace),
+            None => discover,
+        };
+        Poll::Ready(Ok(discover))
     }
 }
 
@@ -140,8 +232,46 @@ where
             make_endpoint,
             make_futures: MakeFutures::new(),
             pending_removals: Vec::new(),
+            inserted: IndexSet::new(),
+            remove_before_reinsert: false,
+            remove_grace: None,
+            delayed_removals: DelayedRemovals {
+                queue: DelayQueue::new(),
+                keys: IndexMap::new(),
+            },
         }
     }
+
+    /// See `MakeEndpoint::with_remove_grace`.
+    pub fn with_remove_grace(self, grace: Duration) -> Self {
+        Self {
+            remove_grace: Some(grace),
+            ..self
+        }
+    }
+
+    /// See `MakeEndpoint::with_remove_before_reinsert`.
+    pub fn with_remove_before_reinsert(self, remove_before_reinsert: bool) -> Self {
+        Self {
+            remove_before_reinsert,
+            ..self
+        }
+    }
+
+    /// See `MakeEndpoint::with_ordered_inserts`.
+    pub fn with_ordered_inserts(mut self, ordered_inserts: bool) -> Self {
+        self.make_futures.ordered = ordered_inserts;
+        self
+    }
+
+    /// Returns the number of endpoints whose services are being built.
+    ///
+    /// Makes that have been canceled, e.g. because their endpoint was
+    /// removed, are not counted. Services that are built, but held so that
+    /// they are inserted in order, are counted.
+    pub fn pending_makes(&self) -> usize {
+        self.make_futures.cancelations.len()
+    }
 }
 
 impl<D, E> Stream for Discover<D, E>
@@ -162,8 +292,15 @@ where
             return Poll::Ready(Some(Ok(Change::Remove(key?))));
         }
 
-        if let Poll::Ready(Some(res)) = self.project().make_futures.poll_next(cx) {
+        let this = self.project();
+        if let Poll::Ready(key) = this.delayed_removals.poll_expired(cx) {
+            this.inserted.remove(&key);
+            return Poll::Ready(Some(Ok(Change::Remove(key))));
+        }
+
+        if let Poll::Ready(Some(res)) = this.make_futures.poll_next(cx) {
             let (key, svc) = res.map_err(Into::into)?;
+            this.inserted.insert(key.clone());
             return Poll::Ready(Some(Ok(Change::Insert(key, svc))));
         }
 
@@ -187,6 +324,7 @@ where
             let mut this = self.as_mut().project();
             if let Some(key) = this.pending_removals.pop() {
                 this.make_futures.remove(&key);
+                this.inserted.remove(&key);
                 return Poll::Ready(Ok(key));
             }
 
@@ -200,19 +338,71 @@ where
                 .map_err(Into::into)?
             {
                 Change::Insert(key, target) => {
+                    // The endpoint is back, so its original service need not
+                    // be removed.
+                    this.delayed_removals.cancel(&key);
+
                     // Start building the service and continue. If a pending
                     // service exists for this addr, it will be canceled.
                     let fut = this.make_endpoint.call(target);
-                    this.make_futures.push(key, fut);
-                }
-                Change::Remove(key) => {
-                    this.pending_removals.push(key);
+                    this.make_futures.push(key.clone(), fut);
+
+                    // The original service is removed immediately, so it is
+                    // necessarily removed before its replacement is built
+                    // and inserted.
+                    if *this.remove_before_reinsert && this.inserted.remove(&key) {
+                        return Poll::Ready(Ok(key));
+                    }
                 }
+                Change::Remove(key) => match *this.remove_grace {
+                    // Inserted services continue to serve until the grace
+                    // period elapses, though a replacement that is still
+                    // being built is canceled.
+                    Some(grace) if this.inserted.contains(&key) => {
+                        this.make_futures.remove(&key);
+                        this.delayed_removals.delay(key, grace);
+                    }
+                    _ => this.pending_removals.push(key),
+                },
             }
         }
     }
 }
 
+// === impl DelayedRemovals ===
+
+impl<K: Clone + Eq + Hash> DelayedRemovals<K> {
+    fn delay(&mut self, key: K, grace: Duration) {
+        if !self.keys.contains_key(&key) {
+            let delay = self.queue.insert(key.clone(), grace);
+            self.keys.insert(key, delay);
+        }
+    }
+
+    fn cancel(&mut self, key: &K) {
+        if let Some(delay) = self.keys.remove(key) {
+            self.queue.remove(&delay);
+        }
+    }
+
+    fn poll_expired(&mut self, cx: &mut Context<'_>) -> Poll<K> {
+        match ready!(self.queue.poll_expired(cx)) {
+            Some(Ok(expired)) => {
+                let key = expired.into_inner();
+                self.keys.remove(&key);
+                Poll::Ready(key)
+            }
+            Some(Err(error)) => {
+                tracing::warn!(%error, "Timer failed; removals remain delayed");
+                Poll::Pending
+            }
+            // There are no delayed removals; the queue is polled again once
+            // one is added.
+            None => Poll::Pending,
+        }
+    }
+}
+
 // === impl MakeFutures ===
 
 impl<K: Clone + Eq + Hash, F: TryFuture> MakeFutures<K, F> {
@@ -220,6 +410,10 @@ impl<K: Clone + Eq + Hash, F: TryFuture> MakeFutures<K, F> {
         Self {
             futures: FuturesUnordered::new(),
             cancelations: IndexMap::new(),
+            ordered: false,
+            next_seq: 0,
+            seqs: VecDeque::new(),
+            completed: HashMap::new(),
         }
     }
 
@@ -228,10 +422,16 @@ impl<K: Clone + Eq + Hash, F: TryFuture> MakeFutures<K, F> {
         if let Some(prior) = self.cancelations.insert(key.clone(), cancel) {
             let _ = prior.send(());
         }
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        if self.ordered {
+            self.seqs.push_back(seq);
+        }
         self.futures.push(MakeFuture {
+            seq,
             key: Some(key),
             inner,
-            canceled,
+            canceled: Some(canceled),
         });
     }
 
@@ -242,22 +442,77 @@ impl<K: Clone + Eq + Hash, F: TryFuture> MakeFutures<K, F> {
     }
 }
 
+/// Cancels all pending makes when the stream is dropped, so that a torn-down
+/// `Discover` cancels its makes just as removing each endpoint would.
+#[pinned_drop]
+impl<K, F: TryFuture> PinnedDrop for MakeFutures<K, F> {
+    fn drop(self: Pin<&mut Self>) {
+        for (_, cancel) in self.project().cancelations.drain(..) {
+            let _ = cancel.send(());
+        }
+    }
+}
+
+impl<K, F: TryFuture> MakeFutures<K, F> {
+    /// The number of canceled makes that may be skipped before the stream
+    /// yields, so that cancelation churn cannot monopolize the task.
+    const MAX_CANCELED_PER_POLL: usize = 32;
+}
+
 impl<K: Eq + Hash, F: TryFuture> Stream for MakeFutures<K, F> {
     type Item = Result<(K, F::Ok), F::Error>;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut canceled = 0;
         loop {
             let this = self.as_mut().project();
-            return match ready!(this.futures.poll_next(cx)) {
-                Some(Err(MakeError::Canceled)) => continue,
-                Some(Err(MakeError::Inner(err))) => Poll::Ready(Some(Err(err))),
-                Some(Ok((key, svc))) => {
+            let head = match this.seqs.front() {
+                Some(seq) => this.completed.remove(seq),
+                None => None,
+            };
+            let made = match head {
+                // The earliest unreleased make has completed, so it is
+                // released unless it was canceled while it was held.
+                Some(mut made) => {
+                    this.seqs.pop_front();
+                    match made.canceled.try_recv() {
+                        Ok(()) => None,
+                        Err(_) => Some(made),
+                    }
+                }
+                None => match ready!(this.futures.poll_next(cx)) {
+                    Some(Err(MakeError::Canceled(seq))) => {
+                        if let Some(i) = this.seqs.iter().position(|s| *s == seq) {
+                            this.seqs.remove(i);
+                        }
+                        None
+                    }
+                    Some(Err(MakeError::Inner(err))) => return Poll::Ready(Some(Err(err))),
+                    Some(Ok(made)) if *this.ordered => {
+                        this.completed.insert(made.seq, made);
+                        continue;
+                    }
+                    Some(Ok(made)) => Some(made),
+                    None => return Poll::Ready(None),
+                },
+            };
+
+            match made {
+                Some(Made { key, svc, .. }) => {
                     let _rm = this.cancelations.remove(&key);
                     debug_assert!(_rm.is_some(), "cancelation missing");
-                    Poll::Ready(Some(Ok((key, svc))))
+                    return Poll::Ready(Some(Ok((key, svc))));
                 }
-                None => Poll::Ready(None),
-            };
+                None => {
+                    canceled += 1;
+                    if canceled == Self::MAX_CANCELED_PER_POLL {
+                        // Yield so that the resolution may be polled, and
+                        // resume skipping canceled makes on the next poll.
+                        cx.waker().wake_by_ref();
+                        return Poll::Pending;
+                    }
+                }
+            }
         }
     }
 }
@@ -265,16 +520,21 @@ impl<K: Eq + Hash, F: TryFuture> Stream for MakeFutures<K, F> {
 // === impl MakeFuture ===
 
 impl<K, F: TryFuture> Future for MakeFuture<K, F> {
-    type Output = Result<(K, F::Ok), MakeError<F::Error>>;
+    type Output = Result<Made<K, F::Ok>, MakeError<F::Error>>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let this = self.project();
-        if let Poll::Ready(Ok(())) = this.canceled.poll(cx) {
-            return Poll::Ready(Err(MakeError::Canceled));
+        let canceled = this.canceled.as_mut().expect("polled after complete");
+        if let Poll::Ready(Ok(())) = Pin::new(canceled).poll(cx) {
+            return Poll::Ready(Err(MakeError::Canceled(*this.seq)));
         }
         let svc = ready!(this.inner.try_poll(cx))?;
-        let key = this.key.take().expect("polled after complete");
-        Poll::Ready(Ok((key, svc)))
+        Poll::Ready(Ok(Made {
+            seq: *this.seq,
+            key: this.key.take().expect("polled after complete"),
+            svc,
+            canceled: this.canceled.take().expect("polled after complete"),
+        }))
     }
 }
 
@@ -493,6 +753,89 @@ mod tests {
         );
     }
 
+    #[test]
+    fn pending_makes_tracks_in_flight_makes() {
+        let (mut reso_tx, reso_rx) = mpsc::channel(3);
+        let mut makes = Vec::new();
+        let mut make_rxs = Vec::new();
+        for _ in 0..3 {
+            let (tx, rx) = oneshot::channel::<()>();
+            makes.push(tx);
+            make_rxs.push(rx);
+        }
+        let mut discover = task::spawn(Discover::new(Dx(reso_rx), Svc(make_rxs)));
+        assert_pending!(discover.poll_next(), "ready without updates");
+        assert_eq!(discover.pending_makes(), 0);
+
+        for port in 0..3 {
+            let addr = SocketAddr::from(([127, 0, 0, 1], port));
+            reso_tx.try_send(Change::Insert(addr, ())).ok().unwrap();
+        }
+        assert_pending!(discover.poll_next(), "ready without service being made");
+        assert_eq!(discover.pending_makes(), 3);
+
+        for (i, make) in makes.into_iter().enumerate() {
+            make.send(()).unwrap();
+            match assert_ready!(discover.poll_next())
+                .expect("discover stream mustn't end")
+                .expect("discover can't fail")
+            {
+                Change::Insert(..) => {}
+                Change::Remove(..) => panic!("unexpected remove"),
+            }
+            assert_eq!(discover.pending_makes(), 2 - i);
+        }
+    }
+
+    #[tokio::test]
+    async fn remove_grace_delays_removal() {
+        use futures::StreamExt;
+        use tokio::time;
+
+        let grace = Duration::from_millis(50);
+        let (mut tx, reso_rx) = mpsc::channel(2);
+        let discover = Discover::new(Dx(reso_rx), service_fn(|()| future::ok::<(), Error>(())))
+            .with_remove_grace(grace);
+        futures::pin_mut!(discover);
+        let addr = SocketAddr::from(([127, 0, 0, 1], 80));
+
+        tx.try_send(Change::Insert(addr, ())).ok().unwrap();
+        match discover.next().await.unwrap().unwrap() {
+            Change::Insert(a, ()) => assert_eq!(a, addr),
+            Change::Remove(..) => panic!("unexpected remove"),
+        }
+
+        // The removal is released once the grace period elapses.
+        tx.try_send(Change::Remove(addr)).ok().unwrap();
+        let next = time::timeout(grace / 2, discover.next()).await;
+        assert!(next.is_err(), "removal must be delayed");
+        match time::timeout(grace, discover.next()).await {
+            Ok(Some(Ok(Change::Remove(a)))) => assert_eq!(a, addr),
+            _ => panic!("removal must be released after the grace period"),
+        }
+
+        // A re-insert cancels the delayed removal.
+        tx.try_send(Change::Insert(addr, ())).ok().unwrap();
+        match discover.next().await.unwrap().unwrap() {
+            Change::Insert(a, ()) => assert_eq!(a, addr),
+            Change::Remove(..) => panic!("unexpected remove"),
+        }
+        tx.try_send(Change::Remove(addr)).ok().unwrap();
+        let next = time::timeout(grace / 2, discover.next()).await;
+        assert!(next.is_err(), "removal must be delayed");
+        tx.try_send(Change::Insert(addr, ())).ok().unwrap();
+        match discover.next().await.unwrap().unwrap() {
+            Change::Insert(a, ()) => assert_eq!(a, addr),
+            Change::Remove(..) => panic!("unexpected remove"),
+        }
+        let next = time::timeout(grace * 2, discover.next()).await;
+        assert!(
+            next.is_err(),
+            "removal must be canceled; got {:?}",
+            next.map(|_| ())
+        );
+    }
+
     #[test]
     fn cancelation_of_pending_service() {
         let (mut tx, reso_rx) = mpsc::channel(1);
@@ -525,4 +868,225 @@ mod tests {
             "cancelation remains"
         );
     }
+
+    #[test]
+    fn reinsert_removes_original_first() {
+        let (mut tx, reso_rx) = mpsc::channel(2);
+        let mut discover = task::spawn(
+            Discover::new(Dx(reso_rx), service_fn(|()| future::ok::<(), Error>(())))
+                .with_remove_before_reinsert(true),
+        );
+        assert_pending!(discover.poll_next(), "ready without updates");
+
+        let addr = SocketAddr::from(([127, 0, 0, 1], 80));
+        tx.try_send(Change::Insert(addr, ())).ok().unwrap();
+        match assert_ready!(discover.poll_next())
+            .expect("discover stream mustn't end")
+            .expect("discover can't fail")
+        {
+            Change::Insert(a, ()) => assert_eq!(a, addr),
+            Change::Remove(..) => panic!("unexpected remove"),
+        }
+
+        // The endpoint's metadata changed, so it's inserted again.
+        tx.try_send(Change::Insert(addr, ())).ok().unwrap();
+        match assert_ready!(discover.poll_next())
+            .expect("discover stream mustn't end")
+            .expect("discover can't fail")
+        {
+            Change::Insert(..) => panic!("original service must be removed first"),
+            Change::Remove(a) => assert_eq!(a, addr),
+        }
+        match assert_ready!(discover.poll_next())
+            .expect("discover stream mustn't end")
+            .expect("discover can't fail")
+        {
+            Change::Insert(a, ()) => assert_eq!(a, addr),
+            Change::Remove(..) => panic!("unexpected remove"),
+        }
+        assert_pending!(discover.poll_next());
+    }
+
+    #[test]
+    fn ready_gate_holds_insert_until_ready() {
+        use crate::ready_gate::ReadyGate;
+        use tokio::sync::watch;
+
+        let (ready_tx, ready_rx) = watch::channel(false);
+        let (mut tx, reso_rx) = mpsc::channel(2);
+        let gate = ReadyGate::new(|rx: &watch::Receiver<bool>| {
+            let mut rx = rx.clone();
+            async move {
+                while !*rx.borrow() {
+                    rx.recv().await;
+                }
+            }
+        });
+        let make = service_fn(move |()| future::ok::<_, Error>(ready_rx.clone()));
+        let mut discover = task::spawn(Discover::new(Dx(reso_rx), gate.into_make(make)));
+
+        let addr = SocketAddr::from(([127, 0, 0, 1], 80));
+        tx.try_send(Change::Insert(addr, ())).ok().unwrap();
+        assert_pending!(
+            discover.poll_next(),
+            "service must not be inserted until ready"
+        );
+
+        ready_tx.broadcast(true).unwrap();
+        assert!(discover.is_woken());
+        match assert_ready!(discover.poll_next())
+            .expect("discover stream mustn't end")
+            .expect("discover can't fail")
+        {
+            Change::Insert(a, rx) => {
+                assert_eq!(a, addr);
+                assert!(*rx.borrow());
+            }
+            Change::Remove(..) => panic!("unexpected remove"),
+        }
+    }
+
+    #[test]
+    fn dropping_discover_cancels_pending_makes() {
+        let (mut tx, reso_rx) = mpsc::channel(2);
+
+        let mut discover = task::spawn(Discover::new(
+            Dx(reso_rx),
+            service_fn(|()| future::pending::<Result<Svc<()>, Error>>()),
+        ));
+        assert_pending!(discover.poll_next(), "ready without updates");
+
+        let addr0 = SocketAddr::from(([127, 0, 0, 1], 80));
+        let addr1 = SocketAddr::from(([127, 0, 0, 2], 80));
+        tx.try_send(Change::Insert(addr0, ())).ok().unwrap();
+        tx.try_send(Change::Insert(addr1, ())).ok().unwrap();
+        assert_pending!(discover.poll_next(), "ready without service being made");
+        assert_eq!(
+            discover.make_futures.cancelations.len(),
+            2,
+            "no pending cancelation"
+        );
+
+        // Hold on to the pending makes so that they outlive the discover.
+        let futures =
+            std::mem::replace(&mut discover.make_futures.futures, FuturesUnordered::new());
+        drop(discover);
+
+        let mut futures = task::spawn(futures);
+        for _ in 0..2 {
+            match assert_ready!(futures.poll_next()) {
+                Some(Err(MakeError::Canceled(_))) => {}
+                _ => panic!("pending make must be canceled"),
+            }
+        }
+        assert!(assert_ready!(futures.poll_next()).is_none());
+    }
+
+    #[test]
+    fn canceled_makes_yield() {
+        type Makes = MakeFutures<usize, future::Pending<Result<(), Error>>>;
+
+        let mut makes = task::spawn(Makes::new());
+        let count = Makes::MAX_CANCELED_PER_POLL * 3;
+        for key in 0..count {
+            makes.push(key, future::pending());
+            makes.remove(&key);
+        }
+
+        // Each poll skips a bounded number of canceled makes before yielding,
+        // but the stream is woken to make progress.
+        let mut polls = 0;
+        loop {
+            polls += 1;
+            match makes.poll_next() {
+                Poll::Pending => assert!(makes.is_woken(), "yielded without waking"),
+                Poll::Ready(None) => break,
+                Poll::Ready(Some(_)) => panic!("canceled make must be skipped"),
+            }
+        }
+        assert_eq!(polls, 4);
+    }
+
+    #[test]
+    fn removed_ready_make_is_not_inserted() {
+        let (mut tx, reso_rx) = mpsc::channel(2);
+        let mut discover = task::spawn(Discover::new(
+            Dx(reso_rx),
+            service_fn(|()| future::ok::<(), Error>(())),
+        ));
+        assert_pending!(discover.poll_next(), "ready without updates");
+
+        // The endpoint is removed after its make is ready, but before the make
+        // is polled.
+        let addr = SocketAddr::from(([127, 0, 0, 1], 80));
+        tx.try_send(Change::Insert(addr, ())).ok().unwrap();
+        tx.try_send(Change::Remove(addr)).ok().unwrap();
+        match assert_ready!(discover.poll_next())
+            .expect("discover stream mustn't end")
+            .expect("discover can't fail")
+        {
+            Change::Insert(..) => panic!("unexpected insert"),
+            Change::Remove(a) => assert_eq!(a, addr),
+        }
+        assert_pending!(discover.poll_next(), "removed service must not be inserted");
+        assert_eq!(discover.pending_makes(), 0);
+    }
+
+    #[test]
+    fn sorted_inserts_are_ordered_across_makes() {
+        use crate::from_resolve::{self, InsertOrder};
+        use linkerd2_proxy_core::{resolve::Update, test_util::Updates};
+
+        let addr = |n: u8| SocketAddr::from(([127, 0, 0, n], 80));
+        let updates = Updates::default();
+        updates.push(Update::Add(vec![
+            (addr(3), ()),
+            (addr(1), ()),
+            (addr(2), ()),
+        ]));
+        let resolved =
+            from_resolve::Discover::new(updates.clone()).with_insert_order(InsertOrder::Sorted);
+
+        // The endpoints' services are made in sorted order, but they are built
+        // in reverse.
+        let (make1_tx, make1_rx) = oneshot::channel::<usize>();
+        let (make2_tx, make2_rx) = oneshot::channel::<usize>();
+        let (make3_tx, make3_rx) = oneshot::channel::<usize>();
+        let mut discover = task::spawn(
+            Discover::new(resolved, Svc(vec![make3_rx, make2_rx, make1_rx]))
+                .with_ordered_inserts(true),
+        );
+        assert_pending!(discover.poll_next(), "ready without service being made");
+        assert_eq!(discover.pending_makes(), 3);
+
+        make3_tx.send(3).unwrap();
+        make2_tx.send(2).unwrap();
+        assert_pending!(discover.poll_next(), "inserted before earlier endpoints");
+
+        // A held service is not inserted once its endpoint is removed.
+        updates.push(Update::Remove(vec![addr(2)]));
+        match assert_ready!(discover.poll_next())
+            .expect("discover stream mustn't end")
+            .expect("discover can't fail")
+        {
+            Change::Remove(a) => assert_eq!(a, addr(2)),
+            Change::Insert(a, _) => panic!("unexpected insert of {}", a),
+        }
+
+        make1_tx.send(1).unwrap();
+        for n in &[1, 3] {
+            match assert_ready!(discover.poll_next())
+                .expect("discover stream mustn't end")
+                .expect("discover can't fail")
+            {
+                Change::Insert(a, svc) => {
+                    assert_eq!(a, addr(*n));
+                    assert_eq!(svc, *n as usize);
+                }
+                Change::Remove(a) => panic!("unexpected remove of {}", a),
+            }
+        }
+        assert_pending!(discover.poll_next(), "removed service must not be inserted");
+        assert_eq!(discover.pending_makes(), 0);
+    }
 }
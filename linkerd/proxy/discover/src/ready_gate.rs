@@ -0,0 +1,111 @@
+//! Holds back newly-made endpoint services until they report that they are
+//! ready to receive traffic, so that a balancer does not dispatch requests to
+//! backends that are still starting.
+//!
+//! Services are held in the `Discover`'s set of pending makes, so a service
+//! that is removed before it becomes ready is never inserted.
+
+use futures::{ready, TryFuture};
+use pin_project::pin_project;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// Determines when a made service is ready to be inserted.
+///
+/// `G` produces a future from each made service that completes once the
+/// service is ready.
+#[derive(Clone, Debug)]
+pub struct ReadyGate<G>(G);
+
+/// Makes services with an inner `M`-typed make service, completing only once
+/// the made service is ready.
+#[derive(Clone, Debug)]
+pub struct MakeReady<M, G> {
+    make: M,
+    gate: G,
+}
+
+#[pin_project(project = MakeReadyProj)]
+pub enum MakeReadyFuture<F: TryFuture, G, R> {
+    Making {
+        #[pin]
+        future: F,
+        gate: Option<G>,
+    },
+    Gated {
+        #[pin]
+        ready: R,
+        service: Option<F::Ok>,
+    },
+}
+
+// === impl ReadyGate ===
+
+impl<G> ReadyGate<G> {
+    pub fn new(gate: G) -> Self {
+        ReadyGate(gate)
+    }
+
+    pub(crate) fn into_make<M>(self, make: M) -> MakeReady<M, G> {
+        MakeReady { make, gate: self.0 }
+    }
+}
+
+// === impl MakeReady ===
+
+impl<T, M, G, R> tower::Service<T> for MakeReady<M, G>
+where
+    M: tower::Service<T>,
+    G: Fn(&M::Response) -> R + Clone,
+    R: Future<Output = ()>,
+{
+    type Response = M::Response;
+    type Error = M::Error;
+    type Future = MakeReadyFuture<M::Future, G, R>;
+
+    #[inline]
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.make.poll_ready(cx)
+    }
+
+    #[inline]
+    fn call(&mut self, target: T) -> Self::Future {
+        MakeReadyFuture::Making {
+            future: self.make.call(target),
+            gate: Some(self.gate.clone()),
+        }
+    }
+}
+
+// === impl MakeReadyFuture ===
+
+impl<F, G, R> Future for MakeReadyFuture<F, G, R>
+where
+    F: TryFuture,
+    G: Fn(&F::Ok) -> R,
+    R: Future<Output = ()>,
+{
+    type Output = Result<F::Ok, F::Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        loop {
+            match self.as_mut().project() {
+                MakeReadyProj::Making { future, gate } => {
+                    let service = ready!(future.try_poll(cx))?;
+                    let gate = gate.take().expect("polled after ready");
+                    let ready = gate(&service);
+                    self.set(MakeReadyFuture::Gated {
+                        ready,
+                        service: Some(service),
+                    });
+                }
+                MakeReadyProj::Gated { ready, service } => {
+                    ready!(ready.poll(cx));
+                    let service = service.take().expect("polled after ready");
+                    return Poll::Ready(Ok(service));
+                }
+            }
+        }
+    }
+}
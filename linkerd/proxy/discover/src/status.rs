@@ -0,0 +1,76 @@
+//! Reports on the endpoints a `Discover` currently holds, e.g. so that the
+//! proxy may shed load when none of a destination's endpoints can serve it.
+
+use indexmap::IndexMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+/// A handle that reports whether a `Discover` holds any endpoints and whether
+/// any of them are ready.
+///
+/// By default, every endpoint that is present is considered ready. If
+/// readiness is tracked, endpoints are not ready until they are marked ready
+/// via `set_ready`.
+#[derive(Clone, Debug, Default)]
+pub struct EndpointStatus(Arc<Mutex<Inner>>);
+
+#[derive(Debug, Default)]
+struct Inner {
+    track_readiness: bool,
+    /// Each present endpoint's readiness.
+    endpoints: IndexMap<SocketAddr, bool>,
+}
+
+// === impl EndpointStatus ===
+
+impl EndpointStatus {
+    /// Returns a status in which endpoints are not ready until they are
+    /// marked ready.
+    pub fn tracking_readiness() -> Self {
+        Self(Arc::new(Mutex::new(Inner {
+            track_readiness: true,
+            endpoints: IndexMap::new(),
+        })))
+    }
+
+    /// Returns true if no endpoints are present.
+    pub fn is_empty(&self) -> bool {
+        self.0.lock().unwrap().endpoints.is_empty()
+    }
+
+    /// Returns true if at least one present endpoint is ready.
+    pub fn any_ready(&self) -> bool {
+        self.0
+            .lock()
+            .unwrap()
+            .endpoints
+            .values()
+            .any(|ready| *ready)
+    }
+
+    /// Records whether the endpoint at `addr` is ready.
+    ///
+    /// Has no effect if the endpoint is not present or if readiness is not
+    /// tracked.
+    pub fn set_ready(&self, addr: SocketAddr, ready: bool) {
+        let mut inner = self.0.lock().unwrap();
+        if !inner.track_readiness {
+            return;
+        }
+        if let Some(r) = inner.endpoints.get_mut(&addr) {
+            *r = ready;
+        }
+    }
+
+    /// Notes that the endpoint at `addr` is present, retaining its readiness
+    /// if it was already present.
+    pub(crate) fn insert(&self, addr: SocketAddr) {
+        let mut inner = self.0.lock().unwrap();
+        let ready = !inner.track_readiness;
+        inner.endpoints.entry(addr).or_insert(ready);
+    }
+
+    pub(crate) fn remove(&self, addr: &SocketAddr) {
+        self.0.lock().unwrap().endpoints.swap_remove(addr);
+    }
+}
@@ -0,0 +1,574 @@
+#![deny(warnings, rust_2018_idioms)]
+
+//! Resolves a name's endpoints via DNS.
+
+pub mod refine;
+pub mod srv;
+
+use futures::{future, prelude::*, ready};
+use indexmap::IndexSet;
+use linkerd2_addr::NameAddr;
+use linkerd2_dns as dns;
+use linkerd2_proxy_api_resolve::Metadata;
+use linkerd2_proxy_core::resolve::{
+    self, DiscoverySource, ErrorKind, Refresh, ResolveError, Update,
+};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::time;
+use tracing::{debug, trace};
+
+/// Resolves a `NameAddr` to an endpoint for each of the name's addresses.
+///
+/// The name is looked up again when its records' TTL expires, so that the
+/// resolution tracks changes to the name's addresses.
+#[derive(Clone, Debug)]
+pub struct Resolve {
+    dns: dns::Resolver,
+    family: dns::FamilyPreference,
+    prefetch: Option<Duration>,
+    max_addresses: Option<usize>,
+    loopback_shortcut: bool,
+}
+
+pub struct Resolution {
+    dns: dns::Resolver,
+    family: dns::FamilyPreference,
+    prefetch: Option<Duration>,
+    max_addresses: Option<usize>,
+    target: NameAddr,
+    /// The addresses that have been most recently added.
+    addrs: IndexSet<SocketAddr>,
+    /// An update that has been produced but not yet returned.
+    pending: Option<Update<Metadata>>,
+    refresh: Refresh,
+    state: State,
+}
+
+enum State {
+    Init,
+    Pending(dns::IpListFuture),
+    Valid(time::Delay),
+    /// The endpoints are known without a lookup, so the resolution never
+    /// changes.
+    Static,
+}
+
+// === impl Resolve ===
+
+impl Resolve {
+    pub fn new(dns: dns::Resolver) -> Self {
+        Self {
+            dns,
+            family: dns::FamilyPreference::default(),
+            prefetch: None,
+            max_addresses: None,
+            loopback_shortcut: false,
+        }
+    }
+
+    /// Only produces endpoints for addresses permitted by `family`.
+    ///
+    /// This should be used to drop the addresses of families that this proxy
+    /// cannot reach, e.g. IPv6 addresses on an IPv4-only network.
+    pub fn with_family_preference(self, family: dns::FamilyPreference) -> Self {
+        Self { family, ..self }
+    }
+
+    /// Looks each name up again `margin` before its records expire, so that
+    /// the new addresses are known before the old ones expire.
+    ///
+    /// The current endpoints are retained while the lookup is in flight. Each
+    /// resolution has at most one lookup in flight. By default, names are
+    /// looked up again once their records expire.
+    pub fn with_prefetch(self, margin: Duration) -> Self {
+        Self {
+            prefetch: Some(margin),
+            ..self
+        }
+    }
+
+    /// Produces endpoints for at most `max` of a name's addresses.
+    ///
+    /// Addresses are limited after they are ordered by the family preference,
+    /// so that preferred addresses are retained. By default, an endpoint is
+    /// produced for each address.
+    pub fn with_max_addresses(self, max: usize) -> Self {
+        Self {
+            max_addresses: Some(max),
+            ..self
+        }
+    }
+
+    /// Resolves `localhost` to the loopback addresses permitted by the family
+    /// preference, without a lookup.
+    ///
+    /// Only the name `localhost` itself is short-circuited; its subdomains
+    /// and other names that contain a `localhost` label are looked up as
+    /// usual. By default, `localhost` is looked up like any other name.
+    pub fn with_loopback_shortcut(self, loopback_shortcut: bool) -> Self {
+        Self {
+            loopback_shortcut,
+            ..self
+        }
+    }
+}
+
+impl tower::Service<NameAddr> for Resolve {
+    type Response = Resolution;
+    type Error = ResolveError;
+    type Future = future::Ready<Result<Resolution, ResolveError>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, target: NameAddr) -> Self::Future {
+        let resolution = Resolution::new(self.dns.clone(), self.family, target);
+        let mut resolution = Resolution {
+            prefetch: self.prefetch,
+            max_addresses: self.max_addresses,
+            ..resolution
+        };
+        if self.loopback_shortcut && is_localhost(resolution.target.name()) {
+            debug!(target = %resolution.target, "Resolving loopback endpoints");
+            resolution.pending = Some(resolution.loopback());
+            resolution.state = State::Static;
+        }
+        future::ok(resolution)
+    }
+}
+
+// === impl Resolution ===
+
+impl Resolution {
+    fn new(dns: dns::Resolver, family: dns::FamilyPreference, target: NameAddr) -> Self {
+        Self {
+            dns,
+            family,
+            prefetch: None,
+            max_addresses: None,
+            target,
+            addrs: IndexSet::new(),
+            pending: None,
+            refresh: Refresh::default(),
+            state: State::Init,
+        }
+    }
+
+    /// Returns a handle that looks up the name again on demand, rather than
+    /// when its records expire.
+    ///
+    /// The endpoints are retained until the lookup completes, and addresses
+    /// that are still resolved are updated in place. A refresh has no effect
+    /// on a resolution that does not require a lookup.
+    pub fn refresh_handle(&self) -> Refresh {
+        self.refresh.clone()
+    }
+
+    /// Builds an endpoint for each loopback address permitted by the family
+    /// preference. The endpoints do not expire.
+    fn loopback(&mut self) -> Update<Metadata> {
+        let port = self.target.port();
+        let loopback = [
+            IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ];
+        let endpoints = self
+            .family
+            .apply(loopback.iter().cloned())
+            .into_iter()
+            .map(|ip| (SocketAddr::new(ip, port), Metadata::empty()))
+            .collect::<Vec<_>>();
+        if endpoints.is_empty() {
+            return Update::Empty;
+        }
+        self.addrs = endpoints.iter().map(|(addr, _)| *addr).collect();
+        Update::Add(endpoints)
+    }
+
+    /// Diffs a lookup's addresses against the current set of addresses.
+    ///
+    /// Addresses that are no longer present are removed before the new set of
+    /// endpoints is added. Addresses that are still present are added again so
+    /// that their metadata reflects the new lookup's expiry.
+    ///
+    /// If none of the addresses are permitted by the family preference, the
+    /// resolution becomes empty.
+    fn update(&mut self, ips: dns::IpList) -> Update<Metadata> {
+        let mut endpoints = to_endpoints(&ips, self.family, self.target.port());
+        if let Some(max) = self.max_addresses {
+            if endpoints.len() > max {
+                debug!(endpoints = endpoints.len(), %max, "Limiting endpoints");
+                endpoints.truncate(max);
+            }
+        }
+        if endpoints.is_empty() {
+            self.addrs.clear();
+            return Update::Empty;
+        }
+
+        let addrs = endpoints
+            .iter()
+            .map(|(addr, _)| *addr)
+            .collect::<IndexSet<_>>();
+        let removed = self.addrs.difference(&addrs).cloned().collect::<Vec<_>>();
+        self.addrs = addrs;
+
+        let add = Update::Add(endpoints);
+        if removed.is_empty() {
+            return add;
+        }
+        self.pending = Some(add);
+        Update::Remove(removed)
+    }
+}
+
+impl resolve::Resolution for Resolution {
+    type Endpoint = Metadata;
+    type Error = ResolveError;
+
+    fn poll(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Update<Self::Endpoint>, Self::Error>> {
+        let this = self.get_mut();
+        if let Some(update) = this.pending.take() {
+            return Poll::Ready(Ok(update));
+        }
+
+        if this.refresh.poll_requested(cx) {
+            if let State::Valid(_) = this.state {
+                debug!("Refreshing on demand");
+                this.state = State::Init;
+            }
+        }
+
+        loop {
+            match this.state {
+                State::Init => {
+                    let lookup = this.dns.resolve_all_ips(this.target.name());
+                    this.state = State::Pending(lookup);
+                }
+                State::Pending(ref mut lookup) => {
+                    let ips = ready!(lookup.as_mut().poll(cx)).map_err(to_resolve_error)?;
+                    trace!(?ips, "Resolved");
+                    let refresh = refresh_at(ips.valid_until(), this.prefetch, Instant::now());
+                    this.state = State::Valid(time::delay_until(time::Instant::from_std(refresh)));
+                    return Poll::Ready(Ok(this.update(ips)));
+                }
+                State::Valid(ref mut expiry) => {
+                    ready!(expiry.poll_unpin(cx));
+                    trace!("Refreshing");
+                    this.state = State::Init;
+                }
+                State::Static => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/// Returns true if `name` is `localhost`, whether or not it is absolute.
+fn is_localhost(name: &dns::Name) -> bool {
+    name.without_trailing_dot()
+        .eq_ignore_ascii_case("localhost")
+}
+
+/// Returns true if a failed lookup indicates that the name definitively has no
+/// addresses, so that its endpoints should be cleared.
+///
+/// Only negative answers from a nameserver, e.g. `NXDOMAIN`, or a lookup that
+/// produced no usable addresses are definitive. Other failures, including
+/// timeouts and `SERVFAIL` responses, are transient: the prior endpoints
+/// should be retained until the name can be looked up again.
+pub fn should_clear_endpoints(error: &dns::Error) -> bool {
+    match error {
+        dns::Error::NoAddressesFound => true,
+        dns::Error::ResolutionFailed(e) => match e.kind() {
+            dns::ResolveErrorKind::NoRecordsFound { response_code, .. } => matches!(
+                response_code,
+                dns::ResponseCode::NXDomain | dns::ResponseCode::NoError
+            ),
+            _ => false,
+        },
+        dns::Error::TaskLost | dns::Error::Saturated(_) | dns::Error::Canceled => false,
+    }
+}
+
+/// Categorizes a failed lookup.
+fn to_resolve_error(error: dns::Error) -> ResolveError {
+    let kind = if should_clear_endpoints(&error) {
+        ErrorKind::NotFound
+    } else {
+        ErrorKind::Transient
+    };
+    ResolveError::new(DiscoverySource::Dns, kind, error)
+}
+
+/// Determines when a lookup whose records are valid until `valid_until`
+/// should be refreshed.
+///
+/// If the records expire within the prefetch margin, e.g. because their TTL is
+/// shorter than the margin, they are refreshed when they expire, so that a
+/// name is not looked up repeatedly.
+fn refresh_at(valid_until: Instant, prefetch: Option<Duration>, now: Instant) -> Instant {
+    prefetch
+        .and_then(|margin| valid_until.checked_sub(margin))
+        .filter(|refresh| *refresh > now)
+        .unwrap_or(valid_until)
+}
+
+/// Builds an endpoint for each resolved address permitted by `family`.
+fn to_endpoints(
+    ips: &dns::IpList,
+    family: dns::FamilyPreference,
+    port: u16,
+) -> Vec<(SocketAddr, Metadata)> {
+    family
+        .apply(ips.iter())
+        .into_iter()
+        .map(|ip| {
+            let meta = Metadata::empty().with_valid_until(ips.valid_until());
+            (SocketAddr::new(ip, port), meta)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tower::Service;
+
+    fn resolution(family: dns::FamilyPreference) -> Resolution {
+        let (dns, _task) =
+            dns::Resolver::new(Default::default(), Default::default()).expect("must build");
+        let target = NameAddr::from_str("web.example.com:8080").unwrap();
+        Resolution::new(dns, family, target)
+    }
+
+    fn resolve(target: &str) -> Resolution {
+        let (dns, _task) =
+            dns::Resolver::new(Default::default(), Default::default()).expect("must build");
+        Resolve::new(dns)
+            .with_loopback_shortcut(true)
+            .call(NameAddr::from_str(target).unwrap())
+            .into_inner()
+            .expect("resolve must succeed")
+    }
+
+    #[test]
+    fn loopback_shortcut() {
+        let resolution = resolve("localhost:8080");
+        assert!(matches!(resolution.state, State::Static));
+        assert_eq!(
+            resolution.pending,
+            Some(Update::Add(vec![
+                (([127, 0, 0, 1], 8080).into(), Metadata::empty()),
+                ((Ipv6Addr::LOCALHOST, 8080).into(), Metadata::empty()),
+            ]))
+        );
+        let resolution = resolve("LOCALHOST.:8080");
+        assert!(matches!(resolution.state, State::Static));
+
+        for target in &["localhost.example.com:8080", "web.localhost:8080"] {
+            let resolution = resolve(target);
+            assert!(matches!(resolution.state, State::Init), "{}", target);
+            assert!(resolution.pending.is_none(), "{}", target);
+        }
+    }
+
+    #[test]
+    fn categorizes_lookup_errors() {
+        let cases = vec![
+            (dns::Error::NoAddressesFound, ErrorKind::NotFound),
+            (dns::Error::TaskLost, ErrorKind::Transient),
+            (
+                dns::Error::ResolutionFailed(dns::ResolveErrorKind::Timeout.into()),
+                ErrorKind::Transient,
+            ),
+        ];
+        for (error, kind) in cases.into_iter() {
+            let msg = error.to_string();
+            let error = to_resolve_error(error);
+            assert_eq!(error.discovery_source(), DiscoverySource::Dns);
+            assert_eq!(error.kind(), kind, "{}", msg);
+            assert!(error.get_ref().downcast_ref::<dns::Error>().is_some());
+        }
+    }
+
+    #[test]
+    fn clears_endpoints_on_definitive_negatives() {
+        let no_records = |response_code| {
+            dns::Error::ResolutionFailed(
+                dns::ResolveErrorKind::NoRecordsFound {
+                    query: Default::default(),
+                    soa: None,
+                    negative_ttl: None,
+                    response_code,
+                    trusted: true,
+                }
+                .into(),
+            )
+        };
+        let cases = vec![
+            (dns::Error::NoAddressesFound, true),
+            (no_records(dns::ResponseCode::NXDomain), true),
+            (no_records(dns::ResponseCode::NoError), true),
+            (no_records(dns::ResponseCode::ServFail), false),
+            (no_records(dns::ResponseCode::Refused), false),
+            (
+                dns::Error::ResolutionFailed(dns::ResolveErrorKind::Timeout.into()),
+                false,
+            ),
+            (
+                dns::Error::ResolutionFailed(dns::ResolveErrorKind::Message("oops").into()),
+                false,
+            ),
+            (dns::Error::TaskLost, false),
+            (dns::Error::Saturated(Duration::from_secs(1)), false),
+        ];
+        for (error, clear) in cases.into_iter() {
+            assert_eq!(should_clear_endpoints(&error), clear, "{:?}", error);
+            let kind = if clear {
+                ErrorKind::NotFound
+            } else {
+                ErrorKind::Transient
+            };
+            assert_eq!(to_resolve_error(error).kind(), kind);
+        }
+    }
+
+    #[test]
+    fn endpoints_carry_valid_until() {
+        let valid_until = Instant::now() + Duration::from_secs(30);
+        let ips = dns::IpList::new(
+            vec![IpAddr::from([10, 0, 0, 1]), IpAddr::from([10, 0, 0, 2])],
+            valid_until,
+        );
+
+        let endpoints = to_endpoints(&ips, dns::FamilyPreference::Any, 8080);
+        assert_eq!(endpoints.len(), 2);
+        for ((addr, meta), ip) in endpoints.iter().zip(ips.iter()) {
+            assert_eq!(*addr, SocketAddr::new(ip, 8080));
+            assert_eq!(meta.valid_until(), Some(valid_until));
+        }
+    }
+
+    #[test]
+    fn removes_stale_addresses_before_adding() {
+        let mut resolution = resolution(dns::FamilyPreference::Any);
+        let ip0 = IpAddr::from([10, 0, 0, 1]);
+        let ip1 = IpAddr::from([10, 0, 0, 2]);
+
+        let valid_until = Instant::now();
+        let update = resolution.update(dns::IpList::new(vec![ip0, ip1], valid_until));
+        assert_eq!(
+            update,
+            Update::Add(to_endpoints_for(&[ip0, ip1], valid_until))
+        );
+        assert!(resolution.pending.is_none());
+
+        let valid_until = valid_until + Duration::from_secs(30);
+        let update = resolution.update(dns::IpList::new(vec![ip1], valid_until));
+        assert_eq!(update, Update::Remove(vec![SocketAddr::new(ip0, 8080)]));
+        assert_eq!(
+            resolution.pending.take(),
+            Some(Update::Add(to_endpoints_for(&[ip1], valid_until)))
+        );
+    }
+
+    fn to_endpoints_for(ips: &[IpAddr], valid_until: Instant) -> Vec<(SocketAddr, Metadata)> {
+        let ips = dns::IpList::new(ips.to_vec(), valid_until);
+        to_endpoints(&ips, dns::FamilyPreference::Any, 8080)
+    }
+
+    #[test]
+    fn prefetch_refreshes_before_expiry() {
+        let now = Instant::now();
+        let valid_until = now + Duration::from_secs(30);
+        assert_eq!(refresh_at(valid_until, None, now), valid_until);
+        assert_eq!(
+            refresh_at(valid_until, Some(Duration::from_secs(5)), now),
+            valid_until - Duration::from_secs(5),
+            "the lookup must be refreshed before the records expire"
+        );
+        assert_eq!(
+            refresh_at(valid_until, Some(Duration::from_secs(30)), now),
+            valid_until,
+            "records that expire within the margin must not be prefetched"
+        );
+        assert_eq!(
+            refresh_at(valid_until, Some(Duration::from_secs(60)), now),
+            valid_until
+        );
+
+        // When the prefetched lookup completes, the endpoints are updated in
+        // place, without removing them first.
+        let mut resolution = resolution(dns::FamilyPreference::Any);
+        let ip = IpAddr::from([10, 0, 0, 1]);
+        resolution.update(dns::IpList::new(vec![ip], valid_until));
+        let refreshed = valid_until + Duration::from_secs(30);
+        let update = resolution.update(dns::IpList::new(vec![ip], refreshed));
+        assert_eq!(update, Update::Add(to_endpoints_for(&[ip], refreshed)));
+        assert!(resolution.pending.is_none());
+    }
+
+    #[test]
+    fn max_addresses_limits_endpoints() {
+        let mut resolution = Resolution {
+            max_addresses: Some(2),
+            ..resolution(dns::FamilyPreference::V4First)
+        };
+        let ip6 = "fd00::1".parse::<IpAddr>().unwrap();
+        let ips = vec![
+            ip6,
+            IpAddr::from([10, 0, 0, 1]),
+            IpAddr::from([10, 0, 0, 2]),
+            IpAddr::from([10, 0, 0, 3]),
+        ];
+
+        // The addresses are limited after they are ordered by family.
+        let valid_until = Instant::now();
+        let update = resolution.update(dns::IpList::new(ips.clone(), valid_until));
+        assert_eq!(
+            update,
+            Update::Add(to_endpoints_for(&ips[1..3], valid_until))
+        );
+    }
+
+    #[test]
+    fn v4_only_drops_ipv6_endpoints() {
+        let mut resolution = resolution(dns::FamilyPreference::V4Only);
+        let ip4 = IpAddr::from([10, 0, 0, 1]);
+        let ip6 = "fd00::1".parse::<IpAddr>().unwrap();
+
+        let valid_until = Instant::now();
+        let update = resolution.update(dns::IpList::new(vec![ip6, ip4], valid_until));
+        assert_eq!(update, Update::Add(to_endpoints_for(&[ip4], valid_until)));
+
+        // If only IPv6 addresses are resolved, there are no usable endpoints.
+        let update = resolution.update(dns::IpList::new(vec![ip6], valid_until));
+        assert_eq!(update, Update::Empty);
+        assert!(resolution.pending.is_none());
+    }
+
+    #[tokio::test]
+    async fn refresh_looks_up_before_expiry() {
+        let mut resolution = resolution(dns::FamilyPreference::Any);
+        let expiry = time::Instant::now() + Duration::from_secs(60);
+        resolution.state = State::Valid(time::delay_until(expiry));
+
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        let poll = resolve::Resolution::poll(Pin::new(&mut resolution), &mut cx);
+        assert!(poll.is_pending());
+        assert!(matches!(resolution.state, State::Valid(_)));
+
+        // The name is looked up again without waiting for the records to
+        // expire.
+        resolution.refresh_handle().refresh();
+        let _ = resolve::Resolution::poll(Pin::new(&mut resolution), &mut cx);
+        assert!(matches!(resolution.state, State::Pending(_)));
+    }
+}
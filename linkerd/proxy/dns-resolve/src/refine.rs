@@ -0,0 +1,530 @@
+//! Refines a target's name via DNS before it is resolved, e.g. so that a
+//! short name like `web` is expanded by the search path to
+//! `web.ns.svc.cluster.local` before the control plane is queried.
+
+use futures::{future, prelude::*, ready};
+use indexmap::IndexSet;
+use linkerd2_addr::NameAddr;
+use linkerd2_dns as dns;
+use linkerd2_error::Error;
+use linkerd2_exp_backoff::ExponentialBackoff;
+use linkerd2_proxy_core::resolve::{self, Update};
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::time;
+use tracing::{debug, warn};
+
+/// A refined target and the time until which the refinement is valid.
+pub type Refined = Result<(NameAddr, Instant), dns::Error>;
+
+/// Refines a target's name.
+pub trait RefineNameAddr {
+    type Future: Future<Output = Refined>;
+
+    fn refine(&mut self, target: &NameAddr) -> Self::Future;
+}
+
+/// Determines how a resolution proceeds when its target cannot be refined.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OnRefineError {
+    /// The unrefined target is resolved. If a refined target is already
+    /// being resolved, it continues to be used.
+    Passthrough,
+    /// The resolution fails.
+    Fail,
+}
+
+/// Resolves each `NameAddr` via `R` once its name has been refined via `F`.
+///
+/// The name is refined again when its refinement expires, but no sooner than
+/// 5s after it was last refined. If the refined name changes, the prior
+/// name's endpoints are removed and the new name is resolved.
+#[derive(Clone, Debug)]
+pub struct Resolve<F, R> {
+    refine: F,
+    resolve: R,
+    on_error: OnRefineError,
+    backoff: ExponentialBackoff,
+}
+
+/// Determines how long to wait before refining a name again after
+/// consecutive failures.
+#[derive(Debug)]
+struct Retry {
+    backoff: ExponentialBackoff,
+    failures: u32,
+}
+
+pub struct Resolution<F: RefineNameAddr, R: resolve::Resolve<NameAddr>> {
+    refine: F,
+    resolve: R,
+    on_error: OnRefineError,
+    target: NameAddr,
+    /// The target that is being resolved, once it is known.
+    resolved: Option<NameAddr>,
+    refining: Option<Pin<Box<F::Future>>>,
+    /// Elapses when the target should be refined again.
+    refresh: time::Delay,
+    retry: Retry,
+    resolving: Option<Pin<Box<R::Future>>>,
+    resolution: Option<Pin<Box<R::Resolution>>>,
+    /// The addresses that have been added by the current resolution.
+    active: IndexSet<SocketAddr>,
+    pending: VecDeque<Update<R::Endpoint>>,
+}
+
+// === impl RefineNameAddr ===
+
+impl RefineNameAddr for dns::Resolver {
+    type Future = Pin<Box<dyn Future<Output = Refined> + Send + 'static>>;
+
+    fn refine(&mut self, target: &NameAddr) -> Self::Future {
+        let port = target.port();
+        Box::pin(
+            self.refine_name_with_ttl(target.name())
+                .map_ok(move |(name, valid_until)| (NameAddr::new(name, port), valid_until)),
+        )
+    }
+}
+
+// === impl Resolve ===
+
+impl<F, R> Resolve<F, R> {
+    /// The least time to wait before refining a name again, whether its
+    /// refinement failed or expired immediately (e.g. with a TTL of 0).
+    const RETRY: Duration = Duration::from_secs(5);
+
+    const DEFAULT_BACKOFF: ExponentialBackoff = ExponentialBackoff {
+        min: Self::RETRY,
+        max: Duration::from_secs(120),
+        jitter: 0.5,
+    };
+
+    pub fn new(refine: F, resolve: R, on_error: OnRefineError) -> Self
+    where
+        F: RefineNameAddr,
+        R: resolve::Resolve<NameAddr>,
+    {
+        Self {
+            refine,
+            resolve,
+            on_error,
+            backoff: Self::DEFAULT_BACKOFF,
+        }
+    }
+
+    /// Backs off refining a name that fails repeatedly, so that a struggling
+    /// resolver is not queried at a fixed interval during an outage.
+    ///
+    /// The backoff is reset once the name is refined. A name is never
+    /// refined again sooner than 5s after a failure.
+    pub fn with_backoff(self, backoff: ExponentialBackoff) -> Self {
+        Self { backoff, ..self }
+    }
+}
+
+impl<F, R> tower::Service<NameAddr> for Resolve<F, R>
+where
+    F: RefineNameAddr + Clone,
+    R: resolve::Resolve<NameAddr> + Clone,
+{
+    type Response = Resolution<F, R>;
+    type Error = Error;
+    type Future = future::Ready<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, target: NameAddr) -> Self::Future {
+        future::ok(Resolution {
+            refine: self.refine.clone(),
+            resolve: self.resolve.clone(),
+            on_error: self.on_error,
+            target,
+            resolved: None,
+            refining: None,
+            refresh: time::delay_until(time::Instant::now()),
+            retry: Retry {
+                backoff: self.backoff,
+                failures: 0,
+            },
+            resolving: None,
+            resolution: None,
+            active: IndexSet::new(),
+            pending: VecDeque::new(),
+        })
+    }
+}
+
+// === impl Resolution ===
+
+impl<F, R> Resolution<F, R>
+where
+    F: RefineNameAddr,
+    R: resolve::Resolve<NameAddr>,
+{
+    /// Resolves `target` in place of the current resolution, removing the
+    /// current resolution's endpoints.
+    fn resolve(&mut self, target: NameAddr) {
+        debug!(%target, "Resolving");
+        if !self.active.is_empty() {
+            let removed = self.active.drain(..).collect();
+            self.pending.push_back(Update::Remove(removed));
+        }
+        self.resolution = None;
+        self.resolving = None;
+        self.resolved = Some(target);
+    }
+
+    /// Polls the refinement, if it is due, and updates the target that is
+    /// resolved.
+    fn poll_refine(&mut self, cx: &mut Context<'_>) -> Result<(), Error> {
+        if self.refining.is_none() {
+            if self.refresh.poll_unpin(cx).is_pending() {
+                return Ok(());
+            }
+            self.refining = Some(Box::pin(self.refine.refine(&self.target)));
+        }
+
+        let res = match self
+            .refining
+            .as_mut()
+            .expect("must be refining")
+            .as_mut()
+            .poll(cx)
+        {
+            Poll::Ready(res) => res,
+            Poll::Pending => return Ok(()),
+        };
+        self.refining = None;
+        match res {
+            Ok((refined, valid_until)) => {
+                self.retry.failures = 0;
+                // A refinement that is already expired must not be refined
+                // again immediately, or the resolution would spin.
+                let min = time::Instant::now() + Resolve::<F, R>::RETRY;
+                self.refresh = time::delay_until(time::Instant::from_std(valid_until).max(min));
+                if self.resolved.as_ref() != Some(&refined) {
+                    self.resolve(refined);
+                }
+            }
+            Err(error) => {
+                if self.on_error == OnRefineError::Fail {
+                    return Err(error.into());
+                }
+                let retry = self.retry.failed(Resolve::<F, R>::RETRY);
+                warn!(target = %self.target, %error, ?retry, "Failed to refine name");
+                self.refresh = time::delay_for(retry);
+                if self.resolved.is_none() {
+                    self.resolve(self.target.clone());
+                }
+            }
+        }
+        // Ensure that the new refresh is polled.
+        cx.waker().wake_by_ref();
+        Ok(())
+    }
+}
+
+// === impl Retry ===
+
+impl Retry {
+    /// Notes a failure, returning how long to wait before trying again.
+    ///
+    /// The backoff only ever extends the wait beyond `min`.
+    fn failed(&mut self, min: Duration) -> Duration {
+        let backoff = self
+            .backoff
+            .duration(self.failures, &mut rand::thread_rng());
+        self.failures = self.failures.saturating_add(1);
+        backoff.max(min)
+    }
+}
+
+// All of the resolution's pinned state is boxed.
+impl<F: RefineNameAddr, R: resolve::Resolve<NameAddr>> Unpin for Resolution<F, R> {}
+
+impl<F, R> resolve::Resolution for Resolution<F, R>
+where
+    F: RefineNameAddr,
+    R: resolve::Resolve<NameAddr>,
+{
+    type Endpoint = R::Endpoint;
+    type Error = Error;
+
+    fn poll(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Update<Self::Endpoint>, Self::Error>> {
+        let this = self.get_mut();
+        this.poll_refine(cx)?;
+        if let Some(update) = this.pending.pop_front() {
+            return Poll::Ready(Ok(update));
+        }
+
+        if this.resolution.is_none() {
+            let target = match this.resolved.as_ref() {
+                Some(target) => target,
+                None => return Poll::Pending,
+            };
+            if this.resolving.is_none() {
+                ready!(this.resolve.poll_ready(cx)).map_err(Into::into)?;
+                this.resolving = Some(Box::pin(this.resolve.resolve(target.clone())));
+            }
+            let future = this.resolving.as_mut().expect("must be resolving");
+            let resolution = ready!(future.as_mut().poll(cx)).map_err(Into::into)?;
+            this.resolving = None;
+            this.resolution = Some(Box::pin(resolution));
+        }
+
+        let resolution = this.resolution.as_mut().expect("must be resolved");
+        let update = ready!(resolution.as_mut().poll(cx)).map_err(Into::into)?;
+        match update {
+            Update::Add(ref endpoints) => {
+                this.active.extend(endpoints.iter().map(|(addr, _)| *addr));
+            }
+            Update::Remove(ref addrs) => {
+                for addr in addrs.iter() {
+                    this.active.remove(addr);
+                }
+            }
+            Update::Empty | Update::DoesNotExist => this.active.clear(),
+        }
+        Poll::Ready(Ok(update))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use linkerd2_proxy_core::resolve::Resolution as _;
+    use linkerd2_proxy_core::test_util::{next, Updates};
+    use std::sync::{Arc, Mutex};
+    use tower::Service;
+
+    /// Refines each name to the next of the given results.
+    #[derive(Clone, Default)]
+    struct MockRefine(Arc<Mutex<VecDeque<Refined>>>);
+
+    impl RefineNameAddr for MockRefine {
+        type Future = future::Ready<Refined>;
+
+        fn refine(&mut self, _: &NameAddr) -> Self::Future {
+            let res = self
+                .0
+                .lock()
+                .unwrap()
+                .pop_front()
+                .expect("unexpected refine");
+            future::ready(res)
+        }
+    }
+
+    /// Records each target that is resolved, and resolves it to a single
+    /// endpoint.
+    #[derive(Clone, Default)]
+    struct MockResolve(Arc<Mutex<Vec<NameAddr>>>);
+
+    impl tower::Service<NameAddr> for MockResolve {
+        type Response = Updates<()>;
+        type Error = Error;
+        type Future = future::Ready<Result<Updates<()>, Error>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, target: NameAddr) -> Self::Future {
+            let mut targets = self.0.lock().unwrap();
+            targets.push(target);
+            let addr = SocketAddr::from(([10, 0, 0, targets.len() as u8], 8080));
+            future::ok(vec![Update::Add(vec![(addr, ())])].into_iter().collect())
+        }
+    }
+
+    fn name_addr(s: &str) -> NameAddr {
+        NameAddr::from_str(s).unwrap()
+    }
+
+    fn addr(n: u8) -> SocketAddr {
+        ([10, 0, 0, n], 8080).into()
+    }
+
+    #[tokio::test]
+    async fn resolves_refined_name() {
+        time::pause();
+        let refine = MockRefine::default();
+        let resolve = MockResolve::default();
+        let ttl = Duration::from_secs(10);
+        {
+            let mut results = refine.0.lock().unwrap();
+            let refined = name_addr("web.ns.svc.cluster.local.:8080");
+            results.push_back(Ok((refined.clone(), Instant::now() + ttl)));
+            results.push_back(Ok((refined, Instant::now() + ttl * 100)));
+        }
+        let mut resolution = Resolve::new(refine.clone(), resolve.clone(), OnRefineError::Fail)
+            .call(name_addr("web:8080"))
+            .await
+            .expect("resolve must succeed");
+
+        assert_eq!(
+            next(&mut resolution).await,
+            Update::Add(vec![(addr(1), ())])
+        );
+        assert_eq!(
+            *resolve.0.lock().unwrap(),
+            vec![name_addr("web.ns.svc.cluster.local.:8080")]
+        );
+
+        // When the refinement expires, the name is refined again. The name
+        // is unchanged, so its resolution is retained.
+        let next = time::timeout(ttl * 3, next(&mut resolution)).await;
+        assert!(next.is_err(), "unexpected update: {:?}", next);
+        assert!(
+            refine.0.lock().unwrap().is_empty(),
+            "name must be refined again"
+        );
+        assert_eq!(resolve.0.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn refined_name_changes() {
+        time::pause();
+        let refine = MockRefine::default();
+        let resolve = MockResolve::default();
+        {
+            let mut results = refine.0.lock().unwrap();
+            results.push_back(Ok((
+                name_addr("web.ns1.svc.cluster.local.:8080"),
+                Instant::now(),
+            )));
+            let later = Instant::now() + Duration::from_secs(60);
+            results.push_back(Ok((name_addr("web.ns2.svc.cluster.local.:8080"), later)));
+        }
+        let mut resolution = Resolve::new(refine, resolve.clone(), OnRefineError::Fail)
+            .call(name_addr("web:8080"))
+            .await
+            .expect("resolve must succeed");
+
+        assert_eq!(
+            next(&mut resolution).await,
+            Update::Add(vec![(addr(1), ())])
+        );
+        assert_eq!(next(&mut resolution).await, Update::Remove(vec![addr(1)]));
+        assert_eq!(
+            next(&mut resolution).await,
+            Update::Add(vec![(addr(2), ())])
+        );
+        assert_eq!(
+            *resolve.0.lock().unwrap(),
+            vec![
+                name_addr("web.ns1.svc.cluster.local.:8080"),
+                name_addr("web.ns2.svc.cluster.local.:8080"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn expired_refinements_are_not_refined_immediately() {
+        time::pause();
+        let refine = MockRefine::default();
+        let resolve = MockResolve::default();
+        {
+            let mut results = refine.0.lock().unwrap();
+            let refined = name_addr("web.ns.svc.cluster.local.:8080");
+            results.push_back(Ok((refined.clone(), Instant::now())));
+            results.push_back(Ok((refined, Instant::now())));
+        }
+        let mut resolution = Resolve::new(refine.clone(), resolve.clone(), OnRefineError::Fail)
+            .call(name_addr("web:8080"))
+            .await
+            .expect("resolve must succeed");
+        assert_eq!(
+            next(&mut resolution).await,
+            Update::Add(vec![(addr(1), ())])
+        );
+
+        // A TTL of 0 does not cause the name to be refined again until the
+        // minimum refresh has elapsed.
+        let retry = Resolve::<MockRefine, MockResolve>::RETRY;
+        let wait = retry - Duration::from_millis(100);
+        let update = time::timeout(wait, next(&mut resolution)).await;
+        assert!(update.is_err(), "unexpected update: {:?}", update);
+        assert_eq!(refine.0.lock().unwrap().len(), 1, "must not refine yet");
+
+        // Each subsequent refinement is also throttled.
+        let update = time::timeout(retry, next(&mut resolution)).await;
+        assert!(update.is_err(), "unexpected update: {:?}", update);
+        assert!(
+            refine.0.lock().unwrap().is_empty(),
+            "name must be refined again"
+        );
+        assert_eq!(resolve.0.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn retries_back_off_until_refined() {
+        let min = Duration::from_secs(5);
+        let mut retry = Retry {
+            backoff: ExponentialBackoff::new(min, Duration::from_secs(30), 0.0).unwrap(),
+            failures: 0,
+        };
+        let retries = (0..5).map(|_| retry.failed(min)).collect::<Vec<_>>();
+        assert_eq!(
+            retries,
+            vec![5, 10, 20, 30, 30]
+                .into_iter()
+                .map(Duration::from_secs)
+                .collect::<Vec<_>>()
+        );
+
+        // Once the name is refined, the backoff is reset.
+        retry.failures = 0;
+        assert_eq!(retry.failed(min), min);
+
+        // The backoff never retries sooner than the minimum.
+        let mut retry = Retry {
+            backoff: ExponentialBackoff::new(Duration::from_secs(1), min, 0.5).unwrap(),
+            failures: 0,
+        };
+        assert!((0..5).all(|_| retry.failed(min) == min));
+    }
+
+    #[tokio::test]
+    async fn refine_errors_follow_policy() {
+        let refine = MockRefine::default();
+        refine
+            .0
+            .lock()
+            .unwrap()
+            .push_back(Err(dns::Error::NoAddressesFound));
+        let resolve = MockResolve::default();
+        let mut resolution = Resolve::new(refine.clone(), resolve.clone(), OnRefineError::Fail)
+            .call(name_addr("web:8080"))
+            .await
+            .expect("resolve must succeed");
+        let res = future::poll_fn(|cx| Pin::new(&mut resolution).poll(cx)).await;
+        assert!(res.is_err(), "refine failure must fail the resolution");
+        assert!(resolve.0.lock().unwrap().is_empty());
+
+        // The unrefined name is resolved instead.
+        refine
+            .0
+            .lock()
+            .unwrap()
+            .push_back(Err(dns::Error::NoAddressesFound));
+        let mut resolution = Resolve::new(refine, resolve.clone(), OnRefineError::Passthrough)
+            .call(name_addr("web:8080"))
+            .await
+            .expect("resolve must succeed");
+        assert_eq!(
+            next(&mut resolution).await,
+            Update::Add(vec![(addr(1), ())])
+        );
+        assert_eq!(*resolve.0.lock().unwrap(), vec![name_addr("web:8080")]);
+    }
+}
@@ -0,0 +1,342 @@
+//! Resolves a name's endpoints via its DNS SRV records.
+
+use crate::{should_clear_endpoints, to_resolve_error};
+use futures::{future, prelude::*, ready};
+use indexmap::{IndexMap, IndexSet};
+use linkerd2_dns as dns;
+use linkerd2_proxy_api_resolve::{Metadata, ProtocolHint};
+use linkerd2_proxy_core::resolve::{self, ResolveError, Update};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tokio::time;
+use tracing::{debug, trace, warn};
+
+/// Resolves a name to an endpoint for each address of each of its SRV
+/// records' targets.
+///
+/// Each endpoint's metadata carries its SRV record's priority and weight. The
+/// name is looked up again when its records' TTL expires.
+#[derive(Clone, Debug)]
+pub struct Resolve {
+    dns: dns::Resolver,
+    family: dns::FamilyPreference,
+}
+
+pub struct Resolution {
+    dns: dns::Resolver,
+    family: dns::FamilyPreference,
+    target: dns::Name,
+    /// The endpoints most recently resolved for each SRV target.
+    targets: IndexMap<dns::Name, Vec<(SocketAddr, Metadata)>>,
+    /// The addresses that have been most recently added.
+    addrs: IndexSet<SocketAddr>,
+    /// An update that has been produced but not yet returned.
+    pending: Option<Update<Metadata>>,
+    state: State,
+}
+
+/// The SRV targets of a name, with the result of resolving each target's
+/// addresses.
+type Targets = (dns::SrvList, Vec<Result<dns::IpList, dns::Error>>);
+
+enum State {
+    Init,
+    Pending(Pin<Box<dyn Future<Output = Result<Targets, dns::Error>> + Send + 'static>>),
+    Valid(time::Delay),
+}
+
+// === impl Resolve ===
+
+impl Resolve {
+    pub fn new(dns: dns::Resolver) -> Self {
+        Self {
+            dns,
+            family: dns::FamilyPreference::default(),
+        }
+    }
+
+    /// Only produces endpoints for addresses permitted by `family`.
+    pub fn with_family_preference(self, family: dns::FamilyPreference) -> Self {
+        Self { family, ..self }
+    }
+}
+
+impl tower::Service<dns::Name> for Resolve {
+    type Response = Resolution;
+    type Error = ResolveError;
+    type Future = future::Ready<Result<Resolution, ResolveError>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, target: dns::Name) -> Self::Future {
+        future::ok(Resolution::new(self.dns.clone(), self.family, target))
+    }
+}
+
+// === impl Resolution ===
+
+impl Resolution {
+    fn new(dns: dns::Resolver, family: dns::FamilyPreference, target: dns::Name) -> Self {
+        Self {
+            dns,
+            family,
+            target,
+            targets: IndexMap::new(),
+            addrs: IndexSet::new(),
+            pending: None,
+            state: State::Init,
+        }
+    }
+
+    /// Looks up the name's SRV records and then each target's addresses.
+    fn lookup(&self) -> impl Future<Output = Result<Targets, dns::Error>> + Send + 'static {
+        let dns = self.dns.clone();
+        let srv = self.dns.resolve_srv(&self.target);
+        async move {
+            let srv = srv.await?;
+            let ips = future::join_all(srv.iter().map(|t| dns.resolve_all_ips(t.name()))).await;
+            Ok((srv, ips))
+        }
+    }
+
+    /// Diffs a lookup's endpoints against the current set of endpoints.
+    ///
+    /// If a target's addresses could not be resolved, its prior endpoints are
+    /// retained unless the failure indicates that it has no addresses.
+    /// Addresses that are no longer present are removed before the new set of
+    /// endpoints is added.
+    ///
+    /// Returns the update and the time at which the name should be looked up
+    /// again.
+    fn update(
+        &mut self,
+        srv: dns::SrvList,
+        ips: Vec<Result<dns::IpList, dns::Error>>,
+    ) -> (Update<Metadata>, Instant) {
+        let mut valid_until = srv.valid_until();
+        let mut targets = IndexMap::new();
+        for (target, ips) in srv.iter().zip(ips) {
+            let endpoints = match ips {
+                Ok(ips) => {
+                    valid_until = valid_until.min(ips.valid_until());
+                    to_endpoints(target, &ips, srv.valid_until(), self.family)
+                }
+                Err(error) if should_clear_endpoints(&error) => {
+                    debug!(target = %target.name(), %error, "SRV target has no addresses");
+                    continue;
+                }
+                Err(error) => {
+                    warn!(target = %target.name(), %error, "Failed to resolve SRV target");
+                    match self.targets.get(target.name()) {
+                        Some(endpoints) => endpoints.clone(),
+                        None => continue,
+                    }
+                }
+            };
+            targets.insert(target.name().clone(), endpoints);
+        }
+        self.targets = targets;
+
+        // An address may be served by several targets, so only its first
+        // endpoint is used.
+        let mut endpoints = IndexMap::new();
+        for (addr, meta) in self.targets.values().flatten() {
+            endpoints.entry(*addr).or_insert_with(|| meta.clone());
+        }
+        if endpoints.is_empty() {
+            self.addrs.clear();
+            return (Update::Empty, valid_until);
+        }
+
+        let addrs = endpoints.keys().cloned().collect::<IndexSet<_>>();
+        let removed = self.addrs.difference(&addrs).cloned().collect::<Vec<_>>();
+        self.addrs = addrs;
+
+        let add = Update::Add(endpoints.into_iter().collect());
+        if removed.is_empty() {
+            return (add, valid_until);
+        }
+        self.pending = Some(add);
+        (Update::Remove(removed), valid_until)
+    }
+}
+
+impl resolve::Resolution for Resolution {
+    type Endpoint = Metadata;
+    type Error = ResolveError;
+
+    fn poll(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Update<Self::Endpoint>, Self::Error>> {
+        let this = self.get_mut();
+        if let Some(update) = this.pending.take() {
+            return Poll::Ready(Ok(update));
+        }
+
+        loop {
+            match this.state {
+                State::Init => {
+                    this.state = State::Pending(Box::pin(this.lookup()));
+                }
+                State::Pending(ref mut lookup) => {
+                    let (srv, ips) = ready!(lookup.as_mut().poll(cx)).map_err(to_resolve_error)?;
+                    trace!(?srv, "Resolved");
+                    let (update, refresh) = this.update(srv, ips);
+                    this.state = State::Valid(time::delay_until(time::Instant::from_std(refresh)));
+                    return Poll::Ready(Ok(update));
+                }
+                State::Valid(ref mut expiry) => {
+                    ready!(expiry.poll_unpin(cx));
+                    trace!("Refreshing");
+                    this.state = State::Init;
+                }
+            }
+        }
+    }
+}
+
+/// Builds an endpoint for each of a target's addresses permitted by `family`.
+///
+/// SRV weights are relative, so each is scaled such that a weight of 1
+/// corresponds to the default weight.
+fn to_endpoints(
+    target: &dns::SrvTarget,
+    ips: &dns::IpList,
+    srv_valid_until: Instant,
+    family: dns::FamilyPreference,
+) -> Vec<(SocketAddr, Metadata)> {
+    let weight = u32::from(target.weight()) * Metadata::DEFAULT_WEIGHT;
+    let valid_until = srv_valid_until.min(ips.valid_until());
+    family
+        .apply(ips.iter())
+        .into_iter()
+        .map(|ip| {
+            let meta = Metadata::new(
+                IndexMap::default(),
+                ProtocolHint::Unknown,
+                None,
+                weight,
+                None,
+            )
+            .with_priority(target.priority())
+            .with_valid_until(valid_until);
+            (SocketAddr::new(ip, target.port()), meta)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+    use std::net::IpAddr;
+    use std::time::Duration;
+
+    fn resolution() -> Resolution {
+        let (dns, _task) =
+            dns::Resolver::new(Default::default(), Default::default()).expect("must build");
+        let target = dns::Name::try_from("_http._tcp.web.example.com".as_bytes()).unwrap();
+        Resolution::new(dns, dns::FamilyPreference::Any, target)
+    }
+
+    fn target(name: &str, priority: u16, weight: u16) -> dns::SrvTarget {
+        let name = dns::Name::try_from(name.as_bytes()).unwrap();
+        dns::SrvTarget::new(name, 8080, priority, weight)
+    }
+
+    #[test]
+    fn srv_targets_produce_weighted_endpoints() {
+        let mut resolution = resolution();
+        let valid_until = Instant::now() + Duration::from_secs(30);
+        let srv = dns::SrvList::new(
+            vec![
+                target("web-0.example.com", 10, 1),
+                target("web-1.example.com", 20, 3),
+            ],
+            valid_until,
+        );
+        let ips = vec![
+            Ok(dns::IpList::new(
+                vec![IpAddr::from([10, 0, 0, 1])],
+                valid_until,
+            )),
+            Ok(dns::IpList::new(
+                vec![IpAddr::from([10, 0, 0, 2])],
+                valid_until + Duration::from_secs(30),
+            )),
+        ];
+
+        let (update, refresh) = resolution.update(srv, ips);
+        assert_eq!(refresh, valid_until);
+        let endpoints = match update {
+            Update::Add(endpoints) => endpoints,
+            update => panic!("unexpected update: {:?}", update),
+        };
+        assert_eq!(endpoints.len(), 2);
+        let (addr0, meta0) = &endpoints[0];
+        assert_eq!(*addr0, SocketAddr::from(([10, 0, 0, 1], 8080)));
+        assert_eq!(meta0.normalized_weight(), 1.0);
+        assert_eq!(meta0.priority(), Some(10));
+        assert_eq!(meta0.valid_until(), Some(valid_until));
+        let (addr1, meta1) = &endpoints[1];
+        assert_eq!(*addr1, SocketAddr::from(([10, 0, 0, 2], 8080)));
+        assert_eq!(meta1.normalized_weight(), 3.0);
+        assert_eq!(meta1.priority(), Some(20));
+        assert_eq!(meta1.valid_until(), Some(valid_until));
+    }
+
+    #[test]
+    fn retains_targets_that_fail_transiently() {
+        let mut resolution = resolution();
+        let valid_until = Instant::now();
+        let srv = dns::SrvList::new(
+            vec![
+                target("web-0.example.com", 10, 1),
+                target("web-1.example.com", 10, 1),
+            ],
+            valid_until,
+        );
+        let ip0 = IpAddr::from([10, 0, 0, 1]);
+        let ip1 = IpAddr::from([10, 0, 0, 2]);
+        resolution.update(
+            srv.clone(),
+            vec![
+                Ok(dns::IpList::new(vec![ip0], valid_until)),
+                Ok(dns::IpList::new(vec![ip1], valid_until)),
+            ],
+        );
+        let endpoints = resolution.targets.clone();
+
+        // A target that cannot be resolved retains its endpoints, so the
+        // resolution is unchanged.
+        let (update, _) = resolution.update(
+            srv.clone(),
+            vec![
+                Ok(dns::IpList::new(vec![ip0], valid_until)),
+                Err(dns::Error::TaskLost),
+            ],
+        );
+        assert!(resolution.pending.is_none());
+        assert_eq!(resolution.targets, endpoints);
+        assert!(matches!(update, Update::Add(ref eps) if eps.len() == 2));
+
+        // A target that has no addresses is removed.
+        let (update, _) = resolution.update(
+            srv,
+            vec![
+                Ok(dns::IpList::new(vec![ip0], valid_until)),
+                Err(dns::Error::NoAddressesFound),
+            ],
+        );
+        assert_eq!(update, Update::Remove(vec![SocketAddr::new(ip1, 8080)]));
+        assert!(matches!(
+            resolution.pending.take(),
+            Some(Update::Add(ref eps)) if eps.len() == 1
+        ));
+    }
+}
@@ -0,0 +1,480 @@
+//! A middleware that drains a `Resolution` on a background task, so that the
+//! underlying stream continues to be consumed while the resolution's
+//! consumer (e.g. a `Discover` waiting on its endpoint stack) is not ready.
+//!
+//! Updates are coalesced by address as they are buffered, so that each
+//! buffered endpoint is either added or removed once the consumer polls. At
+//! most `capacity` endpoints are buffered; once the buffer is full, the
+//! underlying resolution is no longer polled until the consumer catches up.
+
+use crate::metrics::{Churn, NewChurn};
+use futures::{ready, TryFuture};
+use indexmap::IndexMap;
+use linkerd2_error::Error;
+use linkerd2_proxy_core::resolve::{self, Update};
+use pin_project::{pin_project, pinned_drop};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use tracing::{debug, trace};
+
+#[derive(Clone, Debug)]
+pub struct Resolve<R, M = ()> {
+    resolve: R,
+    capacity: usize,
+    churn: M,
+}
+
+#[pin_project]
+#[derive(Debug)]
+pub struct ResolveFuture<F> {
+    #[pin]
+    future: F,
+    capacity: usize,
+    churn: Churn,
+}
+
+/// Consumes the updates buffered by a `Daemon`.
+#[pin_project(PinnedDrop)]
+pub struct Resolution<E> {
+    buffer: Arc<Mutex<Buffer<E>>>,
+}
+
+/// Drives the underlying resolution, buffering its updates.
+#[pin_project]
+struct Daemon<R: resolve::Resolution> {
+    #[pin]
+    resolution: R,
+    buffer: Arc<Mutex<Buffer<R::Endpoint>>>,
+}
+
+struct Buffer<E> {
+    capacity: usize,
+    updates: Coalesced<E>,
+    /// The error that ended the underlying resolution, if it failed.
+    error: Option<Error>,
+    /// Set when the consumer is dropped, so that the daemon completes.
+    closed: bool,
+    consumer: Option<Waker>,
+    daemon: Option<Waker>,
+}
+
+/// Updates that have been coalesced by address.
+pub(crate) struct Coalesced<E> {
+    /// An `Empty` or `DoesNotExist` update that precedes the coalesced
+    /// endpoints.
+    reset: Option<Update<E>>,
+    /// The coalesced endpoints, by address. An endpoint that has been removed
+    /// is `None`.
+    endpoints: IndexMap<SocketAddr, Option<E>>,
+    churn: Churn,
+}
+
+// === impl Resolve ===
+
+impl<R> Resolve<R> {
+    /// Buffers up to `capacity` endpoints from each resolution.
+    ///
+    /// Resolutions must be created within a tokio runtime, as each is driven
+    /// on a spawned task.
+    pub fn new<T>(capacity: usize, resolve: R) -> Self
+    where
+        Self: resolve::Resolve<T>,
+    {
+        Self {
+            resolve,
+            capacity,
+            churn: (),
+        }
+    }
+}
+
+impl<R, M> Resolve<R, M> {
+    /// Records the updates coalesced by each resolution in `churn`.
+    pub fn with_churn_metrics<N>(self, churn: N) -> Resolve<R, N> {
+        Resolve {
+            resolve: self.resolve,
+            capacity: self.capacity,
+            churn,
+        }
+    }
+}
+
+impl<T, R, M> tower::Service<T> for Resolve<R, M>
+where
+    R: resolve::Resolve<T>,
+    M: NewChurn<T>,
+    R::Resolution: Send + 'static,
+    R::Endpoint: Send + 'static,
+{
+    type Response = Resolution<R::Endpoint>;
+    type Error = R::Error;
+    type Future = ResolveFuture<R::Future>;
+
+    #[inline]
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.resolve.poll_ready(cx)
+    }
+
+    #[inline]
+    fn call(&mut self, target: T) -> Self::Future {
+        let churn = self.churn.new_churn(&target);
+        Self::Future {
+            future: self.resolve.resolve(target),
+            capacity: self.capacity,
+            churn,
+        }
+    }
+}
+
+// === impl ResolveFuture ===
+
+impl<F> Future for ResolveFuture<F>
+where
+    F: TryFuture,
+    F::Ok: resolve::Resolution + Send + 'static,
+    <F::Ok as resolve::Resolution>::Endpoint: Send + 'static,
+{
+    type Output = Result<Resolution<<F::Ok as resolve::Resolution>::Endpoint>, F::Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let resolution = ready!(this.future.try_poll(cx))?;
+        let buffer = Arc::new(Mutex::new(Buffer::new(*this.capacity, this.churn.clone())));
+        tokio::spawn(Daemon {
+            resolution,
+            buffer: buffer.clone(),
+        });
+        Poll::Ready(Ok(Resolution { buffer }))
+    }
+}
+
+// === impl Resolution ===
+
+impl<E> resolve::Resolution for Resolution<E> {
+    type Endpoint = E;
+    type Error = Error;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<Update<E>, Error>> {
+        let mut buffer = self.buffer.lock().unwrap();
+        match buffer.updates.take() {
+            Some(update) => {
+                // The buffer has room, so the daemon may resume.
+                if let Some(waker) = buffer.daemon.take() {
+                    waker.wake();
+                }
+                Poll::Ready(Ok(update))
+            }
+            None => match buffer.error.take() {
+                Some(error) => Poll::Ready(Err(error)),
+                None => {
+                    buffer.consumer = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            },
+        }
+    }
+}
+
+#[pinned_drop]
+impl<E> PinnedDrop for Resolution<E> {
+    fn drop(self: Pin<&mut Self>) {
+        if let Ok(mut buffer) = self.buffer.lock() {
+            buffer.closed = true;
+            if let Some(waker) = buffer.daemon.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+// === impl Daemon ===
+
+impl<R: resolve::Resolution> Future for Daemon<R> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut this = self.project();
+        loop {
+            let mut buffer = this.buffer.lock().unwrap();
+            if buffer.closed {
+                trace!("Resolution dropped");
+                return Poll::Ready(());
+            }
+            // The daemon must be woken when the consumer is dropped, even
+            // while the underlying resolution is pending.
+            buffer.daemon = Some(cx.waker().clone());
+            if buffer.is_full() {
+                trace!(capacity = buffer.capacity, "Buffer full");
+                return Poll::Pending;
+            }
+            drop(buffer);
+
+            let res = ready!(this.resolution.as_mut().poll(cx));
+
+            let mut buffer = this.buffer.lock().unwrap();
+            let done = match res {
+                Ok(update) => {
+                    buffer.updates.push(update);
+                    false
+                }
+                Err(error) => {
+                    let error = error.into();
+                    debug!(%error, "Resolution failed");
+                    buffer.error = Some(error);
+                    true
+                }
+            };
+            if let Some(waker) = buffer.consumer.take() {
+                waker.wake();
+            }
+            if done {
+                return Poll::Ready(());
+            }
+        }
+    }
+}
+
+// === impl Buffer ===
+
+impl<E> Buffer<E> {
+    fn new(capacity: usize, churn: Churn) -> Self {
+        Self {
+            capacity,
+            updates: Coalesced::new(churn),
+            error: None,
+            closed: false,
+            consumer: None,
+            daemon: None,
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.updates.len() >= self.capacity
+    }
+}
+
+// === impl Coalesced ===
+
+impl<E> Default for Coalesced<E> {
+    fn default() -> Self {
+        Self::new(Churn::default())
+    }
+}
+
+impl<E> Coalesced<E> {
+    /// Records merged updates in `churn`.
+    pub(crate) fn new(churn: Churn) -> Self {
+        Self {
+            reset: None,
+            endpoints: IndexMap::new(),
+            churn,
+        }
+    }
+
+    /// Returns the number of coalesced endpoints.
+    pub(crate) fn len(&self) -> usize {
+        self.endpoints.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.reset.is_none() && self.endpoints.is_empty()
+    }
+
+    /// Coalesces an update into the prior updates.
+    ///
+    /// An update that reverses a pending update for the same address is
+    /// recorded as a debounced flap; otherwise, it is recorded as coalesced.
+    pub(crate) fn push(&mut self, update: Update<E>) {
+        match update {
+            Update::Add(endpoints) => {
+                for (addr, ep) in endpoints.into_iter() {
+                    let prior = self.endpoints.insert(addr, Some(ep));
+                    self.record(prior.map(|ep| ep.is_some()), true);
+                }
+            }
+            Update::Remove(addrs) => {
+                for addr in addrs.into_iter() {
+                    let prior = self.endpoints.insert(addr, None);
+                    self.record(prior.map(|ep| ep.is_some()), false);
+                }
+            }
+            // Prior updates are superseded, as the consumer will have no
+            // endpoints.
+            reset @ Update::Empty | reset @ Update::DoesNotExist => {
+                self.endpoints.clear();
+                self.reset = Some(reset);
+            }
+        }
+    }
+
+    fn record(&self, prior_added: Option<bool>, added: bool) {
+        match prior_added {
+            None => {}
+            Some(prior_added) if prior_added == added => self.churn.coalesced_update(),
+            Some(_) => self.churn.debounced_flap(),
+        }
+    }
+
+    /// Takes the next coalesced update, if any: a reset, then all additions,
+    /// then all removals.
+    pub(crate) fn take(&mut self) -> Option<Update<E>> {
+        if let Some(reset) = self.reset.take() {
+            return Some(reset);
+        }
+
+        let mut added = Vec::new();
+        let mut removed = Vec::new();
+        for (addr, ep) in self.endpoints.drain(..) {
+            match ep {
+                Some(ep) => added.push((addr, ep)),
+                None => removed.push(addr),
+            }
+        }
+        if added.is_empty() {
+            if removed.is_empty() {
+                return None;
+            }
+            return Some(Update::Remove(removed));
+        }
+        // Removals are coalesced again, to be taken next.
+        for addr in removed.into_iter() {
+            self.endpoints.insert(addr, None);
+        }
+        Some(Update::Add(added))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use linkerd2_proxy_core::test_util::next;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    /// A resolution that endlessly adds new endpoints, counting the updates
+    /// it produces.
+    struct Endless(Arc<AtomicUsize>);
+
+    impl resolve::Resolution for Endless {
+        type Endpoint = ();
+        type Error = Error;
+
+        fn poll(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<Update<()>, Error>> {
+            let n = self.0.fetch_add(1, Ordering::SeqCst);
+            Poll::Ready(Ok(Update::Add(vec![(addr(n as u16), ())])))
+        }
+    }
+
+    /// A resolution that never produces an update.
+    struct Pending(Arc<()>);
+
+    impl resolve::Resolution for Pending {
+        type Endpoint = ();
+        type Error = Error;
+
+        fn poll(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<Update<()>, Error>> {
+            Poll::Pending
+        }
+    }
+
+    fn addr(n: u16) -> SocketAddr {
+        ([198, 51, 100, 1], 8000 + n).into()
+    }
+
+    #[tokio::test]
+    async fn drains_up_to_capacity_while_not_polled() {
+        let polled = Arc::new(AtomicUsize::new(0));
+        let mut resolution = ResolveFuture {
+            future: futures::future::ok::<_, Error>(Endless(polled.clone())),
+            capacity: 3,
+            churn: Churn::default(),
+        }
+        .await
+        .expect("resolution must succeed");
+
+        // The resolution is drained without being polled by its consumer,
+        // but only until the buffer is full.
+        tokio::time::delay_for(Duration::from_millis(10)).await;
+        assert_eq!(polled.load(Ordering::SeqCst), 3);
+
+        assert_eq!(
+            next(&mut resolution).await,
+            Update::Add(vec![(addr(0), ()), (addr(1), ()), (addr(2), ())])
+        );
+
+        // Once the consumer catches up, the resolution is drained again.
+        tokio::time::delay_for(Duration::from_millis(10)).await;
+        assert_eq!(polled.load(Ordering::SeqCst), 6);
+
+        // The daemon completes once the consumer is dropped.
+        drop(resolution);
+        tokio::time::delay_for(Duration::from_millis(10)).await;
+        assert_eq!(polled.load(Ordering::SeqCst), 6);
+    }
+
+    #[tokio::test]
+    async fn daemon_completes_while_resolution_is_pending() {
+        let pending = Arc::new(());
+        let resolution = ResolveFuture {
+            future: futures::future::ok::<_, Error>(Pending(pending.clone())),
+            capacity: 3,
+            churn: Churn::default(),
+        }
+        .await
+        .expect("resolution must succeed");
+        tokio::time::delay_for(Duration::from_millis(10)).await;
+        assert_eq!(Arc::strong_count(&pending), 2);
+
+        // Once the consumer is dropped, the daemon completes, dropping the
+        // underlying resolution.
+        drop(resolution);
+        tokio::time::delay_for(Duration::from_millis(10)).await;
+        assert_eq!(Arc::strong_count(&pending), 1);
+    }
+
+    #[test]
+    fn coalesces_updates() {
+        let mut buffer = Coalesced::default();
+        buffer.push(Update::Add(vec![(addr(0), ()), (addr(1), ())]));
+        buffer.push(Update::Remove(vec![addr(0), addr(2)]));
+        buffer.push(Update::Add(vec![(addr(3), ())]));
+        assert_eq!(
+            buffer.take(),
+            Some(Update::Add(vec![(addr(1), ()), (addr(3), ())]))
+        );
+        assert_eq!(buffer.take(), Some(Update::Remove(vec![addr(0), addr(2)])));
+        assert_eq!(buffer.take(), None);
+
+        buffer.push(Update::Add(vec![(addr(0), ())]));
+        buffer.push(Update::DoesNotExist);
+        buffer.push(Update::Add(vec![(addr(1), ())]));
+        assert_eq!(buffer.take(), Some(Update::DoesNotExist));
+        assert_eq!(buffer.take(), Some(Update::Add(vec![(addr(1), ())])));
+        assert_eq!(buffer.take(), None);
+    }
+
+    #[test]
+    fn records_churn() {
+        let churn = Churn::default();
+        let mut buffer = Coalesced::new(churn.clone());
+        buffer.push(Update::Add(vec![(addr(0), ()), (addr(1), ())]));
+        assert_eq!(churn.coalesced_updates_total(), 0);
+
+        // Re-adding or re-removing a pending endpoint is coalesced.
+        buffer.push(Update::Add(vec![(addr(0), ())]));
+        buffer.push(Update::Remove(vec![addr(2)]));
+        buffer.push(Update::Remove(vec![addr(2)]));
+        assert_eq!(churn.coalesced_updates_total(), 2);
+        assert_eq!(churn.debounced_flaps_total(), 0);
+
+        // Reversing a pending update is a flap.
+        buffer.push(Update::Remove(vec![addr(1)]));
+        buffer.push(Update::Add(vec![(addr(2), ())]));
+        assert_eq!(churn.debounced_flaps_total(), 2);
+        assert_eq!(churn.coalesced_updates_total(), 2);
+        assert_eq!(churn.duplicate_adds_total(), 0);
+    }
+}
@@ -0,0 +1,244 @@
+//! A middleware that shares a single resolution among all resolves of equal
+//! targets.
+//!
+//! Each resolve returns a subscriber to the target's shared resolution, so
+//! that, e.g., several `Discover`s of the same target are served by a single
+//! stream. The inner resolution is dropped once all of its subscribers are
+//! dropped, and a later resolve of the target starts a new one.
+
+use crate::shared::{Shared, SharedError, Subscriber, WeakShared};
+use futures::future::{self, FutureExt, TryFutureExt};
+use linkerd2_proxy_core::resolve;
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::Hash;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+pub fn cached<T, R>(resolve: R) -> Resolve<T, R>
+where
+    T: Clone + Hash + Eq,
+    R: resolve::Resolve<T>,
+{
+    Resolve {
+        resolve,
+        cache: Arc::new(Mutex::new(HashMap::new())),
+    }
+}
+
+pub struct Resolve<T, R: resolve::Resolve<T>> {
+    resolve: R,
+    cache: Cache<T, R::Resolution>,
+}
+
+pub type ResolveFuture<R> =
+    Pin<Box<dyn Future<Output = Result<Subscriber<R>, SharedError>> + Send + 'static>>;
+
+type Cache<T, R> = Arc<Mutex<HashMap<T, Entry<R>>>>;
+
+type Resolving<R> = Pin<Box<dyn Future<Output = Result<Shared<R>, SharedError>> + Send + 'static>>;
+
+enum Entry<R: resolve::Resolution> {
+    /// The target is being resolved. The lookup is canceled if all of the
+    /// resolves awaiting it are dropped.
+    Resolving(future::WeakShared<Resolving<R>>),
+    Resolved(WeakShared<R>),
+}
+
+// === impl Resolve ===
+
+impl<T, R> Clone for Resolve<T, R>
+where
+    R: resolve::Resolve<T> + Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            resolve: self.resolve.clone(),
+            cache: self.cache.clone(),
+        }
+    }
+}
+
+impl<T, R> tower::Service<T> for Resolve<T, R>
+where
+    T: Clone + Hash + Eq + Send + 'static,
+    R: resolve::Resolve<T>,
+    R::Future: Send + 'static,
+    R::Resolution: Send + 'static,
+    R::Endpoint: Clone + Send + 'static,
+{
+    type Response = Subscriber<R::Resolution>;
+    type Error = SharedError;
+    type Future = ResolveFuture<R::Resolution>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.resolve
+            .poll_ready(cx)
+            .map_err(|e| SharedError::from(e.into()))
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        let cache = self.cache.clone();
+        let mut cache = cache.lock().unwrap();
+        // Drop the entries whose resolutions have been torn down.
+        cache.retain(|_, entry| entry.is_alive());
+
+        let resolving = match cache.get(&target) {
+            Some(Entry::Resolved(weak)) => {
+                if let Some(shared) = weak.upgrade() {
+                    tracing::trace!("Subscribing to cached resolution");
+                    return Box::pin(future::ok(shared.subscribe()));
+                }
+                None
+            }
+            Some(Entry::Resolving(weak)) => weak.upgrade(),
+            None => None,
+        };
+
+        let resolving = match resolving {
+            Some(resolving) => resolving,
+            None => {
+                tracing::trace!("Resolving");
+                let entries = self.cache.clone();
+                let t = target.clone();
+                let resolving: future::Shared<Resolving<R::Resolution>> = self
+                    .resolve
+                    .resolve(target.clone())
+                    .map_err(|e| SharedError::from(e.into()))
+                    .map(move |res| {
+                        let mut entries = entries.lock().unwrap();
+                        match res {
+                            Ok(resolution) => {
+                                let shared = Shared::new(resolution);
+                                entries.insert(t, Entry::Resolved(shared.downgrade()));
+                                Ok(shared)
+                            }
+                            Err(error) => {
+                                entries.remove(&t);
+                                Err(error)
+                            }
+                        }
+                    })
+                    .boxed()
+                    .shared();
+                if let Some(weak) = resolving.downgrade() {
+                    cache.insert(target, Entry::Resolving(weak));
+                }
+                resolving
+            }
+        };
+
+        Box::pin(resolving.map_ok(|shared| shared.subscribe()))
+    }
+}
+
+// === impl Entry ===
+
+impl<R: resolve::Resolution> Entry<R> {
+    fn is_alive(&self) -> bool {
+        match self {
+            Entry::Resolving(weak) => weak.upgrade().is_some(),
+            Entry::Resolved(weak) => weak.upgrade().is_some(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use linkerd2_error::Error;
+    use linkerd2_proxy_core::resolve::{Resolution as _, Update};
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tower::Service;
+
+    /// Counts resolutions as they are created and dropped. Each resolution
+    /// adds a single endpoint.
+    #[derive(Clone, Debug, Default)]
+    struct Counting {
+        resolved: Arc<AtomicUsize>,
+        dropped: Arc<AtomicUsize>,
+    }
+
+    struct CountingResolution {
+        add: Option<Update<()>>,
+        dropped: Arc<AtomicUsize>,
+    }
+
+    impl tower::Service<u16> for Counting {
+        type Response = CountingResolution;
+        type Error = Error;
+        type Future = future::Ready<Result<CountingResolution, Error>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, port: u16) -> Self::Future {
+            self.resolved.fetch_add(1, Ordering::SeqCst);
+            future::ok(CountingResolution {
+                add: Some(Update::Add(vec![(addr(port), ())])),
+                dropped: self.dropped.clone(),
+            })
+        }
+    }
+
+    impl resolve::Resolution for CountingResolution {
+        type Endpoint = ();
+        type Error = Error;
+
+        fn poll(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<Update<()>, Error>> {
+            match self.add.take() {
+                Some(add) => Poll::Ready(Ok(add)),
+                None => Poll::Pending,
+            }
+        }
+    }
+
+    impl Drop for CountingResolution {
+        fn drop(&mut self) {
+            self.dropped.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn addr(port: u16) -> SocketAddr {
+        ([198, 51, 100, 1], port).into()
+    }
+
+    #[tokio::test]
+    async fn equal_targets_share_a_resolution() {
+        let counting = Counting::default();
+        let mut resolve = cached(counting.clone());
+
+        // Concurrent and subsequent resolves of a target share a resolution.
+        let (first, second) = futures::join!(resolve.call(8080), resolve.call(8080));
+        let mut first = first.expect("resolve must succeed");
+        let mut second = second.expect("resolve must succeed");
+        let mut third = resolve.call(8080).await.expect("resolve must succeed");
+        assert_eq!(counting.resolved.load(Ordering::SeqCst), 1);
+
+        // Updates are fanned out to each subscriber.
+        for subscriber in vec![&mut first, &mut second, &mut third] {
+            let update = future::poll_fn(|cx| Pin::new(&mut *subscriber).poll(cx))
+                .await
+                .expect("update must succeed");
+            assert_eq!(update, Update::Add(vec![(addr(8080), ())]));
+        }
+
+        // Other targets are resolved independently.
+        let other = resolve.call(8081).await.expect("resolve must succeed");
+        assert_eq!(counting.resolved.load(Ordering::SeqCst), 2);
+        drop(other);
+        assert_eq!(counting.dropped.load(Ordering::SeqCst), 1);
+
+        // The resolution is torn down once its last subscriber is dropped.
+        drop((first, second));
+        assert_eq!(counting.dropped.load(Ordering::SeqCst), 1);
+        drop(third);
+        assert_eq!(counting.dropped.load(Ordering::SeqCst), 2);
+
+        let _ = resolve.call(8080).await.expect("resolve must succeed");
+        assert_eq!(counting.resolved.load(Ordering::SeqCst), 3);
+    }
+}
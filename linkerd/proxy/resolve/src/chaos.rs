@@ -0,0 +1,369 @@
+//! A middleware that injects synthetic faults into a resolution's updates.
+//!
+//! This is intended for exercising the proxy's handling of failed, delayed,
+//! and empty resolutions, e.g. reconnect backoff and fallbacks, in tests. It
+//! must not be used in production.
+//!
+//! Faults are chosen by a seeded random number generator, so that a given
+//! seed produces the same faults for the same sequence of updates.
+
+use futures::{ready, TryFuture};
+use linkerd2_error::Error;
+use linkerd2_proxy_core::resolve::{self, Update};
+use pin_project::pin_project;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::time;
+use tracing::debug;
+
+#[derive(Clone, Debug)]
+pub struct Resolve<R> {
+    resolve: R,
+    config: Config,
+    /// The number of resolutions that have been started, so that each
+    /// resolution's faults are seeded differently.
+    resolutions: u64,
+}
+
+/// Determines which faults are injected and how often.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Config {
+    seed: u64,
+    /// The probability that an update is replaced by a failure.
+    error_rate: f64,
+    /// The probability that an update is preceded by a spurious `Empty`.
+    no_endpoints_rate: f64,
+    /// The probability that an update is delayed.
+    delay_rate: f64,
+    delay: Duration,
+}
+
+/// A fault injected before an update is returned.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Fault {
+    /// The resolution fails instead of returning the update.
+    Error,
+    /// The resolution becomes empty before the update is returned.
+    NoEndpoints,
+    /// The update is returned once the delay elapses.
+    Delay(Duration),
+}
+
+/// The error returned by a resolution when a failure is injected.
+#[derive(Clone, Debug)]
+pub struct InjectedFailure(());
+
+/// Chooses the fault, if any, injected before each update.
+#[derive(Debug)]
+pub struct Faults {
+    config: Config,
+    rng: StdRng,
+}
+
+#[pin_project]
+#[derive(Debug)]
+pub struct ResolveFuture<F> {
+    #[pin]
+    future: F,
+    faults: Option<Faults>,
+}
+
+#[pin_project]
+pub struct Resolution<R: resolve::Resolution> {
+    #[pin]
+    resolution: R,
+    faults: Faults,
+    /// An update that is returned once its fault has been injected.
+    pending: Option<Update<R::Endpoint>>,
+    delay: Option<time::Delay>,
+}
+
+// === impl Resolve ===
+
+impl<R> Resolve<R> {
+    /// Injects faults into each resolution's updates as determined by
+    /// `config`.
+    pub fn new<T>(config: Config, resolve: R) -> Self
+    where
+        Self: resolve::Resolve<T>,
+    {
+        Self {
+            resolve,
+            config,
+            resolutions: 0,
+        }
+    }
+}
+
+impl<T, R> tower::Service<T> for Resolve<R>
+where
+    R: resolve::Resolve<T>,
+    R::Error: Into<Error>,
+{
+    type Response = Resolution<R::Resolution>;
+    type Error = Error;
+    type Future = ResolveFuture<R::Future>;
+
+    #[inline]
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.resolve.poll_ready(cx).map_err(Into::into)
+    }
+
+    #[inline]
+    fn call(&mut self, target: T) -> Self::Future {
+        let seed = self.config.seed.wrapping_add(self.resolutions);
+        self.resolutions += 1;
+        Self::Future {
+            future: self.resolve.resolve(target),
+            faults: Some(Faults::new(Config {
+                seed,
+                ..self.config
+            })),
+        }
+    }
+}
+
+// === impl Config ===
+
+impl Config {
+    /// Returns a configuration that injects no faults.
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            error_rate: 0.0,
+            no_endpoints_rate: 0.0,
+            delay_rate: 0.0,
+            delay: Duration::from_secs(0),
+        }
+    }
+
+    /// Fails the resolution instead of returning an update with probability
+    /// `rate`.
+    pub fn with_errors(self, rate: f64) -> Self {
+        Self {
+            error_rate: rate,
+            ..self
+        }
+    }
+
+    /// Returns a spurious `Empty` before an update with probability `rate`.
+    pub fn with_no_endpoints(self, rate: f64) -> Self {
+        Self {
+            no_endpoints_rate: rate,
+            ..self
+        }
+    }
+
+    /// Delays an update by `delay` with probability `rate`.
+    pub fn with_delays(self, rate: f64, delay: Duration) -> Self {
+        Self {
+            delay_rate: rate,
+            delay,
+            ..self
+        }
+    }
+}
+
+// === impl Faults ===
+
+impl Faults {
+    pub fn new(config: Config) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(config.seed),
+            config,
+        }
+    }
+
+    /// Chooses the fault injected before the next update, if any.
+    ///
+    /// At most one fault is injected per update.
+    pub fn next_fault(&mut self) -> Option<Fault> {
+        let Config {
+            error_rate,
+            no_endpoints_rate,
+            delay_rate,
+            delay,
+            ..
+        } = self.config;
+        let p = self.rng.gen::<f64>();
+        if p < error_rate {
+            Some(Fault::Error)
+        } else if p < error_rate + no_endpoints_rate {
+            Some(Fault::NoEndpoints)
+        } else if p < error_rate + no_endpoints_rate + delay_rate {
+            Some(Fault::Delay(delay))
+        } else {
+            None
+        }
+    }
+}
+
+// === impl ResolveFuture ===
+
+impl<F> Future for ResolveFuture<F>
+where
+    F: TryFuture,
+    F::Ok: resolve::Resolution,
+    F::Error: Into<Error>,
+{
+    type Output = Result<Resolution<F::Ok>, Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let resolution = ready!(this.future.try_poll(cx)).map_err(Into::into)?;
+        let faults = this.faults.take().expect("polled after ready");
+        Poll::Ready(Ok(Resolution {
+            resolution,
+            faults,
+            pending: None,
+            delay: None,
+        }))
+    }
+}
+
+// === impl Resolution ===
+
+impl<R> resolve::Resolution for Resolution<R>
+where
+    R: resolve::Resolution,
+    R::Error: Into<Error>,
+{
+    type Endpoint = R::Endpoint;
+    type Error = Error;
+
+    fn poll(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Update<Self::Endpoint>, Self::Error>> {
+        let this = self.project();
+        if let Some(delay) = this.delay.as_mut() {
+            ready!(Pin::new(delay).poll(cx));
+            *this.delay = None;
+        }
+        if let Some(update) = this.pending.take() {
+            return Poll::Ready(Ok(update));
+        }
+
+        let update = ready!(this.resolution.poll(cx)).map_err(Into::into)?;
+        match this.faults.next_fault() {
+            None => Poll::Ready(Ok(update)),
+            Some(Fault::Error) => {
+                debug!("Injecting failure");
+                Poll::Ready(Err(InjectedFailure(()).into()))
+            }
+            Some(Fault::NoEndpoints) => {
+                debug!("Injecting empty update");
+                *this.pending = Some(update);
+                Poll::Ready(Ok(Update::Empty))
+            }
+            Some(Fault::Delay(delay)) => {
+                debug!(?delay, "Delaying update");
+                *this.pending = Some(update);
+                let mut delay = time::delay_for(delay);
+                if Pin::new(&mut delay).poll(cx).is_pending() {
+                    *this.delay = Some(delay);
+                    return Poll::Pending;
+                }
+                Poll::Ready(Ok(this.pending.take().expect("update must be pending")))
+            }
+        }
+    }
+}
+
+// === impl InjectedFailure ===
+
+impl std::fmt::Display for InjectedFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad("injected resolution failure")
+    }
+}
+
+impl std::error::Error for InjectedFailure {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use linkerd2_proxy_core::resolve::Resolution as _;
+    use std::collections::VecDeque;
+    use std::net::SocketAddr;
+
+    /// Produces an `Add` for each of the given addresses, in order.
+    struct Adds(VecDeque<SocketAddr>);
+
+    impl resolve::Resolution for Adds {
+        type Endpoint = ();
+        type Error = Error;
+
+        fn poll(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+        ) -> Poll<Result<Update<()>, Self::Error>> {
+            match self.0.pop_front() {
+                Some(addr) => Poll::Ready(Ok(Update::Add(vec![(addr, ())]))),
+                None => Poll::Pending,
+            }
+        }
+    }
+
+    fn config(seed: u64) -> Config {
+        Config::new(seed)
+            .with_errors(0.1)
+            .with_no_endpoints(0.2)
+            .with_delays(0.2, Duration::from_millis(1))
+    }
+
+    fn faults(seed: u64) -> Vec<Option<Fault>> {
+        let mut faults = Faults::new(config(seed));
+        (0..100).map(|_| faults.next_fault()).collect()
+    }
+
+    /// Returns each of a resolution's results, described as a string, until
+    /// the underlying resolution is exhausted.
+    async fn outcomes(seed: u64) -> Vec<String> {
+        let addrs = (1..=20)
+            .map(|n| SocketAddr::from(([198, 51, 100, n], 8080)))
+            .collect();
+        let mut resolution = Resolution {
+            resolution: Adds(addrs),
+            faults: Faults::new(config(seed)),
+            pending: None,
+            delay: None,
+        };
+        let mut outcomes = Vec::new();
+        while !resolution.resolution.0.is_empty() || resolution.pending.is_some() {
+            let next = futures::future::poll_fn(|cx| Pin::new(&mut resolution).poll(cx));
+            outcomes.push(match next.await {
+                Ok(Update::Add(eps)) => eps[0].0.to_string(),
+                Ok(update) => format!("{:?}", update),
+                Err(error) => error.to_string(),
+            });
+        }
+        outcomes
+    }
+
+    #[tokio::test]
+    async fn seeded_faults_are_reproducible() {
+        let faults0 = faults(0);
+        assert_eq!(faults0, faults(0));
+        assert_ne!(faults0, faults(1));
+        for fault in &[
+            Fault::Error,
+            Fault::NoEndpoints,
+            Fault::Delay(Duration::from_millis(1)),
+        ] {
+            assert!(
+                faults0.contains(&Some(*fault)),
+                "{:?} must be injected",
+                fault
+            );
+        }
+        assert!(faults0.contains(&None), "updates must not all be faulted");
+
+        // The same seed produces the same sequence of updates.
+        let outcomes0 = outcomes(0).await;
+        assert_eq!(outcomes0, outcomes(0).await);
+    }
+}
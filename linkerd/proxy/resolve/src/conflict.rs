@@ -0,0 +1,331 @@
+//! A middleware that decides how an endpoint that is re-advertised with a
+//! conflicting TLS identity is handled.
+//!
+//! When a source (or a flapping stream) adds an address that is already
+//! resolved, its new metadata ordinarily replaces the prior metadata. If the
+//! endpoints' TLS identities differ, silently replacing the endpoint may cause
+//! connections to be established with the wrong identity, so the conflict is
+//! resolved according to a `Policy`. Re-advertisements that do not change the
+//! endpoint's identity are always applied.
+
+use futures::{ready, TryFuture};
+use indexmap::IndexMap;
+use linkerd2_proxy_core::resolve::{self, Update};
+use pin_project::pin_project;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tracing::{debug, warn};
+
+/// Describes the TLS identity with which an endpoint is verified.
+pub trait TlsIdentity {
+    /// Returns the endpoint's identity, or `None` if it is not verified.
+    fn tls_identity(&self) -> Option<&str>;
+}
+
+/// Determines how an endpoint that is re-advertised with a different TLS
+/// identity is handled.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Policy {
+    /// The most recently advertised endpoint replaces the prior endpoint.
+    LastWriterWins,
+    /// The endpoint's identity may not change until it is removed. Conflicting
+    /// endpoints are ignored.
+    RejectChange,
+    /// An endpoint with an identity replaces the prior endpoint, but an
+    /// endpoint without an identity never replaces one that has an identity.
+    /// Each conflict is logged.
+    PreferIdentified,
+}
+
+#[derive(Clone, Debug)]
+pub struct Resolve<R> {
+    resolve: R,
+    policy: Policy,
+}
+
+#[pin_project]
+#[derive(Debug)]
+pub struct ResolveFuture<F> {
+    #[pin]
+    future: F,
+    policy: Policy,
+}
+
+#[pin_project]
+#[derive(Debug)]
+pub struct Resolution<R> {
+    #[pin]
+    resolution: R,
+    policy: Policy,
+    /// The identity of each resolved endpoint.
+    identities: IndexMap<SocketAddr, Option<String>>,
+}
+
+// === impl Policy ===
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy::LastWriterWins
+    }
+}
+
+impl Policy {
+    /// Returns true if an endpoint with the identity `new` should replace an
+    /// endpoint with the identity `prior`.
+    fn replaces(&self, prior: Option<&str>, new: Option<&str>) -> bool {
+        match self {
+            Policy::LastWriterWins => true,
+            Policy::RejectChange => false,
+            Policy::PreferIdentified => new.is_some() || prior.is_none(),
+        }
+    }
+}
+
+// === impl Resolve ===
+
+impl<R> Resolve<R> {
+    pub fn new<T>(policy: Policy, resolve: R) -> Self
+    where
+        Self: resolve::Resolve<T>,
+    {
+        Self { resolve, policy }
+    }
+}
+
+impl<T, R> tower::Service<T> for Resolve<R>
+where
+    R: resolve::Resolve<T>,
+    R::Endpoint: TlsIdentity,
+{
+    type Response = Resolution<R::Resolution>;
+    type Error = R::Error;
+    type Future = ResolveFuture<R::Future>;
+
+    #[inline]
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.resolve.poll_ready(cx)
+    }
+
+    #[inline]
+    fn call(&mut self, target: T) -> Self::Future {
+        Self::Future {
+            future: self.resolve.resolve(target),
+            policy: self.policy,
+        }
+    }
+}
+
+// === impl ResolveFuture ===
+
+impl<F> Future for ResolveFuture<F>
+where
+    F: TryFuture,
+    F::Ok: resolve::Resolution,
+{
+    type Output = Result<Resolution<F::Ok>, F::Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let resolution = ready!(this.future.try_poll(cx))?;
+        Poll::Ready(Ok(Resolution {
+            resolution,
+            policy: *this.policy,
+            identities: IndexMap::new(),
+        }))
+    }
+}
+
+// === impl Resolution ===
+
+impl<R> resolve::Resolution for Resolution<R>
+where
+    R: resolve::Resolution,
+    R::Endpoint: TlsIdentity,
+{
+    type Endpoint = R::Endpoint;
+    type Error = R::Error;
+
+    fn poll(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Update<Self::Endpoint>, Self::Error>> {
+        let mut this = self.project();
+        loop {
+            let update = ready!(this.resolution.as_mut().poll(cx))?;
+            match update {
+                Update::Add(endpoints) => {
+                    let policy = *this.policy;
+                    let identities = &mut *this.identities;
+                    let endpoints = endpoints
+                        .into_iter()
+                        .filter(|(addr, ep)| admit(policy, identities, *addr, ep))
+                        .collect::<Vec<_>>();
+                    // If every endpoint was ignored, there's nothing to
+                    // advertise.
+                    if !endpoints.is_empty() {
+                        return Poll::Ready(Ok(Update::Add(endpoints)));
+                    }
+                }
+                Update::Remove(addrs) => {
+                    for addr in addrs.iter() {
+                        this.identities.remove(addr);
+                    }
+                    return Poll::Ready(Ok(Update::Remove(addrs)));
+                }
+                update @ Update::Empty | update @ Update::DoesNotExist => {
+                    this.identities.clear();
+                    return Poll::Ready(Ok(update));
+                }
+            }
+        }
+    }
+}
+
+/// Records the identity of an added endpoint, returning false if the
+/// endpoint is ignored because it conflicts with the resolved endpoint.
+fn admit<E: TlsIdentity>(
+    policy: Policy,
+    identities: &mut IndexMap<SocketAddr, Option<String>>,
+    addr: SocketAddr,
+    ep: &E,
+) -> bool {
+    let new = ep.tls_identity();
+    if let Some(prior) = identities.get(&addr) {
+        let prior = prior.as_deref();
+        if prior != new {
+            if !policy.replaces(prior, new) {
+                warn!(%addr, ?prior, ?new, ?policy, "Ignoring endpoint with conflicting identity");
+                return false;
+            }
+            if policy == Policy::LastWriterWins {
+                debug!(%addr, ?prior, ?new, "Endpoint identity changed");
+            } else {
+                warn!(%addr, ?prior, ?new, ?policy, "Replacing endpoint with conflicting identity");
+            }
+        }
+    }
+    identities.insert(addr, new.map(String::from));
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use linkerd2_proxy_core::{resolve::Resolution as _, test_util::Updates};
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Ep {
+        identity: Option<&'static str>,
+        weight: u32,
+    }
+
+    impl TlsIdentity for Ep {
+        fn tls_identity(&self) -> Option<&str> {
+            self.identity
+        }
+    }
+
+    fn addr() -> SocketAddr {
+        ([198, 51, 100, 1], 8080).into()
+    }
+
+    fn ep(identity: Option<&'static str>) -> Ep {
+        Ep {
+            identity,
+            weight: 1,
+        }
+    }
+
+    const WEB: Option<&str> = Some("web.ns.serviceaccount.identity.linkerd.cluster.local");
+    const EVIL: Option<&str> = Some("evil.ns.serviceaccount.identity.linkerd.cluster.local");
+
+    /// Returns the updates that `policy` produces from `updates`, until the
+    /// underlying resolution is exhausted.
+    fn resolve(policy: Policy, updates: Vec<Update<Ep>>) -> Vec<Update<Ep>> {
+        let mut resolution = Resolution {
+            resolution: updates.into_iter().collect::<Updates<Ep>>(),
+            policy,
+            identities: IndexMap::new(),
+        };
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        let mut polled = Vec::new();
+        while let Poll::Ready(res) = Pin::new(&mut resolution).poll(&mut cx) {
+            polled.push(res.expect("update must succeed"));
+        }
+        polled
+    }
+
+    fn add(ep: Ep) -> Update<Ep> {
+        Update::Add(vec![(addr(), ep)])
+    }
+
+    #[test]
+    fn last_writer_wins() {
+        let updates = vec![add(ep(WEB)), add(ep(EVIL)), add(ep(None))];
+        assert_eq!(resolve(Policy::LastWriterWins, updates.clone()), updates);
+    }
+
+    #[test]
+    fn reject_change() {
+        let reweighted = Ep {
+            weight: 2,
+            ..ep(WEB)
+        };
+        let updates = vec![
+            add(ep(WEB)),
+            add(ep(EVIL)),
+            add(ep(None)),
+            // Changes that do not conflict are applied.
+            add(reweighted.clone()),
+            // Once the endpoint is removed, it may be added with any identity.
+            Update::Remove(vec![addr()]),
+            add(ep(EVIL)),
+        ];
+        assert_eq!(
+            resolve(Policy::RejectChange, updates),
+            vec![
+                add(ep(WEB)),
+                add(reweighted),
+                Update::Remove(vec![addr()]),
+                add(ep(EVIL)),
+            ]
+        );
+    }
+
+    #[test]
+    fn prefer_identified() {
+        let updates = vec![
+            add(ep(None)),
+            add(ep(WEB)),
+            add(ep(None)),
+            add(ep(EVIL)),
+            Update::Empty,
+            add(ep(None)),
+        ];
+        assert_eq!(
+            resolve(Policy::PreferIdentified, updates),
+            vec![
+                add(ep(None)),
+                add(ep(WEB)),
+                add(ep(EVIL)),
+                Update::Empty,
+                add(ep(None)),
+            ]
+        );
+    }
+
+    #[test]
+    fn ignores_only_conflicting_endpoints() {
+        let other = SocketAddr::from(([198, 51, 100, 2], 8080));
+        let updates = vec![
+            add(ep(WEB)),
+            Update::Add(vec![(addr(), ep(EVIL)), (other, ep(EVIL))]),
+        ];
+        assert_eq!(
+            resolve(Policy::RejectChange, updates),
+            vec![add(ep(WEB)), Update::Add(vec![(other, ep(EVIL))])]
+        );
+    }
+}
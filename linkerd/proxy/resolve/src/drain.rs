@@ -0,0 +1,194 @@
+//! A middleware that stops a `Resolution` from adding endpoints once the proxy
+//! has begun to drain, so that new services (and connections) are not built
+//! during shutdown.
+//!
+//! Removals continue to be propagated, so endpoints are still torn down while
+//! the proxy drains.
+
+use futures::{ready, FutureExt, TryFuture};
+use linkerd2_drain as drain;
+use linkerd2_proxy_core::resolve::{self, Update};
+use pin_project::pin_project;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+#[derive(Clone, Debug)]
+pub struct Resolve<R> {
+    resolve: R,
+    drain: drain::Watch,
+}
+
+#[pin_project]
+#[derive(Debug)]
+pub struct ResolveFuture<F> {
+    #[pin]
+    future: F,
+    drain: Option<drain::Watch>,
+}
+
+#[pin_project]
+pub struct Resolution<R> {
+    #[pin]
+    resolution: R,
+    /// Completes when the drain is signaled. Once it completes, the
+    /// resolution is draining and this is `None`.
+    signal: Option<Pin<Box<dyn Future<Output = drain::ReleaseShutdown> + Send + 'static>>>,
+}
+
+// === impl Resolve ===
+
+impl<R> Resolve<R> {
+    /// Suppresses added endpoints once `drain` is signaled.
+    pub fn new<T>(drain: drain::Watch, resolve: R) -> Self
+    where
+        Self: resolve::Resolve<T>,
+    {
+        Self { resolve, drain }
+    }
+}
+
+impl<T, R> tower::Service<T> for Resolve<R>
+where
+    R: resolve::Resolve<T>,
+{
+    type Response = Resolution<R::Resolution>;
+    type Error = R::Error;
+    type Future = ResolveFuture<R::Future>;
+
+    #[inline]
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.resolve.poll_ready(cx)
+    }
+
+    #[inline]
+    fn call(&mut self, target: T) -> Self::Future {
+        Self::Future {
+            future: self.resolve.resolve(target),
+            drain: Some(self.drain.clone()),
+        }
+    }
+}
+
+// === impl ResolveFuture ===
+
+impl<F> Future for ResolveFuture<F>
+where
+    F: TryFuture,
+    F::Ok: resolve::Resolution,
+{
+    type Output = Result<Resolution<F::Ok>, F::Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let resolution = ready!(this.future.try_poll(cx))?;
+        let drain = this.drain.take().expect("polled after ready");
+        Poll::Ready(Ok(Resolution {
+            resolution,
+            signal: Some(drain.signal().boxed()),
+        }))
+    }
+}
+
+// === impl Resolution ===
+
+impl<R: resolve::Resolution> resolve::Resolution for Resolution<R> {
+    type Endpoint = R::Endpoint;
+    type Error = R::Error;
+
+    fn poll(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Update<Self::Endpoint>, Self::Error>> {
+        let mut this = self.project();
+        loop {
+            // The resolution need not be drained itself, so shutdown is
+            // released as soon as the drain is observed.
+            if let Some(signal) = this.signal.as_mut() {
+                if let Poll::Ready(release) = signal.poll_unpin(cx) {
+                    tracing::debug!("draining; suppressing added endpoints");
+                    drop(release);
+                    *this.signal = None;
+                }
+            }
+
+            match ready!(this.resolution.as_mut().poll(cx))? {
+                Update::Add(eps) if this.signal.is_none() => {
+                    tracing::trace!(endpoints = eps.len(), "suppressing add while draining");
+                }
+                update => return Poll::Ready(Ok(update)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use linkerd2_error::Error;
+    use linkerd2_proxy_core::resolve::Resolution as _;
+    use std::collections::VecDeque;
+    use std::net::SocketAddr;
+    use std::sync::{Arc, Mutex};
+
+    /// A resolution that produces the scripted updates.
+    #[derive(Clone, Debug, Default)]
+    struct Scripted(Arc<Mutex<VecDeque<Update<()>>>>);
+
+    impl resolve::Resolution for Scripted {
+        type Endpoint = ();
+        type Error = Error;
+
+        fn poll(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<Update<()>, Error>> {
+            match self.0.lock().unwrap().pop_front() {
+                Some(update) => Poll::Ready(Ok(update)),
+                None => Poll::Pending,
+            }
+        }
+    }
+
+    fn addr0() -> SocketAddr {
+        ([198, 51, 100, 1], 8080).into()
+    }
+
+    fn addr1() -> SocketAddr {
+        ([198, 51, 100, 2], 8080).into()
+    }
+
+    #[tokio::test]
+    async fn suppresses_adds_while_draining() {
+        let (signal, watch) = drain::channel();
+        let updates = Scripted::default();
+        let mut resolution = ResolveFuture {
+            future: futures::future::ok::<_, Error>(updates.clone()),
+            drain: Some(watch),
+        }
+        .await
+        .expect("resolution must succeed");
+
+        updates
+            .0
+            .lock()
+            .unwrap()
+            .push_back(Update::Add(vec![(addr0(), ())]));
+        let update = futures::future::poll_fn(|cx| Pin::new(&mut resolution).poll(cx))
+            .await
+            .expect("update must succeed");
+        assert_eq!(update, Update::Add(vec![(addr0(), ())]));
+
+        let drained = signal.drain();
+        updates.0.lock().unwrap().extend(vec![
+            Update::Add(vec![(addr1(), ())]),
+            Update::Remove(vec![addr0()]),
+        ]);
+        let update = futures::future::poll_fn(|cx| Pin::new(&mut resolution).poll(cx))
+            .await
+            .expect("update must succeed");
+        assert_eq!(update, Update::Remove(vec![addr0()]));
+
+        // The resolution does not hold up the drain.
+        tokio::time::timeout(std::time::Duration::from_secs(1), drained)
+            .await
+            .expect("drain must complete");
+    }
+}
@@ -0,0 +1,350 @@
+//! A middleware that collapses a dual-stack host's endpoints into a single
+//! endpoint.
+//!
+//! When a host is reachable over both IPv4 and IPv6, a resolution may include
+//! an endpoint for each of its addresses, so that a balancer would treat the
+//! host as two backends. Endpoints that identify the same host are collapsed
+//! so that only one of the host's addresses--of the preferred family, as
+//! determined by a `FamilyPreference`--is resolved at a time. If that address
+//! is removed, another of the host's addresses is resolved in its place.
+//! Endpoints that do not identify a host are left as-is.
+
+use futures::{ready, TryFuture};
+use indexmap::{IndexMap, IndexSet};
+use linkerd2_dns::FamilyPreference;
+use linkerd2_proxy_core::resolve::{self, Update};
+use pin_project::pin_project;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tracing::trace;
+
+/// Identifies the host that an endpoint's address belongs to.
+pub trait HostIdentity {
+    /// Returns the endpoint's host, or `None` if the host is unknown.
+    fn host_id(&self) -> Option<&str>;
+}
+
+#[derive(Clone, Debug)]
+pub struct Resolve<R> {
+    resolve: R,
+    prefer: FamilyPreference,
+}
+
+#[pin_project]
+#[derive(Debug)]
+pub struct ResolveFuture<F> {
+    #[pin]
+    future: F,
+    prefer: FamilyPreference,
+}
+
+#[pin_project]
+#[derive(Debug)]
+pub struct Resolution<R, E> {
+    #[pin]
+    resolution: R,
+    hosts: Hosts<E>,
+    /// Removals to be returned after the preceding additions.
+    pending: Option<Update<E>>,
+}
+
+#[derive(Debug)]
+struct Hosts<E> {
+    prefer: FamilyPreference,
+    /// Each identified host's endpoints, by host.
+    hosts: HashMap<String, IndexMap<SocketAddr, E>>,
+    /// The host of each identified endpoint, by address.
+    addrs: HashMap<SocketAddr, String>,
+    /// The addresses that have been resolved.
+    resolved: HashSet<SocketAddr>,
+}
+
+// === impl Resolve ===
+
+impl<R> Resolve<R> {
+    /// Collapses each host's endpoints, resolving an address that `prefer`
+    /// selects from each host's addresses.
+    pub fn new<T>(prefer: FamilyPreference, resolve: R) -> Self
+    where
+        Self: resolve::Resolve<T>,
+    {
+        Self { resolve, prefer }
+    }
+}
+
+impl<T, R> tower::Service<T> for Resolve<R>
+where
+    R: resolve::Resolve<T>,
+    R::Endpoint: HostIdentity + Clone,
+{
+    type Response = Resolution<R::Resolution, R::Endpoint>;
+    type Error = R::Error;
+    type Future = ResolveFuture<R::Future>;
+
+    #[inline]
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.resolve.poll_ready(cx)
+    }
+
+    #[inline]
+    fn call(&mut self, target: T) -> Self::Future {
+        Self::Future {
+            future: self.resolve.resolve(target),
+            prefer: self.prefer,
+        }
+    }
+}
+
+// === impl ResolveFuture ===
+
+impl<F> Future for ResolveFuture<F>
+where
+    F: TryFuture,
+    F::Ok: resolve::Resolution,
+{
+    type Output = Result<Resolution<F::Ok, <F::Ok as resolve::Resolution>::Endpoint>, F::Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let resolution = ready!(this.future.try_poll(cx))?;
+        Poll::Ready(Ok(Resolution::new(*this.prefer, resolution)))
+    }
+}
+
+// === impl Resolution ===
+
+impl<R, E> Resolution<R, E> {
+    fn new(prefer: FamilyPreference, resolution: R) -> Self {
+        Self {
+            resolution,
+            hosts: Hosts {
+                prefer,
+                hosts: HashMap::new(),
+                addrs: HashMap::new(),
+                resolved: HashSet::new(),
+            },
+            pending: None,
+        }
+    }
+}
+
+impl<R> resolve::Resolution for Resolution<R, R::Endpoint>
+where
+    R: resolve::Resolution,
+    R::Endpoint: HostIdentity + Clone,
+{
+    type Endpoint = R::Endpoint;
+    type Error = R::Error;
+
+    fn poll(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Update<Self::Endpoint>, Self::Error>> {
+        let mut this = self.project();
+        if let Some(update) = this.pending.take() {
+            return Poll::Ready(Ok(update));
+        }
+
+        loop {
+            let (adds, removes) = match ready!(this.resolution.as_mut().poll(cx))? {
+                Update::Add(endpoints) => this.hosts.add(endpoints),
+                Update::Remove(addrs) => this.hosts.remove(addrs),
+                update @ Update::Empty | update @ Update::DoesNotExist => {
+                    this.hosts.clear();
+                    return Poll::Ready(Ok(update));
+                }
+            };
+
+            // Addresses are removed only after their replacements are added,
+            // so that a host is never without an endpoint.
+            let removes = if removes.is_empty() {
+                None
+            } else {
+                Some(Update::Remove(removes))
+            };
+            if adds.is_empty() {
+                if let Some(update) = removes {
+                    return Poll::Ready(Ok(update));
+                }
+                trace!("Update did not change the resolved endpoints");
+                continue;
+            }
+            *this.pending = removes;
+            return Poll::Ready(Ok(Update::Add(adds)));
+        }
+    }
+}
+
+// === impl Hosts ===
+
+impl<E: HostIdentity + Clone> Hosts<E> {
+    fn add(&mut self, endpoints: Vec<(SocketAddr, E)>) -> (Vec<(SocketAddr, E)>, Vec<SocketAddr>) {
+        let mut affected = IndexSet::new();
+        let mut unidentified = HashMap::new();
+        let updated = endpoints.iter().map(|(addr, _)| *addr).collect();
+        for (addr, ep) in endpoints.into_iter() {
+            // The endpoint may have moved to another host.
+            self.forget(addr, &mut affected);
+            match ep.host_id().map(String::from) {
+                Some(id) => {
+                    let host = self.hosts.entry(id.clone()).or_default();
+                    host.insert(addr, ep);
+                    affected.extend(host.keys().cloned());
+                    self.addrs.insert(addr, id);
+                }
+                None => {
+                    affected.insert(addr);
+                    unidentified.insert(addr, ep);
+                }
+            }
+        }
+        self.reconcile(affected, unidentified, &updated)
+    }
+
+    fn remove(&mut self, addrs: Vec<SocketAddr>) -> (Vec<(SocketAddr, E)>, Vec<SocketAddr>) {
+        let mut affected = IndexSet::new();
+        for addr in addrs.into_iter() {
+            affected.insert(addr);
+            self.forget(addr, &mut affected);
+        }
+        self.reconcile(affected, HashMap::new(), &HashSet::new())
+    }
+
+    fn clear(&mut self) {
+        self.hosts.clear();
+        self.addrs.clear();
+        self.resolved.clear();
+    }
+
+    /// Removes an address from its host, if it has one, noting the host's
+    /// remaining addresses as affected.
+    fn forget(&mut self, addr: SocketAddr, affected: &mut IndexSet<SocketAddr>) {
+        let id = match self.addrs.remove(&addr) {
+            Some(id) => id,
+            None => return,
+        };
+        if let Some(host) = self.hosts.get_mut(&id) {
+            host.remove(&addr);
+            affected.extend(host.keys().cloned());
+            if host.is_empty() {
+                self.hosts.remove(&id);
+            }
+        }
+    }
+
+    /// Determines which of the affected addresses must be added or removed.
+    ///
+    /// Unidentified endpoints are always resolved. Each identified host
+    /// resolves its first address that is preferred by the
+    /// `FamilyPreference`, if any. Resolved endpoints that were `updated` are
+    /// added again, so that their metadata is current.
+    fn reconcile(
+        &mut self,
+        affected: IndexSet<SocketAddr>,
+        mut unidentified: HashMap<SocketAddr, E>,
+        updated: &HashSet<SocketAddr>,
+    ) -> (Vec<(SocketAddr, E)>, Vec<SocketAddr>) {
+        let mut adds = Vec::new();
+        let mut removes = Vec::new();
+        for addr in affected.into_iter() {
+            let ep = unidentified.remove(&addr).or_else(|| {
+                let host = self.hosts.get(self.addrs.get(&addr)?)?;
+                if self.select(host) != Some(addr) {
+                    return None;
+                }
+                Some(host[&addr].clone())
+            });
+            match ep {
+                Some(ep) => {
+                    if self.resolved.insert(addr) || updated.contains(&addr) {
+                        adds.push((addr, ep));
+                    }
+                }
+                None => {
+                    if self.resolved.remove(&addr) {
+                        removes.push(addr);
+                    }
+                }
+            }
+        }
+        (adds, removes)
+    }
+
+    fn select(&self, host: &IndexMap<SocketAddr, E>) -> Option<SocketAddr> {
+        let ip = *self
+            .prefer
+            .preferred(host.keys().map(SocketAddr::ip))
+            .first()?;
+        host.keys().find(|addr| addr.ip() == ip).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use linkerd2_proxy_core::test_util::{next, Updates};
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Ep(Option<&'static str>);
+
+    impl HostIdentity for Ep {
+        fn host_id(&self) -> Option<&str> {
+            self.0
+        }
+    }
+
+    fn v4(n: u8) -> SocketAddr {
+        ([198, 51, 100, n], 8080).into()
+    }
+
+    fn v6(n: u16) -> SocketAddr {
+        ([0x2001, 0xdb8, 0, 0, 0, 0, 0, n], 8080).into()
+    }
+
+    #[tokio::test]
+    async fn collapses_dual_stack_hosts() {
+        let updates = Updates::default();
+        let mut resolution = Resolution::new(FamilyPreference::V6First, updates.clone());
+
+        // Each host's IPv6 endpoint is resolved; endpoints without a host are
+        // resolved as-is.
+        updates.push(Update::Add(vec![
+            (v4(1), Ep(Some("web-1"))),
+            (v6(1), Ep(Some("web-1"))),
+            (v4(2), Ep(Some("web-2"))),
+            (v4(3), Ep(None)),
+        ]));
+        assert_eq!(
+            next(&mut resolution).await,
+            Update::Add(vec![
+                (v6(1), Ep(Some("web-1"))),
+                (v4(2), Ep(Some("web-2"))),
+                (v4(3), Ep(None)),
+            ])
+        );
+
+        // A host's preferred address replaces its other address.
+        updates.push(Update::Add(vec![(v6(2), Ep(Some("web-2")))]));
+        assert_eq!(
+            next(&mut resolution).await,
+            Update::Add(vec![(v6(2), Ep(Some("web-2")))])
+        );
+        assert_eq!(next(&mut resolution).await, Update::Remove(vec![v4(2)]));
+
+        // Removing an unselected address is not resolved, and removing a
+        // selected address falls back to the host's other address.
+        updates.push(Update::Remove(vec![v4(2)]));
+        updates.push(Update::Remove(vec![v6(1), v4(3)]));
+        assert_eq!(
+            next(&mut resolution).await,
+            Update::Add(vec![(v4(1), Ep(Some("web-1")))])
+        );
+        assert_eq!(
+            next(&mut resolution).await,
+            Update::Remove(vec![v6(1), v4(3)])
+        );
+    }
+}
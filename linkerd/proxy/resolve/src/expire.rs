@@ -0,0 +1,244 @@
+//! A middleware that removes endpoints once they expire.
+//!
+//! Some sources, like DNS, never remove endpoints explicitly; their endpoints
+//! are only valid until their records' TTL elapses. Each added endpoint that
+//! expires is removed at its expiry unless it is added again with a later
+//! one.
+
+use futures::{ready, TryFuture};
+use linkerd2_proxy_core::resolve::{self, Update};
+use pin_project::pin_project;
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tokio::time::{self, delay_queue, DelayQueue};
+use tracing::debug;
+
+/// Describes when an endpoint expires.
+pub trait Expires {
+    /// Returns the time until which the endpoint is valid, or `None` if it
+    /// remains valid until it is removed.
+    fn valid_until(&self) -> Option<Instant>;
+}
+
+#[derive(Clone, Debug)]
+pub struct Resolve<R> {
+    resolve: R,
+}
+
+#[pin_project]
+#[derive(Debug)]
+pub struct ResolveFuture<F> {
+    #[pin]
+    future: F,
+}
+
+#[pin_project]
+#[derive(Debug)]
+pub struct Resolution<R> {
+    #[pin]
+    resolution: R,
+    expiries: DelayQueue<SocketAddr>,
+    /// The expiry of each endpoint that expires.
+    keys: HashMap<SocketAddr, delay_queue::Key>,
+}
+
+// === impl Resolve ===
+
+impl<R> Resolve<R> {
+    pub fn new<T>(resolve: R) -> Self
+    where
+        Self: resolve::Resolve<T>,
+    {
+        Self { resolve }
+    }
+}
+
+impl<T, R> tower::Service<T> for Resolve<R>
+where
+    R: resolve::Resolve<T>,
+    R::Endpoint: Expires,
+{
+    type Response = Resolution<R::Resolution>;
+    type Error = R::Error;
+    type Future = ResolveFuture<R::Future>;
+
+    #[inline]
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.resolve.poll_ready(cx)
+    }
+
+    #[inline]
+    fn call(&mut self, target: T) -> Self::Future {
+        Self::Future {
+            future: self.resolve.resolve(target),
+        }
+    }
+}
+
+// === impl ResolveFuture ===
+
+impl<F> Future for ResolveFuture<F>
+where
+    F: TryFuture,
+    F::Ok: resolve::Resolution,
+{
+    type Output = Result<Resolution<F::Ok>, F::Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let resolution = ready!(self.project().future.try_poll(cx))?;
+        Poll::Ready(Ok(Resolution::new(resolution)))
+    }
+}
+
+// === impl Resolution ===
+
+impl<R> Resolution<R> {
+    fn new(resolution: R) -> Self {
+        Self {
+            resolution,
+            expiries: DelayQueue::new(),
+            keys: HashMap::new(),
+        }
+    }
+}
+
+impl<R> resolve::Resolution for Resolution<R>
+where
+    R: resolve::Resolution,
+    R::Endpoint: Expires,
+{
+    type Endpoint = R::Endpoint;
+    type Error = R::Error;
+
+    fn poll(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Update<Self::Endpoint>, Self::Error>> {
+        let this = self.project();
+
+        // Remove all of the endpoints that have expired. The queue is polled
+        // on each call, so that it is always registered for expiries that
+        // were scheduled by prior updates.
+        let mut expired = Vec::new();
+        while let Poll::Ready(Some(res)) = this.expiries.poll_expired(cx) {
+            match res {
+                Ok(exp) => {
+                    let addr = exp.into_inner();
+                    this.keys.remove(&addr);
+                    expired.push(addr);
+                }
+                Err(error) => {
+                    tracing::warn!(%error, "Timer failed; endpoints will not expire");
+                    break;
+                }
+            }
+        }
+        if !expired.is_empty() {
+            debug!(endpoints = expired.len(), "Removing expired endpoints");
+            return Poll::Ready(Ok(Update::Remove(expired)));
+        }
+
+        let update = ready!(this.resolution.poll(cx))?;
+        match update {
+            Update::Add(ref endpoints) => {
+                for (addr, ep) in endpoints.iter() {
+                    match ep.valid_until() {
+                        // Refreshing an endpoint reschedules its expiry.
+                        Some(valid_until) => {
+                            let at = time::Instant::from_std(valid_until);
+                            match this.keys.get(addr) {
+                                Some(key) => this.expiries.reset_at(key, at),
+                                None => {
+                                    let key = this.expiries.insert_at(*addr, at);
+                                    this.keys.insert(*addr, key);
+                                }
+                            }
+                        }
+                        None => {
+                            if let Some(key) = this.keys.remove(addr) {
+                                this.expiries.remove(&key);
+                            }
+                        }
+                    }
+                }
+            }
+            Update::Remove(ref addrs) => {
+                for addr in addrs.iter() {
+                    if let Some(key) = this.keys.remove(addr) {
+                        this.expiries.remove(&key);
+                    }
+                }
+            }
+            Update::Empty | Update::DoesNotExist => {
+                this.expiries.clear();
+                this.keys.clear();
+            }
+        }
+        Poll::Ready(Ok(update))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use linkerd2_proxy_core::test_util::{next, next_within, Updates};
+    use std::time::Duration;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Ep(Instant);
+
+    impl Expires for Ep {
+        fn valid_until(&self) -> Option<Instant> {
+            Some(self.0)
+        }
+    }
+
+    fn addr(n: u8) -> SocketAddr {
+        ([198, 51, 100, n], 8080).into()
+    }
+
+    #[tokio::test]
+    async fn removes_endpoints_unless_refreshed() {
+        let ttl = Duration::from_millis(100);
+        let updates = Updates::default();
+        let mut resolution = Resolution::new(updates.clone());
+
+        let add = Update::Add(vec![
+            (addr(1), Ep(Instant::now() + ttl)),
+            (addr(2), Ep(Instant::now() + ttl)),
+        ]);
+        updates.push(add.clone());
+        assert_eq!(next(&mut resolution).await, add);
+
+        // The first endpoint is refreshed before it expires.
+        time::delay_for(ttl / 2).await;
+        let refresh = Update::Add(vec![(addr(1), Ep(Instant::now() + ttl))]);
+        updates.push(refresh.clone());
+        assert_eq!(next(&mut resolution).await, refresh);
+
+        // The second endpoint is removed at its expiry...
+        assert_eq!(
+            next_within(&mut resolution, ttl).await,
+            Some(Update::Remove(vec![addr(2)]))
+        );
+
+        // ...and the first endpoint is removed at its refreshed expiry.
+        assert_eq!(
+            next_within(&mut resolution, ttl).await,
+            Some(Update::Remove(vec![addr(1)]))
+        );
+
+        // Explicitly removed endpoints do not expire.
+        let add = Update::Add(vec![(addr(3), Ep(Instant::now() + ttl / 2))]);
+        updates.push(add.clone());
+        assert_eq!(next(&mut resolution).await, add);
+        let remove = Update::Remove(vec![addr(3)]);
+        updates.push(remove.clone());
+        assert_eq!(next(&mut resolution).await, remove);
+        assert_eq!(next_within(&mut resolution, ttl).await, None);
+    }
+}
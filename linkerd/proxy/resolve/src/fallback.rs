@@ -0,0 +1,388 @@
+//! A middleware that falls back to a secondary resolution while the primary
+//! resolution has no endpoints.
+//!
+//! This is intended for falling back to DNS when the control plane does not
+//! know of a destination. Both resolutions are driven for as long as the
+//! resolution is held, so that the fallback endpoints are known as soon as
+//! the primary resolution reports that it has none. The fallback endpoints
+//! are used once the primary resolution produces an `Empty` or `DoesNotExist`
+//! update, until it adds endpoints again; a `RecoveryPolicy` determines how
+//! the fallback endpoints are replaced at that point.
+
+use futures::{ready, TryFuture};
+use indexmap::{IndexMap, IndexSet};
+use linkerd2_error::Error;
+use linkerd2_proxy_core::resolve::{self, Update};
+use pin_project::pin_project;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::time;
+use tracing::debug;
+
+/// Determines how fallback endpoints are replaced once the primary
+/// resolution has endpoints again.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RecoveryPolicy {
+    /// The fallback endpoints are removed as soon as the primary endpoints
+    /// are added, so that requests are never routed to both.
+    Replace,
+    /// The fallback endpoints are retained alongside the primary endpoints
+    /// for the given transition window, so that requests are not routed to
+    /// endpoints that are still being connected.
+    Union(Duration),
+}
+
+#[derive(Clone, Debug)]
+pub struct Resolve<P, F> {
+    primary: P,
+    fallback: F,
+    policy: RecoveryPolicy,
+}
+
+#[pin_project]
+pub struct ResolveFuture<P: TryFuture, F: TryFuture> {
+    #[pin]
+    primary: P,
+    #[pin]
+    fallback: F,
+    resolved: (Option<P::Ok>, Option<F::Ok>),
+    policy: RecoveryPolicy,
+}
+
+#[pin_project]
+pub struct Resolution<P, F, E> {
+    #[pin]
+    primary: P,
+    #[pin]
+    fallback: F,
+    policy: RecoveryPolicy,
+    mode: Mode,
+    /// The addresses of the primary resolution's endpoints.
+    primary_addrs: IndexSet<SocketAddr>,
+    /// The fallback resolution's endpoints, whether or not they are used.
+    fallback_endpoints: IndexMap<SocketAddr, E>,
+    /// The addresses of the fallback endpoints that have been added.
+    added: IndexSet<SocketAddr>,
+    /// Removals to be returned after the preceding additions.
+    pending: Option<Update<E>>,
+}
+
+#[derive(Debug)]
+enum Mode {
+    Primary,
+    Fallback,
+    /// The primary resolution has recovered, and the fallback endpoints are
+    /// removed once the window elapses.
+    Transition(time::Delay),
+}
+
+// === impl Resolve ===
+
+impl<P, F> Resolve<P, F> {
+    pub fn new(policy: RecoveryPolicy, primary: P, fallback: F) -> Self {
+        Self {
+            primary,
+            fallback,
+            policy,
+        }
+    }
+}
+
+impl<T, P, F> tower::Service<T> for Resolve<P, F>
+where
+    T: Clone,
+    P: resolve::Resolve<T>,
+    P::Endpoint: Clone,
+    P::Error: Into<Error>,
+    F: resolve::Resolve<T, Endpoint = P::Endpoint>,
+    F::Error: Into<Error>,
+{
+    type Response = Resolution<P::Resolution, F::Resolution, P::Endpoint>;
+    type Error = Error;
+    type Future = ResolveFuture<P::Future, F::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        ready!(self.primary.poll_ready(cx)).map_err(Into::into)?;
+        ready!(self.fallback.poll_ready(cx)).map_err(Into::into)?;
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        Self::Future {
+            primary: self.primary.resolve(target.clone()),
+            fallback: self.fallback.resolve(target),
+            resolved: (None, None),
+            policy: self.policy,
+        }
+    }
+}
+
+// === impl ResolveFuture ===
+
+impl<P, F> Future for ResolveFuture<P, F>
+where
+    P: TryFuture,
+    P::Ok: resolve::Resolution,
+    P::Error: Into<Error>,
+    F: TryFuture,
+    F::Ok: resolve::Resolution,
+    F::Error: Into<Error>,
+{
+    type Output = Result<Resolution<P::Ok, F::Ok, <P::Ok as resolve::Resolution>::Endpoint>, Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let (primary, fallback) = this.resolved;
+        if primary.is_none() {
+            if let Poll::Ready(res) = this.primary.try_poll(cx) {
+                *primary = Some(res.map_err(Into::into)?);
+            }
+        }
+        if fallback.is_none() {
+            if let Poll::Ready(res) = this.fallback.try_poll(cx) {
+                *fallback = Some(res.map_err(Into::into)?);
+            }
+        }
+        if primary.is_none() || fallback.is_none() {
+            return Poll::Pending;
+        }
+
+        Poll::Ready(Ok(Resolution::new(
+            *this.policy,
+            primary.take().unwrap(),
+            fallback.take().unwrap(),
+        )))
+    }
+}
+
+// === impl Resolution ===
+
+impl<P, F, E> Resolution<P, F, E> {
+    fn new(policy: RecoveryPolicy, primary: P, fallback: F) -> Self {
+        Self {
+            primary,
+            fallback,
+            policy,
+            mode: Mode::Primary,
+            primary_addrs: IndexSet::new(),
+            fallback_endpoints: IndexMap::new(),
+            added: IndexSet::new(),
+            pending: None,
+        }
+    }
+}
+
+impl<P, F> resolve::Resolution for Resolution<P, F, P::Endpoint>
+where
+    P: resolve::Resolution,
+    P::Endpoint: Clone,
+    P::Error: Into<Error>,
+    F: resolve::Resolution<Endpoint = P::Endpoint>,
+    F::Error: Into<Error>,
+{
+    type Endpoint = P::Endpoint;
+    type Error = Error;
+
+    fn poll(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Update<Self::Endpoint>, Self::Error>> {
+        let mut this = self.project();
+        if let Some(update) = this.pending.take() {
+            return Poll::Ready(Ok(update));
+        }
+
+        loop {
+            if let Mode::Transition(ref mut delay) = this.mode {
+                if Pin::new(delay).poll(cx).is_ready() {
+                    debug!("Transition elapsed; removing fallback endpoints");
+                    *this.mode = Mode::Primary;
+                    let removed = this.added.drain(..).collect::<Vec<_>>();
+                    if !removed.is_empty() {
+                        return Poll::Ready(Ok(Update::Remove(removed)));
+                    }
+                }
+            }
+
+            if let Poll::Ready(res) = this.primary.as_mut().poll(cx) {
+                match res.map_err(Into::into)? {
+                    Update::Add(endpoints) => {
+                        for (addr, _) in endpoints.iter() {
+                            this.primary_addrs.insert(*addr);
+                            // The primary endpoint supersedes the fallback.
+                            this.added.remove(addr);
+                        }
+                        if let Mode::Fallback = this.mode {
+                            match *this.policy {
+                                RecoveryPolicy::Replace => {
+                                    debug!("Primary recovered; replacing fallback endpoints");
+                                    *this.mode = Mode::Primary;
+                                    let removed = this.added.drain(..).collect::<Vec<_>>();
+                                    if !removed.is_empty() {
+                                        *this.pending = Some(Update::Remove(removed));
+                                    }
+                                }
+                                RecoveryPolicy::Union(window) => {
+                                    debug!(
+                                        ?window,
+                                        "Primary recovered; retaining fallback endpoints"
+                                    );
+                                    *this.mode = Mode::Transition(time::delay_for(window));
+                                }
+                            }
+                        }
+                        return Poll::Ready(Ok(Update::Add(endpoints)));
+                    }
+                    Update::Remove(addrs) => {
+                        for addr in addrs.iter() {
+                            this.primary_addrs.remove(addr);
+                            this.added.remove(addr);
+                        }
+                        return Poll::Ready(Ok(Update::Remove(addrs)));
+                    }
+                    reset @ Update::Empty | reset @ Update::DoesNotExist => {
+                        if let Mode::Fallback = this.mode {
+                            continue;
+                        }
+                        debug!("Primary has no endpoints; falling back");
+                        *this.mode = Mode::Fallback;
+                        let primary = std::mem::take(this.primary_addrs);
+                        if this.fallback_endpoints.is_empty() {
+                            this.added.clear();
+                            return Poll::Ready(Ok(reset));
+                        }
+
+                        // Add the fallback endpoints before removing the
+                        // primary endpoints, so that there is no gap.
+                        let removed = primary
+                            .into_iter()
+                            .chain(std::mem::take(this.added))
+                            .filter(|addr| !this.fallback_endpoints.contains_key(addr))
+                            .collect::<IndexSet<_>>();
+                        if !removed.is_empty() {
+                            *this.pending = Some(Update::Remove(removed.into_iter().collect()));
+                        }
+                        let endpoints = this
+                            .fallback_endpoints
+                            .iter()
+                            .map(|(addr, ep)| (*addr, ep.clone()))
+                            .collect::<Vec<_>>();
+                        this.added.extend(this.fallback_endpoints.keys().cloned());
+                        return Poll::Ready(Ok(Update::Add(endpoints)));
+                    }
+                }
+            }
+
+            let update = match ready!(this.fallback.as_mut().poll(cx)) {
+                Ok(update) => update,
+                Err(e) => return Poll::Ready(Err(e.into())),
+            };
+            let falling_back = matches!(this.mode, Mode::Fallback);
+            match update {
+                Update::Add(endpoints) => {
+                    for (addr, ep) in endpoints.iter() {
+                        this.fallback_endpoints.insert(*addr, ep.clone());
+                    }
+                    if falling_back {
+                        this.added.extend(endpoints.iter().map(|(addr, _)| *addr));
+                        return Poll::Ready(Ok(Update::Add(endpoints)));
+                    }
+                }
+                Update::Remove(addrs) => {
+                    let mut removed = Vec::new();
+                    for addr in addrs.into_iter() {
+                        this.fallback_endpoints.remove(&addr);
+                        if falling_back && this.added.remove(&addr) {
+                            removed.push(addr);
+                        }
+                    }
+                    if !removed.is_empty() {
+                        return Poll::Ready(Ok(Update::Remove(removed)));
+                    }
+                }
+                reset @ Update::Empty | reset @ Update::DoesNotExist => {
+                    this.fallback_endpoints.clear();
+                    if falling_back {
+                        this.added.clear();
+                        return Poll::Ready(Ok(reset));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use linkerd2_proxy_core::test_util::{next, next_within, Updates};
+
+    type Fallback = Resolution<Updates<()>, Updates<()>, ()>;
+
+    fn addr(n: u8) -> SocketAddr {
+        ([198, 51, 100, n], 8080).into()
+    }
+
+    /// Builds a resolution whose primary resolution does not exist, so that
+    /// it is using its fallback endpoint.
+    async fn falling_back(policy: RecoveryPolicy) -> (Fallback, Updates<()>) {
+        let primary = Updates::default();
+        let fallback = Updates::default();
+        let mut resolution = Resolution::new(policy, primary.clone(), fallback.clone());
+
+        fallback.push(Update::Add(vec![(addr(1), ())]));
+        primary.push(Update::DoesNotExist);
+        assert_eq!(next(&mut resolution).await, Update::DoesNotExist);
+        assert_eq!(
+            next(&mut resolution).await,
+            Update::Add(vec![(addr(1), ())])
+        );
+
+        (resolution, primary)
+    }
+
+    #[tokio::test]
+    async fn replace_swaps_endpoints_on_recovery() {
+        let (mut resolution, primary) = falling_back(RecoveryPolicy::Replace).await;
+
+        // The fallback endpoint is removed immediately after the primary
+        // endpoint is added.
+        primary.push(Update::Add(vec![(addr(2), ())]));
+        assert_eq!(
+            next(&mut resolution).await,
+            Update::Add(vec![(addr(2), ())])
+        );
+        assert_eq!(next(&mut resolution).await, Update::Remove(vec![addr(1)]));
+
+        // Falling back again adds the fallback endpoint before removing the
+        // primary endpoint.
+        primary.push(Update::Empty);
+        assert_eq!(
+            next(&mut resolution).await,
+            Update::Add(vec![(addr(1), ())])
+        );
+        assert_eq!(next(&mut resolution).await, Update::Remove(vec![addr(2)]));
+    }
+
+    #[tokio::test]
+    async fn union_retains_fallback_during_transition() {
+        let window = Duration::from_millis(100);
+        let (mut resolution, primary) = falling_back(RecoveryPolicy::Union(window)).await;
+
+        primary.push(Update::Add(vec![(addr(2), ())]));
+        assert_eq!(
+            next(&mut resolution).await,
+            Update::Add(vec![(addr(2), ())])
+        );
+
+        // The fallback endpoint is retained until the window elapses.
+        assert_eq!(next_within(&mut resolution, window / 2).await, None);
+        assert_eq!(
+            next_within(&mut resolution, window).await,
+            Some(Update::Remove(vec![addr(1)]))
+        );
+    }
+}
@@ -0,0 +1,452 @@
+//! A `Resolve` that sources endpoints from a local file, so that the proxy
+//! may be configured statically (e.g. by GitOps tooling) without a control
+//! plane.
+//!
+//! The file is a JSON list of endpoints, e.g.:
+//!
+//! ```json
+//! [
+//!   { "addr": "10.1.1.1:8080", "weight": 10, "labels": { "zone": "a" } },
+//!   { "addr": "10.1.1.2:8080", "tls_identity": "web.ns.serviceaccount.identity.linkerd.cluster.local" }
+//! ]
+//! ```
+//!
+//! Only `addr` is required. The file is read periodically, on the blocking
+//! thread pool, and, when its contents change, the difference from the prior
+//! endpoints is returned. A file that cannot be read or parsed is ignored
+//! until it changes again, so the prior endpoints are retained.
+
+use crate::recover::reconcile_after_connect;
+use indexmap::IndexMap;
+use linkerd2_error::Error;
+use linkerd2_proxy_core::resolve::{self, Update};
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::{task, time};
+use tracing::{debug, warn};
+
+/// Resolves all targets to the endpoints listed in a file.
+#[derive(Clone, Debug)]
+pub struct Resolve {
+    path: PathBuf,
+    interval: Duration,
+}
+
+#[derive(Debug)]
+pub struct Resolution {
+    path: PathBuf,
+    interval: time::Interval,
+    /// The contents of the most recently read file.
+    contents: String,
+    /// A read of the file that has not yet completed.
+    reading: Option<task::JoinHandle<io::Result<String>>>,
+    endpoints: IndexMap<SocketAddr, Endpoint>,
+    /// Updates that have been computed but not yet returned.
+    pending: VecDeque<Update<Endpoint>>,
+}
+
+/// An endpoint listed in the file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Endpoint {
+    weight: u32,
+    labels: BTreeMap<String, String>,
+    tls_identity: Option<String>,
+}
+
+/// Indicates that the file's contents are not a valid list of endpoints.
+#[derive(Clone, Debug)]
+pub struct InvalidFile(String);
+
+// === impl Resolve ===
+
+impl Resolve {
+    /// Checks `path` for changes every `interval`.
+    pub fn new(path: impl Into<PathBuf>, interval: Duration) -> Self {
+        Self {
+            path: path.into(),
+            interval,
+        }
+    }
+}
+
+impl<T> tower::Service<T> for Resolve {
+    type Response = Resolution;
+    type Error = Error;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    /// Fails if the file cannot be loaded initially.
+    fn call(&mut self, _: T) -> Self::Future {
+        Box::pin(Resolution::load(self.path.clone(), self.interval))
+    }
+}
+
+// === impl Resolution ===
+
+impl Resolution {
+    async fn load(path: PathBuf, interval: Duration) -> Result<Self, Error> {
+        let contents = read(path.clone()).await??;
+        let endpoints = parse(&contents)?;
+        debug!(path = %path.display(), endpoints = endpoints.len(), "Loaded");
+
+        let mut resolution = Self {
+            interval: time::interval_at(time::Instant::now() + interval, interval),
+            path,
+            contents,
+            reading: None,
+            endpoints: IndexMap::new(),
+            pending: VecDeque::new(),
+        };
+        resolution.update(endpoints, true);
+        Ok(resolution)
+    }
+
+    /// Reloads the file if its contents have changed since it was last read.
+    fn reload(&mut self, contents: String) {
+        if self.contents == contents {
+            return;
+        }
+        // The file is not reloaded again until it changes, even if it is
+        // invalid.
+        self.contents = contents;
+
+        match parse(&self.contents) {
+            Ok(endpoints) => {
+                debug!(path = %self.path.display(), endpoints = endpoints.len(), "Reloaded");
+                self.update(endpoints, false);
+            }
+            Err(error) => {
+                warn!(path = %self.path.display(), %error, "Retaining prior endpoints");
+            }
+        }
+    }
+
+    /// Computes the updates from the current endpoints to `endpoints`.
+    fn update(&mut self, endpoints: Vec<(SocketAddr, Endpoint)>, initial: bool) {
+        // An empty file is only reported initially or when it removes the
+        // prior endpoints.
+        if endpoints.is_empty() && self.endpoints.is_empty() && !initial {
+            return;
+        }
+        let snapshot = if endpoints.is_empty() {
+            Update::Empty
+        } else {
+            Update::Add(endpoints)
+        };
+        let (update, next) = match reconcile_after_connect(&self.endpoints, snapshot) {
+            Some(updates) => updates,
+            None => return,
+        };
+
+        for update in std::iter::once(update).chain(next) {
+            match update {
+                Update::Add(ref eps) => self.endpoints.extend(eps.iter().cloned()),
+                Update::Remove(ref addrs) => {
+                    for addr in addrs.iter() {
+                        self.endpoints.remove(addr);
+                    }
+                }
+                Update::Empty | Update::DoesNotExist => self.endpoints.clear(),
+            }
+            self.pending.push_back(update);
+        }
+    }
+}
+
+impl resolve::Resolution for Resolution {
+    type Endpoint = Endpoint;
+    type Error = Error;
+
+    fn poll(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Update<Endpoint>, Error>> {
+        loop {
+            if let Some(update) = self.pending.pop_front() {
+                return Poll::Ready(Ok(update));
+            }
+
+            if let Some(reading) = self.reading.as_mut() {
+                let read = futures::ready!(Pin::new(reading).poll(cx));
+                self.reading = None;
+                match read.map_err(Error::from).and_then(|r| Ok(r?)) {
+                    Ok(contents) => self.reload(contents),
+                    Err(error) => {
+                        warn!(path = %self.path.display(), %error, "Failed to read endpoints file");
+                    }
+                }
+                continue;
+            }
+
+            futures::ready!(self.interval.poll_tick(cx));
+            self.reading = Some(read(self.path.clone()));
+        }
+    }
+}
+
+/// Reads `path` on the blocking thread pool, so that the resolution's task is
+/// not blocked on the filesystem.
+fn read(path: PathBuf) -> task::JoinHandle<io::Result<String>> {
+    task::spawn_blocking(move || std::fs::read_to_string(path))
+}
+
+fn parse(contents: &str) -> Result<Vec<(SocketAddr, Endpoint)>, InvalidFile> {
+    use serde_json::Value;
+
+    let value = serde_json::from_str::<Value>(contents).map_err(|e| InvalidFile(e.to_string()))?;
+    let entries = value
+        .as_array()
+        .ok_or_else(|| InvalidFile("expected a list of endpoints".into()))?;
+
+    let mut endpoints = IndexMap::with_capacity(entries.len());
+    for entry in entries.iter() {
+        let addr = entry
+            .get("addr")
+            .and_then(Value::as_str)
+            .ok_or_else(|| InvalidFile("each endpoint must have an `addr`".into()))?;
+        let addr = addr
+            .parse::<SocketAddr>()
+            .map_err(|_| InvalidFile(format!("invalid address: {:?}", addr)))?;
+
+        let weight = match entry.get("weight") {
+            None => Endpoint::DEFAULT_WEIGHT,
+            Some(w) => w
+                .as_u64()
+                .filter(|w| *w <= u64::from(u32::MAX))
+                .ok_or_else(|| InvalidFile(format!("invalid weight for {}", addr)))?
+                as u32,
+        };
+
+        let mut labels = BTreeMap::new();
+        if let Some(ls) = entry.get("labels") {
+            let ls = ls
+                .as_object()
+                .ok_or_else(|| InvalidFile(format!("invalid labels for {}", addr)))?;
+            for (k, v) in ls.iter() {
+                let v = v
+                    .as_str()
+                    .ok_or_else(|| InvalidFile(format!("invalid label {:?} for {}", k, addr)))?;
+                labels.insert(k.clone(), v.to_string());
+            }
+        }
+
+        let tls_identity = match entry.get("tls_identity") {
+            None => None,
+            Some(id) => Some(
+                id.as_str()
+                    .ok_or_else(|| InvalidFile(format!("invalid TLS identity for {}", addr)))?
+                    .to_string(),
+            ),
+        };
+
+        let endpoint = Endpoint {
+            weight,
+            labels,
+            tls_identity,
+        };
+        if endpoints.insert(addr, endpoint).is_some() {
+            return Err(InvalidFile(format!("duplicate address: {}", addr)));
+        }
+    }
+
+    Ok(endpoints.into_iter().collect())
+}
+
+// === impl Endpoint ===
+
+impl Endpoint {
+    /// The weight of endpoints that do not specify one.
+    pub const DEFAULT_WEIGHT: u32 = 1;
+
+    pub fn weight(&self) -> u32 {
+        self.weight
+    }
+
+    pub fn labels(&self) -> &BTreeMap<String, String> {
+        &self.labels
+    }
+
+    /// Returns the TLS identity expected of the endpoint, if it is meshed.
+    pub fn tls_identity(&self) -> Option<&str> {
+        self.tls_identity.as_deref()
+    }
+}
+
+// === impl InvalidFile ===
+
+impl fmt::Display for InvalidFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid endpoints file: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidFile {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::FutureExt;
+    use linkerd2_proxy_core::test_util::next;
+    use tower::Service;
+
+    /// A file in the temporary directory that is removed when dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, contents: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "linkerd2-proxy-resolve-{}-{}.json",
+                std::process::id(),
+                name
+            ));
+            let file = TempFile(path);
+            file.write(contents);
+            file
+        }
+
+        /// Overwrites the file. Changes are detected by content, so the
+        /// modification time need not change.
+        fn write(&self, contents: &str) {
+            std::fs::write(&self.0, contents).expect("file must be written");
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn addr(n: u8) -> SocketAddr {
+        ([198, 51, 100, n], 8080).into()
+    }
+
+    const INTERVAL: Duration = Duration::from_millis(10);
+
+    #[tokio::test]
+    async fn initial_load() {
+        let file = TempFile::new(
+            "initial_load",
+            r#"[
+                {"addr": "198.51.100.1:8080", "weight": 10, "labels": {"zone": "a"}},
+                {"addr": "198.51.100.2:8080", "tls_identity": "web.ns.serviceaccount.identity.linkerd.cluster.local"}
+            ]"#,
+        );
+        let mut resolution = Resolve::new(&file.0, INTERVAL)
+            .call(())
+            .await
+            .expect("file must load");
+
+        let mut labels = BTreeMap::new();
+        labels.insert("zone".to_string(), "a".to_string());
+        let web = Endpoint {
+            weight: 10,
+            labels,
+            tls_identity: None,
+        };
+        let meshed = Endpoint {
+            weight: Endpoint::DEFAULT_WEIGHT,
+            labels: BTreeMap::new(),
+            tls_identity: Some("web.ns.serviceaccount.identity.linkerd.cluster.local".into()),
+        };
+        assert_eq!(
+            next(&mut resolution).now_or_never(),
+            Some(Update::Add(vec![(addr(1), web), (addr(2), meshed)]))
+        );
+
+        // Without changes, there are no further updates.
+        let next = time::timeout(INTERVAL * 5, next(&mut resolution)).await;
+        assert!(next.is_err(), "no updates expected; got {:?}", next);
+
+        let missing = Resolve::new(file.0.with_extension("missing"), INTERVAL)
+            .call(())
+            .await;
+        assert!(missing.is_err(), "a missing file must fail");
+    }
+
+    #[tokio::test]
+    async fn reload_adds_endpoint() {
+        let file = TempFile::new("reload_adds", r#"[{"addr": "198.51.100.1:8080"}]"#);
+        let mut resolution = Resolve::new(&file.0, INTERVAL)
+            .call(())
+            .await
+            .expect("file must load");
+        assert_eq!(
+            next(&mut resolution).await,
+            Update::Add(vec![(addr(1), Endpoint::default_weighted())])
+        );
+
+        file.write(r#"[{"addr": "198.51.100.1:8080"}, {"addr": "198.51.100.2:8080"}]"#);
+        assert_eq!(
+            next(&mut resolution).await,
+            Update::Add(vec![(addr(2), Endpoint::default_weighted())])
+        );
+
+        file.write("[]");
+        assert_eq!(next(&mut resolution).await, Update::Empty);
+    }
+
+    #[tokio::test]
+    async fn reload_retains_endpoints_on_parse_error() {
+        let file = TempFile::new("reload_invalid", r#"[{"addr": "198.51.100.1:8080"}]"#);
+        let mut resolution = Resolve::new(&file.0, INTERVAL)
+            .call(())
+            .await
+            .expect("file must load");
+        assert_eq!(
+            next(&mut resolution).await,
+            Update::Add(vec![(addr(1), Endpoint::default_weighted())])
+        );
+
+        file.write(r#"[{"addr": "198.51.100.2:8080"#);
+        let next_update = time::timeout(INTERVAL * 5, next(&mut resolution)).await;
+        assert!(
+            next_update.is_err(),
+            "an invalid file must not update endpoints; got {:?}",
+            next_update
+        );
+
+        // Once the file is fixed, it is reloaded against the prior endpoints.
+        file.write(r#"[{"addr": "198.51.100.2:8080"}]"#);
+        assert_eq!(
+            next(&mut resolution).await,
+            Update::Add(vec![(addr(2), Endpoint::default_weighted())])
+        );
+        assert_eq!(next(&mut resolution).await, Update::Remove(vec![addr(1)]));
+    }
+
+    #[test]
+    fn parse_errors() {
+        for contents in &[
+            "{}",
+            r#"[{"weight": 1}]"#,
+            r#"[{"addr": "web:8080"}]"#,
+            r#"[{"addr": "198.51.100.1:8080", "weight": -1}]"#,
+            r#"[{"addr": "198.51.100.1:8080", "labels": {"zone": 1}}]"#,
+            r#"[{"addr": "198.51.100.1:8080"}, {"addr": "198.51.100.1:8080"}]"#,
+        ] {
+            assert!(parse(contents).is_err(), "{} must be invalid", contents);
+        }
+    }
+
+    impl Endpoint {
+        fn default_weighted() -> Self {
+            Self {
+                weight: Self::DEFAULT_WEIGHT,
+                labels: BTreeMap::new(),
+                tls_identity: None,
+            }
+        }
+    }
+}
@@ -1,6 +1,32 @@
 #![deny(warnings, rust_2018_idioms)]
 
+pub mod buffer;
+pub mod cached;
+#[cfg(any(test, feature = "chaos"))]
+pub mod chaos;
+pub mod conflict;
+pub mod drain;
+pub mod dual_stack;
+pub mod expire;
+pub mod fallback;
+#[cfg(any(test, feature = "file"))]
+pub mod file;
+pub mod log_updates;
 pub mod make_unpin;
 pub mod map_endpoint;
+pub mod metrics;
+pub mod min_endpoints;
+pub mod rate_limit;
+#[cfg(any(test, feature = "file"))]
+pub mod record;
 pub mod recover;
+#[cfg(any(test, feature = "replay"))]
+pub mod replay;
+pub mod round_robin;
+pub mod shared;
+pub mod tag_source;
+pub mod throttle;
+#[cfg(any(test, feature = "file"))]
+pub mod warm;
+pub use cached::cached;
 pub use make_unpin::make_unpin;
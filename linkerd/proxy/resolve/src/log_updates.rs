@@ -0,0 +1,200 @@
+//! A middleware that logs each update produced by a `Resolution`, tagged
+//! with the resolution's target.
+
+use futures::{ready, TryFuture};
+use linkerd2_proxy_core::resolve::{self, DisplayUpdate};
+use pin_project::pin_project;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tracing::Level;
+
+#[derive(Clone, Debug)]
+pub struct Resolve<R> {
+    resolve: R,
+    level: Level,
+}
+
+#[pin_project]
+#[derive(Debug)]
+pub struct ResolveFuture<T, F> {
+    #[pin]
+    future: F,
+    target: Option<T>,
+    level: Level,
+}
+
+#[pin_project]
+#[derive(Clone, Debug)]
+pub struct Resolution<T, R> {
+    #[pin]
+    resolution: R,
+    target: T,
+    level: Level,
+}
+
+// === impl Resolve ===
+
+impl<R> Resolve<R> {
+    /// Logs each update at `level`.
+    pub fn new<T>(level: Level, resolve: R) -> Self
+    where
+        Self: resolve::Resolve<T>,
+    {
+        Self { resolve, level }
+    }
+}
+
+impl<T, R> tower::Service<T> for Resolve<R>
+where
+    T: fmt::Display + Clone,
+    R: resolve::Resolve<T>,
+{
+    type Response = Resolution<T, R::Resolution>;
+    type Error = R::Error;
+    type Future = ResolveFuture<T, R::Future>;
+
+    #[inline]
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.resolve.poll_ready(cx)
+    }
+
+    #[inline]
+    fn call(&mut self, target: T) -> Self::Future {
+        let future = self.resolve.resolve(target.clone());
+        Self::Future {
+            future,
+            target: Some(target),
+            level: self.level,
+        }
+    }
+}
+
+// === impl ResolveFuture ===
+
+impl<T, F> Future for ResolveFuture<T, F>
+where
+    F: TryFuture,
+    F::Ok: resolve::Resolution,
+{
+    type Output = Result<Resolution<T, F::Ok>, F::Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let resolution = ready!(this.future.try_poll(cx))?;
+        let target = this.target.take().expect("polled after ready");
+        Poll::Ready(Ok(Resolution {
+            resolution,
+            target,
+            level: *this.level,
+        }))
+    }
+}
+
+// === impl Resolution ===
+
+impl<T, R> resolve::Resolution for Resolution<T, R>
+where
+    T: fmt::Display,
+    R: resolve::Resolution,
+{
+    type Endpoint = R::Endpoint;
+    type Error = R::Error;
+
+    fn poll(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<resolve::Update<Self::Endpoint>, Self::Error>> {
+        let this = self.project();
+        let update = ready!(this.resolution.poll(cx))?;
+
+        let target = &*this.target;
+        let update_display = DisplayUpdate(&update);
+        // `tracing`'s macros require a constant level.
+        match *this.level {
+            Level::ERROR => tracing::error!(%target, update = %update_display),
+            Level::WARN => tracing::warn!(%target, update = %update_display),
+            Level::INFO => tracing::info!(%target, update = %update_display),
+            Level::DEBUG => tracing::debug!(%target, update = %update_display),
+            Level::TRACE => tracing::trace!(%target, update = %update_display),
+        }
+
+        Poll::Ready(Ok(update))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use linkerd2_proxy_core::{
+        resolve::{Resolution as _, Update},
+        test_util::Updates,
+    };
+    use linkerd2_tracing_test::Recorder;
+    use std::net::SocketAddr;
+
+    fn addr0() -> SocketAddr {
+        ([198, 51, 100, 1], 8080).into()
+    }
+
+    fn addr1() -> SocketAddr {
+        ([198, 51, 100, 2], 8080).into()
+    }
+
+    #[test]
+    fn forwards_updates_unchanged() {
+        let updates = vec![
+            Update::Add(vec![(addr0(), ()), (addr1(), ())]),
+            Update::Remove(vec![addr0()]),
+            Update::Empty,
+            Update::DoesNotExist,
+        ];
+        let mut resolution = Resolution {
+            resolution: updates.iter().cloned().collect::<Updates<()>>(),
+            target: "web.example.com:8080",
+            level: Level::DEBUG,
+        };
+
+        let events = Recorder::new(Level::DEBUG);
+        tracing::subscriber::with_default(events.clone(), || {
+            for expected in updates.into_iter() {
+                let update = futures::executor::block_on(futures::future::poll_fn(|cx| {
+                    Pin::new(&mut resolution).poll(cx)
+                }))
+                .expect("update must succeed");
+                assert_eq!(update, expected);
+            }
+        });
+
+        assert_eq!(
+            events.events(),
+            vec![
+                "target=web.example.com:8080 update=Add [198.51.100.1:8080, 198.51.100.2:8080]",
+                "target=web.example.com:8080 update=Remove [198.51.100.1:8080]",
+                "target=web.example.com:8080 update=Empty",
+                "target=web.example.com:8080 update=DoesNotExist",
+            ]
+        );
+    }
+
+    #[test]
+    fn does_not_log_below_enabled_level() {
+        let mut resolution = Resolution {
+            resolution: vec![Update::Empty].into_iter().collect::<Updates<()>>(),
+            target: "web.example.com:8080",
+            level: Level::TRACE,
+        };
+
+        let events = Recorder::new(Level::DEBUG);
+        tracing::subscriber::with_default(events.clone(), || {
+            let update = futures::executor::block_on(futures::future::poll_fn(|cx| {
+                Pin::new(&mut resolution).poll(cx)
+            }))
+            .expect("update must succeed");
+            assert_eq!(update, Update::Empty);
+        });
+
+        assert!(events.events().is_empty());
+    }
+}
@@ -0,0 +1,224 @@
+//! Metrics that quantify the endpoint churn suppressed by this crate's
+//! middlewares.
+
+use linkerd2_metrics::{metrics, Counter, FmtLabels, FmtMetrics};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+metrics! {
+    resolve_duplicate_adds_total: Counter {
+        "Total number of endpoint additions that were suppressed because the endpoint was already known"
+    },
+    resolve_debounced_flaps_total: Counter {
+        "Total number of endpoints that were added and removed, or removed and re-added, before either update was returned"
+    },
+    resolve_coalesced_updates_total: Counter {
+        "Total number of endpoint updates that were merged into a pending update for the same address"
+    }
+}
+
+/// Counts the updates suppressed by each authority's resolutions.
+///
+/// An authority's counters are dropped once they have been reported after all
+/// of its resolutions have completed, so that the number of authorities that
+/// are tracked is bounded by the number of active resolutions.
+#[derive(Clone, Debug, Default)]
+pub struct ChurnMetrics {
+    by_authority: Arc<Mutex<HashMap<Authority, Churn>>>,
+}
+
+/// Counts the updates suppressed by a single authority's resolutions.
+///
+/// A default `Churn` is not registered, so its counts are not reported.
+#[derive(Clone, Debug, Default)]
+pub struct Churn {
+    duplicate_adds: Arc<Counter>,
+    debounced_flaps: Arc<Counter>,
+    coalesced_updates: Arc<Counter>,
+}
+
+/// Obtains the `Churn` for a resolution's target.
+///
+/// `()` is used when churn is not reported.
+pub trait NewChurn<T> {
+    fn new_churn(&self, target: &T) -> Churn;
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct Authority(String);
+
+// === impl ChurnMetrics ===
+
+impl ChurnMetrics {
+    /// Returns the counters for `authority`, registering them if necessary.
+    pub fn authority(&self, authority: &str) -> Churn {
+        let mut by_authority = self.by_authority.lock().unwrap();
+        by_authority
+            .entry(Authority(authority.to_string()))
+            .or_default()
+            .clone()
+    }
+}
+
+impl<T: fmt::Display> NewChurn<T> for ChurnMetrics {
+    fn new_churn(&self, target: &T) -> Churn {
+        self.authority(&target.to_string())
+    }
+}
+
+impl FmtMetrics for ChurnMetrics {
+    fn fmt_metrics(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut by_authority = self.by_authority.lock().unwrap();
+        if by_authority.is_empty() {
+            return Ok(());
+        }
+
+        resolve_duplicate_adds_total.fmt_help(f)?;
+        resolve_duplicate_adds_total.fmt_scopes(f, by_authority.iter(), |c| &c.duplicate_adds)?;
+
+        resolve_debounced_flaps_total.fmt_help(f)?;
+        resolve_debounced_flaps_total.fmt_scopes(f, by_authority.iter(), |c| &c.debounced_flaps)?;
+
+        resolve_coalesced_updates_total.fmt_help(f)?;
+        resolve_coalesced_updates_total
+            .fmt_scopes(f, by_authority.iter(), |c| &c.coalesced_updates)?;
+
+        // Authorities that are no longer resolved have now reported their
+        // final counts.
+        by_authority.retain(|_, churn| churn.is_active());
+
+        Ok(())
+    }
+}
+
+// === impl Churn ===
+
+impl Churn {
+    pub(crate) fn duplicate_adds(&self, n: usize) {
+        self.duplicate_adds.add(n as u64);
+    }
+
+    pub(crate) fn debounced_flap(&self) {
+        self.debounced_flaps.incr();
+    }
+
+    pub(crate) fn coalesced_update(&self) {
+        self.coalesced_updates.incr();
+    }
+
+    /// Returns true if the counters are shared with a resolution, i.e. other
+    /// than by the `ChurnMetrics` that registered them.
+    fn is_active(&self) -> bool {
+        Arc::strong_count(&self.duplicate_adds) > 1
+    }
+
+    /// Returns the number of endpoint additions that were suppressed because
+    /// the endpoint was already known.
+    pub fn duplicate_adds_total(&self) -> u64 {
+        self.duplicate_adds.value()
+    }
+
+    /// Returns the number of endpoints whose addition and removal cancelled
+    /// out before either was returned.
+    pub fn debounced_flaps_total(&self) -> u64 {
+        self.debounced_flaps.value()
+    }
+
+    /// Returns the number of endpoint updates that were merged into a pending
+    /// update for the same address.
+    pub fn coalesced_updates_total(&self) -> u64 {
+        self.coalesced_updates.value()
+    }
+}
+
+/// Records all resolutions' churn in a single `Churn`.
+impl<T> NewChurn<T> for Churn {
+    fn new_churn(&self, _: &T) -> Churn {
+        self.clone()
+    }
+}
+
+impl<T> NewChurn<T> for () {
+    fn new_churn(&self, _: &T) -> Churn {
+        Churn::default()
+    }
+}
+
+// === impl Authority ===
+
+impl FmtLabels for Authority {
+    fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "authority=\"{}\"", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_by_authority() {
+        let metrics = ChurnMetrics::default();
+        let web = metrics.new_churn(&"web.example.com:8080");
+        web.duplicate_adds(2);
+        web.debounced_flap();
+        metrics.authority("db.example.com:5432").coalesced_update();
+
+        // Counters are shared by all of an authority's resolutions.
+        assert_eq!(
+            metrics
+                .authority("web.example.com:8080")
+                .duplicate_adds_total(),
+            2
+        );
+
+        let text = metrics.as_display().to_string();
+        for line in &[
+            "resolve_duplicate_adds_total{authority=\"web.example.com:8080\"} 2",
+            "resolve_debounced_flaps_total{authority=\"web.example.com:8080\"} 1",
+            "resolve_coalesced_updates_total{authority=\"db.example.com:5432\"} 1",
+        ] {
+            assert!(text.contains(line), "{:?} must contain {:?}", text, line);
+        }
+    }
+
+    #[test]
+    fn evicts_authorities_without_resolutions() {
+        let metrics = ChurnMetrics::default();
+        let web = metrics.authority("web.example.com:8080");
+        metrics.authority("db.example.com:5432").coalesced_update();
+
+        // An authority's final counts are reported once its resolutions have
+        // completed.
+        let text = metrics.as_display().to_string();
+        assert!(
+            text.contains("authority=\"db.example.com:5432\""),
+            "{:?}",
+            text
+        );
+        let text = metrics.as_display().to_string();
+        assert!(
+            !text.contains("authority=\"db.example.com:5432\""),
+            "{:?}",
+            text
+        );
+
+        // Authorities with resolutions are retained.
+        web.debounced_flap();
+        let text = metrics.as_display().to_string();
+        assert!(
+            text.contains("resolve_debounced_flaps_total{authority=\"web.example.com:8080\"} 1"),
+            "{:?}",
+            text
+        );
+        drop(web);
+        let text = metrics.as_display().to_string();
+        assert!(
+            text.contains("resolve_debounced_flaps_total{authority=\"web.example.com:8080\"} 1"),
+            "{:?}",
+            text
+        );
+        assert_eq!(metrics.as_display().to_string(), "");
+    }
+}
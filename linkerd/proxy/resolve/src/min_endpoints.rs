@@ -0,0 +1,289 @@
+//! A middleware that holds back removals that would leave a resolution with
+//! fewer than a minimum number of endpoints.
+//!
+//! This keeps a balancer from collapsing onto a few endpoints while a flappy
+//! service's endpoints are transiently removed. Held removals are released
+//! once replacement endpoints are added or after a timeout.
+
+use futures::{ready, FutureExt, TryFuture};
+use indexmap::IndexSet;
+use linkerd2_proxy_core::resolve::{self, Update};
+use pin_project::pin_project;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::time;
+use tracing::debug;
+
+#[derive(Clone, Debug)]
+pub struct Resolve<R> {
+    resolve: R,
+    min: usize,
+    timeout: Duration,
+}
+
+#[pin_project]
+#[derive(Debug)]
+pub struct ResolveFuture<F> {
+    #[pin]
+    future: F,
+    min: usize,
+    timeout: Duration,
+}
+
+#[pin_project]
+#[derive(Debug)]
+pub struct Resolution<R, E> {
+    #[pin]
+    resolution: R,
+    min: usize,
+    timeout: Duration,
+    /// The addresses of all endpoints that have been added and not removed,
+    /// including those whose removal is held.
+    live: IndexSet<SocketAddr>,
+    held: IndexSet<SocketAddr>,
+    /// Releases the held removals when it fires.
+    release: Option<time::Delay>,
+    pending: VecDeque<Update<E>>,
+}
+
+// === impl Resolve ===
+
+impl<R> Resolve<R> {
+    /// Holds removals that would leave fewer than `min` endpoints for up to
+    /// `timeout`.
+    pub fn new<T>(min: usize, timeout: Duration, resolve: R) -> Self
+    where
+        Self: resolve::Resolve<T>,
+    {
+        Self {
+            resolve,
+            min,
+            timeout,
+        }
+    }
+}
+
+impl<T, R> tower::Service<T> for Resolve<R>
+where
+    R: resolve::Resolve<T>,
+{
+    type Response = Resolution<R::Resolution, R::Endpoint>;
+    type Error = R::Error;
+    type Future = ResolveFuture<R::Future>;
+
+    #[inline]
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.resolve.poll_ready(cx)
+    }
+
+    #[inline]
+    fn call(&mut self, target: T) -> Self::Future {
+        Self::Future {
+            future: self.resolve.resolve(target),
+            min: self.min,
+            timeout: self.timeout,
+        }
+    }
+}
+
+// === impl ResolveFuture ===
+
+impl<F> Future for ResolveFuture<F>
+where
+    F: TryFuture,
+    F::Ok: resolve::Resolution,
+{
+    type Output = Result<Resolution<F::Ok, <F::Ok as resolve::Resolution>::Endpoint>, F::Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let resolution = ready!(this.future.try_poll(cx))?;
+        Poll::Ready(Ok(Resolution::new(resolution, *this.min, *this.timeout)))
+    }
+}
+
+// === impl Resolution ===
+
+impl<R, E> Resolution<R, E> {
+    fn new(resolution: R, min: usize, timeout: Duration) -> Self {
+        Self {
+            resolution,
+            min,
+            timeout,
+            live: IndexSet::new(),
+            held: IndexSet::new(),
+            release: None,
+            pending: VecDeque::new(),
+        }
+    }
+}
+
+impl<R: resolve::Resolution> resolve::Resolution for Resolution<R, R::Endpoint> {
+    type Endpoint = R::Endpoint;
+    type Error = R::Error;
+
+    fn poll(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Update<Self::Endpoint>, Self::Error>> {
+        let mut this = self.project();
+        loop {
+            if let Some(update) = this.pending.pop_front() {
+                return Poll::Ready(Ok(update));
+            }
+
+            if let Some(release) = this.release.as_mut() {
+                if release.poll_unpin(cx).is_ready() {
+                    debug!(removals = %this.held.len(), "Releasing held removals");
+                    *this.release = None;
+                    let removed = this.held.drain(..).collect::<Vec<_>>();
+                    for addr in removed.iter() {
+                        this.live.remove(addr);
+                    }
+                    return Poll::Ready(Ok(Update::Remove(removed)));
+                }
+            }
+
+            let update = ready!(this.resolution.as_mut().poll(cx))?;
+            match update {
+                Update::Add(endpoints) => {
+                    for (addr, _) in endpoints.iter() {
+                        // An endpoint that is added again need not be removed.
+                        this.held.remove(addr);
+                        this.live.insert(*addr);
+                    }
+                    this.pending.push_back(Update::Add(endpoints));
+
+                    // Release the held removals once there are enough
+                    // replacements.
+                    if !this.held.is_empty() && this.live.len() - this.held.len() >= *this.min {
+                        let removed = this.held.drain(..).collect::<Vec<_>>();
+                        for addr in removed.iter() {
+                            this.live.remove(addr);
+                        }
+                        this.pending.push_back(Update::Remove(removed));
+                    }
+                    if this.held.is_empty() {
+                        *this.release = None;
+                    }
+                }
+
+                Update::Remove(addrs) => {
+                    let mut removed = Vec::with_capacity(addrs.len());
+                    for addr in addrs.into_iter() {
+                        if !this.live.contains(&addr) {
+                            removed.push(addr);
+                        } else if this.live.len() - this.held.len() > *this.min {
+                            this.live.remove(&addr);
+                            removed.push(addr);
+                        } else {
+                            this.held.insert(addr);
+                        }
+                    }
+
+                    if !this.held.is_empty() && this.release.is_none() {
+                        debug!(removals = %this.held.len(), "Holding removals");
+                        *this.release = Some(time::delay_for(*this.timeout));
+                    }
+                    if !removed.is_empty() {
+                        this.pending.push_back(Update::Remove(removed));
+                    }
+                }
+
+                update @ Update::Empty | update @ Update::DoesNotExist => {
+                    // The resolution no longer has any endpoints, so there's
+                    // nothing to hold.
+                    this.live.clear();
+                    this.held.clear();
+                    *this.release = None;
+                    this.pending.push_back(update);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use linkerd2_proxy_core::test_util::{next, next_within, Updates};
+
+    fn addr(n: u8) -> SocketAddr {
+        ([198, 51, 100, n], 8080).into()
+    }
+
+    #[tokio::test]
+    async fn held_remove_released_by_timeout() {
+        let updates = Updates::default();
+        let mut resolution = Resolution::new(updates.clone(), 2, Duration::from_millis(50));
+
+        updates.push(Update::Add(vec![
+            (addr(1), ()),
+            (addr(2), ()),
+            (addr(3), ()),
+        ]));
+        assert_eq!(
+            next(&mut resolution).await,
+            Update::Add(vec![(addr(1), ()), (addr(2), ()), (addr(3), ())])
+        );
+
+        // The first removal leaves the minimum number of endpoints, but the
+        // second would drop below it and is held.
+        updates.push(Update::Remove(vec![addr(1), addr(2)]));
+        assert_eq!(next(&mut resolution).await, Update::Remove(vec![addr(1)]));
+        assert_eq!(
+            next_within(&mut resolution, Duration::from_millis(10)).await,
+            None,
+            "removal must be held"
+        );
+
+        assert_eq!(
+            next_within(&mut resolution, Duration::from_secs(1)).await,
+            Some(Update::Remove(vec![addr(2)])),
+            "removal must be released after the timeout"
+        );
+    }
+
+    #[tokio::test]
+    async fn held_remove_canceled_by_replacement() {
+        let updates = Updates::default();
+        let mut resolution = Resolution::new(updates.clone(), 2, Duration::from_secs(60));
+
+        updates.push(Update::Add(vec![(addr(1), ()), (addr(2), ())]));
+        next(&mut resolution).await;
+
+        updates.push(Update::Remove(vec![addr(1)]));
+        assert_eq!(
+            next_within(&mut resolution, Duration::from_millis(10)).await,
+            None,
+            "removal must be held"
+        );
+
+        // Once a replacement is added, the held removal is released.
+        updates.push(Update::Add(vec![(addr(3), ())]));
+        assert_eq!(
+            next(&mut resolution).await,
+            Update::Add(vec![(addr(3), ())])
+        );
+        assert_eq!(next(&mut resolution).await, Update::Remove(vec![addr(1)]));
+
+        // If a held endpoint is added again, its removal is canceled.
+        updates.push(Update::Remove(vec![addr(2)]));
+        assert_eq!(
+            next_within(&mut resolution, Duration::from_millis(10)).await,
+            None,
+            "removal must be held"
+        );
+        updates.push(Update::Add(vec![(addr(2), ())]));
+        assert_eq!(
+            next(&mut resolution).await,
+            Update::Add(vec![(addr(2), ())])
+        );
+        assert!(resolution.held.is_empty());
+        assert!(resolution.release.is_none());
+    }
+}
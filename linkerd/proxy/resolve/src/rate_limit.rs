@@ -0,0 +1,190 @@
+//! A `Resolve` that limits the rate at which new resolutions are initiated, so
+//! that a burst of new targets (e.g. at startup) does not overwhelm the
+//! control plane.
+//!
+//! Resolutions in excess of the rate are queued: each resolution's future
+//! waits until its turn before the inner resolution is initiated.
+
+use futures::ready;
+use linkerd2_proxy_core::resolve;
+use pin_project::pin_project;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::time::{self, Instant};
+
+#[derive(Clone, Debug)]
+pub struct Resolve<R> {
+    resolve: R,
+    /// The minimum amount of time between resolutions.
+    interval: Duration,
+    /// The time at which the next resolution may be initiated, shared across
+    /// clones so that the rate is enforced globally.
+    next: Arc<Mutex<Instant>>,
+}
+
+#[pin_project(project = ResolveFutureProj)]
+pub enum ResolveFuture<T, R: resolve::Resolve<T>> {
+    Limited {
+        #[pin]
+        delay: time::Delay,
+        target: Option<T>,
+        resolve: R,
+    },
+    Resolving(#[pin] R::Future),
+}
+
+// === impl Resolve ===
+
+impl<R> Resolve<R> {
+    /// Initiates at most `rate` resolutions per `per`.
+    ///
+    /// # Panics
+    ///
+    /// If `rate` is zero.
+    pub fn new<T>(rate: u32, per: Duration, resolve: R) -> Self
+    where
+        Self: resolve::Resolve<T>,
+    {
+        assert!(rate > 0, "rate must be positive");
+        Self {
+            resolve,
+            interval: per / rate,
+            next: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+}
+
+impl<T, R> tower::Service<T> for Resolve<R>
+where
+    R: resolve::Resolve<T> + Clone,
+{
+    type Response = R::Resolution;
+    type Error = R::Error;
+    type Future = ResolveFuture<T, R>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // The inner resolver is driven to readiness once the resolution's
+        // turn has come.
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        let at = {
+            let mut next = self.next.lock().unwrap();
+            let at = std::cmp::max(*next, Instant::now());
+            *next = at + self.interval;
+            at
+        };
+        tracing::trace!(delay = ?at.saturating_duration_since(Instant::now()), "limiting");
+        ResolveFuture::Limited {
+            delay: time::delay_until(at),
+            target: Some(target),
+            resolve: self.resolve.clone(),
+        }
+    }
+}
+
+// === impl ResolveFuture ===
+
+impl<T, R> Future for ResolveFuture<T, R>
+where
+    R: resolve::Resolve<T>,
+{
+    type Output = Result<R::Resolution, R::Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        loop {
+            match self.as_mut().project() {
+                ResolveFutureProj::Limited {
+                    delay,
+                    target,
+                    resolve,
+                } => {
+                    ready!(delay.poll(cx));
+                    ready!(resolve.poll_ready(cx))?;
+                    let target = target.take().expect("polled after ready");
+                    let future = resolve.resolve(target);
+                    self.set(ResolveFuture::Resolving(future));
+                }
+                ResolveFutureProj::Resolving(future) => return future.poll(cx),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future;
+    use linkerd2_error::Error;
+    use linkerd2_proxy_core::resolve::{Resolve as _, Update};
+
+    /// Records the time at which each resolution is initiated.
+    #[derive(Clone, Debug, Default)]
+    struct Recording(Arc<Mutex<Vec<Instant>>>);
+
+    struct Idle;
+
+    impl tower::Service<()> for Recording {
+        type Response = Idle;
+        type Error = Error;
+        type Future = future::Ready<Result<Idle, Error>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _: ()) -> Self::Future {
+            self.0.lock().unwrap().push(Instant::now());
+            future::ok(Idle)
+        }
+    }
+
+    impl resolve::Resolution for Idle {
+        type Endpoint = ();
+        type Error = Error;
+
+        fn poll(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<Update<()>, Error>> {
+            Poll::Pending
+        }
+    }
+
+    #[tokio::test]
+    async fn limits_burst_of_resolutions() {
+        time::pause();
+        let interval = Duration::from_millis(20);
+        let inner = Recording::default();
+        let started = inner.0.clone();
+        let mut resolve = Resolve::new(1, interval, inner);
+
+        let mut resolutions = future::join_all((0..5).map(|_| resolve.resolve(())));
+        let resolutions = loop {
+            if let Poll::Ready(resolutions) = futures::poll!(&mut resolutions) {
+                break resolutions;
+            }
+            time::advance(Duration::from_millis(1)).await;
+        };
+        for res in resolutions {
+            assert!(res.is_ok(), "resolution must succeed");
+        }
+
+        let started = started.lock().unwrap();
+        assert_eq!(started.len(), 5);
+        for (i, start) in started.iter().enumerate().skip(1) {
+            let elapsed = *start - started[0];
+            // The timer and the clock have millisecond granularity, so each
+            // resolution is initiated within a millisecond of its turn.
+            let turn = interval * i as u32;
+            let ms = Duration::from_millis(1);
+            assert!(
+                elapsed + ms >= turn && elapsed <= turn + ms,
+                "resolution {} must be initiated at the rate; elapsed {:?}",
+                i,
+                elapsed
+            );
+        }
+    }
+}
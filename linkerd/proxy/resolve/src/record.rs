@@ -0,0 +1,317 @@
+//! A middleware that records each update produced by a `Resolution` to a log,
+//! so that discovery bugs may be reproduced by replaying the log.
+//!
+//! The log is written as JSON lines, one per update, e.g.:
+//!
+//! ```json
+//! {"target":"web.ns.svc.cluster.local:8080","at_ms":0,"update":"add","endpoints":[{"addr":"10.1.1.1:8080","metadata":null}]}
+//! {"target":"web.ns.svc.cluster.local:8080","at_ms":1520,"update":"remove","addrs":["10.1.1.1:8080"]}
+//! {"target":"web.ns.svc.cluster.local:8080","at_ms":3000,"update":"empty"}
+//! ```
+//!
+//! where `at_ms` is the time at which the update was returned, relative to
+//! the start of its resolution. Recording does not modify the resolution's
+//! updates. The log is written by a background thread, so that resolutions
+//! are not blocked on its I/O, and failures to write it are ignored.
+
+use futures::{channel::mpsc, executor, ready, TryFuture};
+use linkerd2_proxy_core::resolve::{self, Update};
+use pin_project::pin_project;
+use serde_json::{json, Value};
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::thread;
+use std::time::Duration;
+use tokio::time;
+use tracing::warn;
+
+/// An endpoint whose metadata may be recorded and replayed.
+pub trait Endpoint: Sized {
+    fn to_json(&self) -> Value;
+
+    /// Returns `None` if `value` does not describe an endpoint.
+    fn from_json(value: &Value) -> Option<Self>;
+}
+
+/// A handle to the thread that writes recorded updates to a log.
+///
+/// The thread exits once every handle has been dropped.
+#[derive(Clone, Debug)]
+pub struct Log(mpsc::UnboundedSender<Value>);
+
+#[derive(Clone, Debug)]
+pub struct Resolve<R> {
+    resolve: R,
+    log: Log,
+}
+
+#[pin_project]
+#[derive(Debug)]
+pub struct ResolveFuture<F> {
+    #[pin]
+    future: F,
+    target: Option<String>,
+    log: Log,
+}
+
+#[pin_project]
+#[derive(Debug)]
+pub struct Resolution<R> {
+    #[pin]
+    resolution: R,
+    target: String,
+    log: Log,
+    started: time::Instant,
+}
+
+/// An update read from a log.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry<E> {
+    pub target: String,
+    /// The time at which the update was returned, relative to the start of
+    /// its resolution.
+    pub at: Duration,
+    pub update: Update<E>,
+}
+
+/// Indicates that a log could not be parsed.
+#[derive(Clone, Debug)]
+pub struct InvalidLog(String);
+
+// === impl Log ===
+
+impl Log {
+    /// Spawns a thread that writes each recorded update to `writer`, as a
+    /// line of JSON.
+    pub fn spawn<W>(mut writer: W) -> io::Result<Self>
+    where
+        W: io::Write + Send + 'static,
+    {
+        let (tx, rx) = mpsc::unbounded::<Value>();
+        thread::Builder::new()
+            .name("resolve-record".into())
+            .spawn(move || {
+                for line in executor::block_on_stream(rx) {
+                    if let Err(error) = writeln!(writer, "{}", line).and_then(|()| writer.flush()) {
+                        warn!(%error, "Failed to record update");
+                    }
+                }
+            })?;
+        Ok(Log(tx))
+    }
+
+    fn write(&self, line: Value) {
+        // If the thread has exited, the update is not recorded.
+        let _ = self.0.unbounded_send(line);
+    }
+}
+
+// === impl Resolve ===
+
+impl<R> Resolve<R> {
+    /// Records each resolution's updates to `log`.
+    ///
+    /// The log is shared by all resolutions; each update is tagged with its
+    /// resolution's target.
+    pub fn new<T>(log: Log, resolve: R) -> Self
+    where
+        Self: resolve::Resolve<T>,
+    {
+        Self { resolve, log }
+    }
+}
+
+impl<T, R> tower::Service<T> for Resolve<R>
+where
+    T: fmt::Display,
+    R: resolve::Resolve<T>,
+    R::Endpoint: Endpoint,
+{
+    type Response = Resolution<R::Resolution>;
+    type Error = R::Error;
+    type Future = ResolveFuture<R::Future>;
+
+    #[inline]
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.resolve.poll_ready(cx)
+    }
+
+    #[inline]
+    fn call(&mut self, target: T) -> Self::Future {
+        Self::Future {
+            target: Some(target.to_string()),
+            future: self.resolve.resolve(target),
+            log: self.log.clone(),
+        }
+    }
+}
+
+// === impl ResolveFuture ===
+
+impl<F> Future for ResolveFuture<F>
+where
+    F: TryFuture,
+    F::Ok: resolve::Resolution,
+{
+    type Output = Result<Resolution<F::Ok>, F::Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let resolution = ready!(this.future.try_poll(cx))?;
+        Poll::Ready(Ok(Resolution {
+            resolution,
+            target: this.target.take().expect("polled after ready"),
+            log: this.log.clone(),
+            started: time::Instant::now(),
+        }))
+    }
+}
+
+// === impl Resolution ===
+
+impl<R> resolve::Resolution for Resolution<R>
+where
+    R: resolve::Resolution,
+    R::Endpoint: Endpoint,
+{
+    type Endpoint = R::Endpoint;
+    type Error = R::Error;
+
+    fn poll(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Update<Self::Endpoint>, Self::Error>> {
+        let this = self.project();
+        let update = ready!(this.resolution.poll(cx))?;
+
+        let at = time::Instant::now().saturating_duration_since(*this.started);
+        this.log.write(to_json(this.target, at, &update));
+
+        Poll::Ready(Ok(update))
+    }
+}
+
+fn to_json<E: Endpoint>(target: &str, at: Duration, update: &Update<E>) -> Value {
+    let at_ms = at.as_millis() as u64;
+    match update {
+        Update::Add(endpoints) => {
+            let endpoints = endpoints
+                .iter()
+                .map(|(addr, ep)| json!({ "addr": addr.to_string(), "metadata": ep.to_json() }))
+                .collect::<Vec<_>>();
+            json!({ "target": target, "at_ms": at_ms, "update": "add", "endpoints": endpoints })
+        }
+        Update::Remove(addrs) => {
+            let addrs = addrs.iter().map(ToString::to_string).collect::<Vec<_>>();
+            json!({ "target": target, "at_ms": at_ms, "update": "remove", "addrs": addrs })
+        }
+        Update::Empty => json!({ "target": target, "at_ms": at_ms, "update": "empty" }),
+        Update::DoesNotExist => {
+            json!({ "target": target, "at_ms": at_ms, "update": "does_not_exist" })
+        }
+    }
+}
+
+// === impl Entry ===
+
+impl<E: Endpoint> Entry<E> {
+    /// Parses each line of a log.
+    pub fn parse_log(log: &str) -> Result<Vec<Self>, InvalidLog> {
+        log.lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                let value =
+                    serde_json::from_str::<Value>(line).map_err(|e| InvalidLog(e.to_string()))?;
+                Self::from_json(&value)
+            })
+            .collect()
+    }
+
+    fn from_json(value: &Value) -> Result<Self, InvalidLog> {
+        let target = value
+            .get("target")
+            .and_then(Value::as_str)
+            .ok_or_else(|| InvalidLog("each update must have a `target`".into()))?
+            .to_string();
+        let at = value
+            .get("at_ms")
+            .and_then(Value::as_u64)
+            .map(Duration::from_millis)
+            .ok_or_else(|| InvalidLog(format!("invalid time for {}", target)))?;
+
+        let update = match value.get("update").and_then(Value::as_str) {
+            Some("add") => {
+                let endpoints = value
+                    .get("endpoints")
+                    .and_then(Value::as_array)
+                    .ok_or_else(|| InvalidLog(format!("invalid endpoints for {}", target)))?;
+                let endpoints = endpoints
+                    .iter()
+                    .map(|ep| {
+                        let addr = parse_addr(ep.get("addr"))?;
+                        let metadata = ep
+                            .get("metadata")
+                            .and_then(E::from_json)
+                            .ok_or_else(|| InvalidLog(format!("invalid metadata for {}", addr)))?;
+                        Ok((addr, metadata))
+                    })
+                    .collect::<Result<Vec<_>, InvalidLog>>()?;
+                Update::Add(endpoints)
+            }
+            Some("remove") => {
+                let addrs = value
+                    .get("addrs")
+                    .and_then(Value::as_array)
+                    .ok_or_else(|| InvalidLog(format!("invalid addresses for {}", target)))?;
+                let addrs = addrs
+                    .iter()
+                    .map(|addr| parse_addr(Some(addr)))
+                    .collect::<Result<Vec<_>, InvalidLog>>()?;
+                Update::Remove(addrs)
+            }
+            Some("empty") => Update::Empty,
+            Some("does_not_exist") => Update::DoesNotExist,
+            _ => return Err(InvalidLog(format!("invalid update for {}", target))),
+        };
+
+        Ok(Self { target, at, update })
+    }
+}
+
+fn parse_addr(value: Option<&Value>) -> Result<SocketAddr, InvalidLog> {
+    let addr = value
+        .and_then(Value::as_str)
+        .ok_or_else(|| InvalidLog("each address must be a string".into()))?;
+    addr.parse()
+        .map_err(|_| InvalidLog(format!("invalid address: {:?}", addr)))
+}
+
+// === impl InvalidLog ===
+
+impl fmt::Display for InvalidLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid resolution log: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidLog {}
+
+// === impl Endpoint ===
+
+impl Endpoint for () {
+    fn to_json(&self) -> Value {
+        Value::Null
+    }
+
+    fn from_json(value: &Value) -> Option<Self> {
+        if value.is_null() {
+            Some(())
+        } else {
+            None
+        }
+    }
+}
@@ -1,21 +1,80 @@
 //! A middleware that recovers a resolution after some failures.
 
+use crate::metrics::{Churn, NewChurn};
 use futures::{ready, stream::TryStreamExt, FutureExt};
 use indexmap::IndexMap;
 use linkerd2_error::{Error, Recover};
-use linkerd2_proxy_core::resolve::{self, Resolution as _, Update};
+use linkerd2_proxy_core::resolve::{
+    self, Refresh, Resolution as _, ResolveError, StatusDetail, Update,
+};
 use pin_project::pin_project;
 use std::future::Future;
 use std::net::SocketAddr;
 use std::pin::Pin;
-use std::task::{Context, Poll};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+use tokio::time;
 
 #[derive(Clone, Debug)]
-pub struct Resolve<E, R> {
+pub struct Resolve<E, R, M = ()> {
     resolve: R,
     recover: E,
+    max_lifetime: Option<Duration>,
+    give_up: Option<(Duration, GiveUp)>,
+    churn: M,
 }
 
+/// Determines how a resolution terminates once it has given up reconnecting.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GiveUp {
+    /// The resolution's endpoints are removed with an `Update::Empty`, after
+    /// which no further updates are produced.
+    Empty,
+    /// The resolution fails with a `GaveUp` error.
+    Fail,
+}
+
+/// Describes the most recent failure of a resolution, for diagnostics.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ErrorSummary {
+    message: String,
+    /// The gRPC status with which the resolver failed, if any.
+    status: Option<StatusDetail>,
+    at: time::Instant,
+}
+
+/// Describes a resolution's state for diagnostics, e.g. so that an admin
+/// endpoint may report each authority's resolution.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ResolutionSnapshot {
+    /// The addresses of the resolution's current endpoints.
+    pub endpoints: Vec<SocketAddr>,
+    /// The number of updates that have been received but not yet returned.
+    pub pending_updates: usize,
+    pub last_error: Option<ErrorSummary>,
+    /// The number of times the resolution has been reconnected.
+    pub reconnects: u64,
+    pub connection: Connection,
+    /// Whether the resolution has returned an update.
+    pub initialized: bool,
+}
+
+/// The state of a resolution's connection to its underlying resolver.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Connection {
+    Connected,
+    Connecting,
+    /// The resolution failed and is waiting before it reconnects.
+    BackingOff,
+    Paused,
+    GaveUp,
+}
+
+/// Indicates that a resolution could not be reconnected within the
+/// configured duration.
+#[derive(Clone, Debug)]
+pub struct GaveUp(Duration);
+
 #[pin_project]
 pub struct ResolveFuture<T, E: Recover, R: resolve::Resolve<T>> {
     inner: Option<Inner<T, E, R>>,
@@ -26,6 +85,18 @@ pub struct Resolution<T, E: Recover, R: resolve::Resolve<T>> {
     inner: Inner<T, E, R>,
     cache: IndexMap<SocketAddr, R::Endpoint>,
     reconcile: Option<Update<R::Endpoint>>,
+    /// The sequence number of the most recently returned update.
+    sequence: u64,
+    paused: Paused,
+    refresh: Refresh,
+}
+
+/// Tracks whether a resolution has been paused, and the task to notify when
+/// it is resumed.
+#[derive(Debug, Default)]
+struct Paused {
+    paused: bool,
+    waker: Option<Waker>,
 }
 
 #[pin_project]
@@ -33,6 +104,16 @@ struct Inner<T, E: Recover, R: resolve::Resolve<T>> {
     target: T,
     resolve: R,
     recover: E,
+    max_lifetime: Option<Duration>,
+    give_up: Option<(Duration, GiveUp)>,
+    /// The time at which the resolution first failed since it was last
+    /// connected.
+    failing_since: Option<time::Instant>,
+    /// The most recent failure since the resolution was last connected.
+    last_error: Option<ErrorSummary>,
+    /// The number of times the resolution has connected.
+    connects: u64,
+    churn: Churn,
     state: State<R::Future, R::Resolution, E::Backoff>,
 }
 
@@ -63,6 +144,8 @@ enum State<F, R: resolve::Resolution, B> {
         #[pin]
         resolution: R,
         initial: Option<Update<R::Endpoint>>,
+        /// Fires when the resolution has reached its maximum lifetime.
+        expiry: Option<time::Delay>,
     },
 
     Recover {
@@ -71,19 +154,69 @@ enum State<F, R: resolve::Resolution, B> {
     },
 
     Backoff(Option<B>),
+
+    /// The resolution has stopped reconnecting. `emptied` is set once the
+    /// resolution has produced its final `Update::Empty`.
+    GaveUp {
+        emptied: bool,
+    },
 }
 
 // === impl Resolve ===
 
 impl<E, R> Resolve<E, R> {
     pub fn new(recover: E, resolve: R) -> Self {
-        Self { resolve, recover }
+        Self {
+            resolve,
+            recover,
+            max_lifetime: None,
+            give_up: None,
+            churn: (),
+        }
     }
 }
 
-impl<T, E, R> tower::Service<T> for Resolve<E, R>
+impl<E, R, M> Resolve<E, R, M> {
+    /// Reconnects each resolution after it has been connected for
+    /// `max_lifetime`.
+    ///
+    /// The resolution's state is reconciled after reconnecting, so the
+    /// reconnect is not observable if the endpoints have not changed.
+    pub fn with_max_lifetime(self, max_lifetime: Duration) -> Self {
+        Self {
+            max_lifetime: Some(max_lifetime),
+            ..self
+        }
+    }
+
+    /// Stops reconnecting each resolution once it has failed to connect for
+    /// `after`.
+    ///
+    /// By default, resolutions are reconnected indefinitely.
+    pub fn with_give_up_after(self, after: Duration, give_up: GiveUp) -> Self {
+        Self {
+            give_up: Some((after, give_up)),
+            ..self
+        }
+    }
+
+    /// Records the additions that each resolution suppresses when it
+    /// reconciles a reconnect in `churn`.
+    pub fn with_churn_metrics<N>(self, churn: N) -> Resolve<E, R, N> {
+        Resolve {
+            resolve: self.resolve,
+            recover: self.recover,
+            max_lifetime: self.max_lifetime,
+            give_up: self.give_up,
+            churn,
+        }
+    }
+}
+
+impl<T, E, R, M> tower::Service<T> for Resolve<E, R, M>
 where
     T: Clone,
+    M: NewChurn<T>,
     R: resolve::Resolve<T> + Clone,
     R::Resolution: Unpin,
     R::Future: Unpin,
@@ -113,6 +246,12 @@ where
                 target: target.clone(),
                 recover: self.recover.clone(),
                 resolve: self.resolve.clone(),
+                max_lifetime: self.max_lifetime,
+                give_up: self.give_up,
+                failing_since: None,
+                last_error: None,
+                connects: 0,
+                churn: self.churn.new_churn(&target),
             }),
         }
     }
@@ -145,6 +284,9 @@ where
             inner,
             cache: IndexMap::default(),
             reconcile: None,
+            sequence: 0,
+            paused: Paused::default(),
+            refresh: Refresh::default(),
         }))
     }
 }
@@ -169,6 +311,18 @@ where
         cx: &mut Context<'_>,
     ) -> Poll<Result<Update<Self::Endpoint>, Self::Error>> {
         let mut this = self.project();
+        if this.paused.paused {
+            this.paused.waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+
+        // A requested refresh reconnects the resolution. As when the
+        // resolution has reached its maximum lifetime, the new resolution's
+        // initial update is reconciled against the cache.
+        if this.refresh.poll_requested(cx) {
+            this.inner.refresh();
+        }
+
         loop {
             // If a reconciliation update is buffered (i.e. after
             // reconcile_after_reconnect), process it immediately.
@@ -181,12 +335,16 @@ where
                 State::Connected {
                     ref mut resolution,
                     ref mut initial,
+                    ref mut expiry,
                 } => {
                     // XXX Due to linkerd/linkerd2#3362, errors can't be discovered
                     // eagerly, so we must potentially read the first update to be
                     // sure it didn't fail. If that's the case, then reconcile the
                     // cache against the initial update.
                     if let Some(initial) = initial.take() {
+                        this.inner
+                            .churn
+                            .duplicate_adds(duplicate_adds(this.cache, &initial));
                         // The initial state afer a reconnect may be identitical to
                         // the prior state, and so there may be no updates to
                         // advertise.
@@ -199,6 +357,17 @@ where
                         }
                     }
 
+                    // If the resolution has reached its maximum lifetime,
+                    // reconnect. The new resolution's initial update is
+                    // reconciled against the cache as if it had failed.
+                    if let Some(expiry) = expiry.as_mut() {
+                        if expiry.poll_unpin(cx).is_ready() {
+                            tracing::debug!("reconnecting after maximum lifetime");
+                            this.inner.state = State::Disconnected { backoff: None };
+                            continue;
+                        }
+                    }
+
                     // Process the resolution stream, updating the cache.
                     //
                     // Attempt recovery/backoff if the resolution fails.
@@ -215,6 +384,16 @@ where
                         }
                     }
                 }
+                // Once the resolution has given up, its endpoints are removed
+                // and it never produces another update.
+                State::GaveUp { ref mut emptied } => {
+                    if *emptied {
+                        return Poll::Pending;
+                    }
+                    *emptied = true;
+                    this.update_active(&Update::Empty);
+                    return Poll::Ready(Ok(Update::Empty));
+                }
                 // XXX(eliza): note that this match was originally an `if let`,
                 // but that doesn't work with `#[project]` for some kinda reason
                 _ => {}
@@ -225,6 +404,161 @@ where
     }
 }
 
+impl<T, E, R> Resolution<T, E, R>
+where
+    T: Clone,
+    R: resolve::Resolve<T>,
+    R::Future: Unpin,
+    R::Resolution: Unpin,
+    R::Endpoint: Clone + PartialEq,
+    E: Recover,
+    E::Backoff: Unpin,
+{
+    /// Polls for the next update, along with its sequence number.
+    ///
+    /// Sequence numbers start at 1 and increase with each update. They are
+    /// not reset when the resolution reconnects, so consumers may use them to
+    /// detect updates that are duplicated or reordered.
+    pub fn poll_sequenced(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(u64, Update<R::Endpoint>), Error>> {
+        let update = ready!(resolve::Resolution::poll(self.as_mut(), cx))?;
+        Poll::Ready(Ok((self.sequence, update)))
+    }
+
+    /// Stops resolving, e.g. to simulate a partition from the control plane.
+    ///
+    /// The underlying resolution is dropped and no updates are produced until
+    /// the resolution is resumed. The resolved endpoints are retained.
+    pub fn pause(self: Pin<&mut Self>) {
+        let this = self.project();
+        if this.paused.paused {
+            return;
+        }
+        tracing::debug!("pausing");
+        this.paused.paused = true;
+        if let State::GaveUp { .. } = this.inner.state {
+            return;
+        }
+        this.inner.state = State::Disconnected { backoff: None };
+    }
+
+    /// Resumes a paused resolution.
+    ///
+    /// The resolution is reconnected and its new state is reconciled against
+    /// the retained endpoints, as if it had recovered from a failure.
+    pub fn resume(self: Pin<&mut Self>) {
+        let this = self.project();
+        if !this.paused.paused {
+            return;
+        }
+        tracing::debug!("resuming");
+        this.paused.paused = false;
+        if let Some(waker) = this.paused.waker.take() {
+            waker.wake();
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.paused
+    }
+
+    /// Returns a handle that reconnects the resolution on demand.
+    ///
+    /// The resolved endpoints are retained while the resolution reconnects,
+    /// and its new state is reconciled against them, so a refresh is not
+    /// observable if the endpoints have not changed. A refresh has no effect
+    /// while the resolution is paused or once it has given up.
+    pub fn refresh_handle(&self) -> Refresh {
+        self.refresh.clone()
+    }
+
+    /// Returns the most recent failure that the resolution recovered from,
+    /// unless it has since reconnected and received an update.
+    pub fn last_error(&self) -> Option<&ErrorSummary> {
+        self.inner.last_error.as_ref()
+    }
+}
+
+impl<T, E, R> Resolution<T, E, R>
+where
+    R: resolve::Resolve<T>,
+    R::Endpoint: Clone + PartialEq,
+    E: Recover,
+{
+    /// Returns the updates that describe the resolution's current state, for
+    /// diagnostics.
+    ///
+    /// The current endpoints are returned as an `Add`, followed by any updates
+    /// that have been received but not yet returned by `poll`. The resolution
+    /// is not modified.
+    pub fn snapshot(&self) -> Vec<Update<R::Endpoint>> {
+        let mut updates = Vec::new();
+        if !self.cache.is_empty() {
+            let endpoints = self
+                .cache
+                .iter()
+                .map(|(addr, endpoint)| (*addr, endpoint.clone()))
+                .collect();
+            updates.push(Update::Add(endpoints));
+        }
+        if let Some(update) = self.reconcile.as_ref() {
+            updates.push(update.clone());
+        }
+        if let State::Connected {
+            initial: Some(ref initial),
+            ..
+        } = self.inner.state
+        {
+            if let Some((update, reconcile)) = reconcile_after_connect(&self.cache, initial.clone())
+            {
+                updates.push(update);
+                updates.extend(reconcile);
+            }
+        }
+        updates
+    }
+
+    /// Describes the resolution's current state, for diagnostics.
+    pub fn resolution_snapshot(&self) -> ResolutionSnapshot {
+        // The snapshot begins with the current endpoints, if there are any,
+        // followed by the pending updates.
+        let mut pending_updates = self.snapshot().len();
+        if !self.cache.is_empty() {
+            pending_updates -= 1;
+        }
+        let connection = match self.inner.state {
+            _ if self.paused.paused => Connection::Paused,
+            State::Connected { .. } => Connection::Connected,
+            State::Disconnected { .. } | State::Connecting { .. } | State::Pending { .. } => {
+                Connection::Connecting
+            }
+            State::Recover { .. } | State::Backoff(_) => Connection::BackingOff,
+            State::GaveUp { .. } => Connection::GaveUp,
+        };
+        ResolutionSnapshot {
+            endpoints: self.cache.keys().cloned().collect(),
+            pending_updates,
+            last_error: self.inner.last_error.clone(),
+            reconnects: self.inner.connects.saturating_sub(1),
+            connection,
+            initialized: self.sequence > 0,
+        }
+    }
+}
+
+impl<T, E, R> resolve::ResolutionStatus for Resolution<T, E, R>
+where
+    R: resolve::Resolve<T>,
+    E: Recover,
+{
+    /// Returns true if the cache of resolved endpoints is not empty.
+    fn is_fully_resolved(&self) -> bool {
+        !self.cache.is_empty()
+    }
+}
+
 impl<T, E, R> ResolutionProj<'_, T, E, R>
 where
     T: Clone,
@@ -232,7 +566,10 @@ where
     R::Endpoint: Clone + PartialEq,
     E: Recover,
 {
+    /// Applies an update that is about to be returned to the cache, assigning
+    /// it the next sequence number.
     fn update_active(&mut self, update: &Update<R::Endpoint>) {
+        *self.sequence += 1;
         match update {
             Update::Add(ref endpoints) => {
                 self.cache.extend(endpoints.clone());
@@ -261,6 +598,20 @@ where
     E: Recover,
     E::Backoff: Unpin,
 {
+    /// Reconnects a connected resolution. A resolution that is backing off
+    /// reconnects immediately, retaining its backoff in case the connection
+    /// attempt fails.
+    fn refresh(&mut self) {
+        self.state = match self.state {
+            State::Connected { .. } => State::Disconnected { backoff: None },
+            State::Backoff(ref mut backoff) => State::Disconnected {
+                backoff: backoff.take(),
+            },
+            _ => return,
+        };
+        tracing::debug!("reconnecting after a refresh was requested");
+    }
+
     /// Drives the state forward until its connected.
     fn poll_connected(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
         loop {
@@ -307,14 +658,18 @@ where
                     },
                     Ok(initial) => {
                         tracing::trace!("connected");
+                        self.failing_since = None;
+                        self.last_error = None;
+                        self.connects += 1;
                         State::Connected {
                             resolution: resolution.take().expect("illegal state"),
                             initial: Some(initial),
+                            expiry: self.max_lifetime.map(time::delay_for),
                         }
                     }
                 },
 
-                State::Connected { .. } => return Poll::Ready(Ok(())),
+                State::Connected { .. } | State::GaveUp { .. } => return Poll::Ready(Ok(())),
 
                 // If any stage failed, try to recover. If the error is
                 // recoverable, start (or continue) backing off...
@@ -324,7 +679,23 @@ where
                 } => {
                     let err = error.take().expect("illegal state");
                     tracing::debug!(%err, "recovering");
+                    self.last_error = Some(ErrorSummary::new(&*err));
                     let new_backoff = self.recover.recover(err)?;
+
+                    // If the resolution has been failing for too long, stop
+                    // reconnecting.
+                    let failing_since = *self.failing_since.get_or_insert_with(time::Instant::now);
+                    if let Some((after, give_up)) = self.give_up {
+                        if failing_since.elapsed() >= after {
+                            tracing::debug!(?after, ?give_up, "giving up");
+                            self.state = State::GaveUp { emptied: false };
+                            if give_up == GiveUp::Fail {
+                                return Poll::Ready(Err(GaveUp(after).into()));
+                            }
+                            return Poll::Ready(Ok(()));
+                        }
+                    }
+
                     State::Backoff(backoff.take().or(Some(new_backoff)))
                 }
 
@@ -348,9 +719,124 @@ where
     }
 }
 
+// === impl ResolutionSnapshot ===
+
+impl ResolutionSnapshot {
+    /// Returns a JSON representation of the snapshot.
+    ///
+    /// The age of the last error is reported in seconds, as its instant is
+    /// not meaningful outside of the process.
+    #[cfg(any(test, feature = "file"))]
+    pub fn to_json(&self) -> serde_json::Value {
+        use serde_json::json;
+
+        let last_error = self.last_error.as_ref().map(|e| {
+            let status = e.status().map(|s| {
+                json!({
+                    "code": s.code(),
+                    "message": s.message(),
+                    "reason": s.reason(),
+                })
+            });
+            json!({
+                "message": e.message(),
+                "status": status,
+                "age_secs": e.at().elapsed().as_secs_f64(),
+            })
+        });
+        json!({
+            "endpoint_count": self.endpoints.len(),
+            "endpoints": self.endpoints.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            "pending_updates": self.pending_updates,
+            "last_error": last_error,
+            "reconnects": self.reconnects,
+            "connection": self.connection.as_str(),
+            "initialized": self.initialized,
+        })
+    }
+}
+
+// === impl Connection ===
+
+impl Connection {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Connection::Connected => "connected",
+            Connection::Connecting => "connecting",
+            Connection::BackingOff => "backing_off",
+            Connection::Paused => "paused",
+            Connection::GaveUp => "gave_up",
+        }
+    }
+}
+
+// === impl ErrorSummary ===
+
+impl ErrorSummary {
+    fn new(error: &(dyn std::error::Error + 'static)) -> Self {
+        // The resolver's error may be wrapped by other middleware.
+        let mut status = None;
+        let mut source = Some(error);
+        while let Some(e) = source {
+            if let Some(e) = e.downcast_ref::<ResolveError>() {
+                status = e.status().cloned();
+                break;
+            }
+            source = e.source();
+        }
+        Self {
+            message: error.to_string(),
+            status,
+            at: time::Instant::now(),
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Returns the gRPC status with which the resolver failed, if any.
+    pub fn status(&self) -> Option<&StatusDetail> {
+        self.status.as_ref()
+    }
+
+    /// Returns the time at which the failure was observed.
+    pub fn at(&self) -> time::Instant {
+        self.at
+    }
+}
+
+impl std::fmt::Display for ErrorSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+// === impl GaveUp ===
+
+impl std::fmt::Display for GaveUp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "resolution failed to reconnect for {:?}", self.0)
+    }
+}
+
+impl std::error::Error for GaveUp {}
+
+/// Returns the number of endpoints in an initial update that are unchanged
+/// from the cache, and so are not advertised again.
+fn duplicate_adds<E: PartialEq>(cache: &IndexMap<SocketAddr, E>, initial: &Update<E>) -> usize {
+    match initial {
+        Update::Add(endpoints) => endpoints
+            .iter()
+            .filter(|(addr, ep)| cache.get(addr) == Some(ep))
+            .count(),
+        _ => 0,
+    }
+}
+
 /// Computes the updates needed after a connection is (re-)established.
 // Raw fn for easier testing.
-fn reconcile_after_connect<E: PartialEq>(
+pub(crate) fn reconcile_after_connect<E: PartialEq>(
     cache: &IndexMap<SocketAddr, E>,
     initial: Update<E>,
 ) -> Option<(Update<E>, Option<Update<E>>)> {
@@ -406,6 +892,8 @@ fn reconcile_after_connect<E: PartialEq>(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
 
     pub fn addr0() -> SocketAddr {
         ([198, 51, 100, 1], 8080).into()
@@ -450,6 +938,11 @@ mod tests {
             reconcile_after_connect(&cache, Update::Add(vec![(addr0(), 0), (addr1(), 0)])),
             Some((Update::Add(vec![(addr1(), 0)]), None)),
         );
+        assert_eq!(
+            duplicate_adds(&cache, &Update::Add(vec![(addr0(), 0), (addr1(), 0)])),
+            1
+        );
+        assert_eq!(duplicate_adds(&cache, &Update::Add(vec![(addr0(), 1)])), 0);
     }
 
     #[test]
@@ -511,4 +1004,572 @@ mod tests {
             "DNEs should be passed through"
         );
     }
+
+    /// Resolves a fixed set of endpoints, counting each resolution.
+    #[derive(Clone, Debug, Default)]
+    struct StableResolve(Arc<std::sync::atomic::AtomicUsize>);
+
+    struct StableResolution(Option<Update<usize>>);
+
+    impl tower::Service<()> for StableResolve {
+        type Response = StableResolution;
+        type Error = Error;
+        type Future = futures::future::Ready<Result<StableResolution, Error>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _: ()) -> Self::Future {
+            self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            let add = Update::Add(vec![(addr0(), 0), (addr1(), 0)]);
+            futures::future::ok(StableResolution(Some(add)))
+        }
+    }
+
+    impl resolve::Resolution for StableResolution {
+        type Endpoint = usize;
+        type Error = Error;
+
+        fn poll(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+        ) -> Poll<Result<Update<usize>, Error>> {
+            match self.0.take() {
+                Some(update) => Poll::Ready(Ok(update)),
+                None => Poll::Pending,
+            }
+        }
+    }
+
+    /// Resolves to a resolution that produces the scripted updates.
+    #[derive(Clone, Debug, Default)]
+    struct ScriptedResolve(Arc<Mutex<VecDeque<Update<usize>>>>);
+
+    struct ScriptedResolution(Arc<Mutex<VecDeque<Update<usize>>>>);
+
+    impl tower::Service<()> for ScriptedResolve {
+        type Response = ScriptedResolution;
+        type Error = Error;
+        type Future = futures::future::Ready<Result<ScriptedResolution, Error>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _: ()) -> Self::Future {
+            futures::future::ok(ScriptedResolution(self.0.clone()))
+        }
+    }
+
+    impl resolve::Resolution for ScriptedResolution {
+        type Endpoint = usize;
+        type Error = Error;
+
+        fn poll(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<Update<usize>, Error>> {
+            match self.0.lock().unwrap().pop_front() {
+                Some(update) => Poll::Ready(Ok(update)),
+                None => Poll::Pending,
+            }
+        }
+    }
+
+    /// Resolves a single endpoint once; thereafter, the resolution and all
+    /// subsequent resolutions fail. Counts each resolution.
+    #[derive(Clone, Debug, Default)]
+    struct FailingResolve(Arc<std::sync::atomic::AtomicUsize>);
+
+    struct FailingResolution(Option<Update<usize>>);
+
+    impl tower::Service<()> for FailingResolve {
+        type Response = FailingResolution;
+        type Error = Error;
+        type Future = futures::future::Ready<Result<FailingResolution, Error>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _: ()) -> Self::Future {
+            if self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst) > 0 {
+                return futures::future::err("unavailable".into());
+            }
+            let add = Update::Add(vec![(addr0(), 0)]);
+            futures::future::ok(FailingResolution(Some(add)))
+        }
+    }
+
+    impl resolve::Resolution for FailingResolution {
+        type Endpoint = usize;
+        type Error = Error;
+
+        fn poll(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+        ) -> Poll<Result<Update<usize>, Error>> {
+            match self.0.take() {
+                Some(update) => Poll::Ready(Ok(update)),
+                None => Poll::Ready(Err("disconnected".into())),
+            }
+        }
+    }
+
+    type Backoff = Pin<Box<dyn futures::Stream<Item = Result<(), Error>> + Send>>;
+
+    /// Backs off for 10ms before each reconnect.
+    fn backoff(_: Error) -> Result<Backoff, Error> {
+        let delays = futures::stream::unfold((), |()| async {
+            time::delay_for(Duration::from_millis(10)).await;
+            Some((Ok(()), ()))
+        });
+        Ok(Box::pin(delays))
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_failing_to_reconnect() {
+        use futures::StreamExt;
+        use std::sync::atomic::Ordering;
+        use tower::Service;
+
+        for &give_up in &[GiveUp::Empty, GiveUp::Fail] {
+            let resolve = FailingResolve::default();
+            let resolutions = resolve.0.clone();
+            let mut resolution = Resolve::new(backoff, resolve)
+                .with_give_up_after(Duration::from_millis(50), give_up)
+                .call(())
+                .await
+                .expect("resolution must succeed");
+            let mut updates =
+                futures::stream::poll_fn(|cx| Pin::new(&mut resolution).poll(cx).map(Some));
+
+            let update = updates.next().await.unwrap().expect("update must succeed");
+            assert_eq!(update, Update::Add(vec![(addr0(), 0)]));
+
+            let next = time::timeout(Duration::from_secs(1), updates.next())
+                .await
+                .expect("the resolution must give up")
+                .unwrap();
+            match give_up {
+                GiveUp::Empty => assert_eq!(next.expect("update must succeed"), Update::Empty),
+                GiveUp::Fail => assert!(next.unwrap_err().is::<GaveUp>()),
+            }
+            let attempts = resolutions.load(Ordering::SeqCst);
+            assert!(attempts > 2, "must reconnect before giving up");
+
+            // No further reconnects are attempted.
+            if give_up == GiveUp::Empty {
+                let next = time::timeout(Duration::from_millis(100), updates.next()).await;
+                assert!(next.is_err(), "no updates expected; got {:?}", next);
+            } else {
+                time::delay_for(Duration::from_millis(100)).await;
+            }
+            assert_eq!(resolutions.load(Ordering::SeqCst), attempts);
+        }
+    }
+
+    /// Resolves each connection to a resolution that produces the next script
+    /// of updates. Once a script is exhausted, its resolution fails.
+    #[derive(Clone, Debug, Default)]
+    struct ReconnectingResolve(Arc<Mutex<VecDeque<Vec<Update<usize>>>>>);
+
+    struct ReconnectingResolution(VecDeque<Update<usize>>);
+
+    impl tower::Service<()> for ReconnectingResolve {
+        type Response = ReconnectingResolution;
+        type Error = Error;
+        type Future = futures::future::Ready<Result<ReconnectingResolution, Error>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _: ()) -> Self::Future {
+            match self.0.lock().unwrap().pop_front() {
+                Some(script) => futures::future::ok(ReconnectingResolution(script.into())),
+                None => futures::future::err("no more connections".into()),
+            }
+        }
+    }
+
+    impl resolve::Resolution for ReconnectingResolution {
+        type Endpoint = usize;
+        type Error = Error;
+
+        fn poll(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+        ) -> Poll<Result<Update<usize>, Error>> {
+            match self.0.pop_front() {
+                Some(update) => Poll::Ready(Ok(update)),
+                None => Poll::Ready(Err("disconnected".into())),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn sequence_increases_across_reconnects() {
+        use tower::Service;
+
+        let addr2 = SocketAddr::from(([198, 51, 100, 3], 8080));
+        let resolve = ReconnectingResolve::default();
+        resolve.0.lock().unwrap().extend(vec![
+            vec![
+                Update::Add(vec![(addr0(), 0)]),
+                Update::Add(vec![(addr1(), 0)]),
+            ],
+            vec![
+                Update::Add(vec![(addr0(), 0), (addr2, 0)]),
+                Update::Remove(vec![addr0()]),
+            ],
+        ]);
+        let mut resolution = Resolve::new(linkerd2_error::recover::Immediately::new(), resolve)
+            .call(())
+            .await
+            .expect("resolution must succeed");
+
+        let mut updates = Vec::new();
+        for _ in 0..5 {
+            let update =
+                futures::future::poll_fn(|cx| Pin::new(&mut resolution).poll_sequenced(cx))
+                    .await
+                    .expect("update must succeed");
+            updates.push(update);
+        }
+        assert_eq!(
+            updates,
+            vec![
+                (1, Update::Add(vec![(addr0(), 0)])),
+                (2, Update::Add(vec![(addr1(), 0)])),
+                // After reconnecting, the new state is reconciled.
+                (3, Update::Add(vec![(addr2, 0)])),
+                (4, Update::Remove(vec![addr1()])),
+                (5, Update::Remove(vec![addr0()])),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn pause_and_resume_with_reconcile() {
+        use tower::Service;
+
+        let addr2 = SocketAddr::from(([198, 51, 100, 3], 8080));
+        let resolve = ReconnectingResolve::default();
+        let connections = resolve.0.clone();
+        connections.lock().unwrap().extend(vec![
+            vec![
+                Update::Add(vec![(addr0(), 0), (addr1(), 0)]),
+                Update::Add(vec![(addr2, 0)]),
+            ],
+            vec![Update::Add(vec![(addr0(), 0)])],
+        ]);
+        let mut resolution = Resolve::new(linkerd2_error::recover::Immediately::new(), resolve)
+            .call(())
+            .await
+            .expect("resolution must succeed");
+        let mut resolution = Pin::new(&mut resolution);
+
+        let update = futures::future::poll_fn(|cx| resolution.as_mut().poll(cx))
+            .await
+            .expect("update must succeed");
+        assert_eq!(update, Update::Add(vec![(addr0(), 0), (addr1(), 0)]));
+
+        // While paused, the connection's remaining updates are not produced.
+        resolution.as_mut().pause();
+        assert!(resolution.is_paused());
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        assert!(resolution.as_mut().poll(&mut cx).is_pending());
+        assert_eq!(connections.lock().unwrap().len(), 1, "must not reconnect");
+
+        // Once resumed, the resolution reconnects and reconciles.
+        resolution.as_mut().resume();
+        assert!(!resolution.is_paused());
+        let update = futures::future::poll_fn(|cx| resolution.as_mut().poll(cx))
+            .await
+            .expect("update must succeed");
+        assert_eq!(update, Update::Remove(vec![addr1()]));
+        assert!(connections.lock().unwrap().is_empty(), "must reconnect");
+        assert_eq!(resolution.snapshot(), vec![Update::Add(vec![(addr0(), 0)])]);
+    }
+
+    #[tokio::test]
+    async fn last_error_is_cleared_after_reconnecting() {
+        use tower::Service;
+
+        let resolve = ReconnectingResolve::default();
+        resolve.0.lock().unwrap().extend(vec![
+            vec![Update::Add(vec![(addr0(), 0)])],
+            vec![Update::Add(vec![(addr0(), 0), (addr1(), 0)])],
+        ]);
+        let mut resolution = Resolve::new(backoff, resolve)
+            .call(())
+            .await
+            .expect("resolution must succeed");
+        assert_eq!(resolution.last_error(), None);
+
+        let update = futures::future::poll_fn(|cx| Pin::new(&mut resolution).poll(cx))
+            .await
+            .expect("update must succeed");
+        assert_eq!(update, Update::Add(vec![(addr0(), 0)]));
+
+        // The stream fails and the resolution backs off before reconnecting.
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        assert!(Pin::new(&mut resolution).poll(&mut cx).is_pending());
+        let error = resolution.last_error().expect("error must be recorded");
+        assert_eq!(error.message(), "disconnected");
+
+        let update = futures::future::poll_fn(|cx| Pin::new(&mut resolution).poll(cx))
+            .await
+            .expect("update must succeed");
+        assert_eq!(update, Update::Add(vec![(addr1(), 0)]));
+        assert_eq!(resolution.last_error(), None);
+    }
+
+    #[tokio::test]
+    async fn error_summary_preserves_status() {
+        use linkerd2_proxy_core::resolve::{DiscoverySource, ErrorKind};
+
+        let status = StatusDetail::new(7, "not authorized", Some("identity-mismatch".into()));
+        let error: Error = ResolveError::new(
+            DiscoverySource::Destination,
+            ErrorKind::Permission,
+            "denied",
+        )
+        .with_status(status.clone())
+        .into();
+        let summary = ErrorSummary::new(&*error);
+        assert_eq!(summary.message(), "destination resolution failed: denied");
+        assert_eq!(summary.status(), Some(&status));
+
+        let snapshot = ResolutionSnapshot {
+            endpoints: vec![],
+            pending_updates: 0,
+            last_error: Some(summary),
+            reconnects: 0,
+            connection: Connection::BackingOff,
+            initialized: false,
+        };
+        let json = snapshot.to_json();
+        assert_eq!(json["last_error"]["status"]["code"], 7);
+        assert_eq!(json["last_error"]["status"]["reason"], "identity-mismatch");
+
+        let error: Error = "disconnected".into();
+        assert_eq!(ErrorSummary::new(&*error).status(), None);
+    }
+
+    #[tokio::test]
+    async fn resolution_snapshot_describes_state() {
+        use tower::Service;
+
+        let resolve = ReconnectingResolve::default();
+        resolve.0.lock().unwrap().extend(vec![
+            vec![Update::Add(vec![(addr0(), 0)])],
+            vec![Update::Add(vec![(addr0(), 0), (addr1(), 0)])],
+        ]);
+        let mut resolution = Resolve::new(backoff, resolve)
+            .call(())
+            .await
+            .expect("resolution must succeed");
+
+        // The initial update has been received but not returned.
+        let snapshot = resolution.resolution_snapshot();
+        assert_eq!(snapshot.endpoints, vec![]);
+        assert_eq!(snapshot.pending_updates, 1);
+        assert_eq!(snapshot.connection, Connection::Connected);
+        assert!(!snapshot.initialized);
+
+        let update = futures::future::poll_fn(|cx| Pin::new(&mut resolution).poll(cx))
+            .await
+            .expect("update must succeed");
+        assert_eq!(update, Update::Add(vec![(addr0(), 0)]));
+
+        // The stream fails and the resolution backs off before reconnecting.
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        assert!(Pin::new(&mut resolution).poll(&mut cx).is_pending());
+        let snapshot = resolution.resolution_snapshot();
+        assert_eq!(snapshot.endpoints, vec![addr0()]);
+        assert_eq!(snapshot.pending_updates, 0);
+        assert_eq!(snapshot.reconnects, 0);
+        assert_eq!(snapshot.connection, Connection::BackingOff);
+        assert!(snapshot.initialized);
+        assert_eq!(
+            snapshot.last_error.as_ref().map(ErrorSummary::message),
+            Some("disconnected")
+        );
+
+        let json = snapshot.to_json();
+        assert_eq!(json["endpoint_count"], 1);
+        assert_eq!(json["endpoints"][0], addr0().to_string());
+        assert_eq!(json["connection"], "backing_off");
+        assert_eq!(json["last_error"]["message"], "disconnected");
+
+        let update = futures::future::poll_fn(|cx| Pin::new(&mut resolution).poll(cx))
+            .await
+            .expect("update must succeed");
+        assert_eq!(update, Update::Add(vec![(addr1(), 0)]));
+        let snapshot = resolution.resolution_snapshot();
+        assert_eq!(snapshot.endpoints, vec![addr0(), addr1()]);
+        assert_eq!(snapshot.reconnects, 1);
+        assert_eq!(snapshot.connection, Connection::Connected);
+        assert_eq!(snapshot.last_error, None);
+        assert_eq!(snapshot.to_json()["last_error"], serde_json::Value::Null);
+    }
+
+    #[tokio::test]
+    async fn is_fully_resolved_tracks_endpoints() {
+        use resolve::ResolutionStatus;
+        use tower::Service;
+
+        let resolve = ScriptedResolve::default();
+        let updates = resolve.0.clone();
+        updates
+            .lock()
+            .unwrap()
+            .push_back(Update::Add(vec![(addr0(), 0), (addr1(), 0)]));
+        let mut resolution = Resolve::new(linkerd2_error::recover::Immediately::new(), resolve)
+            .call(())
+            .await
+            .expect("resolution must succeed");
+        assert!(!resolution.is_fully_resolved());
+
+        let steps = vec![
+            (Update::Add(vec![(addr0(), 0), (addr1(), 0)]), true),
+            (Update::Remove(vec![addr0()]), true),
+            (Update::Remove(vec![addr1()]), false),
+            (Update::Add(vec![(addr0(), 0)]), true),
+            (Update::Empty, false),
+            (Update::Add(vec![(addr1(), 0)]), true),
+            (Update::DoesNotExist, false),
+        ];
+        // The initial update has already been read.
+        for (i, (update, resolved)) in steps.into_iter().enumerate() {
+            if i > 0 {
+                updates.lock().unwrap().push_back(update.clone());
+            }
+            let next = futures::future::poll_fn(|cx| Pin::new(&mut resolution).poll(cx))
+                .await
+                .expect("update must succeed");
+            assert_eq!(next, update);
+            assert_eq!(
+                resolution.is_fully_resolved(),
+                resolved,
+                "after {:?}",
+                update
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn snapshot_does_not_disturb_updates() {
+        use tower::Service;
+
+        let resolve = ScriptedResolve::default();
+        let updates = resolve.0.clone();
+        updates
+            .lock()
+            .unwrap()
+            .push_back(Update::Add(vec![(addr0(), 0), (addr1(), 0)]));
+        let mut resolution = Resolve::new(linkerd2_error::recover::Immediately::new(), resolve)
+            .call(())
+            .await
+            .expect("resolution must succeed");
+
+        // The initial update has been received but not yet returned.
+        let add = Update::Add(vec![(addr0(), 0), (addr1(), 0)]);
+        assert_eq!(resolution.snapshot(), vec![add.clone()]);
+        assert_eq!(resolution.snapshot(), vec![add.clone()]);
+        let next = futures::future::poll_fn(|cx| Pin::new(&mut resolution).poll(cx))
+            .await
+            .expect("update must succeed");
+        assert_eq!(next, add);
+
+        // Thereafter, the snapshot reflects the current endpoints.
+        updates
+            .lock()
+            .unwrap()
+            .push_back(Update::Remove(vec![addr0()]));
+        let next = futures::future::poll_fn(|cx| Pin::new(&mut resolution).poll(cx))
+            .await
+            .expect("update must succeed");
+        assert_eq!(next, Update::Remove(vec![addr0()]));
+        assert_eq!(resolution.snapshot(), vec![Update::Add(vec![(addr1(), 0)])]);
+    }
+
+    #[tokio::test]
+    async fn reconnect_after_max_lifetime_without_churn() {
+        use std::sync::atomic::Ordering;
+        use tower::Service;
+
+        let resolve = StableResolve::default();
+        let resolutions = resolve.0.clone();
+        let churn = Churn::default();
+        let mut resolution = Resolve::new(linkerd2_error::recover::Immediately::new(), resolve)
+            .with_max_lifetime(Duration::from_millis(10))
+            .with_churn_metrics(churn.clone())
+            .call(())
+            .await
+            .expect("resolution must succeed");
+
+        let update = futures::future::poll_fn(|cx| Pin::new(&mut resolution).poll(cx))
+            .await
+            .expect("update must succeed");
+        assert_eq!(update, Update::Add(vec![(addr0(), 0), (addr1(), 0)]));
+
+        // The resolution is reconnected several times, but as the endpoints
+        // don't change, no updates are produced.
+        let next = time::timeout(
+            Duration::from_millis(100),
+            futures::future::poll_fn(|cx| Pin::new(&mut resolution).poll(cx)),
+        )
+        .await;
+        assert!(next.is_err(), "no updates expected; got {:?}", next);
+        let reconnects = resolutions.load(Ordering::SeqCst) as u64 - 1;
+        assert!(reconnects > 0);
+
+        // Both endpoints are suppressed after each reconnect.
+        assert_eq!(churn.duplicate_adds_total(), reconnects * 2);
+    }
+
+    #[tokio::test]
+    async fn refresh_reconnects_without_churn() {
+        use std::sync::atomic::Ordering;
+        use tower::Service;
+
+        let resolve = StableResolve::default();
+        let resolutions = resolve.0.clone();
+        let churn = Churn::default();
+        let mut resolution = Resolve::new(linkerd2_error::recover::Immediately::new(), resolve)
+            .with_churn_metrics(churn.clone())
+            .call(())
+            .await
+            .expect("resolution must succeed");
+
+        let update = futures::future::poll_fn(|cx| Pin::new(&mut resolution).poll(cx))
+            .await
+            .expect("update must succeed");
+        assert_eq!(update, Update::Add(vec![(addr0(), 0), (addr1(), 0)]));
+        assert_eq!(resolutions.load(Ordering::SeqCst), 1);
+
+        // The refresh is requested while the resolution is waiting for an
+        // update, so the resolution must be notified.
+        let refresh = resolution.refresh_handle();
+        tokio::spawn(async move {
+            time::delay_for(Duration::from_millis(10)).await;
+            refresh.refresh();
+        });
+
+        // The resolution is reconnected, but as the endpoints don't change,
+        // no updates are produced and the endpoints are retained.
+        let next = time::timeout(
+            Duration::from_millis(100),
+            futures::future::poll_fn(|cx| Pin::new(&mut resolution).poll(cx)),
+        )
+        .await;
+        assert!(next.is_err(), "no updates expected; got {:?}", next);
+        assert_eq!(resolutions.load(Ordering::SeqCst), 2);
+        assert_eq!(churn.duplicate_adds_total(), 2);
+        assert_eq!(
+            resolution.snapshot(),
+            vec![Update::Add(vec![(addr0(), 0), (addr1(), 0)])]
+        );
+    }
 }
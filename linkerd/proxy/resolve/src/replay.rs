@@ -0,0 +1,299 @@
+//! A `Resolve` that replays the updates recorded by `record`, so that
+//! discovery bugs may be reproduced deterministically in tests.
+//!
+//! Each target's updates are returned in the order they were recorded, with
+//! the recorded time between them, optionally accelerated. Once a target's
+//! updates are exhausted, its resolution produces no further updates.
+
+use crate::record::{Endpoint, Entry, InvalidLog};
+use futures::{ready, FutureExt};
+use linkerd2_error::Error;
+use linkerd2_proxy_core::resolve::{self, Update};
+use std::collections::VecDeque;
+use std::fmt;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::time;
+
+#[derive(Debug)]
+pub struct Resolve<E> {
+    entries: Arc<Vec<Entry<E>>>,
+    speedup: u32,
+}
+
+#[derive(Debug)]
+pub struct Resolution<E> {
+    entries: VecDeque<Entry<E>>,
+    speedup: u32,
+    /// The recorded time of the most recently returned update.
+    last: Duration,
+    /// Elapses when the next update is returned.
+    delay: Option<time::Delay>,
+}
+
+// === impl Resolve ===
+
+impl<E: Endpoint> Resolve<E> {
+    /// Replays the updates in a log written by `record`.
+    pub fn from_log(log: &str) -> Result<Self, InvalidLog> {
+        Ok(Self::new(Entry::parse_log(log)?))
+    }
+}
+
+impl<E> Resolve<E> {
+    pub fn new(entries: Vec<Entry<E>>) -> Self {
+        Self {
+            entries: Arc::new(entries),
+            speedup: 1,
+        }
+    }
+
+    /// Divides the time between updates by `speedup`.
+    pub fn with_speedup(self, speedup: u32) -> Self {
+        Self {
+            speedup: speedup.max(1),
+            ..self
+        }
+    }
+}
+
+impl<E> Clone for Resolve<E> {
+    fn clone(&self) -> Self {
+        Self {
+            entries: self.entries.clone(),
+            speedup: self.speedup,
+        }
+    }
+}
+
+impl<T: fmt::Display, E: Clone> tower::Service<T> for Resolve<E> {
+    type Response = Resolution<E>;
+    type Error = Error;
+    type Future = futures::future::Ready<Result<Resolution<E>, Error>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        let target = target.to_string();
+        let entries = self
+            .entries
+            .iter()
+            .filter(|e| e.target == target)
+            .cloned()
+            .collect();
+        futures::future::ok(Resolution {
+            entries,
+            speedup: self.speedup,
+            last: Duration::from_secs(0),
+            delay: None,
+        })
+    }
+}
+
+// === impl Resolution ===
+
+impl<E> resolve::Resolution for Resolution<E>
+where
+    E: Unpin,
+{
+    type Endpoint = E;
+    type Error = Error;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<Update<E>, Error>> {
+        let this = &mut *self;
+        loop {
+            if let Some(delay) = this.delay.as_mut() {
+                ready!(delay.poll_unpin(cx));
+                this.delay = None;
+                let entry = this.entries.pop_front().expect("delayed without an update");
+                this.last = entry.at;
+                return Poll::Ready(Ok(entry.update));
+            }
+
+            let at = match this.entries.front() {
+                Some(entry) => entry.at,
+                None => return Poll::Pending,
+            };
+            // Updates from separate recorded resolutions may restart the
+            // clock, in which case they are returned immediately.
+            let wait = at.checked_sub(this.last).unwrap_or_default() / this.speedup;
+            this.delay = Some(time::delay_for(wait));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record;
+    use linkerd2_proxy_core::resolve::Resolution as _;
+    use linkerd2_proxy_core::test_util::next;
+    use serde_json::{json, Value};
+    use std::collections::BTreeMap;
+    use std::io;
+    use std::net::SocketAddr;
+    use std::sync::mpsc;
+    use tower::Service;
+
+    /// Endpoint metadata that is recorded in the log.
+    #[derive(Clone, Debug, PartialEq)]
+    struct Labels(BTreeMap<String, String>);
+
+    impl Endpoint for Labels {
+        fn to_json(&self) -> Value {
+            json!(self.0)
+        }
+
+        fn from_json(value: &Value) -> Option<Self> {
+            let labels = value
+                .as_object()?
+                .iter()
+                .map(|(k, v)| Some((k.clone(), v.as_str()?.to_string())))
+                .collect::<Option<_>>()?;
+            Some(Labels(labels))
+        }
+    }
+
+    fn labels(zone: &str) -> Labels {
+        let mut labels = BTreeMap::new();
+        labels.insert("zone".to_string(), zone.to_string());
+        Labels(labels)
+    }
+
+    fn addr(n: u8) -> SocketAddr {
+        ([198, 51, 100, n], 8080).into()
+    }
+
+    /// Sends each write to the test, so that the log may be read once the
+    /// writer is dropped.
+    struct Writes(mpsc::Sender<Vec<u8>>);
+
+    impl io::Write for Writes {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0
+                .send(buf.to_vec())
+                .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Resolves to a resolution that returns each update after `INTERVAL`.
+    #[derive(Clone)]
+    struct Scripted(Vec<Update<Labels>>);
+
+    struct ScriptedResolution(VecDeque<Update<Labels>>, Option<time::Delay>);
+
+    const INTERVAL: Duration = Duration::from_millis(20);
+
+    impl tower::Service<&'static str> for Scripted {
+        type Response = ScriptedResolution;
+        type Error = Error;
+        type Future = futures::future::Ready<Result<ScriptedResolution, Error>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _: &'static str) -> Self::Future {
+            futures::future::ok(ScriptedResolution(self.0.iter().cloned().collect(), None))
+        }
+    }
+
+    impl resolve::Resolution for ScriptedResolution {
+        type Endpoint = Labels;
+        type Error = Error;
+
+        fn poll(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<Result<Update<Labels>, Error>> {
+            if self.0.is_empty() {
+                return Poll::Pending;
+            }
+            let delay = self.1.get_or_insert_with(|| time::delay_for(INTERVAL));
+            ready!(delay.poll_unpin(cx));
+            self.1 = None;
+            Poll::Ready(Ok(self.0.pop_front().unwrap()))
+        }
+    }
+
+    #[tokio::test]
+    async fn replays_recorded_updates() {
+        let updates = vec![
+            Update::Add(vec![(addr(1), labels("a")), (addr(2), labels("b"))]),
+            Update::Remove(vec![addr(1)]),
+            Update::Empty,
+            Update::DoesNotExist,
+        ];
+        let (tx, rx) = mpsc::channel();
+        let log = record::Log::spawn(Writes(tx)).expect("log must be spawned");
+        let mut recording = record::Resolve::new(log, Scripted(updates.clone()))
+            .call("web.example.com:8080")
+            .await
+            .expect("resolution must succeed");
+        for update in updates.iter() {
+            assert_eq!(&next(&mut recording).await, update);
+        }
+
+        // The log is complete once the writer thread exits.
+        drop(recording);
+        let log = String::from_utf8(rx.iter().flatten().collect()).expect("log must be UTF-8");
+        let entries = Entry::<Labels>::parse_log(&log).expect("log must be valid");
+        assert_eq!(entries.len(), updates.len());
+        for (entry, update) in entries.iter().zip(updates.iter()) {
+            assert_eq!(entry.target, "web.example.com:8080");
+            assert_eq!(&entry.update, update);
+        }
+        for pair in entries.windows(2) {
+            assert!(pair[0].at < pair[1].at, "times must increase");
+        }
+
+        // The updates are replayed in order, with accelerated timing.
+        let mut replay = Resolve::<Labels>::from_log(&log)
+            .expect("log must be valid")
+            .with_speedup(10)
+            .call("web.example.com:8080")
+            .await
+            .expect("replay must succeed");
+        for update in updates.iter() {
+            assert_eq!(&next(&mut replay).await, update);
+        }
+        let next = time::timeout(INTERVAL, next(&mut replay)).await;
+        assert!(next.is_err(), "no further updates expected; got {:?}", next);
+
+        // Other targets' updates are not replayed.
+        let mut other = Resolve::<Labels>::from_log(&log)
+            .expect("log must be valid")
+            .call("api.example.com:8080")
+            .await
+            .expect("replay must succeed");
+        let next = futures::future::poll_fn(|cx| Poll::Ready(Pin::new(&mut other).poll(cx)));
+        assert!(next.await.is_pending());
+    }
+
+    #[test]
+    fn invalid_logs() {
+        for log in &[
+            "[]",
+            r#"{"at_ms": 0, "update": "empty"}"#,
+            r#"{"target": "web:8080", "update": "empty"}"#,
+            r#"{"target": "web:8080", "at_ms": 0, "update": "reset"}"#,
+            r#"{"target": "web:8080", "at_ms": 0, "update": "remove", "addrs": ["web:8080"]}"#,
+            r#"{"target": "web:8080", "at_ms": 0, "update": "add", "endpoints": [{"addr": "198.51.100.1:8080", "metadata": 1}]}"#,
+        ] {
+            assert!(
+                Entry::<Labels>::parse_log(log).is_err(),
+                "{} must be invalid",
+                log
+            );
+        }
+    }
+}
@@ -0,0 +1,203 @@
+//! A `Resolve` that distributes resolutions across several backing resolvers,
+//! e.g. one per control plane replica.
+//!
+//! Each resolution is backed by a single resolver, chosen in round-robin
+//! order. When this is wrapped by `recover::Resolve`, a failed resolution is
+//! re-issued on the next resolver and its endpoints are reconciled, so that
+//! a single failing replica does not disrupt the resolution.
+
+use linkerd2_proxy_core::resolve;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+use std::task::{Context, Poll};
+
+#[derive(Debug)]
+pub struct Resolve<R> {
+    resolvers: Vec<R>,
+    /// The index of the next resolver, shared across clones so that
+    /// resolutions are distributed across all resolvers.
+    next: Arc<AtomicUsize>,
+    /// The index of the resolver that has been driven to readiness.
+    ready: Option<usize>,
+}
+
+// === impl Resolve ===
+
+impl<R> Resolve<R> {
+    /// Distributes resolutions across `resolvers`.
+    ///
+    /// # Panics
+    ///
+    /// If `resolvers` is empty.
+    pub fn new<T>(resolvers: Vec<R>) -> Self
+    where
+        Self: resolve::Resolve<T>,
+    {
+        assert!(!resolvers.is_empty(), "at least one resolver is required");
+        Self {
+            resolvers,
+            next: Arc::new(AtomicUsize::new(0)),
+            ready: None,
+        }
+    }
+}
+
+impl<R: Clone> Clone for Resolve<R> {
+    fn clone(&self) -> Self {
+        Self {
+            resolvers: self.resolvers.clone(),
+            next: self.next.clone(),
+            // Each clone must drive its own resolver to readiness.
+            ready: None,
+        }
+    }
+}
+
+impl<T, R> tower::Service<T> for Resolve<R>
+where
+    R: resolve::Resolve<T>,
+{
+    type Response = R::Resolution;
+    type Error = R::Error;
+    type Future = R::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let idx = match self.ready {
+            Some(idx) => idx,
+            None => {
+                let idx = self.next.fetch_add(1, Ordering::Relaxed) % self.resolvers.len();
+                self.ready = Some(idx);
+                idx
+            }
+        };
+
+        let res = self.resolvers[idx].poll_ready(cx);
+        if let Poll::Ready(Err(_)) = res {
+            self.ready = None;
+        }
+        res
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        let idx = self.ready.take().expect("called before ready");
+        self.resolvers[idx].resolve(target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recover;
+    use futures::future;
+    use linkerd2_error::Error;
+    use linkerd2_proxy_core::resolve::{Resolution as _, Update};
+    use std::net::SocketAddr;
+    use std::pin::Pin;
+    use std::time::Duration;
+    use tokio::time;
+    use tower::Service;
+
+    fn addr0() -> SocketAddr {
+        ([198, 51, 100, 1], 8080).into()
+    }
+
+    fn addr1() -> SocketAddr {
+        ([198, 51, 100, 2], 8080).into()
+    }
+
+    /// Resolves a fixed set of endpoints. If the replica is failing, each
+    /// resolution fails after its endpoints are added.
+    #[derive(Clone, Debug, Default)]
+    struct Replica {
+        resolutions: Arc<AtomicUsize>,
+        failing: bool,
+    }
+
+    struct ReplicaResolution {
+        add: Option<Update<usize>>,
+        failing: bool,
+    }
+
+    impl tower::Service<()> for Replica {
+        type Response = ReplicaResolution;
+        type Error = Error;
+        type Future = future::Ready<Result<ReplicaResolution, Error>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _: ()) -> Self::Future {
+            self.resolutions.fetch_add(1, Ordering::SeqCst);
+            future::ok(ReplicaResolution {
+                add: Some(Update::Add(vec![(addr0(), 0), (addr1(), 0)])),
+                failing: self.failing,
+            })
+        }
+    }
+
+    impl resolve::Resolution for ReplicaResolution {
+        type Endpoint = usize;
+        type Error = Error;
+
+        fn poll(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+        ) -> Poll<Result<Update<usize>, Error>> {
+            if let Some(add) = self.add.take() {
+                return Poll::Ready(Ok(add));
+            }
+            if self.failing {
+                return Poll::Ready(Err("replica failed".into()));
+            }
+            Poll::Pending
+        }
+    }
+
+    #[tokio::test]
+    async fn distributes_resolutions() {
+        let replicas = vec![Replica::default(), Replica::default()];
+        let mut resolve = Resolve::new(replicas.clone());
+
+        for _ in 0..4 {
+            future::poll_fn(|cx| resolve.poll_ready(cx)).await.unwrap();
+            resolve.call(()).await.expect("resolution must succeed");
+        }
+        for replica in replicas.iter() {
+            assert_eq!(replica.resolutions.load(Ordering::SeqCst), 2);
+        }
+    }
+
+    #[tokio::test]
+    async fn fails_over_without_churn() {
+        let failing = Replica {
+            failing: true,
+            ..Replica::default()
+        };
+        let healthy = Replica::default();
+        let resolve = Resolve::new(vec![failing.clone(), healthy.clone()]);
+        let mut resolve =
+            recover::Resolve::new(linkerd2_error::recover::Immediately::new(), resolve);
+        future::poll_fn(|cx| resolve.poll_ready(cx)).await.unwrap();
+        let mut resolution = resolve.call(()).await.expect("resolution must succeed");
+
+        let update = future::poll_fn(|cx| Pin::new(&mut resolution).poll(cx))
+            .await
+            .expect("update must succeed");
+        assert_eq!(update, Update::Add(vec![(addr0(), 0), (addr1(), 0)]));
+
+        // The failing replica's resolution fails and is re-issued on the
+        // healthy replica. Its endpoints are unchanged, so no updates are
+        // produced.
+        let next = time::timeout(
+            Duration::from_millis(100),
+            future::poll_fn(|cx| Pin::new(&mut resolution).poll(cx)),
+        )
+        .await;
+        assert!(next.is_err(), "no updates expected; got {:?}", next);
+        assert_eq!(failing.resolutions.load(Ordering::SeqCst), 1);
+        assert_eq!(healthy.resolutions.load(Ordering::SeqCst), 1);
+    }
+}
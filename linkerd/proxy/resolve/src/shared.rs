@@ -0,0 +1,344 @@
+//! Shares a single resolution among several subscribers, so that concurrent
+//! lookups of the same target may be served by a single query.
+//!
+//! Subscribers that join after the resolution has produced updates are first
+//! sent the current set of endpoints, followed by all subsequent updates, so
+//! that every subscriber observes a consistent view of the resolution.
+
+use indexmap::IndexMap;
+use linkerd2_error::Error;
+use linkerd2_proxy_core::resolve::{self, Update};
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::{error, fmt};
+
+pub struct Shared<R: resolve::Resolution> {
+    inner: Arc<Mutex<Inner<R>>>,
+}
+
+/// A resolution that observes a shared resolution.
+pub struct Subscriber<R: resolve::Resolution> {
+    id: usize,
+    inner: Arc<Mutex<Inner<R>>>,
+}
+
+/// A reference to a shared resolution that does not keep it alive.
+pub(crate) struct WeakShared<R: resolve::Resolution> {
+    inner: std::sync::Weak<Mutex<Inner<R>>>,
+}
+
+/// Indicates that the shared resolution failed.
+#[derive(Clone, Debug)]
+pub struct SharedError(Arc<Error>);
+
+struct Inner<R: resolve::Resolution> {
+    resolution: Pin<Box<R>>,
+    /// The endpoints that have been added and not yet removed, in the order
+    /// in which they were added.
+    endpoints: IndexMap<SocketAddr, R::Endpoint>,
+    /// Set when the resolution indicates that it has no endpoints: `true` if
+    /// the target exists, and `false` if it does not.
+    exists: Option<bool>,
+    error: Option<Arc<Error>>,
+    subscribers: HashMap<usize, Queue<R::Endpoint>>,
+    next_id: usize,
+}
+
+/// The updates that have not yet been observed by a subscriber.
+struct Queue<E> {
+    updates: VecDeque<Update<E>>,
+    waker: Option<Waker>,
+}
+
+// === impl Shared ===
+
+impl<R: resolve::Resolution> Shared<R> {
+    pub fn new(resolution: R) -> Self {
+        let inner = Inner {
+            resolution: Box::pin(resolution),
+            endpoints: IndexMap::new(),
+            exists: None,
+            error: None,
+            subscribers: HashMap::new(),
+            next_id: 0,
+        };
+        Self {
+            inner: Arc::new(Mutex::new(inner)),
+        }
+    }
+
+    /// Returns a reference that is valid for as long as this handle or any
+    /// of its subscribers is held.
+    pub(crate) fn downgrade(&self) -> WeakShared<R> {
+        WeakShared {
+            inner: Arc::downgrade(&self.inner),
+        }
+    }
+}
+
+impl<R> Shared<R>
+where
+    R: resolve::Resolution,
+    R::Endpoint: Clone,
+{
+    /// Returns a new subscriber to the shared resolution.
+    ///
+    /// The subscriber first observes the resolution's current state, before
+    /// any updates that are produced after it subscribed.
+    pub fn subscribe(&self) -> Subscriber<R> {
+        let mut inner = self.inner.lock().unwrap();
+        let id = inner.next_id;
+        inner.next_id += 1;
+        let queue = Queue {
+            updates: inner.replay(),
+            waker: None,
+        };
+        inner.subscribers.insert(id, queue);
+        Subscriber {
+            id,
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<R: resolve::Resolution> Clone for Shared<R> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+// === impl WeakShared ===
+
+impl<R: resolve::Resolution> WeakShared<R> {
+    pub(crate) fn upgrade(&self) -> Option<Shared<R>> {
+        self.inner.upgrade().map(|inner| Shared { inner })
+    }
+}
+
+// === impl Subscriber ===
+
+impl<R> resolve::Resolution for Subscriber<R>
+where
+    R: resolve::Resolution,
+    R::Endpoint: Clone,
+    R::Error: Into<Error>,
+{
+    type Endpoint = R::Endpoint;
+    type Error = SharedError;
+
+    fn poll(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Update<Self::Endpoint>, Self::Error>> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.poll_subscriber(self.id, cx)
+    }
+}
+
+impl<R: resolve::Resolution> Drop for Subscriber<R> {
+    fn drop(&mut self) {
+        if let Ok(mut inner) = self.inner.lock() {
+            inner.subscribers.remove(&self.id);
+            // This subscriber may have been the last to poll the shared
+            // resolution, so the remaining subscribers must poll it again to
+            // be notified of its updates.
+            for queue in inner.subscribers.values_mut() {
+                if let Some(waker) = queue.waker.take() {
+                    waker.wake();
+                }
+            }
+        }
+    }
+}
+
+// === impl Inner ===
+
+impl<R> Inner<R>
+where
+    R: resolve::Resolution,
+    R::Endpoint: Clone,
+{
+    /// Returns the updates that bring a new subscriber up to date.
+    fn replay(&self) -> VecDeque<Update<R::Endpoint>> {
+        let mut updates = VecDeque::with_capacity(1);
+        if !self.endpoints.is_empty() {
+            let endpoints = self
+                .endpoints
+                .iter()
+                .map(|(addr, endpoint)| (*addr, endpoint.clone()))
+                .collect();
+            updates.push_back(Update::Add(endpoints));
+        } else if let Some(exists) = self.exists {
+            updates.push_back(if exists {
+                Update::Empty
+            } else {
+                Update::DoesNotExist
+            });
+        }
+        updates
+    }
+
+    fn poll_subscriber(
+        &mut self,
+        id: usize,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Update<R::Endpoint>, SharedError>>
+    where
+        R::Error: Into<Error>,
+    {
+        loop {
+            let queue = self
+                .subscribers
+                .get_mut(&id)
+                .expect("subscriber must be registered");
+            if let Some(update) = queue.updates.pop_front() {
+                return Poll::Ready(Ok(update));
+            }
+            if let Some(error) = self.error.as_ref() {
+                return Poll::Ready(Err(SharedError(error.clone())));
+            }
+            queue.waker = Some(cx.waker().clone());
+
+            match futures::ready!(self.resolution.as_mut().poll(cx)) {
+                Ok(update) => self.publish(update),
+                Err(error) => {
+                    self.error = Some(Arc::new(error.into()));
+                    self.wake_all();
+                }
+            }
+        }
+    }
+
+    /// Records an update in the current state and sends it to all
+    /// subscribers.
+    fn publish(&mut self, update: Update<R::Endpoint>) {
+        match update {
+            Update::Add(ref endpoints) => {
+                self.exists = None;
+                for (addr, endpoint) in endpoints.iter() {
+                    self.endpoints.insert(*addr, endpoint.clone());
+                }
+            }
+            Update::Remove(ref addrs) => {
+                for addr in addrs.iter() {
+                    self.endpoints.remove(addr);
+                }
+            }
+            Update::Empty => {
+                self.endpoints.clear();
+                self.exists = Some(true);
+            }
+            Update::DoesNotExist => {
+                self.endpoints.clear();
+                self.exists = Some(false);
+            }
+        }
+
+        for queue in self.subscribers.values_mut() {
+            queue.updates.push_back(update.clone());
+        }
+        self.wake_all();
+    }
+
+    fn wake_all(&mut self) {
+        for queue in self.subscribers.values_mut() {
+            if let Some(waker) = queue.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+// === impl SharedError ===
+
+impl From<Error> for SharedError {
+    fn from(error: Error) -> Self {
+        SharedError(Arc::new(error))
+    }
+}
+
+impl fmt::Display for SharedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl error::Error for SharedError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&**self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::task::noop_waker_ref;
+    use linkerd2_proxy_core::resolve::Resolution as _;
+    use linkerd2_proxy_core::test_util::Updates;
+
+    fn addr(n: u8) -> SocketAddr {
+        ([198, 51, 100, n], 8080).into()
+    }
+
+    fn next(subscriber: &mut Subscriber<Updates<()>>) -> Option<Update<()>> {
+        let mut cx = Context::from_waker(noop_waker_ref());
+        match Pin::new(subscriber).poll(&mut cx) {
+            Poll::Ready(update) => Some(update.expect("update must succeed")),
+            Poll::Pending => None,
+        }
+    }
+
+    #[test]
+    fn late_subscriber_observes_current_endpoints() {
+        let updates = Updates::default();
+        let shared = Shared::new(updates.clone());
+        let mut first = shared.subscribe();
+
+        updates.push(Update::Add(vec![(addr(1), ()), (addr(2), ())]));
+        updates.push(Update::Add(vec![(addr(3), ())]));
+        updates.push(Update::Remove(vec![addr(2)]));
+        assert_eq!(
+            next(&mut first),
+            Some(Update::Add(vec![(addr(1), ()), (addr(2), ())]))
+        );
+        assert_eq!(next(&mut first), Some(Update::Add(vec![(addr(3), ())])));
+        assert_eq!(next(&mut first), Some(Update::Remove(vec![addr(2)])));
+        assert_eq!(next(&mut first), None);
+
+        // The late subscriber observes the current endpoints, in the order in
+        // which they were added, and then only new updates.
+        let mut second = shared.subscribe();
+        assert_eq!(
+            next(&mut second),
+            Some(Update::Add(vec![(addr(1), ()), (addr(3), ())]))
+        );
+        assert_eq!(next(&mut second), None);
+
+        updates.push(Update::Add(vec![(addr(4), ())]));
+        assert_eq!(next(&mut second), Some(Update::Add(vec![(addr(4), ())])));
+        assert_eq!(next(&mut second), None);
+        assert_eq!(next(&mut first), Some(Update::Add(vec![(addr(4), ())])));
+        assert_eq!(next(&mut first), None);
+    }
+
+    #[test]
+    fn late_subscriber_observes_no_endpoints() {
+        let updates = Updates::default();
+        let shared = Shared::new(updates.clone());
+        let mut first = shared.subscribe();
+
+        updates.push(Update::Add(vec![(addr(1), ())]));
+        updates.push(Update::DoesNotExist);
+        next(&mut first);
+        next(&mut first);
+
+        let mut second = shared.subscribe();
+        assert_eq!(next(&mut second), Some(Update::DoesNotExist));
+        assert_eq!(next(&mut second), None);
+    }
+}
@@ -0,0 +1,290 @@
+//! Tags each resolved endpoint with the source that discovered it, so that
+//! diagnostics can distinguish endpoints when several sources are combined.
+
+use crate::map_endpoint::{self, MapEndpoint};
+use futures::ready;
+use indexmap::IndexMap;
+pub use linkerd2_proxy_core::resolve::DiscoverySource;
+use linkerd2_proxy_core::resolve::{self, Update};
+use pin_project::pin_project;
+use std::net::SocketAddr;
+use std::ops::Deref;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tracing::debug;
+
+/// An endpoint, tagged with its source.
+///
+/// Dereferences to the inner endpoint, so that stacks that operate on the
+/// inner endpoint are unaffected by the tag.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tagged<E> {
+    source: DiscoverySource,
+    endpoint: E,
+}
+
+/// Tags all of a resolution's endpoints with a `DiscoverySource`.
+#[derive(Copy, Clone, Debug)]
+pub struct TagSource(DiscoverySource);
+
+/// Remembers the source that added each of a resolution's tagged endpoints,
+/// e.g. once several sources have been merged into one resolution, so that
+/// each removed endpoint is attributed to the source that added it.
+#[pin_project]
+#[derive(Debug)]
+pub struct Sources<R> {
+    #[pin]
+    resolution: R,
+    sources: IndexMap<SocketAddr, DiscoverySource>,
+    /// The endpoints removed by the most recent update.
+    removed: Vec<(SocketAddr, DiscoverySource)>,
+}
+
+/// Resolves endpoints with an inner `R`-typed resolver, tagging each with the
+/// resolver's source.
+pub type Resolve<R> = map_endpoint::Resolve<TagSource, R>;
+
+pub fn resolve<T, R>(source: DiscoverySource, resolve: R) -> Resolve<R>
+where
+    Resolve<R>: linkerd2_proxy_core::resolve::Resolve<T>,
+{
+    map_endpoint::Resolve::new(TagSource(source), resolve)
+}
+
+// === impl Tagged ===
+
+impl<E> Tagged<E> {
+    pub fn new(source: DiscoverySource, endpoint: E) -> Self {
+        Self { source, endpoint }
+    }
+
+    pub fn source(&self) -> DiscoverySource {
+        self.source
+    }
+
+    pub fn into_inner(self) -> E {
+        self.endpoint
+    }
+}
+
+impl<E> Deref for Tagged<E> {
+    type Target = E;
+
+    fn deref(&self) -> &E {
+        &self.endpoint
+    }
+}
+
+// === impl TagSource ===
+
+impl<T, E> MapEndpoint<T, E> for TagSource {
+    type Out = Tagged<E>;
+
+    fn map_endpoint(&self, _: &T, _: SocketAddr, endpoint: E) -> Tagged<E> {
+        Tagged::new(self.0, endpoint)
+    }
+}
+
+// === impl Sources ===
+
+impl<R> Sources<R> {
+    pub fn new(resolution: R) -> Self {
+        Self {
+            resolution,
+            sources: IndexMap::new(),
+            removed: Vec::new(),
+        }
+    }
+
+    /// Returns the source that added the endpoint at `addr`, if it has not
+    /// been removed.
+    pub fn source(&self, addr: &SocketAddr) -> Option<DiscoverySource> {
+        self.sources.get(addr).copied()
+    }
+
+    /// Returns the endpoints that were removed by the most recent update,
+    /// each with the source that added it.
+    pub fn removed(&self) -> &[(SocketAddr, DiscoverySource)] {
+        &self.removed
+    }
+}
+
+impl<R, E> resolve::Resolution for Sources<R>
+where
+    R: resolve::Resolution<Endpoint = Tagged<E>>,
+{
+    type Endpoint = Tagged<E>;
+    type Error = R::Error;
+
+    fn poll(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Update<Self::Endpoint>, Self::Error>> {
+        let this = self.project();
+        let update = ready!(this.resolution.poll(cx))?;
+        this.removed.clear();
+        match update {
+            Update::Add(ref endpoints) => {
+                for (addr, endpoint) in endpoints.iter() {
+                    this.sources.insert(*addr, endpoint.source());
+                }
+            }
+            Update::Remove(ref addrs) => {
+                for addr in addrs.iter() {
+                    if let Some(source) = this.sources.remove(addr) {
+                        debug!(%addr, ?source, "Removing endpoint");
+                        this.removed.push((*addr, source));
+                    }
+                }
+            }
+            Update::Empty | Update::DoesNotExist => {
+                this.removed.extend(this.sources.drain(..));
+            }
+        }
+        Poll::Ready(Ok(update))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fallback;
+    use futures::future;
+    use linkerd2_error::Error;
+    use linkerd2_proxy_core::resolve::Resolution;
+    use linkerd2_proxy_core::test_util::{next, next_within, Updates};
+    use std::time::Duration;
+    use tower::Service;
+
+    /// Resolves a single endpoint.
+    #[derive(Clone, Debug)]
+    struct One(SocketAddr);
+
+    struct OneResolution(Option<Update<&'static str>>);
+
+    impl tower::Service<()> for One {
+        type Response = OneResolution;
+        type Error = Error;
+        type Future = future::Ready<Result<OneResolution, Error>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _: ()) -> Self::Future {
+            future::ok(OneResolution(Some(Update::Add(vec![(self.0, "ep")]))))
+        }
+    }
+
+    impl Resolution for OneResolution {
+        type Endpoint = &'static str;
+        type Error = Error;
+
+        fn poll(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+        ) -> Poll<Result<Update<&'static str>, Error>> {
+            match self.0.take() {
+                Some(update) => Poll::Ready(Ok(update)),
+                None => Poll::Pending,
+            }
+        }
+    }
+
+    /// Resolves to a shared set of updates.
+    #[derive(Clone, Debug)]
+    struct Shared(Updates<&'static str>);
+
+    impl tower::Service<()> for Shared {
+        type Response = Updates<&'static str>;
+        type Error = Error;
+        type Future = future::Ready<Result<Updates<&'static str>, Error>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _: ()) -> Self::Future {
+            future::ok(self.0.clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn tags_endpoints_by_source() {
+        let addr0 = SocketAddr::from(([198, 51, 100, 1], 8080));
+        let addr1 = SocketAddr::from(([198, 51, 100, 2], 8080));
+        let mut dst = resolve(DiscoverySource::Destination, One(addr0));
+        let mut dns = resolve(DiscoverySource::Dns, One(addr1));
+
+        let mut adds = Vec::new();
+        for resolve in &mut [&mut dst, &mut dns] {
+            let mut resolution = resolve.call(()).await.expect("resolution must succeed");
+            match future::poll_fn(|cx| Pin::new(&mut resolution).poll(cx)).await {
+                Ok(Update::Add(eps)) => adds.extend(eps),
+                res => panic!("unexpected update: {:?}", res),
+            }
+        }
+
+        assert_eq!(
+            adds,
+            vec![
+                (addr0, Tagged::new(DiscoverySource::Destination, "ep")),
+                (addr1, Tagged::new(DiscoverySource::Dns, "ep")),
+            ]
+        );
+        // The tagged endpoint dereferences to the inner endpoint.
+        assert_eq!(*adds[0].1, "ep");
+    }
+
+    #[tokio::test]
+    async fn attributes_removals_to_merged_sources() {
+        let addr0 = SocketAddr::from(([198, 51, 100, 1], 8080));
+        let addr1 = SocketAddr::from(([198, 51, 100, 2], 8080));
+        let dst = Updates::default();
+        let dns = Updates::default();
+        let mut merged = fallback::Resolve::new(
+            fallback::RecoveryPolicy::Replace,
+            resolve(DiscoverySource::Destination, Shared(dst.clone())),
+            resolve(DiscoverySource::Dns, Shared(dns.clone())),
+        );
+        let mut resolution = Sources::new(merged.call(()).await.expect("resolution must succeed"));
+
+        dst.push(Update::Add(vec![(addr0, "ep")]));
+        dns.push(Update::Add(vec![(addr1, "ep")]));
+        assert_eq!(
+            next(&mut resolution).await,
+            Update::Add(vec![(
+                addr0,
+                Tagged::new(DiscoverySource::Destination, "ep")
+            )])
+        );
+        assert!(
+            next_within(&mut resolution, Duration::from_millis(10))
+                .await
+                .is_none(),
+            "the fallback endpoints must not be used while the primary has endpoints"
+        );
+        assert_eq!(
+            resolution.source(&addr0),
+            Some(DiscoverySource::Destination)
+        );
+        assert_eq!(resolution.source(&addr1), None);
+
+        // The primary endpoint is replaced by the fallback endpoint.
+        dst.push(Update::Empty);
+        assert_eq!(
+            next(&mut resolution).await,
+            Update::Add(vec![(addr1, Tagged::new(DiscoverySource::Dns, "ep"))])
+        );
+        assert_eq!(next(&mut resolution).await, Update::Remove(vec![addr0]));
+        assert_eq!(
+            resolution.removed(),
+            &[(addr0, DiscoverySource::Destination)][..]
+        );
+
+        dns.push(Update::Remove(vec![addr1]));
+        assert_eq!(next(&mut resolution).await, Update::Remove(vec![addr1]));
+        assert_eq!(resolution.removed(), &[(addr1, DiscoverySource::Dns)][..]);
+        assert_eq!(resolution.source(&addr1), None);
+    }
+}
@@ -0,0 +1,236 @@
+//! A middleware that limits the rate at which a resolution's updates are
+//! returned.
+//!
+//! Once an update has been returned, subsequent updates are coalesced by
+//! address until the interval elapses, at which point their net change is
+//! returned as a single batch. This smooths bursts of updates for consumers
+//! that do expensive work per update, e.g. rebuilding a balancer's ring.
+
+use crate::buffer::Coalesced;
+use crate::metrics::{Churn, NewChurn};
+use futures::{ready, TryFuture};
+use linkerd2_proxy_core::resolve::{self, Update};
+use pin_project::pin_project;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::time;
+use tracing::trace;
+
+#[derive(Clone, Debug)]
+pub struct Resolve<R, M = ()> {
+    resolve: R,
+    interval: Duration,
+    churn: M,
+}
+
+#[pin_project]
+#[derive(Debug)]
+pub struct ResolveFuture<F> {
+    #[pin]
+    future: F,
+    interval: Duration,
+    churn: Churn,
+}
+
+#[pin_project]
+pub struct Resolution<R: resolve::Resolution> {
+    #[pin]
+    resolution: R,
+    interval: Duration,
+    /// Elapses once the next batch of updates may be returned, if a batch
+    /// has been returned within the interval.
+    delay: Option<time::Delay>,
+    updates: Coalesced<R::Endpoint>,
+    /// Set while a batch is returned, so that its updates are returned
+    /// without waiting for the interval.
+    flushing: bool,
+    /// The error that ended the inner resolution, which is returned once the
+    /// prior updates have been returned.
+    error: Option<R::Error>,
+}
+
+// === impl Resolve ===
+
+impl<R> Resolve<R> {
+    /// Returns each resolution's updates at most once per `interval`.
+    pub fn new<T>(interval: Duration, resolve: R) -> Self
+    where
+        Self: resolve::Resolve<T>,
+    {
+        Self {
+            resolve,
+            interval,
+            churn: (),
+        }
+    }
+}
+
+impl<R, M> Resolve<R, M> {
+    /// Records the updates coalesced by each resolution in `churn`.
+    pub fn with_churn_metrics<N>(self, churn: N) -> Resolve<R, N> {
+        Resolve {
+            resolve: self.resolve,
+            interval: self.interval,
+            churn,
+        }
+    }
+}
+
+impl<T, R, M> tower::Service<T> for Resolve<R, M>
+where
+    R: resolve::Resolve<T>,
+    M: NewChurn<T>,
+{
+    type Response = Resolution<R::Resolution>;
+    type Error = R::Error;
+    type Future = ResolveFuture<R::Future>;
+
+    #[inline]
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.resolve.poll_ready(cx)
+    }
+
+    #[inline]
+    fn call(&mut self, target: T) -> Self::Future {
+        let churn = self.churn.new_churn(&target);
+        Self::Future {
+            future: self.resolve.resolve(target),
+            interval: self.interval,
+            churn,
+        }
+    }
+}
+
+// === impl ResolveFuture ===
+
+impl<F> Future for ResolveFuture<F>
+where
+    F: TryFuture,
+    F::Ok: resolve::Resolution,
+{
+    type Output = Result<Resolution<F::Ok>, F::Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let resolution = ready!(this.future.try_poll(cx))?;
+        Poll::Ready(Ok(Resolution::new(
+            *this.interval,
+            this.churn.clone(),
+            resolution,
+        )))
+    }
+}
+
+// === impl Resolution ===
+
+impl<R: resolve::Resolution> Resolution<R> {
+    fn new(interval: Duration, churn: Churn, resolution: R) -> Self {
+        Self {
+            resolution,
+            interval,
+            delay: None,
+            updates: Coalesced::new(churn),
+            flushing: false,
+            error: None,
+        }
+    }
+}
+
+impl<R: resolve::Resolution> resolve::Resolution for Resolution<R> {
+    type Endpoint = R::Endpoint;
+    type Error = R::Error;
+
+    fn poll(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Update<Self::Endpoint>, Self::Error>> {
+        let mut this = self.project();
+
+        // Coalesce all of the updates that are ready, so that the inner
+        // resolution is consumed even while updates are being withheld.
+        while this.error.is_none() {
+            match this.resolution.as_mut().poll(cx) {
+                Poll::Ready(Ok(update)) => this.updates.push(update),
+                Poll::Ready(Err(error)) => *this.error = Some(error),
+                Poll::Pending => break,
+            }
+        }
+
+        if !*this.flushing {
+            if let Some(delay) = this.delay.as_mut() {
+                ready!(Pin::new(delay).poll(cx));
+                *this.delay = None;
+            }
+            *this.flushing = true;
+        }
+
+        match this.updates.take() {
+            Some(update) => {
+                if this.updates.is_empty() {
+                    // The batch is complete, so the next batch is withheld
+                    // until the interval elapses.
+                    trace!(interval = ?this.interval, "Batch returned");
+                    *this.flushing = false;
+                    *this.delay = Some(time::delay_for(*this.interval));
+                }
+                Poll::Ready(Ok(update))
+            }
+            None => {
+                *this.flushing = false;
+                match this.error.take() {
+                    Some(error) => Poll::Ready(Err(error)),
+                    None => Poll::Pending,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::FutureExt;
+    use linkerd2_proxy_core::test_util::{next, next_within, Updates};
+    use std::net::SocketAddr;
+
+    fn addr(n: u8) -> SocketAddr {
+        ([198, 51, 100, n], 8080).into()
+    }
+
+    #[tokio::test]
+    async fn coalesces_updates_within_interval() {
+        let interval = Duration::from_millis(100);
+        let updates = Updates::default();
+        let churn = Churn::default();
+        let mut resolution = Resolution::new(interval, churn.clone(), updates.clone());
+
+        // The first update is not delayed.
+        updates.push(Update::Add(vec![(addr(1), ())]));
+        assert_eq!(
+            next(&mut resolution).now_or_never(),
+            Some(Update::Add(vec![(addr(1), ())]))
+        );
+
+        updates.push(Update::Add(vec![(addr(2), ())]));
+        updates.push(Update::Remove(vec![addr(1)]));
+        updates.push(Update::Add(vec![(addr(3), ())]));
+        updates.push(Update::Remove(vec![addr(3)]));
+        updates.push(Update::Add(vec![(addr(4), ())]));
+        assert_eq!(next_within(&mut resolution, interval / 2).await, None);
+        assert_eq!(churn.debounced_flaps_total(), 1);
+
+        // The net change is returned once the interval elapses: one addition
+        // and one removal, without waiting between them.
+        assert_eq!(
+            next_within(&mut resolution, interval).await,
+            Some(Update::Add(vec![(addr(2), ()), (addr(4), ())]))
+        );
+        assert_eq!(
+            next(&mut resolution).now_or_never(),
+            Some(Update::Remove(vec![addr(1), addr(3)]))
+        );
+        assert_eq!(next_within(&mut resolution, interval * 2).await, None);
+    }
+}
@@ -0,0 +1,485 @@
+//! A middleware that seeds resolutions with the endpoints that were known
+//! before the proxy restarted.
+//!
+//! Each resolution's live endpoints are recorded in a `WarmCache`, which may
+//! be exported as a JSON snapshot. A cache imported from a snapshot seeds
+//! each resolution of a target with its prior endpoints, as provisional
+//! endpoints, so that requests need not wait for the resolution to be rebuilt
+//! from scratch. Provisional endpoints that are not confirmed by the
+//! resolution within a window are removed.
+
+use futures::{ready, TryFuture};
+use indexmap::IndexSet;
+use linkerd2_proxy_core::resolve::{self, Update};
+use pin_project::{pin_project, pinned_drop};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::time;
+use tracing::debug;
+
+/// Records the live endpoints of each target's resolution.
+#[derive(Clone, Debug, Default)]
+pub struct WarmCache {
+    /// The live endpoints of each target, with the ID of the resolution that
+    /// most recently recorded them.
+    live: Arc<Mutex<HashMap<String, (usize, IndexSet<SocketAddr>)>>>,
+    /// Assigns each resolution an ID, so that a dropped resolution does not
+    /// remove the endpoints recorded by a newer resolution of its target.
+    resolutions: Arc<AtomicUsize>,
+    /// The endpoints imported from a snapshot that have not yet been used to
+    /// seed a resolution.
+    imported: Arc<Mutex<HashMap<String, Vec<SocketAddr>>>>,
+}
+
+/// Indicates that a snapshot could not be imported.
+#[derive(Clone, Debug)]
+pub struct InvalidSnapshot(String);
+
+#[derive(Clone, Debug)]
+pub struct Resolve<R, N> {
+    resolve: R,
+    cache: WarmCache,
+    window: Duration,
+    new_endpoint: N,
+}
+
+#[pin_project]
+#[derive(Debug)]
+pub struct ResolveFuture<F, N> {
+    #[pin]
+    future: F,
+    target: Option<String>,
+    cache: WarmCache,
+    window: Duration,
+    new_endpoint: N,
+}
+
+#[pin_project(PinnedDrop)]
+pub struct Resolution<R: resolve::Resolution> {
+    #[pin]
+    resolution: R,
+    target: String,
+    cache: WarmCache,
+    id: usize,
+    /// The imported endpoints, until they are returned.
+    provisional: Option<Update<R::Endpoint>>,
+    /// The imported endpoints that have not been confirmed by the resolution.
+    unconfirmed: IndexSet<SocketAddr>,
+    /// Elapses when unconfirmed endpoints are removed.
+    expiry: Option<time::Delay>,
+    live: IndexSet<SocketAddr>,
+}
+
+// === impl WarmCache ===
+
+impl WarmCache {
+    /// Exports the live endpoints of each target's resolution, keyed by the
+    /// target.
+    ///
+    /// Only addresses are exported: endpoint metadata is rebuilt by the
+    /// resolution once it is confirmed.
+    pub fn export(&self) -> Value {
+        let live = self.live.lock().unwrap();
+        let map = live
+            .iter()
+            .map(|(target, (_, addrs))| {
+                let addrs = addrs.iter().map(|a| Value::from(a.to_string())).collect();
+                (target.clone(), Value::Array(addrs))
+            })
+            .collect::<Map<_, _>>();
+        Value::Object(map)
+    }
+
+    /// Builds a cache that seeds each target's first resolution with the
+    /// endpoints in `snapshot`.
+    pub fn import(snapshot: &Value) -> Result<Self, InvalidSnapshot> {
+        let targets = snapshot
+            .as_object()
+            .ok_or_else(|| InvalidSnapshot("snapshot must be an object".into()))?;
+        let mut imported = HashMap::with_capacity(targets.len());
+        for (target, addrs) in targets.iter() {
+            let addrs = addrs
+                .as_array()
+                .ok_or_else(|| InvalidSnapshot(format!("{}: addresses must be a list", target)))?
+                .iter()
+                .map(|addr| {
+                    addr.as_str()
+                        .and_then(|a| a.parse().ok())
+                        .ok_or_else(|| InvalidSnapshot(format!("{}: invalid address", target)))
+                })
+                .collect::<Result<Vec<SocketAddr>, _>>()?;
+            imported.insert(target.clone(), addrs);
+        }
+        Ok(Self {
+            live: Arc::default(),
+            resolutions: Arc::default(),
+            imported: Arc::new(Mutex::new(imported)),
+        })
+    }
+
+    /// Takes the imported endpoints for `target`, so that only its first
+    /// resolution is seeded.
+    fn take_imported(&self, target: &str) -> Vec<SocketAddr> {
+        self.imported
+            .lock()
+            .unwrap()
+            .remove(target)
+            .unwrap_or_default()
+    }
+
+    fn record(&self, target: &str, id: usize, live: &IndexSet<SocketAddr>) {
+        self.live
+            .lock()
+            .unwrap()
+            .insert(target.to_string(), (id, live.clone()));
+    }
+
+    /// Removes the live endpoints of `target`, unless they were recorded by
+    /// another resolution.
+    fn remove(&self, target: &str, id: usize) {
+        let mut live = self.live.lock().unwrap();
+        if live.get(target).map(|(owner, _)| *owner) == Some(id) {
+            live.remove(target);
+        }
+    }
+}
+
+// === impl InvalidSnapshot ===
+
+impl fmt::Display for InvalidSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid resolution snapshot: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidSnapshot {}
+
+// === impl Resolve ===
+
+impl<R, N> Resolve<R, N> {
+    /// Records each resolution's endpoints in `cache`, and seeds it with the
+    /// endpoints imported for its target, each built by `new_endpoint`.
+    ///
+    /// Imported endpoints that the resolution does not add within `window`
+    /// are removed.
+    pub fn new<T>(cache: WarmCache, window: Duration, new_endpoint: N, resolve: R) -> Self
+    where
+        Self: resolve::Resolve<T>,
+    {
+        Self {
+            resolve,
+            cache,
+            window,
+            new_endpoint,
+        }
+    }
+}
+
+impl<T, R, N> tower::Service<T> for Resolve<R, N>
+where
+    T: fmt::Display,
+    R: resolve::Resolve<T>,
+    N: Fn(SocketAddr) -> R::Endpoint + Clone,
+{
+    type Response = Resolution<R::Resolution>;
+    type Error = R::Error;
+    type Future = ResolveFuture<R::Future, N>;
+
+    #[inline]
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.resolve.poll_ready(cx)
+    }
+
+    #[inline]
+    fn call(&mut self, target: T) -> Self::Future {
+        let name = target.to_string();
+        Self::Future {
+            future: self.resolve.resolve(target),
+            target: Some(name),
+            cache: self.cache.clone(),
+            window: self.window,
+            new_endpoint: self.new_endpoint.clone(),
+        }
+    }
+}
+
+// === impl ResolveFuture ===
+
+impl<F, N> Future for ResolveFuture<F, N>
+where
+    F: TryFuture,
+    F::Ok: resolve::Resolution,
+    N: Fn(SocketAddr) -> <F::Ok as resolve::Resolution>::Endpoint,
+{
+    type Output = Result<Resolution<F::Ok>, F::Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let resolution = ready!(this.future.try_poll(cx))?;
+        let target = this.target.take().expect("polled after ready");
+        let imported = this.cache.take_imported(&target);
+        Poll::Ready(Ok(Resolution::new(
+            target,
+            this.cache.clone(),
+            imported,
+            *this.window,
+            &*this.new_endpoint,
+            resolution,
+        )))
+    }
+}
+
+// === impl Resolution ===
+
+impl<R: resolve::Resolution> Resolution<R> {
+    fn new(
+        target: String,
+        cache: WarmCache,
+        imported: Vec<SocketAddr>,
+        window: Duration,
+        new_endpoint: impl Fn(SocketAddr) -> R::Endpoint,
+        resolution: R,
+    ) -> Self {
+        let (provisional, expiry) = if imported.is_empty() {
+            (None, None)
+        } else {
+            debug!(%target, endpoints = imported.len(), "Seeding resolution");
+            let endpoints = imported.iter().map(|a| (*a, new_endpoint(*a))).collect();
+            (Some(Update::Add(endpoints)), Some(time::delay_for(window)))
+        };
+        let id = cache.resolutions.fetch_add(1, Ordering::Relaxed);
+        Self {
+            resolution,
+            target,
+            cache,
+            id,
+            provisional,
+            unconfirmed: imported.into_iter().collect(),
+            expiry,
+            live: IndexSet::new(),
+        }
+    }
+}
+
+impl<R: resolve::Resolution> resolve::Resolution for Resolution<R> {
+    type Endpoint = R::Endpoint;
+    type Error = R::Error;
+
+    fn poll(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Update<Self::Endpoint>, Self::Error>> {
+        let mut this = self.project();
+        if let Some(update) = this.provisional.take() {
+            this.live.extend(this.unconfirmed.iter().cloned());
+            this.cache.record(this.target, *this.id, this.live);
+            return Poll::Ready(Ok(update));
+        }
+
+        if let Some(expiry) = this.expiry.as_mut() {
+            if Pin::new(expiry).poll(cx).is_ready() {
+                *this.expiry = None;
+                if !this.unconfirmed.is_empty() {
+                    let removed = this.unconfirmed.drain(..).collect::<Vec<_>>();
+                    debug!(target = %this.target, endpoints = removed.len(), "Removing unconfirmed endpoints");
+                    this.live.retain(|addr| !removed.contains(addr));
+                    this.cache.record(this.target, *this.id, this.live);
+                    return Poll::Ready(Ok(Update::Remove(removed)));
+                }
+            }
+        }
+
+        let update = ready!(this.resolution.as_mut().poll(cx))?;
+        match update {
+            Update::Add(ref endpoints) => {
+                for (addr, _) in endpoints.iter() {
+                    this.unconfirmed.remove(addr);
+                    this.live.insert(*addr);
+                }
+            }
+            Update::Remove(ref addrs) => {
+                for addr in addrs.iter() {
+                    this.unconfirmed.remove(addr);
+                    this.live.remove(addr);
+                }
+            }
+            Update::Empty | Update::DoesNotExist => {
+                this.unconfirmed.clear();
+                this.live.clear();
+            }
+        }
+        if this.unconfirmed.is_empty() {
+            *this.expiry = None;
+        }
+        this.cache.record(this.target, *this.id, this.live);
+        Poll::Ready(Ok(update))
+    }
+}
+
+/// A resolution's endpoints are no longer live once it is dropped.
+#[pinned_drop]
+impl<R: resolve::Resolution> PinnedDrop for Resolution<R> {
+    fn drop(self: Pin<&mut Self>) {
+        let this = self.project();
+        this.cache.remove(this.target, *this.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::FutureExt;
+    use linkerd2_proxy_core::test_util::{next, Updates};
+
+    const TARGET: &str = "web.example.com:8080";
+
+    fn addr(n: u8) -> SocketAddr {
+        ([198, 51, 100, n], 8080).into()
+    }
+
+    fn resolution(
+        cache: &WarmCache,
+        window: Duration,
+        updates: Updates<()>,
+    ) -> Resolution<Updates<()>> {
+        let imported = cache.take_imported(TARGET);
+        Resolution::new(
+            TARGET.to_string(),
+            cache.clone(),
+            imported,
+            window,
+            |_| (),
+            updates,
+        )
+    }
+
+    #[tokio::test]
+    async fn export_round_trip() {
+        let cache = WarmCache::default();
+        let updates = Updates::default();
+        let mut resolution = resolution(&cache, Duration::from_secs(10), updates.clone());
+        updates.push(Update::Add(vec![(addr(1), ()), (addr(2), ())]));
+        updates.push(Update::Remove(vec![addr(1)]));
+        updates.push(Update::Add(vec![(addr(3), ())]));
+        for _ in 0..3 {
+            next(&mut resolution).await;
+        }
+
+        let snapshot = cache.export();
+        assert_eq!(
+            snapshot,
+            serde_json::json!({ TARGET: ["198.51.100.2:8080", "198.51.100.3:8080"] })
+        );
+
+        // The imported cache seeds a resolution with the exported endpoints,
+        // but only once.
+        let imported = WarmCache::import(&snapshot).expect("snapshot must be valid");
+        let mut seeded = resolution_for(&imported);
+        assert_eq!(
+            next(&mut seeded).now_or_never(),
+            Some(Update::Add(vec![(addr(2), ()), (addr(3), ())]))
+        );
+        assert!(imported.take_imported(TARGET).is_empty());
+        assert_eq!(imported.export(), snapshot);
+
+        // Dropped resolutions are not exported.
+        drop(resolution);
+        drop(seeded);
+        assert_eq!(cache.export(), serde_json::json!({}));
+        assert_eq!(imported.export(), serde_json::json!({}));
+
+        assert!(WarmCache::import(&serde_json::json!([])).is_err());
+        assert!(WarmCache::import(&serde_json::json!({ TARGET: ["nope"] })).is_err());
+    }
+
+    #[tokio::test]
+    async fn dropped_resolution_retains_newer_endpoints() {
+        let cache = WarmCache::default();
+        let old_updates = Updates::default();
+        let mut old = resolution(&cache, Duration::from_secs(10), old_updates.clone());
+        old_updates.push(Update::Add(vec![(addr(1), ())]));
+        next(&mut old).await;
+
+        // A newer resolution of the same target records its endpoints before
+        // the older resolution is dropped.
+        let new_updates = Updates::default();
+        let mut new = resolution(&cache, Duration::from_secs(10), new_updates.clone());
+        new_updates.push(Update::Add(vec![(addr(2), ())]));
+        next(&mut new).await;
+        drop(old);
+        assert_eq!(
+            cache.export(),
+            serde_json::json!({ TARGET: ["198.51.100.2:8080"] })
+        );
+
+        drop(new);
+        assert_eq!(cache.export(), serde_json::json!({}));
+    }
+
+    fn resolution_for(cache: &WarmCache) -> Resolution<Updates<()>> {
+        resolution(cache, Duration::from_secs(10), Updates::default())
+    }
+
+    fn imported(addrs: &[SocketAddr]) -> WarmCache {
+        let addrs = addrs.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        WarmCache::import(&serde_json::json!({ TARGET: addrs })).expect("snapshot must be valid")
+    }
+
+    #[tokio::test]
+    async fn confirmed_endpoints_are_retained() {
+        let window = Duration::from_millis(50);
+        let cache = imported(&[addr(1), addr(2)]);
+        let updates = Updates::default();
+        let mut resolution = resolution(&cache, window, updates.clone());
+        assert_eq!(
+            next(&mut resolution).await,
+            Update::Add(vec![(addr(1), ()), (addr(2), ())])
+        );
+
+        updates.push(Update::Add(vec![(addr(1), ()), (addr(2), ())]));
+        assert_eq!(
+            next(&mut resolution).await,
+            Update::Add(vec![(addr(1), ()), (addr(2), ())])
+        );
+
+        // Nothing is removed once the window elapses.
+        let next = time::timeout(window * 2, next(&mut resolution)).await;
+        assert!(next.is_err(), "unexpected update: {:?}", next);
+    }
+
+    #[tokio::test]
+    async fn unconfirmed_endpoints_expire() {
+        let window = Duration::from_millis(50);
+        let cache = imported(&[addr(1), addr(2)]);
+        let updates = Updates::default();
+        let mut resolution = resolution(&cache, window, updates.clone());
+        assert_eq!(
+            next(&mut resolution).await,
+            Update::Add(vec![(addr(1), ()), (addr(2), ())])
+        );
+
+        updates.push(Update::Add(vec![(addr(2), ()), (addr(3), ())]));
+        assert_eq!(
+            next(&mut resolution).await,
+            Update::Add(vec![(addr(2), ()), (addr(3), ())])
+        );
+
+        // The endpoint that was not confirmed is removed once the window
+        // elapses.
+        assert_eq!(next(&mut resolution).await, Update::Remove(vec![addr(1)]));
+        assert_eq!(
+            cache.export(),
+            serde_json::json!({ TARGET: ["198.51.100.2:8080", "198.51.100.3:8080"] })
+        );
+    }
+}
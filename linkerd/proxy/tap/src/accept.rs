@@ -19,6 +19,7 @@ use tower::Service;
 pub struct AcceptPermittedClients {
     permitted_client_ids: Arc<IndexSet<identity::Name>>,
     server: Server,
+    allow_http1: bool,
 }
 
 pub type ServeFuture = Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'static>>;
@@ -28,6 +29,20 @@ impl AcceptPermittedClients {
         Self {
             permitted_client_ids,
             server,
+            allow_http1: false,
+        }
+    }
+
+    /// Accepts HTTP/1.1 tap connections in addition to HTTP/2.
+    ///
+    /// Connections that begin with the HTTP/2 preface are still served over
+    /// HTTP/2. The gRPC tap protocol relies on HTTP/2, so an HTTP/1.1 client
+    /// may only observe responses that complete without trailers, e.g. a
+    /// failure to authorize the tap; it cannot stream tap events.
+    pub fn with_http1(self, allow_http1: bool) -> Self {
+        Self {
+            allow_http1,
+            ..self
         }
     }
 
@@ -37,10 +52,11 @@ impl AcceptPermittedClients {
         T::ObserveStream: Send + 'static,
     {
         let svc = TapServer::new(tap);
+        let http2_only = !self.allow_http1;
         Box::pin(async move {
             hyper::server::conn::Http::new()
                 .with_executor(trace::Executor::new())
-                .http2_only(true)
+                .http2_only(http2_only)
                 .serve_connection(io, HyperServerSvc::new(svc))
                 .await
                 .map_err(Into::into)
@@ -108,3 +124,58 @@ pub mod unauthenticated {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    const OBSERVE_HTTP1: &[u8] = b"POST /io.linkerd.proxy.tap.Tap/Observe HTTP/1.1\r\n\
+        host: tap.example.com\r\n\
+        content-type: application/grpc\r\n\
+        content-length: 0\r\n\
+        \r\n";
+
+    fn accept() -> AcceptPermittedClients {
+        let (_, _, server) = crate::new();
+        AcceptPermittedClients::new(Default::default(), server)
+    }
+
+    /// Returns the server and client ends of a loopback connection.
+    async fn connect() -> (BoxedIo, TcpStream) {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.expect("must bind");
+        let addr = listener.local_addr().expect("must have an address");
+        let client = TcpStream::connect(addr).await.expect("must connect");
+        let (server, _) = listener.accept().await.expect("must accept");
+        (BoxedIo::new(server), client)
+    }
+
+    #[tokio::test]
+    async fn serves_http1_when_enabled() {
+        let (io, mut client) = connect().await;
+        let accept = accept().with_http1(true);
+        tokio::spawn(accept.serve_unauthenticated(io, "unauthenticated"));
+
+        client.write_all(OBSERVE_HTTP1).await.expect("must write");
+        let mut buf = [0u8; 1024];
+        let sz = client.read(&mut buf).await.expect("must read");
+        let rsp = String::from_utf8_lossy(&buf[..sz]);
+        assert!(
+            rsp.starts_with("HTTP/1.1 200"),
+            "unexpected response: {}",
+            rsp
+        );
+        assert!(rsp.contains("grpc-status"), "unexpected response: {}", rsp);
+    }
+
+    #[tokio::test]
+    async fn rejects_http1_by_default() {
+        let (io, mut client) = connect().await;
+        let serve = tokio::spawn(accept().serve_unauthenticated(io, "unauthenticated"));
+
+        client.write_all(OBSERVE_HTTP1).await.expect("must write");
+        let res = serve.await.expect("serve must not panic");
+        assert!(res.is_err(), "an HTTP/1.1 connection must fail");
+    }
+}
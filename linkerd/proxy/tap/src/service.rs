@@ -4,17 +4,22 @@ use super::Inspect;
 use futures::{ready, TryFuture};
 use http;
 use hyper::body::HttpBody;
+use linkerd2_dns_name::{Name, SuffixSet};
 use linkerd2_proxy_http::HasH2Reason;
 use linkerd2_stack::NewService;
 use pin_project::{pin_project, pinned_drop};
+use std::convert::TryFrom;
 use std::future::Future;
 use std::pin::Pin;
+use std::sync::Arc;
 use std::task::{Context, Poll};
+use tracing::trace;
 
 /// A layer that wraps MakeServices to record taps.
 #[derive(Clone, Debug)]
 pub struct Layer<T> {
     registry: Registry<T>,
+    filter: AuthorityFilter,
 }
 
 /// Makes wrapped Services to record taps.
@@ -22,6 +27,7 @@ pub struct Layer<T> {
 pub struct MakeService<M, T> {
     inner: M,
     registry: Registry<T>,
+    filter: AuthorityFilter,
 }
 
 /// Future returned by `MakeService`.
@@ -31,6 +37,7 @@ pub struct MakeFuture<F, I, T> {
     inner: F,
     inspect: I,
     registry: Registry<T>,
+    filter: AuthorityFilter,
 }
 
 /// A middleware that records HTTP taps.
@@ -39,6 +46,7 @@ pub struct Service<S, I, T> {
     inner: S,
     inspect: I,
     registry: Registry<T>,
+    filter: AuthorityFilter,
 }
 
 #[pin_project]
@@ -48,6 +56,15 @@ pub struct ResponseFuture<F, T> {
     taps: Vec<T>,
 }
 
+/// A coarse pre-filter on request authorities.
+///
+/// Requests whose authority is not contained by the filter's suffixes are not
+/// offered to any of the registered taps, so they never reach the per-tap
+/// match rules. This complements, rather than replaces, the fine-grained
+/// matching configured by each gRPC `Observe` request.
+#[derive(Clone, Debug, Default)]
+struct AuthorityFilter(Option<Arc<SuffixSet>>);
+
 // A `Body` instrumented with taps.
 #[pin_project(PinnedDrop, project = BodyProj)]
 #[derive(Debug)]
@@ -66,7 +83,22 @@ where
 
 impl<T> Layer<T> {
     pub(super) fn new(registry: Registry<T>) -> Self {
-        Self { registry }
+        Self {
+            registry,
+            filter: AuthorityFilter::default(),
+        }
+    }
+
+    /// Only offers requests to taps if their authority is contained by one
+    /// of the given suffixes.
+    ///
+    /// This is a coarse pre-filter; taps still apply their own match rules
+    /// to the requests that pass it.
+    pub fn with_authority_filter(self, suffixes: SuffixSet) -> Self {
+        Self {
+            filter: AuthorityFilter(Some(Arc::new(suffixes))),
+            ..self
+        }
     }
 }
 
@@ -80,6 +112,7 @@ where
         MakeService {
             inner,
             registry: self.registry.clone(),
+            filter: self.filter.clone(),
         }
     }
 }
@@ -100,6 +133,7 @@ where
             inner: self.inner.new_service(target),
             inspect,
             registry: self.registry.clone(),
+            filter: self.filter.clone(),
         }
     }
 }
@@ -124,6 +158,7 @@ where
             inner: self.inner.call(target),
             inspect,
             registry: self.registry.clone(),
+            filter: self.filter.clone(),
         }
     }
 }
@@ -145,12 +180,42 @@ where
             inner,
             inspect: this.inspect.clone(),
             registry: this.registry.clone(),
+            filter: this.filter.clone(),
         }))
     }
 }
 
 // === Service ===
 
+impl<S, I, T> Service<S, I, T>
+where
+    I: Inspect,
+    T: Tap,
+{
+    /// Offers the request to each registered tap, returning the request-body
+    /// and response taps of those that matched.
+    fn tap<B: HttpBody>(
+        &self,
+        req: &http::Request<B>,
+    ) -> (Vec<T::TapRequestPayload>, Vec<T::TapResponse>) {
+        let mut req_taps = Vec::new();
+        let mut rsp_taps = Vec::new();
+
+        if !self.filter.permits(req, &self.inspect) {
+            return (req_taps, rsp_taps);
+        }
+
+        for mut t in self.registry.get_taps() {
+            if let Some((req_tap, rsp_tap)) = t.tap(req, &self.inspect) {
+                req_taps.push(req_tap);
+                rsp_taps.push(rsp_tap);
+            }
+        }
+
+        (req_taps, rsp_taps)
+    }
+}
+
 impl<S, I, T, A, B> tower::Service<http::Request<A>> for Service<S, I, T>
 where
     S: tower::Service<http::Request<Body<A, T::TapRequestPayload>>, Response = http::Response<B>>,
@@ -174,15 +239,7 @@ where
 
     fn call(&mut self, req: http::Request<A>) -> Self::Future {
         // Record the request and obtain request-body and response taps.
-        let mut req_taps = Vec::new();
-        let mut rsp_taps = Vec::new();
-
-        for mut t in self.registry.get_taps() {
-            if let Some((req_tap, rsp_tap)) = t.tap(&req, &self.inspect) {
-                req_taps.push(req_tap);
-                rsp_taps.push(rsp_tap);
-            }
-        }
+        let (req_taps, rsp_taps) = self.tap(&req);
 
         // Install the request taps into the request body.
         let req = req.map(move |inner| Body {
@@ -236,6 +293,32 @@ where
     }
 }
 
+// === AuthorityFilter ===
+
+impl AuthorityFilter {
+    fn permits<B, I: Inspect>(&self, req: &http::Request<B>, inspect: &I) -> bool {
+        let suffixes = match self.0 {
+            Some(ref suffixes) => suffixes,
+            None => return true,
+        };
+        if suffixes.contains_root() {
+            return true;
+        }
+
+        let name = inspect.authority(req).and_then(|a| {
+            let authority = a.parse::<http::uri::Authority>().ok()?;
+            Name::try_from(authority.host().as_bytes()).ok()
+        });
+        match name {
+            Some(ref name) if suffixes.contains(name) => true,
+            name => {
+                trace!("Authority does not match the tap filter: {:?}", name);
+                false
+            }
+        }
+    }
+}
+
 // === Body ===
 
 // `T` need not implement Default.
@@ -346,3 +429,155 @@ fn eos<T: TapPayload>(taps: &mut Vec<T>, trailers: Option<&http::HeaderMap>) {
         tap.eos(trailers);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Buf;
+    use indexmap::IndexMap;
+    use linkerd2_conditional::Conditional;
+    use linkerd2_identity as identity;
+    use linkerd2_proxy_transport::tls::ReasonForNoIdentity;
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// A tap that counts the requests offered to it.
+    #[derive(Clone, Debug, Default)]
+    struct CountTap(Arc<AtomicUsize>);
+
+    struct NoPayload;
+
+    #[derive(Clone, Debug)]
+    struct Target;
+
+    impl Tap for CountTap {
+        type TapRequestPayload = NoPayload;
+        type TapResponse = NoPayload;
+        type TapResponsePayload = NoPayload;
+
+        fn can_tap_more(&self) -> bool {
+            true
+        }
+
+        fn tap<B: HttpBody, I: Inspect>(
+            &mut self,
+            _: &http::Request<B>,
+            _: &I,
+        ) -> Option<(NoPayload, NoPayload)> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Some((NoPayload, NoPayload))
+        }
+    }
+
+    impl TapPayload for NoPayload {
+        fn data<B: Buf>(&mut self, _: &B) {}
+
+        fn eos(self, _: Option<&http::HeaderMap>) {}
+
+        fn fail<E: HasH2Reason>(self, _: &E) {}
+    }
+
+    impl TapResponse for NoPayload {
+        type TapPayload = NoPayload;
+
+        fn tap<B: HttpBody>(self, _: &http::Response<B>) -> NoPayload {
+            NoPayload
+        }
+
+        fn fail<E: HasH2Reason>(self, _: &E) {}
+    }
+
+    impl Inspect for Target {
+        fn src_addr<B>(&self, _: &http::Request<B>) -> Option<SocketAddr> {
+            None
+        }
+
+        fn src_tls<'a, B>(
+            &self,
+            _: &'a http::Request<B>,
+        ) -> Conditional<&'a identity::Name, ReasonForNoIdentity> {
+            Conditional::None(ReasonForNoIdentity::Disabled)
+        }
+
+        fn dst_addr<B>(&self, _: &http::Request<B>) -> Option<SocketAddr> {
+            None
+        }
+
+        fn dst_labels<B>(&self, _: &http::Request<B>) -> Option<&IndexMap<String, String>> {
+            None
+        }
+
+        fn dst_tls<B>(
+            &self,
+            _: &http::Request<B>,
+        ) -> Conditional<&identity::Name, ReasonForNoIdentity> {
+            Conditional::None(ReasonForNoIdentity::Disabled)
+        }
+
+        fn route_labels<B>(&self, _: &http::Request<B>) -> Option<Arc<IndexMap<String, String>>> {
+            None
+        }
+
+        fn is_outbound<B>(&self, _: &http::Request<B>) -> bool {
+            true
+        }
+    }
+
+    fn service(filter: Option<&str>) -> (Service<(), Target, CountTap>, Arc<AtomicUsize>) {
+        let tap = CountTap::default();
+        let count = tap.0.clone();
+        let registry = Registry::new();
+        registry.register(tap);
+
+        let filter = filter.map(|sfx| {
+            let sfx = linkerd2_dns_name::Suffix::try_from(sfx).unwrap();
+            Arc::new(SuffixSet::from(vec![sfx]))
+        });
+        let svc = Service {
+            inner: (),
+            inspect: Target,
+            registry,
+            filter: AuthorityFilter(filter),
+        };
+        (svc, count)
+    }
+
+    fn req(authority: &str) -> http::Request<hyper::Body> {
+        http::Request::builder()
+            .uri(format!("http://{}/", authority))
+            .body(hyper::Body::empty())
+            .unwrap()
+    }
+
+    #[test]
+    fn unfiltered_taps_all_authorities() {
+        let (svc, count) = service(None);
+        assert_eq!(svc.tap(&req("web.ns.svc.cluster.local:8080")).1.len(), 1);
+        assert_eq!(svc.tap(&req("10.1.1.1:8080")).1.len(), 1);
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn filter_rejects_nonmatching_authorities_early() {
+        let (svc, count) = service(Some("ns.svc.cluster.local"));
+
+        let (req_taps, rsp_taps) = svc.tap(&req("web.ns.svc.cluster.local:8080"));
+        assert_eq!((req_taps.len(), rsp_taps.len()), (1, 1));
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+
+        // Neither of these requests is offered to the registered tap.
+        assert!(svc
+            .tap(&req("web.other.svc.cluster.local:8080"))
+            .1
+            .is_empty());
+        assert!(svc.tap(&req("10.1.1.1:8080")).1.is_empty());
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn root_filter_taps_all_authorities() {
+        let (svc, count) = service(Some("."));
+        assert_eq!(svc.tap(&req("10.1.1.1:8080")).1.len(), 1);
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,92 @@
+#![deny(warnings, rust_2018_idioms)]
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use tracing::{
+    field::{Field, Visit},
+    span, Event, Level, Metadata, Subscriber,
+};
+
+/// A `Subscriber` that records the fields of each enabled span and event, so
+/// that tests may assert on what was traced.
+#[derive(Clone, Debug)]
+pub struct Recorder {
+    level: Level,
+    spans: Arc<Mutex<Vec<String>>>,
+    events: Arc<Mutex<Vec<String>>>,
+}
+
+/// Formats each recorded field as `name=value`, separated by spaces.
+struct FmtFields<'a>(&'a mut String);
+
+// === impl Recorder ===
+
+impl Recorder {
+    /// Records spans and events at `level` and more severe levels.
+    pub fn new(level: Level) -> Self {
+        Self {
+            level,
+            spans: Arc::new(Mutex::new(Vec::new())),
+            events: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Returns the name of each span that was created, followed by its
+    /// fields, including those recorded after the span was created.
+    pub fn spans(&self) -> Vec<String> {
+        self.spans.lock().unwrap().clone()
+    }
+
+    /// Returns the fields of each event that was traced.
+    pub fn events(&self) -> Vec<String> {
+        self.events.lock().unwrap().clone()
+    }
+}
+
+impl Default for Recorder {
+    fn default() -> Self {
+        Self::new(Level::TRACE)
+    }
+}
+
+impl Subscriber for Recorder {
+    fn enabled(&self, meta: &Metadata<'_>) -> bool {
+        *meta.level() <= self.level
+    }
+
+    fn new_span(&self, span: &span::Attributes<'_>) -> span::Id {
+        let mut fields = span.metadata().name().to_string();
+        span.record(&mut FmtFields(&mut fields));
+        let mut spans = self.spans.lock().unwrap();
+        spans.push(fields);
+        span::Id::from_u64(spans.len() as u64)
+    }
+
+    fn record(&self, id: &span::Id, values: &span::Record<'_>) {
+        let mut spans = self.spans.lock().unwrap();
+        values.record(&mut FmtFields(&mut spans[id.into_u64() as usize - 1]));
+    }
+
+    fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut fields = String::new();
+        event.record(&mut FmtFields(&mut fields));
+        self.events.lock().unwrap().push(fields);
+    }
+
+    fn enter(&self, _: &span::Id) {}
+
+    fn exit(&self, _: &span::Id) {}
+}
+
+// === impl FmtFields ===
+
+impl Visit for FmtFields<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if !self.0.is_empty() {
+            self.0.push(' ');
+        }
+        self.0.push_str(&format!("{}={:?}", field.name(), value));
+    }
+}